@@ -0,0 +1,182 @@
+//! Compares harbor's `ThreadPool` with the `threadpool` crate: how long a
+//! job waits before a worker picks it up, how many no-op jobs each pool
+//! gets through, and how many requests a server built on each answers.
+//!
+//! Run with `cargo bench`. Each measurement is repeated and the median
+//! printed, so numbers from the same machine can be compared across
+//! changes to the pool.
+
+use std::{
+    io::{BufReader, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use harbor::server::{Router, handle_request, parse_request, write_response};
+
+const WORKERS: usize = 4;
+const ROUNDS: usize = 5;
+const DISPATCHES: usize = 2_000;
+const JOBS: usize = 100_000;
+const CLIENTS: usize = 8;
+const REQUESTS: usize = 250;
+
+/// The two pools behind one interface.
+trait Pool {
+    fn run(&self, job: Box<dyn FnOnce() + Send>);
+}
+
+impl Pool for harbor::ThreadPool {
+    fn run(&self, job: Box<dyn FnOnce() + Send>) {
+        self.execute(move || {
+            job();
+            Ok(String::new())
+        });
+    }
+}
+
+impl Pool for threadpool::ThreadPool {
+    fn run(&self, job: Box<dyn FnOnce() + Send>) {
+        self.execute(job);
+    }
+}
+
+fn main() {
+    println!("{:<28} {:>14} {:>14}", "benchmark", "harbor", "threadpool");
+    compare(
+        "dispatch latency (median)",
+        "",
+        [
+            median(|| dispatch_latency(&harbor_pool())),
+            median(|| dispatch_latency(&threadpool::ThreadPool::new(WORKERS))),
+        ],
+    );
+    compare(
+        "no-op jobs",
+        "jobs/s",
+        [
+            median(|| job_throughput(harbor_pool)),
+            median(|| job_throughput(|| threadpool::ThreadPool::new(WORKERS))),
+        ],
+    );
+
+    // Both answer `/` with the built-in `hello.html` page.
+    let harbor_server = harbor::test::TestServer::spawn(Router::new());
+    let external_server = spawn_external_server();
+    compare(
+        "HTTP requests",
+        "req/s",
+        [
+            median(|| http_throughput(harbor_server.addr())),
+            median(|| http_throughput(external_server)),
+        ],
+    );
+}
+
+fn harbor_pool() -> harbor::ThreadPool {
+    harbor::ThreadPool::build(WORKERS).expect("workers is not zero")
+}
+
+/// A measurement: a duration, or a rate per second.
+enum Measured {
+    Latency(Duration),
+    Rate(f64),
+}
+
+fn compare(name: &str, unit: &str, [harbor, threadpool]: [Measured; 2]) {
+    let show = |measured: Measured| match measured {
+        Measured::Latency(latency) => format!("{:.2?}", latency),
+        Measured::Rate(rate) => format!("{:.0} {}", rate, unit),
+    };
+    println!("{:<28} {:>14} {:>14}", name, show(harbor), show(threadpool));
+}
+
+/// Runs `bench` `ROUNDS` times and keeps the median result.
+fn median(mut bench: impl FnMut() -> Measured) -> Measured {
+    let mut results: Vec<Measured> = (0..ROUNDS).map(|_| bench()).collect();
+    let key = |measured: &Measured| match measured {
+        Measured::Latency(latency) => latency.as_secs_f64(),
+        Measured::Rate(rate) => *rate,
+    };
+    results.sort_by(|a, b| key(a).total_cmp(&key(b)));
+    results.swap_remove(ROUNDS / 2)
+}
+
+/// The median time from handing a job to an idle pool until it starts.
+fn dispatch_latency(pool: &impl Pool) -> Measured {
+    let (sender, receiver) = mpsc::channel();
+    let mut latencies: Vec<Duration> = (0..DISPATCHES)
+        .map(|_| {
+            let sender = sender.clone();
+            let submitted = Instant::now();
+            pool.run(Box::new(move || {
+                let _ = sender.send(submitted.elapsed());
+            }));
+            receiver.recv().expect("the job ran")
+        })
+        .collect();
+    latencies.sort();
+    Measured::Latency(latencies[latencies.len() / 2])
+}
+
+/// Jobs per second through a fresh pool, including waiting for the last.
+fn job_throughput<P: Pool>(pool: impl FnOnce() -> P) -> Measured {
+    let pool = pool();
+    let (sender, receiver) = mpsc::channel();
+    let started = Instant::now();
+    for _ in 0..JOBS {
+        let sender = sender.clone();
+        pool.run(Box::new(move || {
+            let _ = sender.send(());
+        }));
+    }
+    for _ in 0..JOBS {
+        receiver.recv().expect("the job ran");
+    }
+    Measured::Rate(JOBS as f64 / started.elapsed().as_secs_f64())
+}
+
+/// Requests per second from `CLIENTS` threads, one connection each.
+fn http_throughput(addr: SocketAddr) -> Measured {
+    let started = Instant::now();
+    let clients: Vec<_> = (0..CLIENTS)
+        .map(|_| {
+            thread::spawn(move || {
+                for _ in 0..REQUESTS {
+                    let mut stream = TcpStream::connect(addr).expect("the server is up");
+                    stream
+                        .write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n")
+                        .expect("request sent");
+                    let mut response = Vec::new();
+                    stream.read_to_end(&mut response).expect("response read");
+                    assert!(response.starts_with(b"HTTP/1.1 200"));
+                }
+            })
+        })
+        .collect();
+    for client in clients {
+        client.join().expect("the client finished");
+    }
+    Measured::Rate((CLIENTS * REQUESTS) as f64 / started.elapsed().as_secs_f64())
+}
+
+/// The `external_server` binary's design: harbor's HTTP layer on the
+/// `threadpool` crate.
+fn spawn_external_server() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("a free port");
+    let addr = listener.local_addr().expect("a bound address");
+    thread::spawn(move || {
+        let pool = threadpool::ThreadPool::new(WORKERS);
+        for stream in listener.incoming().flatten() {
+            pool.execute(move || {
+                let mut stream = stream;
+                if let Ok(Some(request)) = parse_request(&mut BufReader::new(&stream)) {
+                    let _ = write_response(&mut stream, handle_request(&request));
+                }
+            });
+        }
+    });
+    addr
+}
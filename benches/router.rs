@@ -0,0 +1,78 @@
+//! How long `Router::dispatch` takes to find a route as routers grow: with
+//! routes kept in a tree of path segments the cost should follow the
+//! length of the path, not the number of routes.
+//!
+//! Run with `cargo bench --bench router`. Each measurement is repeated and
+//! the median printed.
+
+use std::{
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use harbor::server::{HttpRequest, Response, Router, parse_request};
+
+const ROUTE_COUNTS: [usize; 4] = [10, 100, 1_000, 10_000];
+const ROUNDS: usize = 5;
+const LOOKUPS: usize = 100_000;
+
+fn main() {
+    println!("{:>8} {:>16}", "routes", "per lookup");
+    for routes in ROUTE_COUNTS {
+        let router = router(routes);
+        // The routes registered last, which a scan would reach last, and a
+        // path no route matches.
+        let last = routes / 4 - 1;
+        let requests: Vec<HttpRequest> = [
+            format!("/pages/{}/about", last),
+            format!("/users{}/42", last),
+            format!("/api/v{}/items/7/detail", last),
+            format!("/files{}/css/site.css", last),
+            "/missing/route".to_string(),
+        ]
+        .iter()
+        .map(|path| request(path))
+        .collect();
+        let lookup = median(|| lookups(&router, &requests));
+        println!("{:>8} {:>16.2?}", routes, lookup);
+    }
+}
+
+/// A router with `routes` routes, a quarter each of static paths, paths
+/// with a `{name}` segment, nested ones with several, and wildcards.
+fn router(routes: usize) -> Router {
+    (0..routes / 4).fold(Router::new(), |router, i| {
+        router
+            .get(&format!("/pages/{}/about", i), ok)
+            .get(&format!("/users{}/{{id}}", i), ok)
+            .get(&format!("/api/v{}/items/{{item}}/detail", i), ok)
+            .get(&format!("/files{}/*path", i), ok)
+    })
+}
+
+fn ok(_: &HttpRequest) -> Response {
+    Response::new(200)
+}
+
+fn request(path: &str) -> HttpRequest {
+    let raw = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+    parse_request(&mut raw.as_bytes())
+        .expect("a valid request")
+        .expect("a whole request")
+}
+
+/// The average time of one `dispatch` over `LOOKUPS` of `requests`.
+fn lookups(router: &Router, requests: &[HttpRequest]) -> Duration {
+    let started = Instant::now();
+    for request in requests.iter().cycle().take(LOOKUPS) {
+        black_box(router.dispatch(black_box(request)));
+    }
+    started.elapsed() / LOOKUPS as u32
+}
+
+/// Runs `bench` `ROUNDS` times and keeps the median result.
+fn median(mut bench: impl FnMut() -> Duration) -> Duration {
+    let mut results: Vec<Duration> = (0..ROUNDS).map(|_| bench()).collect();
+    results.sort();
+    results[ROUNDS / 2]
+}
@@ -0,0 +1,13 @@
+#![no_main]
+
+use harbor::server::HttpRequest;
+use libfuzzer_sys::fuzz_target;
+
+// Parses `data` as pipelined requests until it runs out or one is refused.
+// Any panic, hang or runaway allocation is a bug in the parser.
+fuzz_target!(|data: &[u8]| {
+    let mut rest = data;
+    while let Ok(Some(request)) = HttpRequest::parse(&mut rest) {
+        assert!(request.body().len() <= data.len());
+    }
+});
@@ -1,5 +1,8 @@
-use harbor::server::handle_connection;
-use std::net::TcpListener;
+use harbor::server::{handle_request, parse_request, write_response};
+use std::{
+    io::BufReader,
+    net::{TcpListener, TcpStream},
+};
 use threadpool::ThreadPool;
 
 pub fn start(addr: &str) {
@@ -10,7 +13,7 @@ pub fn start(addr: &str) {
             let stream = stream.unwrap();
 
             pool.execute(move || {
-                if let Err(err) = handle_connection(stream) {
+                if let Err(err) = handle(stream) {
                     eprintln!("Error al manejar la conexión: {}", err);
                 }
             });
@@ -18,6 +21,14 @@ pub fn start(addr: &str) {
     }
 }
 
+fn handle(mut stream: TcpStream) -> Result<(), harbor::HarborError> {
+    let request = parse_request(&mut BufReader::new(&stream))?;
+    if let Some(request) = request {
+        write_response(&mut stream, handle_request(&request))?;
+    }
+    Ok(())
+}
+
 fn main() {
     start("127.0.0.1:7879");
 }
@@ -0,0 +1,353 @@
+//! A small load generator for harbor (or any HTTP/1.1 server).
+//!
+//! Opens N concurrent connections, sends M `GET` requests on each, and
+//! reports throughput and latency percentiles. Given several addresses it
+//! loads each in turn and compares them. Run with `--help` to see the
+//! flags.
+use std::{
+    env,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::TcpStream,
+    process, thread,
+    time::{Duration, Instant},
+};
+
+const USAGE: &str = "Usage: harbor-bench [OPTIONS] [ADDR]...
+
+Arguments:
+  [ADDR]...              Servers to load, one after the other, and compare
+                         [default: 127.0.0.1:7878]
+
+Options:
+  -c, --connections <N>  Concurrent connections [default: 8]
+  -n, --requests <M>     Requests sent by each connection [default: 100]
+  --path <PATH>          Path requested [default: /]
+  --keep-alive           Reuse each connection instead of reconnecting
+                         for every request (needs keep_alive on the server)
+  -h, --help             Print this help";
+
+#[derive(Debug, Clone, PartialEq)]
+struct Options {
+    addresses: Vec<String>,
+    connections: usize,
+    requests: usize,
+    path: String,
+    keep_alive: bool,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            addresses: vec!["127.0.0.1:7878".to_string()],
+            connections: 8,
+            requests: 100,
+            path: "/".to_string(),
+            keep_alive: false,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Command {
+    Run(Options),
+    Help,
+}
+
+/// What one connection measured.
+#[derive(Debug, Default)]
+struct Sample {
+    latencies: Vec<Duration>,
+    errors: usize,
+}
+
+fn main() {
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(Command::Run(options)) => options,
+        Ok(Command::Help) => {
+            println!("{}", USAGE);
+            return;
+        }
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, USAGE);
+            process::exit(2);
+        }
+    };
+
+    let mut results = Vec::new();
+    for address in &options.addresses {
+        println!(
+            "Running {} connections x {} requests against http://{}{}",
+            options.connections, options.requests, address, options.path
+        );
+        let (mut latencies, errors, elapsed) = run(address, &options);
+        println!("{}\n", report(&mut latencies, errors, elapsed));
+        results.push((
+            address,
+            throughput(latencies.len(), elapsed),
+            latencies.is_empty(),
+        ));
+    }
+
+    if results.len() > 1 {
+        println!("{}", compare(&results));
+    }
+    if results.iter().any(|&(_, _, failed)| failed) {
+        process::exit(1);
+    }
+}
+
+/// Loads `address` from every connection at once, returning the latencies
+/// of the requests that succeeded, the number that failed and the time it
+/// all took.
+fn run(address: &str, options: &Options) -> (Vec<Duration>, usize, Duration) {
+    let started = Instant::now();
+    let workers: Vec<_> = (0..options.connections)
+        .map(|_| {
+            let address = address.to_string();
+            let options = options.clone();
+            thread::spawn(move || run_connection(&address, &options))
+        })
+        .collect();
+
+    let mut latencies = Vec::new();
+    let mut errors = 0;
+    for worker in workers {
+        match worker.join() {
+            Ok(sample) => {
+                latencies.extend(sample.latencies);
+                errors += sample.errors;
+            }
+            Err(_) => errors += options.requests,
+        }
+    }
+    (latencies, errors, started.elapsed())
+}
+
+fn parse_args(args: impl Iterator<Item = String>) -> Result<Command, String> {
+    let mut options = Options::default();
+    let mut addresses = Vec::new();
+    let mut args = args;
+    while let Some(arg) = args.next() {
+        let (name, inline) = match arg.split_once('=') {
+            Some((name, value)) if name.starts_with('-') => (name.to_string(), Some(value)),
+            _ => (arg.clone(), None),
+        };
+        let mut value = || match inline {
+            Some(value) => Ok(value.to_string()),
+            None => args
+                .next()
+                .ok_or_else(|| format!("missing value for {}", name)),
+        };
+
+        match name.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-c" | "--connections" => options.connections = count(&name, &value()?)?,
+            "-n" | "--requests" => options.requests = count(&name, &value()?)?,
+            "--path" => {
+                let path = value()?;
+                if !path.starts_with('/') {
+                    return Err(format!("path must start with '/': {}", path));
+                }
+                options.path = path;
+            }
+            "--keep-alive" => options.keep_alive = true,
+            flag if flag.starts_with('-') => return Err(format!("unknown option: {}", flag)),
+            _ => addresses.push(arg),
+        }
+    }
+    if !addresses.is_empty() {
+        options.addresses = addresses;
+    }
+    Ok(Command::Run(options))
+}
+
+fn count(name: &str, value: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(count) if count > 0 => Ok(count),
+        _ => Err(format!("invalid value for {}: {}", name, value)),
+    }
+}
+
+/// Sends `options.requests` requests, timing each from the first byte
+/// written to the last byte of the response read.
+fn run_connection(address: &str, options: &Options) -> Sample {
+    let mut sample = Sample::default();
+    let mut connection: Option<BufReader<TcpStream>> = None;
+
+    for _ in 0..options.requests {
+        let started = Instant::now();
+        let result = match connection.take() {
+            Some(reader) => Ok(reader),
+            None => TcpStream::connect(address).map(BufReader::new),
+        }
+        .and_then(|mut reader| {
+            let reusable = request(&mut reader, address, options)?;
+            Ok((reader, reusable))
+        });
+
+        match result {
+            Ok((reader, reusable)) => {
+                sample.latencies.push(started.elapsed());
+                if options.keep_alive && reusable {
+                    connection = Some(reader);
+                }
+            }
+            Err(_) => sample.errors += 1,
+        }
+    }
+    sample
+}
+
+/// Writes one request and reads its whole response. Returns whether the
+/// server left the connection open.
+fn request(
+    reader: &mut BufReader<TcpStream>,
+    address: &str,
+    options: &Options,
+) -> io::Result<bool> {
+    let connection = if options.keep_alive {
+        "keep-alive"
+    } else {
+        "close"
+    };
+    write!(
+        reader.get_mut(),
+        "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: {}\r\n\r\n",
+        options.path,
+        address,
+        connection
+    )?;
+
+    let mut status_line = String::new();
+    reader.read_line(&mut status_line)?;
+    if !status_line.starts_with("HTTP/1.") {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not an HTTP response",
+        ));
+    }
+
+    let mut content_length = None;
+    let mut reusable = true;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                content_length = value.trim().parse::<u64>().ok();
+            } else if name.eq_ignore_ascii_case("Connection") {
+                reusable = !value.trim().eq_ignore_ascii_case("close");
+            }
+        }
+    }
+
+    match content_length {
+        Some(length) => {
+            io::copy(&mut reader.by_ref().take(length), &mut io::sink())?;
+            Ok(reusable)
+        }
+        None => {
+            io::copy(reader, &mut io::sink())?;
+            Ok(false)
+        }
+    }
+}
+
+/// Formats the summary printed once every connection is done.
+fn report(latencies: &mut [Duration], errors: usize, elapsed: Duration) -> String {
+    latencies.sort();
+    let mut report = format!(
+        "Requests:   {} ok, {} failed in {:.2?}\nThroughput: {:.1} req/s",
+        latencies.len(),
+        errors,
+        elapsed,
+        throughput(latencies.len(), elapsed)
+    );
+    if !latencies.is_empty() {
+        report.push_str(&format!(
+            "\nLatency:    p50 {:.2?}  p90 {:.2?}  p99 {:.2?}  max {:.2?}",
+            percentile(latencies, 50.0),
+            percentile(latencies, 90.0),
+            percentile(latencies, 99.0),
+            latencies[latencies.len() - 1]
+        ));
+    }
+    report
+}
+
+fn throughput(requests: usize, elapsed: Duration) -> f64 {
+    requests as f64 / elapsed.as_secs_f64().max(f64::EPSILON)
+}
+
+/// Lines up the throughput of each server against the first one's.
+fn compare(results: &[(&String, f64, bool)]) -> String {
+    let baseline = results[0].1.max(f64::EPSILON);
+    let mut comparison = "Comparison:".to_string();
+    for (address, throughput, _) in results {
+        comparison.push_str(&format!(
+            "\n  {:<24} {:>10.1} req/s  {:.2}x",
+            address,
+            throughput,
+            throughput / baseline
+        ));
+    }
+    comparison
+}
+
+/// The nearest-rank percentile of the sorted, non-empty `latencies`.
+fn percentile(latencies: &[Duration], percent: f64) -> Duration {
+    let rank = (percent / 100.0 * latencies.len() as f64).ceil() as usize;
+    latencies[rank.clamp(1, latencies.len()) - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Command, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn parse_args_should_apply_flags_and_address() {
+        let Ok(Command::Run(options)) =
+            parse(&["-c", "4", "--requests=10", "--keep-alive", "127.0.0.1:9000"])
+        else {
+            panic!("expected a run command");
+        };
+
+        assert_eq!(
+            options,
+            Options {
+                addresses: vec!["127.0.0.1:9000".to_string()],
+                connections: 4,
+                requests: 10,
+                path: "/".to_string(),
+                keep_alive: true,
+            }
+        );
+        let Ok(Command::Run(options)) = parse(&["127.0.0.1:7878", "127.0.0.1:7879"]) else {
+            panic!("expected a run command");
+        };
+        assert_eq!(options.addresses, ["127.0.0.1:7878", "127.0.0.1:7879"]);
+        assert!(parse(&["-c", "0"]).is_err());
+        assert!(parse(&["--path", "relative"]).is_err());
+        assert!(parse(&["--verbose"]).is_err());
+    }
+
+    #[test]
+    fn percentile_should_use_the_nearest_rank() {
+        let latencies: Vec<Duration> = (1..=10).map(Duration::from_millis).collect();
+
+        assert_eq!(percentile(&latencies, 50.0), Duration::from_millis(5));
+        assert_eq!(percentile(&latencies, 90.0), Duration::from_millis(9));
+        assert_eq!(percentile(&latencies, 99.0), Duration::from_millis(10));
+        assert_eq!(percentile(&latencies, 0.0), Duration::from_millis(1));
+    }
+}
@@ -0,0 +1,482 @@
+//! A standalone job-queue service built from harbor's pool, job registry
+//! and jobs API.
+//!
+//! Clients queue the job types registered in `registry` with `POST /jobs`
+//! and poll `GET /jobs/{id}` for the result; `/admin/jobs` lists the latest
+//! jobs and `/metrics` reports job counts and traffic in the Prometheus
+//! text format. With `--journal` every accepted job is written down until
+//! it finishes, so jobs a crash or kill interrupted are queued again on
+//! the next start. Run with `--help` to see the flags.
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    env,
+    fmt::Write as _,
+    fs::{self, File},
+    io::{self, Write},
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    thread,
+    time::Duration,
+};
+
+use harbor::{
+    JobPayload, info,
+    json::{self, Value},
+    registry::{JobDescriptor, JobRegistry},
+    server::{Connections, Response, Router, Server, ServerConfig, traffic::Traffic},
+    warn,
+};
+
+const USAGE: &str = "Usage: harbor-jobs [OPTIONS]
+
+Options:
+  --addr <ADDR>      Address to listen on [default: 127.0.0.1:7880]
+  --workers <N>      Number of worker threads [default: 4]
+  --journal <FILE>   Write accepted jobs to this file until they finish,
+                     and queue the unfinished ones again on start
+  -h, --help         Print this help
+
+Job types:
+  echo               Returns its arguments
+  sum                Adds up {\"numbers\": [...]}
+  sleep              Waits {\"ms\": N} milliseconds, at most 60000";
+
+/// Longest a `sleep` job may wait.
+const MAX_SLEEP: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, PartialEq)]
+struct Options {
+    address: String,
+    workers: usize,
+    journal: Option<PathBuf>,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            address: "127.0.0.1:7880".to_string(),
+            workers: 4,
+            journal: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Command {
+    Run(Options),
+    Help,
+}
+
+fn main() {
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(Command::Run(options)) => options,
+        Ok(Command::Help) => {
+            println!("{}", USAGE);
+            return;
+        }
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, USAGE);
+            process::exit(2);
+        }
+    };
+
+    let (journal, unfinished) = match &options.journal {
+        Some(path) => match Journal::open(path) {
+            Ok((journal, unfinished)) => (Some(Arc::new(journal)), unfinished),
+            Err(err) => {
+                eprintln!("error: cannot open the journal {}: {}", path.display(), err);
+                process::exit(1);
+            }
+        },
+        None => (None, Vec::new()),
+    };
+
+    let server = Server::with_config(ServerConfig {
+        address: options.address,
+        workers: options.workers,
+        ..ServerConfig::default()
+    });
+    let metrics = Arc::new(Metrics::new(server.traffic(), server.connections()));
+    let router = Router::new().get("/metrics", {
+        let metrics = Arc::clone(&metrics);
+        move |_| {
+            Response::new(200)
+                .header("Content-Type", "text/plain; version=0.0.4")
+                .body(metrics.render())
+        }
+    });
+    let mut server = server
+        .registry(registry(&metrics, journal.as_ref()))
+        .router(router)
+        .job_dashboard(true);
+    if !unfinished.is_empty() {
+        info!(
+            "Queueing {} jobs an earlier run did not finish",
+            unfinished.len()
+        );
+    }
+    for descriptor in unfinished {
+        server = server.job(descriptor);
+    }
+    #[cfg(feature = "signals")]
+    let server = server.shutdown_on_signals();
+
+    let handle = server.handle();
+    thread::spawn(move || {
+        while !handle.is_stopped() {
+            if let Some(addr) = handle.local_addr() {
+                println!("Listening on http://{}", addr);
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    });
+    // `run` has logged why it could not start.
+    if server.run().is_err() {
+        process::exit(1);
+    }
+}
+
+fn parse_args(args: impl Iterator<Item = String>) -> Result<Command, String> {
+    let mut options = Options::default();
+    let mut args = args;
+    while let Some(arg) = args.next() {
+        let (name, inline) = match arg.split_once('=') {
+            Some((name, value)) => (name.to_string(), Some(value.to_string())),
+            None => (arg, None),
+        };
+        let mut value = || match inline.clone() {
+            Some(value) => Ok(value),
+            None => args
+                .next()
+                .ok_or_else(|| format!("missing value for {}", name)),
+        };
+
+        match name.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "--addr" => options.address = value()?,
+            "--workers" => {
+                let workers = value()?;
+                options.workers = match workers.parse::<usize>() {
+                    Ok(workers) if workers > 0 => workers,
+                    _ => return Err(format!("invalid worker count: {}", workers)),
+                };
+            }
+            "--journal" => options.journal = Some(PathBuf::from(value()?)),
+            _ => return Err(format!("unknown option: {}", name)),
+        }
+    }
+    Ok(Command::Run(options))
+}
+
+/// The job types clients may queue, each counted in `metrics` and, with a
+/// journal, written to it until it finishes.
+fn registry(metrics: &Arc<Metrics>, journal: Option<&Arc<Journal>>) -> JobRegistry {
+    let mut registry = JobRegistry::new();
+    let mut register = |name: &'static str, job: fn(&Value) -> Result<JobPayload, String>| {
+        let counters = metrics.job(name);
+        let journal = journal.cloned();
+        registry.register(name, move |args| {
+            let payload = job(args)?;
+            counters.submitted.fetch_add(1, Ordering::Relaxed);
+            let entry = journal.as_ref().map(|journal| {
+                let entry = journal.queued(&JobDescriptor::new(name, args.clone()));
+                (Arc::clone(journal), entry)
+            });
+            let counters = Arc::clone(&counters);
+            Ok(Box::new(move || {
+                counters.running.fetch_add(1, Ordering::Relaxed);
+                let result = panic::catch_unwind(AssertUnwindSafe(payload));
+                if let Some((journal, entry)) = entry {
+                    journal.done(entry);
+                }
+                counters.running.fetch_sub(1, Ordering::Relaxed);
+                let finished = match result {
+                    Ok(Ok(_)) => &counters.succeeded,
+                    _ => &counters.failed,
+                };
+                finished.fetch_add(1, Ordering::Relaxed);
+                // The pool still sees the panic, to apply its panic policy.
+                result.unwrap_or_else(|panic| panic::resume_unwind(panic))
+            }))
+        });
+    };
+
+    register("echo", |args| {
+        let echo = args.to_string();
+        Ok(Box::new(move || Ok(echo)))
+    });
+    register("sum", |args| {
+        let Some(Value::Array(numbers)) = args.get("numbers") else {
+            return Err("sum needs a \"numbers\" array".to_string());
+        };
+        let sum = numbers
+            .iter()
+            .map(|number| number.as_f64().ok_or("sum only adds numbers"))
+            .sum::<Result<f64, _>>()?;
+        Ok(Box::new(move || Ok(sum.to_string())))
+    });
+    register("sleep", |args| {
+        let wait = match args.get("ms").and_then(Value::as_f64) {
+            Some(ms) if ms >= 0.0 => Duration::from_millis(ms as u64).min(MAX_SLEEP),
+            _ => return Err("sleep needs a non-negative \"ms\"".to_string()),
+        };
+        Ok(Box::new(move || {
+            thread::sleep(wait);
+            Ok(format!("slept {:?}", wait))
+        }))
+    });
+    registry
+}
+
+/// How many jobs of one type were queued and how they went.
+#[derive(Debug, Default)]
+struct Counters {
+    submitted: AtomicU64,
+    running: AtomicU64,
+    succeeded: AtomicU64,
+    failed: AtomicU64,
+}
+
+/// Picks one of a job type's `Counters`.
+type Counter = fn(&Counters) -> &AtomicU64;
+
+/// What `/metrics` reports: the jobs of each type, and the server's
+/// traffic and connections.
+#[derive(Debug)]
+struct Metrics {
+    jobs: Mutex<BTreeMap<&'static str, Arc<Counters>>>,
+    traffic: Arc<Traffic>,
+    connections: Arc<Connections>,
+}
+
+impl Metrics {
+    fn new(traffic: Arc<Traffic>, connections: Arc<Connections>) -> Metrics {
+        Metrics {
+            jobs: Mutex::new(BTreeMap::new()),
+            traffic,
+            connections,
+        }
+    }
+
+    /// The counters of the job type `name`, reported from now on.
+    fn job(&self, name: &'static str) -> Arc<Counters> {
+        Arc::clone(self.jobs.lock().unwrap().entry(name).or_default())
+    }
+
+    /// The metrics in the Prometheus text exposition format.
+    fn render(&self) -> String {
+        let jobs = self.jobs.lock().unwrap();
+        let mut text = String::new();
+        let families: [(&str, &str, Counter); 4] = [
+            ("harbor_jobs_submitted_total", "counter", |c| &c.submitted),
+            ("harbor_jobs_running", "gauge", |c| &c.running),
+            ("harbor_jobs_succeeded_total", "counter", |c| &c.succeeded),
+            ("harbor_jobs_failed_total", "counter", |c| &c.failed),
+        ];
+        for (family, kind, counter) in families {
+            let _ = writeln!(text, "# TYPE {} {}", family, kind);
+            for (name, counters) in jobs.iter() {
+                let value = counter(counters).load(Ordering::Relaxed);
+                let _ = writeln!(text, "{}{{job={}}} {}", family, json::quote(name), value);
+            }
+        }
+
+        let traffic = self.traffic.stats();
+        let connections = self.connections.stats();
+        for (family, kind, value) in [
+            ("harbor_http_requests_total", "counter", traffic.requests),
+            (
+                "harbor_http_read_bytes_total",
+                "counter",
+                traffic.bytes_read,
+            ),
+            (
+                "harbor_http_written_bytes_total",
+                "counter",
+                traffic.bytes_written,
+            ),
+            ("harbor_connections_open", "gauge", connections.open as u64),
+            (
+                "harbor_connections_accepted_total",
+                "counter",
+                connections.accepted,
+            ),
+            (
+                "harbor_client_disconnects_total",
+                "counter",
+                connections.client_disconnects,
+            ),
+        ] {
+            let _ = writeln!(text, "# TYPE {} {}\n{} {}", family, kind, family, value);
+        }
+        text
+    }
+}
+
+/// Jobs accepted and not finished yet, kept in a file so the next start
+/// can queue them again.
+///
+/// Each line is `queued <entry> <descriptor>` once a job is accepted, or
+/// `done <entry>` once it finished, whether it succeeded or not. Lines are
+/// appended as they happen, so the file survives a crash up to the last
+/// one written.
+#[derive(Debug)]
+struct Journal {
+    file: Mutex<File>,
+    next: AtomicU64,
+    /// Entries carried over from the earlier run, until their jobs are
+    /// queued again.
+    recovered: Mutex<Vec<(u64, JobDescriptor)>>,
+}
+
+impl Journal {
+    /// Opens the journal at `path` and returns the jobs an earlier run
+    /// accepted and never finished.
+    ///
+    /// The file starts over with just those jobs: they are written to a
+    /// new file that then replaces the old one, so a crash, or a server
+    /// that fails to start, leaves either journal with every one of them.
+    fn open(path: &Path) -> io::Result<(Journal, Vec<JobDescriptor>)> {
+        let unfinished = match fs::read_to_string(path) {
+            Ok(contents) => unfinished(&contents),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err),
+        };
+        let mut fresh = path.as_os_str().to_owned();
+        fresh.push(".new");
+        let mut file = File::create(&fresh)?;
+        for (entry, descriptor) in unfinished.iter().enumerate() {
+            writeln!(file, "queued {} {}", entry, descriptor)?;
+        }
+        file.sync_all()?;
+        fs::rename(&fresh, path)?;
+
+        let journal = Journal {
+            file: Mutex::new(file),
+            next: AtomicU64::new(unfinished.len() as u64),
+            recovered: Mutex::new(
+                unfinished
+                    .iter()
+                    .cloned()
+                    .enumerate()
+                    .map(|(entry, descriptor)| (entry as u64, descriptor))
+                    .collect(),
+            ),
+        };
+        Ok((journal, unfinished))
+    }
+
+    /// Records that `descriptor` was accepted, returning the entry to mark
+    /// `done`. A job carried over from the earlier run keeps the entry it
+    /// was written down with.
+    fn queued(&self, descriptor: &JobDescriptor) -> u64 {
+        let mut recovered = self.recovered.lock().unwrap();
+        if let Some(index) = recovered.iter().position(|(_, job)| job == descriptor) {
+            return recovered.remove(index).0;
+        }
+        drop(recovered);
+        let entry = self.next.fetch_add(1, Ordering::Relaxed);
+        self.append(&format!("queued {} {}\n", entry, descriptor));
+        entry
+    }
+
+    fn done(&self, entry: u64) {
+        self.append(&format!("done {}\n", entry));
+    }
+
+    fn append(&self, line: &str) {
+        if let Err(err) = self.file.lock().unwrap().write_all(line.as_bytes()) {
+            warn!("Failed to write to the job journal: {}", err);
+        }
+    }
+}
+
+/// The jobs a journal holds as queued and not done, in the order they were
+/// accepted. A line cut short by a crash, or otherwise unreadable, is
+/// skipped.
+fn unfinished(contents: &str) -> Vec<JobDescriptor> {
+    let mut queued = BTreeMap::new();
+    let mut done = BTreeSet::new();
+    for line in contents.lines() {
+        let mut parts = line.splitn(3, ' ');
+        let kind = parts.next();
+        let entry = parts.next().and_then(|entry| entry.parse::<u64>().ok());
+        match (kind, entry, parts.next()) {
+            (Some("queued"), Some(entry), Some(descriptor)) => {
+                match JobDescriptor::parse(descriptor) {
+                    Ok(descriptor) => {
+                        queued.insert(entry, descriptor);
+                    }
+                    Err(err) => warn!("Skipping the journal line {:?}: {}", line, err),
+                }
+            }
+            (Some("done"), Some(entry), None) => {
+                done.insert(entry);
+            }
+            _ => warn!("Skipping the journal line {:?}", line),
+        }
+    }
+    queued
+        .into_iter()
+        .filter(|(entry, _)| !done.contains(entry))
+        .map(|(_, descriptor)| descriptor)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Command, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn parse_args_should_apply_flags() {
+        let Ok(Command::Run(options)) = parse(&[
+            "--addr=0.0.0.0:9000",
+            "--workers",
+            "2",
+            "--journal",
+            "jobs.log",
+        ]) else {
+            panic!("expected a run command");
+        };
+
+        assert_eq!(
+            options,
+            Options {
+                address: "0.0.0.0:9000".to_string(),
+                workers: 2,
+                journal: Some(PathBuf::from("jobs.log")),
+            }
+        );
+        assert!(matches!(parse(&["-h"]), Ok(Command::Help)));
+        assert!(parse(&["--workers", "0"]).is_err());
+        assert!(parse(&["--journal"]).is_err());
+        assert!(parse(&["--verbose"]).is_err());
+    }
+
+    #[test]
+    fn unfinished_should_keep_queued_jobs_that_are_not_done() {
+        let contents = "queued 0 {\"job\":\"echo\",\"args\":1}\n\
+            queued 1 {\"job\":\"sum\",\"args\":{\"numbers\":[1,2]}}\n\
+            done 0\n\
+            queued 2 {\"job\":\"sleep\",\"args\":{\"ms\":5}}\n\
+            queued 3 {\"job\":\"ec";
+
+        let jobs = unfinished(contents);
+
+        assert_eq!(
+            jobs.iter().map(|job| job.job.as_str()).collect::<Vec<_>>(),
+            ["sum", "sleep"]
+        );
+        assert_eq!(jobs[0].args.to_string(), "{\"numbers\":[1,2]}");
+    }
+}
@@ -0,0 +1,389 @@
+//! A small blocking HTTP/1.1 client for `http://` URLs.
+//!
+//! Each request opens its own connection, which the response closes.
+//! Responses come back as the same `Response` the server sends, with the
+//! body already read and decoded.
+//!
+//! ```
+//! use harbor::client;
+//! use harbor::server::{Response, Router};
+//! use harbor::test::TestServer;
+//!
+//! let router = Router::new().post("/echo", |request| Response::new(200).body(request.body()));
+//! let server = TestServer::spawn(router);
+//!
+//! let response = client::post(&server.url("/echo"), "hello").unwrap();
+//! assert_eq!(response.status(), 200);
+//! assert_eq!(response.get_body(), b"hello");
+//! ```
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+use crate::server::{
+    Response,
+    request::{is_token, read_line},
+};
+
+/// How long a request waits to connect, and for each read and write,
+/// unless `Request::timeout` says otherwise.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Sends a `GET` request to `url`.
+pub fn get(url: &str) -> Result<Response, String> {
+    Request::new("GET", url).send()
+}
+
+/// Sends a `POST` request to `url` with `body`.
+pub fn post(url: &str, body: impl Into<Vec<u8>>) -> Result<Response, String> {
+    Request::new("POST", url).body(body).send()
+}
+
+/// A request to send, built up header by header.
+///
+/// `Host`, `Connection: close` and `Content-Length` are added when
+/// sending; a `Host` header set here replaces the one taken from the URL.
+///
+/// # Example
+///
+/// ```no_run
+/// use std::time::Duration;
+///
+/// use harbor::client::Request;
+///
+/// let response = Request::new("PUT", "http://127.0.0.1:7878/notes/1")
+///     .header("Content-Type", "text/plain")
+///     .body("Remember the milk")
+///     .timeout(Duration::from_secs(5))
+///     .send()?;
+/// # Ok::<(), String>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct Request {
+    method: String,
+    url: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+    timeout: Duration,
+}
+
+impl Request {
+    pub fn new(method: &str, url: &str) -> Request {
+        Request {
+            method: method.to_string(),
+            url: url.to_string(),
+            headers: Vec::new(),
+            body: Vec::new(),
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Request {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Request {
+        self.body = body.into();
+        self
+    }
+
+    /// Gives up when connecting, or any single read or write, takes longer
+    /// than `timeout`.
+    pub fn timeout(mut self, timeout: Duration) -> Request {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sends the request and reads the whole response.
+    ///
+    /// Interim `1xx` responses are skipped. `Content-Length` and
+    /// `Transfer-Encoding` are left out of the returned headers, as the
+    /// body has already been read by them.
+    pub fn send(&self) -> Result<Response, String> {
+        let url = Url::parse(&self.url)?;
+        if !is_token(&self.method) {
+            return Err(format!("Invalid method: {:?}", self.method));
+        }
+        for (name, value) in &self.headers {
+            if !is_token(name) || value.contains(['\r', '\n']) {
+                return Err(format!("Invalid header: {:?}: {:?}", name, value));
+            }
+        }
+
+        let mut stream = connect(&url.authority, self.timeout)?;
+        let mut message = self.head(&url).into_bytes();
+        message.extend_from_slice(&self.body);
+        stream
+            .write_all(&message)
+            .map_err(|err| format!("Failed to send request to {}: {}", url.authority, err))?;
+        read_response(&mut BufReader::new(stream), &self.method)
+    }
+
+    /// The request line and headers, blank line included.
+    fn head(&self, url: &Url) -> String {
+        let mut head = format!("{} {} HTTP/1.1\r\n", self.method, url.target);
+        let has = |name: &str| {
+            self.headers
+                .iter()
+                .any(|(key, _)| key.eq_ignore_ascii_case(name))
+        };
+        if !has("Host") {
+            head.push_str(&format!("Host: {}\r\n", url.host));
+        }
+        for (name, value) in &self.headers {
+            if !name.eq_ignore_ascii_case("Connection")
+                && !name.eq_ignore_ascii_case("Content-Length")
+            {
+                head.push_str(&format!("{}: {}\r\n", name, value));
+            }
+        }
+        head.push_str("Connection: close\r\n");
+        if !self.body.is_empty() || matches!(self.method.as_str(), "POST" | "PUT" | "PATCH") {
+            head.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        }
+        head.push_str("\r\n");
+        head
+    }
+}
+
+/// The parts of an `http://` URL a request needs.
+#[derive(Debug, PartialEq)]
+struct Url {
+    /// The host and port as written, for the `Host` header.
+    host: String,
+    /// The host and port to connect to, with the port filled in.
+    authority: String,
+    /// The path and query, without any `#fragment`.
+    target: String,
+}
+
+impl Url {
+    fn parse(url: &str) -> Result<Url, String> {
+        let rest = url
+            .get(..7)
+            .filter(|scheme| scheme.eq_ignore_ascii_case("http://"))
+            .map(|_| &url[7..])
+            .ok_or_else(|| format!("Only http:// URLs are supported: {}", url))?;
+        let rest = rest.split('#').next().unwrap_or(rest);
+        let (host, target) = match rest.find(['/', '?']) {
+            Some(start) => rest.split_at(start),
+            None => (rest, ""),
+        };
+        if host.is_empty() || host.contains('@') {
+            return Err(format!("Invalid host in URL: {}", url));
+        }
+
+        let has_port = match host.rfind(']') {
+            Some(end) => host[end..].contains(':'),
+            None => host.contains(':'),
+        };
+        let authority = if has_port {
+            host.to_string()
+        } else {
+            format!("{}:80", host)
+        };
+        let target = match target {
+            "" => "/".to_string(),
+            query if query.starts_with('?') => format!("/{}", query),
+            path => path.to_string(),
+        };
+        Ok(Url {
+            host: host.to_string(),
+            authority,
+            target,
+        })
+    }
+}
+
+/// Connects to the first of `authority`'s addresses that answers.
+fn connect(authority: &str, timeout: Duration) -> Result<TcpStream, String> {
+    let addrs = authority
+        .to_socket_addrs()
+        .map_err(|err| format!("Failed to resolve {}: {}", authority, err))?;
+    let mut last_error = format!("No addresses found for {}", authority);
+    for addr in addrs {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => {
+                stream
+                    .set_read_timeout(Some(timeout))
+                    .and_then(|_| stream.set_write_timeout(Some(timeout)))
+                    .map_err(|err| err.to_string())?;
+                return Ok(stream);
+            }
+            Err(err) => last_error = format!("Failed to connect to {}: {}", addr, err),
+        }
+    }
+    Err(last_error)
+}
+
+/// Reads a response to a `method` request, skipping interim responses.
+fn read_response<R: BufRead>(reader: &mut R, method: &str) -> Result<Response, String> {
+    loop {
+        let line = read_line(reader)?.ok_or("Connection closed before a response")?;
+        let mut parts = line.splitn(3, ' ');
+        let status = match (parts.next(), parts.next()) {
+            (Some(version), Some(status)) if version.starts_with("HTTP/1.") => status
+                .parse::<u16>()
+                .ok()
+                .filter(|status| (100..=999).contains(status)),
+            _ => None,
+        }
+        .ok_or_else(|| format!("Malformed status line: {}", line))?;
+        let headers = read_headers(reader)?;
+        if (100..=199).contains(&status) && status != 101 {
+            continue;
+        }
+
+        let header = |name: &str| {
+            headers
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.as_str())
+        };
+        let chunked = header("Transfer-Encoding").is_some_and(|codings| {
+            codings
+                .rsplit(',')
+                .next()
+                .is_some_and(|coding| coding.trim().eq_ignore_ascii_case("chunked"))
+        });
+        let body = if method == "HEAD" || matches!(status, 100..=199 | 204 | 304) {
+            Vec::new()
+        } else if chunked {
+            read_chunked(reader)?
+        } else if let Some(length) = header("Content-Length") {
+            let length: u64 = length
+                .parse()
+                .map_err(|_| format!("Invalid Content-Length: {}", length))?;
+            read_all(&mut reader.take(length), Some(length))?
+        } else {
+            read_all(reader, None)?
+        };
+
+        let response = headers
+            .iter()
+            .filter(|(name, _)| {
+                !name.eq_ignore_ascii_case("Content-Length")
+                    && !name.eq_ignore_ascii_case("Transfer-Encoding")
+            })
+            .fold(Response::new(status), |response, (name, value)| {
+                response.header(name, value)
+            });
+        return Ok(response.body(body));
+    }
+}
+
+fn read_headers<R: BufRead>(reader: &mut R) -> Result<Vec<(String, String)>, String> {
+    let mut headers = Vec::new();
+    loop {
+        let line = read_line(reader)?.ok_or("Connection closed in the response headers")?;
+        if line.is_empty() {
+            return Ok(headers);
+        }
+        match line.split_once(':') {
+            Some((name, value)) if is_token(name) => {
+                headers.push((name.to_string(), value.trim().to_string()))
+            }
+            _ => return Err(format!("Malformed header line: {}", line)),
+        }
+    }
+}
+
+/// Reads a `Transfer-Encoding: chunked` body, discarding any trailers.
+fn read_chunked<R: BufRead>(reader: &mut R) -> Result<Vec<u8>, String> {
+    let mut body = Vec::new();
+    loop {
+        let line = read_line(reader)?.ok_or("Connection closed in a chunked body")?;
+        let size = line.split(';').next().unwrap_or(&line).trim();
+        let size =
+            u64::from_str_radix(size, 16).map_err(|_| format!("Malformed chunk size: {}", line))?;
+        if size == 0 {
+            read_headers(reader)?;
+            return Ok(body);
+        }
+        body.extend(read_all(&mut reader.take(size), Some(size))?);
+        if read_line(reader)?.is_none_or(|end| !end.is_empty()) {
+            return Err("Chunk is not followed by a line break".to_string());
+        }
+    }
+}
+
+/// Reads `reader` to the end, which must come after `expected` bytes when
+/// given.
+fn read_all<R: Read>(reader: &mut R, expected: Option<u64>) -> Result<Vec<u8>, String> {
+    let mut body = Vec::new();
+    reader
+        .read_to_end(&mut body)
+        .map_err(|err| format!("Failed to read the response body: {}", err))?;
+    match expected {
+        Some(expected) if (body.len() as u64) < expected => Err(format!(
+            "Response body ended after {} of {} bytes",
+            body.len(),
+            expected
+        )),
+        _ => Ok(body),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn url_parse_should_split_host_and_target() {
+        assert_eq!(
+            Url::parse("http://example.com").unwrap(),
+            Url {
+                host: "example.com".to_string(),
+                authority: "example.com:80".to_string(),
+                target: "/".to_string(),
+            }
+        );
+        assert_eq!(
+            Url::parse("HTTP://[::1]:8080?page=2#top").unwrap(),
+            Url {
+                host: "[::1]:8080".to_string(),
+                authority: "[::1]:8080".to_string(),
+                target: "/?page=2".to_string(),
+            }
+        );
+        assert!(Url::parse("https://example.com/").is_err());
+        assert!(Url::parse("http:///path").is_err());
+    }
+
+    #[test]
+    fn read_response_should_decode_the_body() {
+        let mut sized = &b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\nContent-Length: 5\r\nX-Id: 7\r\n\r\nhello, extra"[..];
+        let mut chunked = &b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n7;ext=1\r\n, world\r\n0\r\nTrailer: x\r\n\r\n"[..];
+        let mut until_close = &b"HTTP/1.0 404 Not Found\r\n\r\ngone"[..];
+
+        let sized = read_response(&mut sized, "GET").unwrap();
+        let chunked = read_response(&mut chunked, "GET").unwrap();
+        let until_close = read_response(&mut until_close, "GET").unwrap();
+        let head = read_response(
+            &mut &b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n"[..],
+            "HEAD",
+        )
+        .unwrap();
+
+        assert_eq!(sized.status(), 200);
+        assert_eq!(sized.get_body(), b"hello");
+        assert_eq!(sized.get_header("X-Id"), Some("7"));
+        assert_eq!(sized.get_header("Content-Length"), None);
+        assert_eq!(chunked.get_body(), b"hello, world");
+        assert_eq!(until_close.status(), 404);
+        assert_eq!(until_close.get_body(), b"gone");
+        assert_eq!(head.get_body(), b"");
+        assert!(
+            read_response(
+                &mut &b"HTTP/1.1 200 OK\r\nContent-Length: 9\r\n\r\nshort"[..],
+                "GET"
+            )
+            .is_err()
+        );
+        assert!(read_response(&mut &b"SSH-2.0-OpenSSH\r\n"[..], "GET").is_err());
+    }
+}
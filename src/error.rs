@@ -0,0 +1,96 @@
+//! The error type shared by the pool and the server.
+
+use std::{error::Error, fmt, io};
+
+use crate::{
+    PoolCreateError,
+    server::{ConfigError, ParseError},
+};
+
+/// Everything that can go wrong in the crate, sorted by what a caller can
+/// do about it.
+///
+/// `Io` errors usually come from the network and may be worth a retry,
+/// `Http` means a client broke the protocol, and `Pool` and `Config` are
+/// mistakes in how the server was set up.
+#[derive(Debug)]
+pub enum HarborError {
+    /// The thread pool could not be created.
+    Pool(PoolCreateError),
+    /// A client sent something that isn't valid HTTP, or was refused.
+    Http(String),
+    /// Reading from or writing to a socket or file failed.
+    Io(io::Error),
+    /// The server configuration is invalid.
+    Config(ConfigError),
+}
+
+impl fmt::Display for HarborError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HarborError::Pool(err) => write!(f, "thread pool: {}", err),
+            HarborError::Http(message) => write!(f, "HTTP: {}", message),
+            HarborError::Io(err) => write!(f, "I/O: {}", err),
+            HarborError::Config(err) => write!(f, "configuration: {}", err),
+        }
+    }
+}
+
+impl Error for HarborError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            HarborError::Pool(err) => Some(err),
+            HarborError::Http(_) => None,
+            HarborError::Io(err) => Some(err),
+            HarborError::Config(err) => Some(err),
+        }
+    }
+}
+
+impl From<PoolCreateError> for HarborError {
+    fn from(err: PoolCreateError) -> HarborError {
+        HarborError::Pool(err)
+    }
+}
+
+impl From<io::Error> for HarborError {
+    fn from(err: io::Error) -> HarborError {
+        HarborError::Io(err)
+    }
+}
+
+/// Failed reads stay `Io` errors; anything else the client sent is `Http`.
+impl From<ParseError> for HarborError {
+    fn from(err: ParseError) -> HarborError {
+        match err {
+            ParseError::Io(kind) => HarborError::Io(kind.into()),
+            err => HarborError::Http(err.to_string()),
+        }
+    }
+}
+
+impl From<ConfigError> for HarborError {
+    fn from(err: ConfigError) -> HarborError {
+        HarborError::Config(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn harbor_error_should_keep_the_underlying_error() {
+        let io: HarborError = io::Error::new(io::ErrorKind::TimedOut, "timed out").into();
+        let pool: HarborError = PoolCreateError::NonValueZeroAllowed.into();
+
+        assert_eq!(io.to_string(), "I/O: timed out");
+        assert!(matches!(&io, HarborError::Io(err) if err.kind() == io::ErrorKind::TimedOut));
+        assert!(io.source().is_some());
+        assert_eq!(
+            pool.to_string(),
+            "thread pool: a thread pool needs at least one thread"
+        );
+        assert!(HarborError::Http("bad".to_string()).source().is_none());
+    }
+}
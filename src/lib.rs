@@ -4,15 +4,31 @@
 //! concurrently.
 
 use std::{
-    collections::HashMap,
+    any::Any,
+    collections::{HashMap, VecDeque},
+    hash::{DefaultHasher, Hash, Hasher},
+    panic::{self, AssertUnwindSafe},
     sync::{
-        Arc, Mutex,
-        mpsc::{Receiver, Sender, channel},
+        Arc, Condvar, Mutex,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
     },
     thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
 use uuid::Uuid;
+
+use queue::{Queue, Sender};
+pub mod client;
+pub mod error;
+pub mod json;
+pub mod log;
+mod queue;
+pub mod registry;
 pub mod server;
+pub mod test;
+pub mod toml;
+
+pub use error::HarborError;
 
 /// Represents a pool of threads that can execute jobs.
 ///
@@ -23,7 +39,13 @@ pub mod server;
 pub struct ThreadPool {
     workers: Vec<Worker>,
     sender: Option<Sender<Job>>,
+    queue: Arc<Queue<Job>>,
     jobs: Arc<Mutex<HashMap<Uuid, JobMetadata>>>,
+    queued: Arc<AtomicUsize>,
+    panics: Arc<Panics>,
+    queue_wait: Arc<QueueWait>,
+    settings: Arc<WorkerSettings>,
+    shutdown: ShutdownSignal,
 }
 
 #[derive(Debug)]
@@ -31,21 +53,364 @@ pub enum PoolCreateError {
     NonValueZeroAllowed,
 }
 
-type JobPayload = Box<dyn FnOnce() -> Result<String, String> + Send + 'static>;
+impl std::fmt::Display for PoolCreateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PoolCreateError::NonValueZeroAllowed => {
+                write!(f, "a thread pool needs at least one thread")
+            }
+        }
+    }
+}
 
-#[derive(Debug, Clone)]
+impl std::error::Error for PoolCreateError {}
+
+/// Tells jobs queued with `execute_with_shutdown` that their pool is
+/// shutting down.
+///
+/// Long-lived jobs, like a queue consumer or a connection kept open for
+/// server-sent events, poll `is_shutdown` between steps or wait on the
+/// signal in place of sleeping, and return once it is raised, so
+/// `shutdown_timeout` and dropping the pool need not wait them out. The
+/// signal is raised once and stays raised; jobs starting after that see
+/// it straight away.
+#[derive(Debug, Clone, Default)]
+pub struct ShutdownSignal {
+    raised: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl ShutdownSignal {
+    pub fn is_shutdown(&self) -> bool {
+        *self.raised.0.lock().unwrap()
+    }
 
-enum JobStatus {
+    /// Blocks until the pool starts shutting down.
+    pub fn wait(&self) {
+        let (raised, changed) = &*self.raised;
+        let _raised = changed
+            .wait_while(raised.lock().unwrap(), |raised| !*raised)
+            .unwrap();
+    }
+
+    /// Blocks until the pool starts shutting down or `timeout` passes,
+    /// and returns whether it is shutting down.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let (raised, changed) = &*self.raised;
+        let (raised, _) = changed
+            .wait_timeout_while(raised.lock().unwrap(), timeout, |raised| !*raised)
+            .unwrap();
+        *raised
+    }
+
+    fn raise(&self) {
+        let (raised, changed) = &*self.raised;
+        *raised.lock().unwrap() = true;
+        changed.notify_all();
+    }
+}
+
+/// The boxed closure executed by a worker for each job.
+pub type JobPayload = Box<dyn FnOnce() -> Result<String, String> + Send + 'static>;
+
+/// The lifecycle state of a job submitted to the pool.
+#[derive(Debug, Clone)]
+pub enum JobStatus {
     Pending,
     Processing,
     Completed,
     Failed(String),
 }
 
+impl JobStatus {
+    /// Returns the name of the state without any attached data.
+    pub fn name(&self) -> &'static str {
+        match self {
+            JobStatus::Pending => "Pending",
+            JobStatus::Processing => "Processing",
+            JobStatus::Completed => "Completed",
+            JobStatus::Failed(_) => "Failed",
+        }
+    }
+}
+
+/// How urgently a job submitted with `ThreadPool::execute_with_priority`
+/// should run: idle workers take higher priorities first.
+///
+/// A steady stream of urgent jobs could keep the others waiting forever;
+/// `ThreadPool::priority_aging` promotes jobs that have waited long
+/// enough so they run eventually.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+impl Priority {
+    /// Every priority, lowest first.
+    pub const ALL: [Priority; 3] = [Priority::Low, Priority::Normal, Priority::High];
+
+    /// The priority a job of this one counts as after waiting `waited`,
+    /// one higher for every `aging` of it, up to `High`.
+    fn aged(self, waited: Duration, aging: Option<Duration>) -> Priority {
+        let steps = aging.map_or(0, |aging| (waited.as_nanos() / aging.as_nanos()) as usize);
+        Priority::ALL[(self as usize)
+            .saturating_add(steps)
+            .min(Priority::ALL.len() - 1)]
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct JobMetadata {
     state: JobStatus,
     result: Option<String>,
+    submitted: Instant,
+    started: Option<Instant>,
+    finished: Option<Instant>,
+    worker: Option<usize>,
+}
+
+impl JobMetadata {
+    fn pending() -> JobMetadata {
+        JobMetadata {
+            state: JobStatus::Pending,
+            result: None,
+            submitted: Instant::now(),
+            started: None,
+            finished: None,
+            worker: None,
+        }
+    }
+
+    pub fn state(&self) -> &JobStatus {
+        &self.state
+    }
+
+    pub fn result(&self) -> Option<&str> {
+        self.result.as_deref()
+    }
+
+    /// How long ago the job was submitted.
+    pub fn age(&self) -> Duration {
+        self.submitted.elapsed()
+    }
+
+    /// How long the job ran, or has been running so far. `None` while it
+    /// waits for a worker.
+    pub fn duration(&self) -> Option<Duration> {
+        let started = self.started?;
+        Some(match self.finished {
+            Some(finished) => finished - started,
+            None => started.elapsed(),
+        })
+    }
+
+    /// The id of the worker running the job, or that ran it.
+    pub fn worker(&self) -> Option<usize> {
+        self.worker
+    }
+
+    /// How long the job waited in the queue before a worker picked it up.
+    /// `None` while it still waits.
+    pub fn queue_wait(&self) -> Option<Duration> {
+        Some(self.started? - self.submitted)
+    }
+
+    fn fail(&mut self, reason: String) {
+        self.finished = Some(Instant::now());
+        self.result = Some(reason.clone());
+        self.state = JobStatus::Failed(reason);
+    }
+}
+
+/// What a `ThreadPool` does when a job panics. Handler panics in the
+/// server are answered with `500` and never reach the pool.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PanicPolicy {
+    /// Fails the job and keeps its worker going with the next one.
+    #[default]
+    Restart,
+    /// Aborts the process, for deployments that would rather crash and be
+    /// restarted by their supervisor than carry on after a bug.
+    Abort,
+    /// Fails the job and keeps going until that many jobs have panicked,
+    /// then stops the pool: queued and later jobs fail without running.
+    CountAndContinue(usize),
+}
+
+/// The pool's panic policy and how many jobs have panicked, shared with
+/// its workers.
+#[derive(Debug, Default)]
+struct Panics {
+    policy: Mutex<PanicPolicy>,
+    count: AtomicUsize,
+}
+
+impl Panics {
+    /// Counts a panic of `job` on `worker`, and whether the pool should
+    /// stop. Aborts the process under `PanicPolicy::Abort`.
+    fn record(&self, worker: usize, job: Uuid, message: &str) -> bool {
+        let count = self.count.fetch_add(1, Ordering::SeqCst) + 1;
+        match *self.policy.lock().unwrap() {
+            PanicPolicy::Restart => false,
+            PanicPolicy::Abort => {
+                error!(
+                    "Worker {} panicked running job '{}' ({}); aborting.",
+                    worker, job, message
+                );
+                std::process::abort();
+            }
+            PanicPolicy::CountAndContinue(max) => count >= max,
+        }
+    }
+}
+
+/// How many of the latest jobs to start are kept for `QueueWaitStats`.
+pub const QUEUE_WAIT_SAMPLES: usize = 1024;
+
+/// The least time between two warnings about jobs over the queue wait
+/// threshold, so an overloaded pool doesn't flood the log.
+const QUEUE_WAIT_WARNING_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long the latest `QUEUE_WAIT_SAMPLES` jobs to start waited in a
+/// `ThreadPool`'s queue before a worker picked them up. Waits that grow
+/// are the first sign of a pool too small for its load.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QueueWaitStats {
+    /// How many jobs the figures cover; zero before the first job starts.
+    pub samples: usize,
+    pub average: Duration,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+    /// Jobs that waited longer than the pool's queue wait threshold since
+    /// it was built, of all jobs and not only the sampled ones.
+    pub exceeded: u64,
+}
+
+/// A callback for jobs that waited over a pool's queue wait threshold.
+type QueueWaitAlert = Arc<dyn Fn(Uuid, Duration) + Send + Sync>;
+
+/// Recent queue waits and the threshold to warn over, shared with the
+/// pool's workers.
+#[derive(Default)]
+struct QueueWait {
+    recent: Mutex<VecDeque<Duration>>,
+    threshold: Mutex<Option<Duration>>,
+    alert: Mutex<Option<QueueWaitAlert>>,
+    exceeded: AtomicU64,
+    last_warning: Mutex<Option<Instant>>,
+}
+
+impl std::fmt::Debug for QueueWait {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("QueueWait")
+            .field("threshold", &self.threshold)
+            .field("exceeded", &self.exceeded)
+            .finish_non_exhaustive()
+    }
+}
+
+impl QueueWait {
+    /// Notes that `job` waited `wait`, warning and calling the alert when
+    /// that is over the threshold.
+    fn record(&self, job: Uuid, wait: Duration) {
+        {
+            let mut recent = self.recent.lock().unwrap();
+            if recent.len() == QUEUE_WAIT_SAMPLES {
+                recent.pop_front();
+            }
+            recent.push_back(wait);
+        }
+        let Some(threshold) = *self.threshold.lock().unwrap() else {
+            return;
+        };
+        if wait <= threshold {
+            return;
+        }
+
+        let exceeded = self.exceeded.fetch_add(1, Ordering::SeqCst) + 1;
+        let warn_now = {
+            let mut last_warning = self.last_warning.lock().unwrap();
+            let due = last_warning.is_none_or(|last| last.elapsed() >= QUEUE_WAIT_WARNING_INTERVAL);
+            if due {
+                *last_warning = Some(Instant::now());
+            }
+            due
+        };
+        if warn_now {
+            warn!(
+                "Job '{}' waited {:.2?} for a worker, over the {:?} threshold ({} jobs so far); the pool may be too small.",
+                job, wait, threshold, exceeded
+            );
+        }
+        let alert = self.alert.lock().unwrap().clone();
+        if let Some(alert) = alert
+            && let Err(panic) = panic::catch_unwind(AssertUnwindSafe(|| alert(job, wait)))
+        {
+            error!("Queue wait alert panicked: {}", panic_message(&*panic));
+        }
+    }
+
+    fn stats(&self) -> QueueWaitStats {
+        let mut waits: Vec<Duration> = self.recent.lock().unwrap().iter().copied().collect();
+        let exceeded = self.exceeded.load(Ordering::SeqCst);
+        if waits.is_empty() {
+            return QueueWaitStats {
+                exceeded,
+                ..QueueWaitStats::default()
+            };
+        }
+        waits.sort_unstable();
+        // The nearest-rank percentile.
+        let percentile = |percent: usize| waits[(percent * waits.len()).div_ceil(100).max(1) - 1];
+        QueueWaitStats {
+            samples: waits.len(),
+            average: waits.iter().sum::<Duration>() / waits.len() as u32,
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+            max: waits[waits.len() - 1],
+            exceeded,
+        }
+    }
+}
+
+/// A cloneable, read-only handle to the jobs tracked by a `ThreadPool`.
+///
+/// Useful to query job metadata from code running inside the pool itself,
+/// where the pool cannot be borrowed.
+#[derive(Clone, Debug)]
+pub struct JobTracker {
+    jobs: Arc<Mutex<HashMap<Uuid, JobMetadata>>>,
+    queue_wait: Arc<QueueWait>,
+}
+
+impl JobTracker {
+    /// How long recent jobs waited for a worker, like
+    /// `ThreadPool::queue_wait`.
+    pub fn queue_wait(&self) -> QueueWaitStats {
+        self.queue_wait.stats()
+    }
+
+    pub fn get(&self, job_id: Uuid) -> Option<JobMetadata> {
+        let jobs = self.jobs.lock().unwrap();
+        jobs.get(&job_id).cloned()
+    }
+
+    /// Returns every tracked job, oldest first.
+    pub fn jobs(&self) -> Vec<(Uuid, JobMetadata)> {
+        let mut jobs: Vec<_> = {
+            let jobs = self.jobs.lock().unwrap();
+            jobs.iter()
+                .map(|(id, metadata)| (*id, metadata.clone()))
+                .collect()
+        };
+        jobs.sort_by_key(|(_, metadata)| metadata.submitted);
+        jobs
+    }
 }
 
 struct Job {
@@ -89,26 +454,144 @@ impl ThreadPool {
             return Err(PoolCreateError::NonValueZeroAllowed);
         }
 
-        let (sendx, recx) = channel::<Job>();
-        let receiver_clone = Arc::new(Mutex::new(recx));
+        let (sendx, queue) = Queue::new(size);
         let jobs: Arc<Mutex<HashMap<Uuid, JobMetadata>>> = Arc::new(Mutex::new(HashMap::new()));
+        let queued = Arc::new(AtomicUsize::new(0));
+        let panics = Arc::new(Panics::default());
+        let queue_wait = Arc::new(QueueWait::default());
+        let settings = Arc::new(WorkerSettings::default());
 
         let mut workers = Vec::with_capacity(size);
 
         for id in 0..size {
             workers.push(Worker::new(
                 id,
-                Arc::clone(&receiver_clone),
+                Arc::clone(&queue),
                 Arc::clone(&jobs),
+                Arc::clone(&queued),
+                Arc::clone(&panics),
+                Arc::clone(&queue_wait),
+                Arc::clone(&settings),
             ));
         }
 
         Ok(ThreadPool {
             workers,
             sender: Some(sendx),
+            queue,
             jobs,
+            queued,
+            panics,
+            queue_wait,
+            settings,
+            shutdown: ShutdownSignal::default(),
         })
     }
+
+    /// Sets what the pool does when a job panics; `PanicPolicy::Restart`
+    /// by default.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use harbor::{PanicPolicy, ThreadPool};
+    /// // Give up on the pool once three jobs have panicked.
+    /// let pool = ThreadPool::build(4)
+    ///     .unwrap()
+    ///     .panic_policy(PanicPolicy::CountAndContinue(3));
+    /// ```
+    pub fn panic_policy(self, policy: PanicPolicy) -> ThreadPool {
+        *self.panics.policy.lock().unwrap() = policy;
+        self
+    }
+
+    /// Returns how many jobs have panicked since the pool was built.
+    pub fn panics(&self) -> usize {
+        self.panics.count.load(Ordering::SeqCst)
+    }
+
+    /// Logs a warning when a job waited longer than `threshold` for a
+    /// worker, at most once a second however many do.
+    pub fn queue_wait_threshold(self, threshold: Duration) -> ThreadPool {
+        *self.queue_wait.threshold.lock().unwrap() = Some(threshold);
+        self
+    }
+
+    /// Also calls `alert` with every job that waited longer than the
+    /// `queue_wait_threshold`, and how long it waited. It runs on the
+    /// worker about to start the job, so keep it quick.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use std::{sync::{Arc, atomic::{AtomicU64, Ordering}}, time::Duration};
+    /// # use harbor::ThreadPool;
+    /// let slow = Arc::new(AtomicU64::new(0));
+    /// let counter = Arc::clone(&slow);
+    /// let pool = ThreadPool::build(4)
+    ///     .unwrap()
+    ///     .queue_wait_threshold(Duration::from_millis(100))
+    ///     .on_queue_wait_exceeded(move |_job, _wait| {
+    ///         counter.fetch_add(1, Ordering::Relaxed);
+    ///     });
+    /// ```
+    pub fn on_queue_wait_exceeded<F>(self, alert: F) -> ThreadPool
+    where
+        F: Fn(Uuid, Duration) + Send + Sync + 'static,
+    {
+        *self.queue_wait.alert.lock().unwrap() = Some(Arc::new(alert));
+        self
+    }
+
+    /// Returns how long the latest jobs waited in the queue before a
+    /// worker picked them up: the average, percentiles and maximum.
+    pub fn queue_wait(&self) -> QueueWaitStats {
+        self.queue_wait.stats()
+    }
+
+    /// Keeps at most `limit` bytes of each job's result, or failure
+    /// message, in its metadata and in the logs; the rest is cut off and
+    /// replaced with a note of how much was dropped. Unlimited by default.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use std::time::Duration;
+    /// # use harbor::{JobStatus, ThreadPool, test::wait_for_state};
+    /// let pool = ThreadPool::build(1).unwrap().max_result_bytes(5);
+    /// let job = pool.execute(|| Ok("a long result".to_string()));
+    ///
+    /// let completed = |state: &JobStatus| matches!(state, JobStatus::Completed);
+    /// let job = wait_for_state(&pool.tracker(), job, completed, Duration::from_secs(5));
+    /// assert_eq!(job.result(), Some("a lon... [8 more bytes truncated]"));
+    /// ```
+    pub fn max_result_bytes(self, limit: usize) -> ThreadPool {
+        self.settings
+            .max_result_bytes
+            .store(limit, Ordering::Relaxed);
+        self
+    }
+
+    /// Runs the pool's workers at `niceness`, from -20 for the most CPU
+    /// time to 19 for the least, so background jobs yield to the threads
+    /// of the application embedding the pool. Each worker switches before
+    /// the next job it runs. Only privileged processes may go below the
+    /// niceness they started with; a worker that is refused logs a
+    /// warning and keeps its own.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use harbor::ThreadPool;
+    /// let background = ThreadPool::build(2).unwrap().niceness(10);
+    /// ```
+    #[cfg(all(target_os = "linux", feature = "niceness"))]
+    pub fn niceness(self, niceness: i32) -> ThreadPool {
+        self.settings
+            .niceness
+            .store(niceness.clamp(-20, 19), Ordering::Relaxed);
+        self
+    }
     /// Executes a new job in the thread pool.
     ///
     /// The provided clousure will be send to an available worker thread
@@ -127,47 +610,314 @@ impl ThreadPool {
     where
         F: FnOnce() -> Result<String, String> + Send + 'static,
     {
-        let job = Job {
-            id: Uuid::new_v4(),
-            payload: Box::new(f),
-        };
-
-        let metadata = JobMetadata {
-            result: None,
-            state: JobStatus::Pending,
-        };
+        self.execute_with_priority(Priority::Normal, f)
+    }
 
-        let job_id = job.id;
-        {
-            let mut jobs_map = self.jobs.lock().unwrap();
-            jobs_map.insert(job_id, metadata);
+    /// Executes a job like `execute`, ahead of the queued jobs of lower
+    /// priority. `execute` queues them as `Priority::Normal`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use harbor::{Priority, ThreadPool};
+    /// let pool = ThreadPool::build(4).unwrap();
+    /// pool.execute_with_priority(Priority::Low, || Ok("nightly report".to_string()));
+    /// pool.execute_with_priority(Priority::High, || Ok("password reset".to_string()));
+    /// ```
+    pub fn execute_with_priority<F>(&self, priority: Priority, f: F) -> Uuid
+    where
+        F: FnOnce() -> Result<String, String> + Send + 'static,
+    {
+        match self.sender.as_ref() {
+            Some(sender) => dispatch(
+                sender,
+                &self.jobs,
+                &self.queued,
+                Lane::Shared(priority),
+                Box::new(f),
+            ),
+            None => Uuid::new_v4(),
         }
+    }
 
-        if let Some(sender) = self.sender.as_ref() {
-            if let Err(err) = sender.send(job) {
-                eprintln!("No one worker active: {}", err);
-            }
-        }
+    /// Executes a job like `execute`, handing it the pool's
+    /// `ShutdownSignal` so it can stop early when the pool shuts down.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use std::time::Duration;
+    /// # use harbor::ThreadPool;
+    /// let pool = ThreadPool::build(2).unwrap();
+    /// pool.execute_with_shutdown(|shutdown| {
+    ///     let mut polls = 0;
+    ///     while !shutdown.wait_timeout(Duration::from_millis(50)) {
+    ///         polls += 1; // Check for new work.
+    ///     }
+    ///     Ok(format!("stopped after {} polls", polls))
+    /// });
+    /// // Returns as soon as the job notices, not after a fixed wait.
+    /// assert!(pool.shutdown_timeout(Duration::from_secs(5)));
+    /// ```
+    pub fn execute_with_shutdown<F>(&self, f: F) -> Uuid
+    where
+        F: FnOnce(ShutdownSignal) -> Result<String, String> + Send + 'static,
+    {
+        let shutdown = self.shutdown.clone();
+        self.execute(move || f(shutdown))
+    }
 
-        job_id
+    /// Promotes queued jobs one `Priority` for every `after` they have
+    /// waited for a worker, so low-priority jobs still run while
+    /// high-priority ones keep coming. A `Low` job waiting twice `after`
+    /// goes before any `High` job queued since. No aging by default.
+    pub fn priority_aging(self, after: Duration) -> ThreadPool {
+        self.queue.age_after(Some(after));
+        self
+    }
+
+    /// Executes a job on the worker that `key` maps to, like `execute`.
+    ///
+    /// Jobs with the same key always run on the same worker, one at a
+    /// time and in the order they were submitted, so work on one entity,
+    /// such as one user, needs no locking of its own. The key only picks
+    /// the worker: jobs with different keys may share one and wait for
+    /// each other.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use harbor::ThreadPool;
+    /// let pool = ThreadPool::build(4).unwrap();
+    /// for step in 0..3 {
+    ///     // Runs after the previous step for user 42.
+    ///     pool.execute_keyed("user-42", move || Ok(format!("step {}", step)));
+    /// }
+    /// ```
+    pub fn execute_keyed<K, F>(&self, key: K, f: F) -> Uuid
+    where
+        K: Hash,
+        F: FnOnce() -> Result<String, String> + Send + 'static,
+    {
+        match self.sender.as_ref() {
+            Some(sender) => dispatch(
+                sender,
+                &self.jobs,
+                &self.queued,
+                Lane::Keyed(hash_key(key)),
+                Box::new(f),
+            ),
+            None => Uuid::new_v4(),
+        }
     }
 
     pub fn get_job_metadata(&self, job_id: Uuid) -> Option<JobMetadata> {
         let job = self.jobs.lock().unwrap();
         job.get(&job_id).cloned()
     }
+
+    /// Returns what each worker is doing: the job it is running and for how
+    /// long, which points at the job a stuck worker is stuck on.
+    pub fn worker_stats(&self) -> Vec<WorkerStats> {
+        self.workers.iter().map(Worker::stats).collect()
+    }
+
+    /// Returns how many jobs are waiting for a free worker. Jobs already
+    /// being executed are not counted.
+    pub fn queue_depth(&self) -> usize {
+        self.queued.load(Ordering::SeqCst)
+    }
+
+    /// Returns a `JobTracker` sharing this pool's job metadata.
+    pub fn tracker(&self) -> JobTracker {
+        JobTracker {
+            jobs: Arc::clone(&self.jobs),
+            queue_wait: Arc::clone(&self.queue_wait),
+        }
+    }
+
+    /// Stops accepting jobs, raises the `ShutdownSignal`, and waits up to
+    /// `timeout` for the workers to finish the jobs already queued.
+    ///
+    /// Returns `true` if every worker exited in time. Workers still busy when
+    /// the timeout expires are detached instead of joined, so this never
+    /// blocks for longer than `timeout`.
+    pub fn shutdown_timeout(mut self, timeout: Duration) -> bool {
+        drop(self.sender.take());
+        self.shutdown.raise();
+
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline && self.workers.iter().any(Worker::is_running) {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let mut finished = true;
+        for worker in &mut self.workers {
+            if worker.is_running() {
+                warn!("Worker {} did not finish in time; detaching it.", worker.id);
+                worker.thread.take();
+                finished = false;
+            }
+        }
+        finished
+    }
+
+    /// Returns a `JobSubmitter` that can queue jobs on this pool.
+    ///
+    /// The pool's workers only shut down once every submitter has been
+    /// dropped, so avoid keeping one alive inside a long-running job.
+    pub fn submitter(&self) -> Option<JobSubmitter> {
+        self.sender.as_ref().map(|sender| JobSubmitter {
+            sender: sender.clone(),
+            jobs: Arc::clone(&self.jobs),
+            queued: Arc::clone(&self.queued),
+            shutdown: self.shutdown.clone(),
+        })
+    }
+}
+
+/// A cloneable handle to submit jobs to a `ThreadPool` from other threads,
+/// including from jobs running inside the pool.
+#[derive(Clone, Debug)]
+pub struct JobSubmitter {
+    sender: Sender<Job>,
+    jobs: Arc<Mutex<HashMap<Uuid, JobMetadata>>>,
+    queued: Arc<AtomicUsize>,
+    shutdown: ShutdownSignal,
+}
+
+impl JobSubmitter {
+    /// Queues a job on the pool, like `ThreadPool::execute`.
+    pub fn execute<F>(&self, f: F) -> Uuid
+    where
+        F: FnOnce() -> Result<String, String> + Send + 'static,
+    {
+        self.submit(Box::new(f))
+    }
+
+    /// Queues a job ahead of those of lower priority, like
+    /// `ThreadPool::execute_with_priority`.
+    pub fn execute_with_priority<F>(&self, priority: Priority, f: F) -> Uuid
+    where
+        F: FnOnce() -> Result<String, String> + Send + 'static,
+    {
+        dispatch(
+            &self.sender,
+            &self.jobs,
+            &self.queued,
+            Lane::Shared(priority),
+            Box::new(f),
+        )
+    }
+
+    /// Queues a job that is told when the pool shuts down, like
+    /// `ThreadPool::execute_with_shutdown`.
+    pub fn execute_with_shutdown<F>(&self, f: F) -> Uuid
+    where
+        F: FnOnce(ShutdownSignal) -> Result<String, String> + Send + 'static,
+    {
+        let shutdown = self.shutdown.clone();
+        self.execute(move || f(shutdown))
+    }
+
+    /// Queues a job on the worker that `key` maps to, like
+    /// `ThreadPool::execute_keyed`.
+    pub fn execute_keyed<K, F>(&self, key: K, f: F) -> Uuid
+    where
+        K: Hash,
+        F: FnOnce() -> Result<String, String> + Send + 'static,
+    {
+        dispatch(
+            &self.sender,
+            &self.jobs,
+            &self.queued,
+            Lane::Keyed(hash_key(key)),
+            Box::new(f),
+        )
+    }
+
+    /// Queues an already boxed payload on the pool.
+    pub fn submit(&self, payload: JobPayload) -> Uuid {
+        dispatch(
+            &self.sender,
+            &self.jobs,
+            &self.queued,
+            Lane::Shared(Priority::Normal),
+            payload,
+        )
+    }
+
+    /// Returns how many jobs are waiting for a free worker, like
+    /// `ThreadPool::queue_depth`.
+    pub fn queue_depth(&self) -> usize {
+        self.queued.load(Ordering::SeqCst)
+    }
+}
+
+/// The same for equal keys for as long as the process runs.
+fn hash_key(key: impl Hash) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Where `dispatch` queues a job.
+#[derive(Debug, Clone, Copy)]
+enum Lane {
+    /// For any worker, by priority.
+    Shared(Priority),
+    /// For the one worker the hashed key maps to, in order.
+    Keyed(u64),
+}
+
+/// Registers the job as `Pending` and sends it to the workers, or to the
+/// one its key maps to. Fails it at once if the pool has stopped.
+fn dispatch(
+    sender: &Sender<Job>,
+    jobs: &Mutex<HashMap<Uuid, JobMetadata>>,
+    queued: &AtomicUsize,
+    lane: Lane,
+    payload: JobPayload,
+) -> Uuid {
+    let job = Job {
+        id: Uuid::new_v4(),
+        payload,
+    };
+
+    let metadata = JobMetadata::pending();
+
+    let job_id = job.id;
+    {
+        let mut jobs_map = jobs.lock().unwrap();
+        jobs_map.insert(job_id, metadata);
+    }
+
+    queued.fetch_add(1, Ordering::SeqCst);
+    let sent = match lane {
+        Lane::Shared(priority) => sender.send(priority, job),
+        Lane::Keyed(key) => sender.send_keyed(key, job),
+    };
+    if sent.is_err() {
+        queued.fetch_sub(1, Ordering::SeqCst);
+        if let Some(metadata) = jobs.lock().unwrap().get_mut(&job_id) {
+            metadata.fail(STOPPED.to_string());
+        }
+    }
+
+    job_id
 }
 
 impl Drop for ThreadPool {
     fn drop(&mut self) {
         drop(self.sender.take());
+        self.shutdown.raise();
         for worker in &mut self.workers {
-            println!("Shutting down worker {}", worker.id);
+            debug!("Shutting down worker {}", worker.id);
             if let Some(thread) = worker.thread.take() {
                 match thread.join() {
                     Ok(thread) => thread,
                     Err(err) => {
-                        eprintln!("The new thread could not be joined {:#?}", err);
+                        error!("The new thread could not be joined {:#?}", err);
                         continue;
                     }
                 }
@@ -176,68 +926,208 @@ impl Drop for ThreadPool {
     }
 }
 
+/// The settings a pool's workers read before each job, which the pool's
+/// builder methods may change after they started.
+#[derive(Debug)]
+struct WorkerSettings {
+    /// Most bytes of a job's result kept in its metadata and logs.
+    max_result_bytes: AtomicUsize,
+    /// The niceness to run jobs at, or `UNCHANGED`.
+    #[cfg(all(target_os = "linux", feature = "niceness"))]
+    niceness: std::sync::atomic::AtomicI32,
+}
+
+impl Default for WorkerSettings {
+    fn default() -> WorkerSettings {
+        WorkerSettings {
+            max_result_bytes: AtomicUsize::new(usize::MAX),
+            #[cfg(all(target_os = "linux", feature = "niceness"))]
+            niceness: std::sync::atomic::AtomicI32::new(UNCHANGED),
+        }
+    }
+}
+
+/// A `WorkerSettings::niceness` leaving workers as they were spawned.
+#[cfg(all(target_os = "linux", feature = "niceness"))]
+const UNCHANGED: i32 = i32::MIN;
+
+/// Switches the calling thread, alone, to `niceness`.
+#[cfg(all(target_os = "linux", feature = "niceness"))]
+fn set_thread_niceness(niceness: i32) -> std::io::Result<()> {
+    // On Linux the priority of a thread id is that thread's own.
+    let thread = unsafe { libc::gettid() };
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, thread as libc::id_t, niceness) } == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Cuts `result` down to at most `limit` bytes, on a character boundary,
+/// and notes how much was dropped.
+fn truncate_result(mut result: String, limit: usize) -> String {
+    if result.len() <= limit {
+        return result;
+    }
+    let mut end = limit;
+    while !result.is_char_boundary(end) {
+        end -= 1;
+    }
+    let dropped = result.len() - end;
+    result.truncate(end);
+    result.push_str(&format!("... [{} more bytes truncated]", dropped));
+    result
+}
+
+/// Why jobs fail once `PanicPolicy::CountAndContinue` stopped the pool.
+const STOPPED: &str = "the pool stopped after too many jobs panicked";
+
+/// The message a panic was raised with, if it was a string.
+pub(crate) fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    match panic.downcast_ref::<&str>() {
+        Some(message) => message,
+        None => panic
+            .downcast_ref::<String>()
+            .map_or("non-string panic payload", String::as_str),
+    }
+}
+
 #[allow(dead_code)]
 #[derive(Debug)]
 
 struct Worker {
     id: usize,
     thread: Option<JoinHandle<()>>,
+    activity: Arc<Mutex<Activity>>,
+}
+
+/// What a worker is doing, shared with its thread.
+#[derive(Debug, Default)]
+struct Activity {
+    /// The job being run, and since when.
+    current: Option<(Uuid, Instant)>,
+    jobs_run: u64,
+}
+
+/// A snapshot of what one worker of a `ThreadPool` is doing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WorkerStats {
+    pub id: usize,
+    /// The job the worker is running, if any.
+    pub job: Option<Uuid>,
+    /// How long it has been running `job`.
+    pub running_for: Option<Duration>,
+    /// Jobs it has finished, whether they succeeded or not.
+    pub jobs_run: u64,
 }
 
 impl Worker {
     fn new(
         id: usize,
-        receiver: Arc<Mutex<Receiver<Job>>>,
+        queue: Arc<Queue<Job>>,
         jobs: Arc<Mutex<HashMap<Uuid, JobMetadata>>>,
+        queued: Arc<AtomicUsize>,
+        panics: Arc<Panics>,
+        queue_wait: Arc<QueueWait>,
+        settings: Arc<WorkerSettings>,
     ) -> Worker {
+        let activity = Arc::new(Mutex::new(Activity::default()));
+        let shared_activity = Arc::clone(&activity);
         let thread = thread::spawn(move || {
+            #[cfg(all(target_os = "linux", feature = "niceness"))]
+            let mut niceness = UNCHANGED;
             loop {
-                let message = match receiver.lock() {
-                    Ok(lock) => lock.recv(),
-                    Err(err) => {
-                        eprintln!("Avoid Mutex poisoning. {}", err);
-                        return;
-                    }
-                };
-                match message {
-                    Ok(job) => {
-                        println!("Worker {id} got a job; executing.");
-                        let job_id = job.id;
+                match queue.recv(id) {
+                    Some(job) => {
+                        queued.fetch_sub(1, Ordering::SeqCst);
+                        #[cfg(all(target_os = "linux", feature = "niceness"))]
                         {
+                            let wanted = settings.niceness.load(Ordering::Relaxed);
+                            if wanted != niceness {
+                                if let Err(err) = set_thread_niceness(wanted) {
+                                    warn!(
+                                        "Worker {} kept its niceness, not {}: {}",
+                                        id, wanted, err
+                                    );
+                                }
+                                niceness = wanted;
+                            }
+                        }
+                        trace!("Worker {id} got a job; executing.");
+                        let job_id = job.id;
+                        let started = Instant::now();
+                        let waited = {
                             let mut jobs_map = jobs.lock().unwrap();
-                            if let Some(metadata) = jobs_map.get_mut(&job_id) {
+                            jobs_map.get_mut(&job_id).map(|metadata| {
                                 metadata.state = JobStatus::Processing;
-                            }
+                                metadata.started = Some(started);
+                                metadata.worker = Some(id);
+                                started - metadata.submitted
+                            })
+                        };
+                        if let Some(waited) = waited {
+                            queue_wait.record(job_id, waited);
                         }
+                        shared_activity.lock().unwrap().current = Some((job_id, started));
 
-                        let result = (job.payload)();
+                        // A panicking job fails instead of taking the worker
+                        // down, unless the panic policy says otherwise.
+                        let mut stop = false;
+                        let result = panic::catch_unwind(AssertUnwindSafe(job.payload))
+                            .unwrap_or_else(|panic| {
+                                let message = format!("job panicked: {}", panic_message(&*panic));
+                                stop = panics.record(id, job_id, &message);
+                                Err(message)
+                            });
+                        let limit = settings.max_result_bytes.load(Ordering::Relaxed);
+                        let result = result
+                            .map(|res_str| truncate_result(res_str, limit))
+                            .map_err(|err_str| truncate_result(err_str, limit));
+                        {
+                            let mut activity = shared_activity.lock().unwrap();
+                            activity.current = None;
+                            activity.jobs_run += 1;
+                        }
 
                         {
                             let mut jobs_map = jobs.lock().unwrap();
                             if let Some(metadata) = jobs_map.get_mut(&job_id) {
+                                metadata.finished = Some(Instant::now());
                                 match result {
                                     Ok(res_str) => {
                                         metadata.state = JobStatus::Completed;
-                                        metadata.result = Some(res_str.clone());
-                                        println!(
+                                        debug!(
                                             "Worker {} finished job '{}' successfully with result: {}",
                                             id, job.id, res_str
                                         );
+                                        metadata.result = Some(res_str);
                                     }
                                     Err(err_str) => {
                                         metadata.state = JobStatus::Failed(err_str.clone());
                                         metadata.result = Some(err_str.clone());
-                                        println!(
-                                            "Worker {} failed job '{}': {}",
-                                            id, job.id, err_str
-                                        );
+                                        warn!("Worker {} failed job '{}': {}", id, job.id, err_str);
                                     }
                                 }
                             }
                         }
+
+                        if stop {
+                            let left = queue.close();
+                            queued.fetch_sub(left.len(), Ordering::SeqCst);
+                            error!(
+                                "{} jobs have panicked; stopping the pool and failing {} queued jobs.",
+                                panics.count.load(Ordering::SeqCst),
+                                left.len()
+                            );
+                            let mut jobs_map = jobs.lock().unwrap();
+                            for job in left {
+                                if let Some(metadata) = jobs_map.get_mut(&job.id) {
+                                    metadata.fail(STOPPED.to_string());
+                                }
+                            }
+                        }
                     }
-                    Err(_) => {
-                        println!("Worker {id} disconnected; shutting down.");
+                    None => {
+                        debug!("Worker {id} disconnected; shutting down.");
                         break;
                     }
                 }
@@ -247,15 +1137,31 @@ impl Worker {
         Worker {
             id,
             thread: Some(thread),
+            activity,
+        }
+    }
+
+    fn stats(&self) -> WorkerStats {
+        let activity = self.activity.lock().unwrap();
+        WorkerStats {
+            id: self.id,
+            job: activity.current.map(|(job, _)| job),
+            running_for: activity.current.map(|(_, started)| started.elapsed()),
+            jobs_run: activity.jobs_run,
         }
     }
+
+    fn is_running(&self) -> bool {
+        self.thread
+            .as_ref()
+            .is_some_and(|thread| !thread.is_finished())
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::time::Duration;
-
     use super::*;
+    use std::sync::mpsc::channel;
 
     #[test]
     fn build_should_create_pool_with_valid_size() {
@@ -291,35 +1197,340 @@ mod tests {
 
     #[test]
     fn get_job_metadata_should_track_job_status() {
-        let pool = ThreadPool::build(2).unwrap();
-        let (tx, rx) = channel();
+        let pool = ThreadPool::build(1).unwrap();
+        let tracker = pool.tracker();
+        let timeout = Duration::from_secs(5);
+        let gate = test::Gate::new();
 
-        let job_id = pool.execute(move || {
-            thread::sleep(Duration::from_secs(10));
-            tx.send(()).unwrap();
-            Ok("Job Done".to_string())
-        });
+        let blocked = pool.execute(gate.job());
+        let job_id = pool.execute(|| Ok("Job Done".to_string()));
+        gate.wait_for_waiting(1, timeout);
 
         let initial_metadata = pool.get_job_metadata(job_id).unwrap();
-        assert!(
-            matches!(initial_metadata.state, JobStatus::Pending)
-                || matches!(initial_metadata.state, JobStatus::Processing)
-        );
+        assert!(matches!(initial_metadata.state, JobStatus::Pending));
         assert_eq!(initial_metadata.result, None);
+        let blocked_metadata = pool.get_job_metadata(blocked).unwrap();
+        assert!(matches!(blocked_metadata.state, JobStatus::Processing));
 
-        rx.recv().unwrap();
-        thread::sleep(Duration::from_secs(5));
-
-        let final_metadata = pool.get_job_metadata(job_id).unwrap();
-        assert!(matches!(final_metadata.state, JobStatus::Completed));
+        gate.open();
+        let final_metadata = test::wait_for_state(
+            &tracker,
+            job_id,
+            |state| matches!(state, JobStatus::Completed),
+            timeout,
+        );
         assert_eq!(final_metadata.result, Some("Job Done".to_string()));
 
         let failed_job_id = pool.execute(move || Err("Job Failed".to_string()));
 
-        thread::sleep(Duration::from_secs(5));
-
-        let failed_metadata = pool.get_job_metadata(failed_job_id).unwrap();
+        let failed_metadata = test::wait_for_state(
+            &tracker,
+            failed_job_id,
+            |state| matches!(state, JobStatus::Failed(_)),
+            timeout,
+        );
         assert!(matches!(failed_metadata.state, JobStatus::Failed(_)));
         assert_eq!(failed_metadata.result, Some("Job Failed".to_string()));
     }
+
+    #[test]
+    fn max_result_bytes_should_truncate_stored_results_on_char_boundaries() {
+        let pool = ThreadPool::build(1).unwrap().max_result_bytes(4);
+        let tracker = pool.tracker();
+        let timeout = Duration::from_secs(5);
+
+        let short = pool.execute(|| Ok("tiny".to_string()));
+        let long = pool.execute(|| Ok("añño".to_string()));
+        let failed = pool.execute(|| Err("x".repeat(1000)));
+
+        let short = test::wait_for_state(
+            &tracker,
+            short,
+            |state| matches!(state, JobStatus::Completed),
+            timeout,
+        );
+        let long = test::wait_for_state(
+            &tracker,
+            long,
+            |state| matches!(state, JobStatus::Completed),
+            timeout,
+        );
+        let failed = test::wait_for_state(
+            &tracker,
+            failed,
+            |state| matches!(state, JobStatus::Failed(_)),
+            timeout,
+        );
+        assert_eq!(short.result(), Some("tiny"));
+        assert_eq!(long.result(), Some("añ... [3 more bytes truncated]"));
+        assert_eq!(failed.result(), Some("xxxx... [996 more bytes truncated]"));
+        assert!(matches!(failed.state(), JobStatus::Failed(reason) if reason.len() < 40));
+    }
+
+    #[test]
+    fn execute_with_priority_should_run_urgent_jobs_first() {
+        let pool = ThreadPool::build(1).unwrap();
+        let gate = test::Gate::new();
+        let order = Arc::new(Mutex::new(Vec::new()));
+        pool.execute(gate.job());
+        gate.wait_for_waiting(1, Duration::from_secs(5));
+
+        for priority in [Priority::Low, Priority::Normal, Priority::High] {
+            let order = Arc::clone(&order);
+            pool.execute_with_priority(priority, move || {
+                order.lock().unwrap().push(priority);
+                Ok(String::new())
+            });
+        }
+        gate.open();
+        assert!(pool.shutdown_timeout(Duration::from_secs(5)));
+
+        assert_eq!(
+            *order.lock().unwrap(),
+            [Priority::High, Priority::Normal, Priority::Low]
+        );
+    }
+
+    #[test]
+    #[cfg(all(target_os = "linux", feature = "niceness"))]
+    fn niceness_should_apply_to_the_workers_only() {
+        let own = || unsafe { libc::getpriority(libc::PRIO_PROCESS, libc::gettid() as libc::id_t) };
+        let before = own();
+        let nicer = (before + 3).min(19);
+        let pool = ThreadPool::build(1).unwrap().niceness(nicer);
+
+        let job = pool.execute(move || Ok(own().to_string()));
+
+        let job = test::wait_for_state(
+            &pool.tracker(),
+            job,
+            |state| matches!(state, JobStatus::Completed),
+            Duration::from_secs(5),
+        );
+        assert_eq!(job.result(), Some(nicer.to_string().as_str()));
+        assert_eq!(own(), before);
+    }
+
+    #[test]
+    fn execute_should_fail_panicking_jobs_and_keep_the_worker() {
+        let pool = ThreadPool::build(1).unwrap();
+
+        let panicked = pool.execute(|| panic!("boom"));
+        let next = pool.execute(|| Ok("still running".to_string()));
+        let tracker = pool.tracker();
+        assert!(pool.shutdown_timeout(Duration::from_secs(5)));
+
+        let panicked = tracker.get(panicked).unwrap();
+        assert!(matches!(panicked.state(), JobStatus::Failed(_)));
+        assert_eq!(panicked.result(), Some("job panicked: boom"));
+        assert!(matches!(
+            tracker.get(next).unwrap().state(),
+            JobStatus::Completed
+        ));
+    }
+
+    #[test]
+    fn count_and_continue_should_stop_the_pool_after_max_panics() {
+        let pool = ThreadPool::build(1)
+            .unwrap()
+            .panic_policy(PanicPolicy::CountAndContinue(2));
+        let tracker = pool.tracker();
+
+        pool.execute(|| panic!("first"));
+        pool.execute(|| panic!("second"));
+        let queued = pool.execute(|| Ok("never runs".to_string()));
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while matches!(tracker.get(queued).unwrap().state(), JobStatus::Pending)
+            && Instant::now() < deadline
+        {
+            thread::sleep(Duration::from_millis(5));
+        }
+        let late = pool.execute(|| Ok("never runs".to_string()));
+
+        assert_eq!(pool.panics(), 2);
+        for job in [queued, late] {
+            let job = tracker.get(job).unwrap();
+            assert!(matches!(job.state(), JobStatus::Failed(_)));
+            assert_eq!(job.result(), Some(STOPPED));
+        }
+        assert_eq!(pool.queue_depth(), 0);
+        assert!(pool.shutdown_timeout(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn queue_wait_should_measure_jobs_behind_a_busy_worker_and_alert_over_threshold() {
+        let (sender, receiver) = channel();
+        let pool = ThreadPool::build(1)
+            .unwrap()
+            .queue_wait_threshold(Duration::from_millis(20))
+            .on_queue_wait_exceeded(move |job, wait| sender.send((job, wait)).unwrap());
+        let tracker = pool.tracker();
+
+        pool.execute(|| {
+            thread::sleep(Duration::from_millis(50));
+            Ok("slow".to_string())
+        });
+        let queued = pool.execute(|| Ok("queued".to_string()));
+        let (alerted, wait) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(pool.shutdown_timeout(Duration::from_secs(5)));
+
+        assert_eq!(alerted, queued);
+        assert_eq!(tracker.get(queued).unwrap().queue_wait(), Some(wait));
+        let stats = tracker.queue_wait();
+        assert_eq!(stats.samples, 2);
+        assert_eq!(stats.exceeded, 1);
+        assert_eq!(stats.max, wait);
+        assert_eq!(stats.p99, wait);
+        assert!(stats.p50 < Duration::from_millis(20));
+        assert!(wait >= Duration::from_millis(40));
+    }
+
+    #[test]
+    fn jobs_should_list_every_job_with_its_worker_and_duration() {
+        let pool = ThreadPool::build(1).unwrap();
+        let first = pool.execute(|| {
+            thread::sleep(Duration::from_millis(20));
+            Ok("first".to_string())
+        });
+        let second = pool.execute(|| Ok("second".to_string()));
+        let tracker = pool.tracker();
+        assert!(pool.shutdown_timeout(Duration::from_secs(5)));
+
+        let jobs = tracker.jobs();
+        let ids: Vec<Uuid> = jobs.iter().map(|(id, _)| *id).collect();
+        assert_eq!(ids, [first, second]);
+        let (_, first) = &jobs[0];
+        assert_eq!(first.worker(), Some(0));
+        assert!(first.duration().unwrap() >= Duration::from_millis(20));
+        assert!(first.age() >= first.duration().unwrap());
+    }
+
+    #[test]
+    fn execute_keyed_should_run_jobs_with_one_key_in_order_on_one_worker() {
+        let pool = ThreadPool::build(4).unwrap();
+        let ids: Vec<Uuid> = (0..20)
+            .map(|step| {
+                pool.execute_keyed("user-42", move || {
+                    thread::sleep(Duration::from_millis(20 - step));
+                    Ok(step.to_string())
+                })
+            })
+            .collect();
+        let tracker = pool.tracker();
+        assert!(pool.shutdown_timeout(Duration::from_secs(5)));
+
+        let jobs: Vec<JobMetadata> = ids.iter().map(|id| tracker.get(*id).unwrap()).collect();
+        let worker = jobs[0].worker();
+        assert!(jobs.iter().all(|job| job.worker() == worker));
+        assert!(
+            jobs.windows(2)
+                .all(|pair| pair[0].finished.unwrap() <= pair[1].started.unwrap())
+        );
+    }
+
+    #[test]
+    fn worker_stats_should_report_the_running_job() {
+        let pool = ThreadPool::build(2).unwrap();
+        let (started, wait_started) = channel();
+        let (release, wait_release) = channel::<()>();
+        let job_id = pool.execute(move || {
+            started.send(()).unwrap();
+            wait_release.recv().unwrap();
+            Ok("released".to_string())
+        });
+        wait_started.recv().unwrap();
+
+        let stats = pool.worker_stats();
+        let busy = stats.iter().find(|worker| worker.job.is_some()).unwrap();
+        assert_eq!(busy.job, Some(job_id));
+        assert!(busy.running_for.is_some());
+        assert_eq!(
+            pool.get_job_metadata(job_id).unwrap().worker(),
+            Some(busy.id)
+        );
+        assert_eq!(
+            stats.iter().filter(|worker| worker.job.is_none()).count(),
+            1
+        );
+
+        release.send(()).unwrap();
+        let tracker = pool.tracker();
+        while tracker.get(job_id).unwrap().result().is_none() {
+            thread::sleep(Duration::from_millis(10));
+        }
+        let busy = pool.worker_stats()[busy.id];
+        assert_eq!((busy.job, busy.jobs_run), (None, 1));
+    }
+
+    #[test]
+    fn queue_depth_should_count_jobs_waiting_for_a_worker() {
+        let pool = ThreadPool::build(1).unwrap();
+        let (started, wait_started) = channel();
+        let (release, wait_release) = channel::<()>();
+        pool.execute(move || {
+            started.send(()).unwrap();
+            wait_release.recv().unwrap();
+            Ok("released".to_string())
+        });
+        wait_started.recv().unwrap();
+        assert_eq!(pool.queue_depth(), 0);
+
+        pool.execute(|| Ok("queued".to_string()));
+        pool.execute(|| Ok("queued".to_string()));
+        assert_eq!(pool.queue_depth(), 2);
+
+        release.send(()).unwrap();
+        assert!(pool.shutdown_timeout(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn shutdown_timeout_should_wait_for_queued_jobs() {
+        let pool = ThreadPool::build(1).unwrap();
+        let job_id = pool.execute(|| {
+            thread::sleep(Duration::from_millis(200));
+            Ok("Job Done".to_string())
+        });
+        let tracker = pool.tracker();
+
+        assert!(pool.shutdown_timeout(Duration::from_secs(5)));
+        assert!(matches!(
+            tracker.get(job_id).unwrap().state,
+            JobStatus::Completed
+        ));
+    }
+
+    #[test]
+    fn shutdown_timeout_should_signal_jobs_waiting_for_shutdown() {
+        let pool = ThreadPool::build(1).unwrap();
+        let tracker = pool.tracker();
+        let job_id = pool.execute_with_shutdown(|shutdown| {
+            shutdown.wait();
+            Ok("Stopped".to_string())
+        });
+        let waiting = test::wait_for_state(
+            &tracker,
+            job_id,
+            |state| matches!(state, JobStatus::Processing),
+            Duration::from_secs(5),
+        );
+        assert!(waiting.result().is_none());
+
+        let started = Instant::now();
+        assert!(pool.shutdown_timeout(Duration::from_secs(5)));
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(tracker.get(job_id).unwrap().result(), Some("Stopped"));
+    }
+
+    #[test]
+    fn shutdown_timeout_should_detach_workers_past_the_deadline() {
+        let pool = ThreadPool::build(1).unwrap();
+        pool.execute(|| {
+            thread::sleep(Duration::from_secs(3));
+            Ok("Job Done".to_string())
+        });
+
+        let started = Instant::now();
+        assert!(!pool.shutdown_timeout(Duration::from_millis(100)));
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
 }
@@ -0,0 +1,260 @@
+//! A minimal logging facade in the style of the `log` crate.
+//!
+//! Diagnostics from the pool and the server go through the `error!`,
+//! `warn!`, `info!`, `debug!` and `trace!` macros. By default records at
+//! `Info` and above are written to stderr; embedders can lower or raise
+//! the level with `set_max_level` and send records elsewhere with
+//! `set_logger`, such as to `JsonLogger` for log collectors.
+//!
+//! ```
+//! use harbor::log::{self, Level, Logger, Record};
+//!
+//! struct Quiet;
+//!
+//! impl Logger for Quiet {
+//!     fn log(&self, record: &Record) {
+//!         if record.level() == Level::Error {
+//!             eprintln!("{}", record.args());
+//!         }
+//!     }
+//! }
+//!
+//! let _ = log::set_logger(Box::new(Quiet));
+//! ```
+
+use std::{
+    fmt,
+    io::Write,
+    sync::{
+        OnceLock,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::SystemTime,
+};
+
+use crate::{json, server::date};
+
+/// How important a record is, from `Error` (most) to `Trace` (least).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    /// Parses a level name such as `"warn"`, ignoring case.
+    pub fn parse(name: &str) -> Option<Level> {
+        match name.to_ascii_lowercase().as_str() {
+            "error" => Some(Level::Error),
+            "warn" => Some(Level::Warn),
+            "info" => Some(Level::Info),
+            "debug" => Some(Level::Debug),
+            "trace" => Some(Level::Trace),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+
+    fn from_index(index: usize) -> Level {
+        match index {
+            0 => Level::Error,
+            1 => Level::Warn,
+            2 => Level::Info,
+            3 => Level::Debug,
+            _ => Level::Trace,
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// One log message with its level and the module that produced it.
+#[derive(Debug)]
+pub struct Record<'a> {
+    level: Level,
+    target: &'a str,
+    args: fmt::Arguments<'a>,
+}
+
+impl<'a> Record<'a> {
+    pub fn level(&self) -> Level {
+        self.level
+    }
+
+    /// The module path of the call site, e.g. `harbor::server`.
+    pub fn target(&self) -> &'a str {
+        self.target
+    }
+
+    pub fn args(&self) -> &fmt::Arguments<'a> {
+        &self.args
+    }
+}
+
+/// Receives the records that pass the maximum level.
+pub trait Logger: Send + Sync {
+    fn log(&self, record: &Record);
+}
+
+/// The logger used until `set_logger` is called: one line per record on
+/// stderr.
+#[derive(Debug, Default)]
+pub struct StderrLogger;
+
+impl Logger for StderrLogger {
+    fn log(&self, record: &Record) {
+        let _ = writeln!(
+            std::io::stderr().lock(),
+            "[{} {}] {}",
+            record.level(),
+            record.target(),
+            record.args()
+        );
+    }
+}
+
+/// Writes one JSON object per record on stderr, with the fields `time`,
+/// `level`, `target` and `message`, for collectors such as Loki or
+/// Elasticsearch to ingest without parsing free-form lines.
+#[derive(Debug, Default)]
+pub struct JsonLogger;
+
+impl Logger for JsonLogger {
+    fn log(&self, record: &Record) {
+        let _ = writeln!(
+            std::io::stderr().lock(),
+            "{}",
+            json_line(record, SystemTime::now())
+        );
+    }
+}
+
+fn json_line(record: &Record, time: SystemTime) -> String {
+    format!(
+        "{{\"time\":{},\"level\":{},\"target\":{},\"message\":{}}}",
+        json::quote(&date::format_rfc3339(time)),
+        json::quote(record.level().name()),
+        json::quote(record.target()),
+        json::quote(&record.args().to_string())
+    )
+}
+
+static LOGGER: OnceLock<Box<dyn Logger>> = OnceLock::new();
+static MAX_LEVEL: AtomicUsize = AtomicUsize::new(Level::Info as usize);
+
+/// Installs the logger for the whole process. Only the first call wins;
+/// later ones return `Err` and leave it unchanged.
+pub fn set_logger(logger: Box<dyn Logger>) -> Result<(), String> {
+    LOGGER
+        .set(logger)
+        .map_err(|_| "a logger is already installed".to_string())
+}
+
+/// Sets the most verbose level that is logged.
+pub fn set_max_level(level: Level) {
+    MAX_LEVEL.store(level as usize, Ordering::Relaxed);
+}
+
+pub fn max_level() -> Level {
+    Level::from_index(MAX_LEVEL.load(Ordering::Relaxed))
+}
+
+/// Whether records at `level` are currently logged.
+pub fn enabled(level: Level) -> bool {
+    level <= max_level()
+}
+
+#[doc(hidden)]
+pub fn __log(level: Level, target: &str, args: fmt::Arguments) {
+    let record = Record {
+        level,
+        target,
+        args,
+    };
+    match LOGGER.get() {
+        Some(logger) => logger.log(&record),
+        None => StderrLogger.log(&record),
+    }
+}
+
+/// Logs at `level`, formatting the message only when it is enabled.
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $($arg:tt)+) => {{
+        let level = $level;
+        if $crate::log::enabled(level) {
+            $crate::log::__log(level, module_path!(), format_args!($($arg)+));
+        }
+    }};
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)+) => { $crate::log!($crate::log::Level::Error, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)+) => { $crate::log!($crate::log::Level::Warn, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)+) => { $crate::log!($crate::log::Level::Info, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)+) => { $crate::log!($crate::log::Level::Debug, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)+) => { $crate::log!($crate::log::Level::Trace, $($arg)+) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels_should_order_from_error_to_trace() {
+        assert!(Level::Error < Level::Warn && Level::Debug < Level::Trace);
+        assert_eq!(Level::parse("WARN"), Some(Level::Warn));
+        assert_eq!(Level::parse("loud"), None);
+        for level in [Level::Error, Level::Info, Level::Trace] {
+            assert_eq!(Level::from_index(level as usize), level);
+        }
+    }
+
+    #[test]
+    fn json_line_should_hold_one_escaped_object() {
+        let record = Record {
+            level: Level::Warn,
+            target: "harbor",
+            args: format_args!("Worker {} failed job: \"{}\"\n", 0, "boom"),
+        };
+        let time = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+
+        assert_eq!(
+            json_line(&record, time),
+            r#"{"time":"2023-11-14T22:13:20.000Z","level":"WARN","target":"harbor","message":"Worker 0 failed job: \"boom\"\n"}"#
+        );
+    }
+}
@@ -1,9 +1,183 @@
 //! A simple multi-threaded web server.
 //!
 //! Listens on `127.0.0.1:7878` and serves content from `hello.hmtl`
-//! or `notFound.html` using a thread pool.
-use harbor::server::start;
+//! or `notFound.html` using a thread pool. Run with `--help` to see the
+//! flags that change the defaults.
+use std::{env, path::PathBuf, process};
+
+use harbor::server::{Server, ServerConfig};
+
+const USAGE: &str = "Usage: harbor [OPTIONS]
+
+Options:
+  --config <FILE>    Load settings from a TOML file; other flags override it
+  --addr <HOST>      Host or IPv4/IPv6 address to bind; repeat it to also
+                     bind others on the same port [default: 127.0.0.1]
+  --port <PORT>      Port to listen on [default: 7878]
+  --workers <N>      Number of worker threads [default: 4]
+  --root <DIR>       Serve static files from this directory
+  -h, --help         Print this help";
+
+#[derive(Debug)]
+enum Command {
+    Run(Box<ServerConfig>),
+    Help,
+}
 
 fn main() {
-    start("127.0.0.1:7878");
+    let config = match parse_args(env::args().skip(1)) {
+        Ok(Command::Run(config)) => *config,
+        Ok(Command::Help) => {
+            println!("{}", USAGE);
+            return;
+        }
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, USAGE);
+            process::exit(2);
+        }
+    };
+
+    let server = Server::with_config(config);
+    #[cfg(feature = "signals")]
+    let server = server.shutdown_on_signals();
+    // `run` has logged why it could not start.
+    if server.run().is_err() {
+        process::exit(1);
+    }
+}
+
+fn parse_args(args: impl Iterator<Item = String>) -> Result<Command, String> {
+    let mut flags = Vec::new();
+    let mut args = args.peekable();
+    while let Some(flag) = args.next() {
+        if flag == "-h" || flag == "--help" {
+            return Ok(Command::Help);
+        }
+        let (name, value) = match flag.split_once('=') {
+            Some((name, value)) => (name.to_string(), value.to_string()),
+            None => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| format!("missing value for {}", flag))?;
+                (flag, value)
+            }
+        };
+        flags.push((name, value));
+    }
+
+    // The config file goes first so the other flags can override it.
+    let mut config = match flags.iter().find(|(name, _)| name == "--config") {
+        Some((_, path)) => ServerConfig::from_file(path).map_err(|err| err.to_string())?,
+        None => ServerConfig::default(),
+    };
+
+    let (host, mut port) = match config.address.rsplit_once(':') {
+        Some((host, port)) => (host.to_string(), port.to_string()),
+        None => (config.address.clone(), "7878".to_string()),
+    };
+    let mut hosts = Vec::new();
+
+    for (name, value) in flags {
+        match name.as_str() {
+            "--config" => {}
+            "--addr" => hosts.push(value),
+            "--port" => {
+                value
+                    .parse::<u16>()
+                    .map_err(|_| format!("invalid port: {}", value))?;
+                port = value;
+            }
+            "--workers" => {
+                config.workers = match value.parse::<usize>() {
+                    Ok(workers) if workers > 0 => workers,
+                    _ => return Err(format!("invalid worker count: {}", value)),
+                };
+            }
+            "--root" => {
+                let root = PathBuf::from(&value);
+                if !root.is_dir() {
+                    return Err(format!("document root is not a directory: {}", value));
+                }
+                config.document_root = Some(root);
+            }
+            _ => return Err(format!("unknown option: {}", name)),
+        }
+    }
+
+    if hosts.is_empty() {
+        hosts.push(host);
+    }
+    let mut addresses = hosts.iter().map(|host| address(host, &port));
+    config.address = addresses.next().expect("at least one host");
+    config.additional_addresses.extend(addresses);
+    Ok(Command::Run(Box::new(config)))
+}
+
+/// Joins `host` and `port`, bracketing IPv6 addresses like `::`.
+fn address(host: &str, port: &str) -> String {
+    if host.contains(':') && !host.starts_with('[') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Command, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn parse_args_should_default_to_the_standard_config() {
+        let Ok(Command::Run(config)) = parse(&[]) else {
+            panic!("expected a run command");
+        };
+
+        assert_eq!(config.address, "127.0.0.1:7878");
+        assert_eq!(config.workers, 4);
+    }
+
+    #[test]
+    fn parse_args_should_apply_flags() {
+        let Ok(Command::Run(config)) = parse(&[
+            "--addr",
+            "0.0.0.0",
+            "--port=8080",
+            "--workers",
+            "8",
+            "--root",
+            ".",
+        ]) else {
+            panic!("expected a run command");
+        };
+
+        assert_eq!(config.address, "0.0.0.0:8080");
+        assert_eq!(config.workers, 8);
+        assert_eq!(config.document_root, Some(PathBuf::from(".")));
+    }
+
+    #[test]
+    fn parse_args_should_bind_every_address_given() {
+        let Ok(Command::Run(config)) =
+            parse(&["--addr", "0.0.0.0", "--addr", "::", "--port", "80"])
+        else {
+            panic!("expected a run command");
+        };
+
+        assert_eq!(config.address, "0.0.0.0:80");
+        assert_eq!(config.additional_addresses, ["[::]:80"]);
+    }
+
+    #[test]
+    fn parse_args_should_reject_invalid_input() {
+        assert!(matches!(parse(&["--help"]), Ok(Command::Help)));
+        assert!(parse(&["--port", "99999"]).is_err());
+        assert!(parse(&["--workers", "0"]).is_err());
+        assert!(parse(&["--port"]).is_err());
+        assert!(parse(&["--verbose", "1"]).is_err());
+        assert!(parse(&["--config", "missing.toml"]).is_err());
+    }
 }
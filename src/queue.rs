@@ -0,0 +1,243 @@
+//! The queue a `ThreadPool`'s workers take their jobs from.
+//!
+//! Any idle worker takes the next shared job, highest priority first,
+//! while keyed jobs wait in the queue of the one worker their key maps to.
+//! Workers exit once every `Sender` is gone and nothing is left to run, or
+//! once the queue is closed.
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+
+use crate::Priority;
+
+#[derive(Debug)]
+pub(crate) struct Queue<T> {
+    state: Mutex<State<T>>,
+    available: Condvar,
+}
+
+#[derive(Debug)]
+struct State<T> {
+    /// The shared jobs of each priority, lowest first, and when they were
+    /// queued.
+    shared: [VecDeque<(T, Instant)>; Priority::ALL.len()],
+    /// How long a shared job waits before it counts as one priority
+    /// higher. Never when `None`.
+    aging: Option<Duration>,
+    /// The jobs only the worker at the same index may run.
+    pinned: Vec<VecDeque<T>>,
+    /// How many `Sender`s are alive.
+    senders: usize,
+    /// Set by `close`, after which nothing more is queued or handed out.
+    closed: bool,
+}
+
+/// Queues jobs for the workers. Cloning it keeps them running longer.
+#[derive(Debug)]
+pub(crate) struct Sender<T> {
+    queue: Arc<Queue<T>>,
+}
+
+impl<T> Queue<T> {
+    /// A queue for `workers` workers, and the first sender.
+    pub(crate) fn new(workers: usize) -> (Sender<T>, Arc<Queue<T>>) {
+        let queue = Arc::new(Queue {
+            state: Mutex::new(State {
+                shared: Default::default(),
+                aging: None,
+                pinned: (0..workers).map(|_| VecDeque::new()).collect(),
+                senders: 1,
+                closed: false,
+            }),
+            available: Condvar::new(),
+        });
+        (
+            Sender {
+                queue: Arc::clone(&queue),
+            },
+            queue,
+        )
+    }
+
+    /// Waits for the next job `worker` may run, its own before shared ones.
+    /// `None` once no sender is left and nothing is queued for it.
+    pub(crate) fn recv(&self, worker: usize) -> Option<T> {
+        let mut state = self.lock();
+        loop {
+            if state.closed {
+                return None;
+            }
+            if let Some(job) = state.pinned[worker].pop_front() {
+                return Some(job);
+            }
+            if let Some(job) = state.next_shared() {
+                return Some(job);
+            }
+            if state.senders == 0 {
+                return None;
+            }
+            state = self
+                .available
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    /// Stops handing out jobs, so every worker's `recv` returns `None`,
+    /// and refuses new ones. Returns the jobs that were still queued.
+    pub(crate) fn close(&self) -> Vec<T> {
+        let mut state = self.lock();
+        state.closed = true;
+        let mut left = Vec::new();
+        for shared in state.shared.iter_mut().rev() {
+            left.extend(shared.drain(..).map(|(job, _)| job));
+        }
+        for pinned in &mut state.pinned {
+            left.extend(pinned.drain(..));
+        }
+        drop(state);
+        self.available.notify_all();
+        left
+    }
+
+    /// Promotes shared jobs one priority for every `after` they wait, or
+    /// stops doing so when `None`.
+    pub(crate) fn age_after(&self, after: Option<Duration>) {
+        self.lock().aging = after.filter(|after| !after.is_zero());
+    }
+
+    /// No job runs while the lock is held, so a poisoned one still guards
+    /// a consistent queue.
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<T> State<T> {
+    /// Takes the shared job of the highest priority once aged, the oldest
+    /// of those that tie. Each priority's oldest job is at its front.
+    fn next_shared(&mut self) -> Option<T> {
+        let now = Instant::now();
+        let mut best: Option<(usize, Priority, Instant)> = None;
+        for (lane, shared) in self.shared.iter().enumerate() {
+            let Some((_, queued)) = shared.front() else {
+                continue;
+            };
+            let aged = Priority::ALL[lane].aged(now - *queued, self.aging);
+            let better = best.is_none_or(|(_, priority, oldest)| {
+                aged > priority || (aged == priority && *queued < oldest)
+            });
+            if better {
+                best = Some((lane, aged, *queued));
+            }
+        }
+        let (lane, _, _) = best?;
+        self.shared[lane].pop_front().map(|(job, _)| job)
+    }
+}
+
+impl<T> Sender<T> {
+    /// Queues `job` for whichever worker is free first, ahead of the jobs
+    /// of lower priority. Hands it back if the queue has been closed.
+    pub(crate) fn send(&self, priority: Priority, job: T) -> Result<(), T> {
+        let mut state = self.queue.lock();
+        if state.closed {
+            return Err(job);
+        }
+        state.shared[priority as usize].push_back((job, Instant::now()));
+        drop(state);
+        self.queue.available.notify_one();
+        Ok(())
+    }
+
+    /// Queues `job` behind the others with the same `key`, for the one
+    /// worker that `key` maps to, like `send`.
+    pub(crate) fn send_keyed(&self, key: u64, job: T) -> Result<(), T> {
+        let mut state = self.queue.lock();
+        if state.closed {
+            return Err(job);
+        }
+        let worker = (key % state.pinned.len() as u64) as usize;
+        state.pinned[worker].push_back(job);
+        drop(state);
+        // Only that worker can take it, and there is no waking it alone.
+        self.queue.available.notify_all();
+        Ok(())
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Sender<T> {
+        self.queue.lock().senders += 1;
+        Sender {
+            queue: Arc::clone(&self.queue),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut state = self.queue.lock();
+        state.senders -= 1;
+        if state.senders == 0 {
+            drop(state);
+            self.queue.available.notify_all();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recv_should_prefer_the_workers_own_jobs_and_end_without_senders() {
+        let (sender, queue) = Queue::new(2);
+        sender.send(Priority::Normal, "shared").unwrap();
+        sender.send_keyed(3, "pinned to 1").unwrap();
+        let second = sender.clone();
+        drop(sender);
+
+        assert_eq!(queue.recv(0), Some("shared"));
+        assert_eq!(queue.recv(1), Some("pinned to 1"));
+        second.send_keyed(3, "also pinned to 1").unwrap();
+        drop(second);
+        assert_eq!(queue.recv(0), None);
+        assert_eq!(queue.recv(1), Some("also pinned to 1"));
+        assert_eq!(queue.recv(1), None);
+    }
+
+    #[test]
+    fn recv_should_prefer_higher_priorities_until_jobs_have_aged() {
+        let (sender, queue) = Queue::new(1);
+        sender.send(Priority::Low, "low").unwrap();
+        sender.send(Priority::Normal, "normal").unwrap();
+        sender.send(Priority::High, "high").unwrap();
+
+        assert_eq!(queue.recv(0), Some("high"));
+        assert_eq!(queue.recv(0), Some("normal"));
+        assert_eq!(queue.recv(0), Some("low"));
+
+        queue.age_after(Some(Duration::from_millis(10)));
+        sender.send(Priority::Low, "starving").unwrap();
+        std::thread::sleep(Duration::from_millis(25));
+        sender.send(Priority::High, "urgent").unwrap();
+
+        assert_eq!(queue.recv(0), Some("starving"));
+        assert_eq!(queue.recv(0), Some("urgent"));
+    }
+
+    #[test]
+    fn close_should_hand_back_queued_jobs_and_refuse_new_ones() {
+        let (sender, queue) = Queue::new(2);
+        sender.send(Priority::Normal, "shared").unwrap();
+        sender.send_keyed(1, "pinned to 1").unwrap();
+
+        assert_eq!(queue.close(), ["shared", "pinned to 1"]);
+        assert_eq!(queue.recv(0), None);
+        assert_eq!(sender.send(Priority::Normal, "late"), Err("late"));
+    }
+}
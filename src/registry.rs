@@ -0,0 +1,191 @@
+//! Named job types that can be submitted without a Rust closure.
+//!
+//! A `JobRegistry` maps a job name to a factory that turns JSON arguments
+//! into a `JobPayload`, so jobs can be requested over HTTP or listed in a
+//! config file as a `JobDescriptor`.
+
+use std::{collections::HashMap, fmt};
+
+use uuid::Uuid;
+
+use crate::{
+    JobPayload, JobSubmitter,
+    json::{self, Value},
+};
+
+type JobFactory = Box<dyn Fn(&Value) -> Result<JobPayload, String> + Send + Sync + 'static>;
+
+/// A job to run, named by its registered type with its JSON arguments.
+///
+/// Unlike a closure it can be written down and read back, so it can cross
+/// process and network boundaries. It displays as the JSON object
+/// `parse` reads.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JobDescriptor {
+    pub job: String,
+    pub args: Value,
+}
+
+impl JobDescriptor {
+    pub fn new(job: &str, args: Value) -> JobDescriptor {
+        JobDescriptor {
+            job: job.to_string(),
+            args,
+        }
+    }
+
+    /// Parses a JSON object such as `{"job": "send_email", "args": {...}}`.
+    pub fn parse(input: &str) -> Result<JobDescriptor, String> {
+        JobDescriptor::from_json(
+            &Value::parse(input).map_err(|err| format!("invalid JSON: {}", err))?,
+        )
+    }
+
+    /// Reads the descriptor from an already parsed JSON object. `args` is
+    /// optional and `null` when left out.
+    pub fn from_json(document: &Value) -> Result<JobDescriptor, String> {
+        let Some(job) = document.get("job").and_then(Value::as_str) else {
+            return Err("missing \"job\" field".to_string());
+        };
+        let args = document.get("args").cloned().unwrap_or(Value::Null);
+        Ok(JobDescriptor::new(job, args))
+    }
+}
+
+impl fmt::Display for JobDescriptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{{\"job\":{},\"args\":{}}}",
+            json::quote(&self.job),
+            self.args
+        )
+    }
+}
+
+/// A table of job names to the factories that build them.
+#[derive(Default)]
+pub struct JobRegistry {
+    factories: HashMap<String, JobFactory>,
+}
+
+impl JobRegistry {
+    pub fn new() -> JobRegistry {
+        JobRegistry::default()
+    }
+
+    /// Registers `factory` under `name`, replacing any previous entry.
+    ///
+    /// The factory receives the request arguments and may reject them by
+    /// returning `Err` with a message for the client.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use harbor::registry::JobRegistry;
+    /// let mut registry = JobRegistry::new();
+    /// registry.register("echo", |args| {
+    ///     let text = args.to_string();
+    ///     Ok(Box::new(move || Ok(text)))
+    /// });
+    /// ```
+    pub fn register<F>(&mut self, name: &str, factory: F)
+    where
+        F: Fn(&Value) -> Result<JobPayload, String> + Send + Sync + 'static,
+    {
+        self.factories.insert(name.to_string(), Box::new(factory));
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.factories.contains_key(name)
+    }
+
+    /// Builds the payload for the job `name` with the given arguments.
+    pub fn create(&self, name: &str, args: &Value) -> Result<JobPayload, String> {
+        match self.factories.get(name) {
+            Some(factory) => factory(args),
+            None => Err(format!("Unknown job type: {}", name)),
+        }
+    }
+
+    /// Builds the job `descriptor` names and queues it with `submitter`,
+    /// returning its id.
+    pub fn submit(
+        &self,
+        submitter: &JobSubmitter,
+        descriptor: &JobDescriptor,
+    ) -> Result<Uuid, String> {
+        let payload = self.create(&descriptor.job, &descriptor.args)?;
+        Ok(submitter.submit(payload))
+    }
+}
+
+impl std::fmt::Debug for JobRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JobRegistry")
+            .field("jobs", &self.factories.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn create_should_build_registered_jobs() {
+        let mut registry = JobRegistry::new();
+        registry.register("greet", |args| {
+            let name = args.get("name").and_then(Value::as_str).unwrap_or("world");
+            let greeting = format!("Hello, {}!", name);
+            Ok(Box::new(move || Ok(greeting)))
+        });
+
+        let args = Value::parse(r#"{"name": "harbor"}"#).unwrap();
+        let payload = registry.create("greet", &args).unwrap();
+
+        assert!(registry.contains("greet"));
+        assert_eq!(payload(), Ok("Hello, harbor!".to_string()));
+    }
+
+    #[test]
+    fn descriptors_should_round_trip_through_json_and_submit() {
+        let mut registry = JobRegistry::new();
+        registry.register("add", |args| {
+            let sum = args.get("a").and_then(Value::as_f64).unwrap_or(0.0)
+                + args.get("b").and_then(Value::as_f64).unwrap_or(0.0);
+            Ok(Box::new(move || Ok(sum.to_string())))
+        });
+        let pool = crate::ThreadPool::build(1).unwrap();
+        let tracker = pool.tracker();
+
+        let descriptor =
+            JobDescriptor::parse(r#"{"job": "add", "args": {"a": 1, "b": 2}}"#).unwrap();
+        assert_eq!(
+            JobDescriptor::parse(&descriptor.to_string()),
+            Ok(descriptor.clone())
+        );
+        let id = registry
+            .submit(&pool.submitter().unwrap(), &descriptor)
+            .unwrap();
+        assert_eq!(
+            JobDescriptor::parse(r#"{"job": "missing"}"#)
+                .and_then(|descriptor| registry.submit(&pool.submitter().unwrap(), &descriptor)),
+            Err("Unknown job type: missing".to_string())
+        );
+        assert!(pool.shutdown_timeout(std::time::Duration::from_secs(5)));
+
+        assert_eq!(tracker.get(id).unwrap().result(), Some("3"));
+        assert_eq!(
+            JobDescriptor::parse(r#"{"args": {}}"#),
+            Err("missing \"job\" field".to_string())
+        );
+    }
+
+    #[test]
+    fn create_should_fail_for_unknown_jobs() {
+        let registry = JobRegistry::new();
+
+        assert!(registry.create("missing", &Value::Null).is_err());
+    }
+}
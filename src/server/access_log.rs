@@ -0,0 +1,394 @@
+//! Access logging to stdout and to rotating files.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::SystemTime,
+};
+
+use crate::{error, json};
+
+use super::{
+    date::{self, DateTime},
+    request::HttpRequest,
+    response::Response,
+};
+
+/// The layout of each access log line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessLogFormat {
+    /// `host - - [date] "request line" status bytes`
+    Common,
+    /// Common Log Format followed by the quoted referer and user agent.
+    Combined,
+    /// One JSON object per request with the fields of `Combined`:
+    /// `time`, `client`, `method`, `target`, `protocol`, `status`, `bytes`,
+    /// `referer` and `user_agent`, the missing ones `null`, followed by
+    /// the whole request's and response's sizes, `request_bytes` and
+    /// `response_bytes`.
+    Json,
+}
+
+impl AccessLogFormat {
+    pub fn parse(name: &str) -> Option<AccessLogFormat> {
+        match name.to_ascii_lowercase().as_str() {
+            "common" => Some(AccessLogFormat::Common),
+            "combined" => Some(AccessLogFormat::Combined),
+            "json" => Some(AccessLogFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+/// When the access log file is rotated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rotation {
+    Never,
+    /// Rotate on the first write of a new UTC day; old files are suffixed
+    /// with the date they cover, e.g. `access.log.2024-01-31`.
+    Daily,
+    /// Rotate before the file would grow past this many bytes; old files
+    /// are numbered, `access.log.1` being the most recent.
+    Size(u64),
+}
+
+/// Writes one line per request to stdout, a file, or both.
+#[derive(Debug)]
+pub struct AccessLog {
+    format: AccessLogFormat,
+    stdout: bool,
+    file: Option<Mutex<RotatingFile>>,
+}
+
+impl AccessLog {
+    /// Creates a logger printing to stdout when `stdout` is set.
+    pub fn new(format: AccessLogFormat, stdout: bool) -> AccessLog {
+        AccessLog {
+            format,
+            stdout,
+            file: None,
+        }
+    }
+
+    /// Also appends every line to the file at `path`, rotating it per
+    /// `rotation` and keeping at most `max_files` rotated files.
+    pub fn with_file(
+        mut self,
+        path: impl Into<PathBuf>,
+        rotation: Rotation,
+        max_files: usize,
+    ) -> io::Result<AccessLog> {
+        self.file = Some(Mutex::new(RotatingFile::open(
+            path.into(),
+            rotation,
+            max_files,
+        )?));
+        Ok(self)
+    }
+
+    pub fn record(&self, peer: Option<SocketAddr>, request: &HttpRequest, response: &Response) {
+        let line = format_entry(self.format, peer, request, response, SystemTime::now());
+
+        if self.stdout {
+            println!("{}", line);
+        }
+        if let Some(file) = &self.file {
+            let mut file = file.lock().unwrap();
+            if let Err(err) = file.write_line(&line, SystemTime::now()) {
+                error!("Failed to write access log: {}", err);
+            }
+        }
+    }
+}
+
+/// Formats one access log entry, without the trailing newline.
+pub fn format_entry(
+    format: AccessLogFormat,
+    peer: Option<SocketAddr>,
+    request: &HttpRequest,
+    response: &Response,
+    time: SystemTime,
+) -> String {
+    if format == AccessLogFormat::Json {
+        return format_json(peer, request, response, time);
+    }
+    let host = request
+        .client_ip()
+        .or(peer.map(|peer| peer.ip()))
+        .map(|ip| ip.to_string())
+        .unwrap_or_else(|| "-".to_string());
+    let request_line = format!(
+        "{} {} {}",
+        request.method(),
+        request.target(),
+        request.version()
+    );
+    let bytes = match response.body_len() {
+        0 => "-".to_string(),
+        len => len.to_string(),
+    };
+
+    let mut line = format!(
+        "{} - - [{}] \"{}\" {} {}",
+        host,
+        date::format_clf(time),
+        escape(&request_line),
+        response.status(),
+        bytes
+    );
+
+    if format == AccessLogFormat::Combined {
+        line.push_str(&format!(
+            " \"{}\" \"{}\"",
+            escape(request.header("Referer").unwrap_or("-")),
+            escape(request.header("User-Agent").unwrap_or("-"))
+        ));
+    }
+    line
+}
+
+fn format_json(
+    peer: Option<SocketAddr>,
+    request: &HttpRequest,
+    response: &Response,
+    time: SystemTime,
+) -> String {
+    let optional = |value: Option<&str>| value.map_or_else(|| "null".to_string(), json::quote);
+    let client = request
+        .client_ip()
+        .or(peer.map(|peer| peer.ip()))
+        .map(|ip| ip.to_string());
+    format!(
+        "{{\"time\":{},\"client\":{},\"method\":{},\"target\":{},\"protocol\":{},\"status\":{},\"bytes\":{},\"referer\":{},\"user_agent\":{},\"request_bytes\":{},\"response_bytes\":{}}}",
+        json::quote(&date::format_rfc3339(time)),
+        optional(client.as_deref()),
+        json::quote(request.method()),
+        json::quote(request.target()),
+        json::quote(request.version()),
+        response.status(),
+        response.body_len(),
+        optional(request.header("Referer")),
+        optional(request.header("User-Agent")),
+        request.wire_len(),
+        response.wire_len(),
+    )
+}
+
+/// Escapes quotes, backslashes and control characters so a field cannot
+/// break out of its quotes or forge extra log lines.
+fn escape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => out.push_str(&format!("\\x{:02x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[derive(Debug)]
+struct RotatingFile {
+    path: PathBuf,
+    rotation: Rotation,
+    max_files: usize,
+    file: File,
+    size: u64,
+    /// Day (since the epoch) of the entries in the current file.
+    day: i64,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, rotation: Rotation, max_files: usize) -> io::Result<RotatingFile> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let metadata = file.metadata()?;
+        let modified = metadata.modified().unwrap_or_else(|_| SystemTime::now());
+
+        Ok(RotatingFile {
+            day: DateTime::from_system_time(modified).days,
+            size: metadata.len(),
+            path,
+            rotation,
+            max_files,
+            file,
+        })
+    }
+
+    fn write_line(&mut self, line: &str, now: SystemTime) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        let today = DateTime::from_system_time(now).days;
+
+        let rotate = match self.rotation {
+            Rotation::Never => false,
+            Rotation::Daily => today != self.day && self.size > 0,
+            Rotation::Size(max) => self.size > 0 && self.size + len > max,
+        };
+        if rotate {
+            self.rotate()?;
+        }
+
+        writeln!(self.file, "{}", line)?;
+        self.size += len;
+        self.day = today;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        match self.rotation {
+            Rotation::Never => return Ok(()),
+            Rotation::Daily => {
+                let covered = SystemTime::UNIX_EPOCH
+                    + std::time::Duration::from_secs(self.day.max(0) as u64 * 86_400);
+                fs::rename(
+                    &self.path,
+                    suffixed(&self.path, &date::format_date(covered)),
+                )?;
+                self.prune_dated()?;
+            }
+            Rotation::Size(_) => {
+                if self.max_files > 0 {
+                    let _ = fs::remove_file(suffixed(&self.path, &self.max_files.to_string()));
+                    for index in (1..self.max_files).rev() {
+                        let from = suffixed(&self.path, &index.to_string());
+                        if from.exists() {
+                            fs::rename(from, suffixed(&self.path, &(index + 1).to_string()))?;
+                        }
+                    }
+                    fs::rename(&self.path, suffixed(&self.path, "1"))?;
+                } else {
+                    fs::remove_file(&self.path)?;
+                }
+            }
+        }
+
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    /// Deletes the oldest date-suffixed files beyond `max_files`.
+    fn prune_dated(&self) -> io::Result<()> {
+        let (dir, prefix) = match (self.path.parent(), self.path.file_name()) {
+            (Some(dir), Some(name)) => (dir, format!("{}.", name.to_string_lossy())),
+            _ => return Ok(()),
+        };
+        let dir = if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir
+        };
+
+        let mut rotated: Vec<PathBuf> = fs::read_dir(dir)?
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_name().to_string_lossy().starts_with(&prefix))
+            .map(|entry| entry.path())
+            .collect();
+        // ISO dates sort chronologically, newest last.
+        rotated.sort();
+        let excess = rotated.len().saturating_sub(self.max_files);
+        for path in &rotated[..excess] {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+fn suffixed(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn request(raw: &str) -> HttpRequest {
+        HttpRequest::parse(&mut raw.as_bytes()).unwrap().unwrap()
+    }
+
+    fn fixture(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("harbor-log-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("access.log")
+    }
+
+    #[test]
+    fn format_entry_should_follow_common_combined_and_json_formats() {
+        let request = request("GET /a?b=\"c\" HTTP/1.1\r\nUser-Agent: curl/8\r\n\r\n");
+        let response = Response::new(200).body("hello");
+        let peer = Some("10.0.0.1:5555".parse().unwrap());
+        let time = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        assert_eq!(
+            format_entry(AccessLogFormat::Common, peer, &request, &response, time),
+            "10.0.0.1 - - [14/Nov/2023:22:13:20 +0000] \"GET /a?b=\\\"c\\\" HTTP/1.1\" 200 5"
+        );
+        assert!(
+            format_entry(
+                AccessLogFormat::Combined,
+                None,
+                &request,
+                &Response::new(404),
+                time
+            )
+            .ends_with("\" 404 - \"-\" \"curl/8\"")
+        );
+        assert_eq!(
+            format_entry(AccessLogFormat::Json, peer, &request, &response, time),
+            r#"{"time":"2023-11-14T22:13:20.000Z","client":"10.0.0.1","method":"GET","target":"/a?b=\"c\"","protocol":"HTTP/1.1","status":200,"bytes":5,"referer":null,"user_agent":"curl/8","request_bytes":45,"response_bytes":80}"#
+        );
+    }
+
+    #[test]
+    fn write_line_should_rotate_by_size() {
+        let path = fixture("size");
+        let mut file = RotatingFile::open(path.clone(), Rotation::Size(10), 2).unwrap();
+        let now = SystemTime::now();
+
+        for line in ["first", "second", "third", "fourth"] {
+            file.write_line(line, now).unwrap();
+        }
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fourth\n");
+        assert_eq!(fs::read_to_string(suffixed(&path, "1")).unwrap(), "third\n");
+        assert_eq!(
+            fs::read_to_string(suffixed(&path, "2")).unwrap(),
+            "second\n"
+        );
+        assert!(!suffixed(&path, "3").exists());
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn write_line_should_rotate_daily() {
+        let path = fixture("daily");
+        let mut file = RotatingFile::open(path.clone(), Rotation::Daily, 1).unwrap();
+        let day_one = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        file.write_line("one", day_one).unwrap();
+        file.write_line("two", day_one + Duration::from_secs(86_400))
+            .unwrap();
+        file.write_line("three", day_one + Duration::from_secs(2 * 86_400))
+            .unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "three\n");
+        assert_eq!(
+            fs::read_to_string(suffixed(&path, "2023-11-15")).unwrap(),
+            "two\n"
+        );
+        assert!(!suffixed(&path, "2023-11-14").exists());
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
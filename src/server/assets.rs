@@ -0,0 +1,141 @@
+//! Files compiled into the binary, so a server needs no document root.
+
+use std::{collections::HashMap, fmt};
+
+use super::{handler::Handler, mime::MimeTypes, request::HttpRequest, response::Response};
+
+/// Serves files embedded with `include_bytes!` by their request path.
+///
+/// A path ending in `/` is answered with its `index.html`. Usually built
+/// with `embed_assets!`.
+///
+/// # Example
+///
+/// ```
+/// # use harbor::server::{EmbeddedAssets, Server};
+/// let assets = EmbeddedAssets::new()
+///     .file("/index.html", include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/hello.html")))
+///     .file("/robots.txt", b"User-agent: *\nDisallow:\n");
+/// let server = Server::new("127.0.0.1:7878").assets(assets);
+/// ```
+#[derive(Clone, Default)]
+pub struct EmbeddedAssets {
+    files: HashMap<String, &'static [u8]>,
+    mime_types: MimeTypes,
+}
+
+impl EmbeddedAssets {
+    pub fn new() -> EmbeddedAssets {
+        EmbeddedAssets::default()
+    }
+
+    /// Serves `contents` at `path`, which should start with `/`.
+    pub fn file(mut self, path: &str, contents: &'static [u8]) -> EmbeddedAssets {
+        self.files.insert(path.to_string(), contents);
+        self
+    }
+
+    /// Sets the table used to pick each file's `Content-Type`.
+    pub fn mime_types(mut self, mime_types: MimeTypes) -> EmbeddedAssets {
+        self.mime_types = mime_types;
+        self
+    }
+
+    /// Answers `GET` requests for an embedded file; `None` otherwise, so
+    /// other handlers can try the request.
+    pub fn serve(&self, request: &HttpRequest) -> Option<Response> {
+        self.serve_path(request, request.path())
+    }
+
+    /// Like `serve`, but looks up `path` instead of the request's own path.
+    pub fn serve_path(&self, request: &HttpRequest, path: &str) -> Option<Response> {
+        if !matches!(request.method(), "GET" | "HEAD") {
+            return None;
+        }
+        let mut path = format!("/{}", path.trim_start_matches('/'));
+        if path.ends_with('/') {
+            path.push_str("index.html");
+        }
+        let contents = self.files.get(&path)?;
+        Some(
+            Response::new(200)
+                .header("Content-Type", self.mime_types.lookup(&path))
+                .body(contents.to_vec()),
+        )
+    }
+
+    /// The paths of the embedded files.
+    pub fn paths(&self) -> impl Iterator<Item = &str> {
+        self.files.keys().map(String::as_str)
+    }
+}
+
+/// Answers with the embedded file, or `404 Not Found`.
+impl Handler for EmbeddedAssets {
+    fn handle(&self, request: &HttpRequest) -> Response {
+        self.serve(request)
+            .unwrap_or_else(|| Response::error_page(404))
+    }
+}
+
+impl fmt::Debug for EmbeddedAssets {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut paths: Vec<&str> = self.paths().collect();
+        paths.sort_unstable();
+        f.debug_struct("EmbeddedAssets")
+            .field("paths", &paths)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Builds `EmbeddedAssets` from request paths and files to embed, which
+/// `include_bytes!` resolves relative to the calling source file.
+///
+/// # Example
+///
+/// ```
+/// let assets = harbor::embed_assets! {
+///     "/index.html" => concat!(env!("CARGO_MANIFEST_DIR"), "/hello.html"),
+///     "/404.html" => concat!(env!("CARGO_MANIFEST_DIR"), "/notFound.html"),
+/// };
+/// assert_eq!(assets.paths().count(), 2);
+/// ```
+#[macro_export]
+macro_rules! embed_assets {
+    ($($path:expr => $file:expr),* $(,)?) => {
+        $crate::server::EmbeddedAssets::new()
+            $(.file($path, include_bytes!($file)))*
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get(path: &str) -> HttpRequest {
+        let raw = format!("GET {} HTTP/1.1\r\n\r\n", path);
+        HttpRequest::parse(&mut raw.as_bytes()).unwrap().unwrap()
+    }
+
+    #[test]
+    fn serve_should_answer_embedded_files_and_index_pages() {
+        let assets = EmbeddedAssets::new()
+            .file("/index.html", b"<h1>Home</h1>")
+            .file("/css/site.css", b"body {}");
+
+        let index = assets.serve(&get("/")).unwrap();
+        let css = assets.serve_path(&get("/static/site.css"), "css/site.css");
+
+        assert_eq!(index.get_body(), b"<h1>Home</h1>");
+        assert_eq!(
+            index.get_header("Content-Type"),
+            Some("text/html; charset=utf-8")
+        );
+        assert_eq!(
+            css.map(|css| css.get_body().to_vec()),
+            Some(b"body {}".to_vec())
+        );
+        assert!(assets.serve(&get("/missing.js")).is_none());
+        assert_eq!(assets.handle(&get("/missing.js")).status(), 404);
+    }
+}
@@ -0,0 +1,107 @@
+//! Request bodies read straight from the connection, for uploads too large
+//! to hold in memory.
+
+use std::io::{self, Read};
+
+use super::{request::HttpRequest, response::Response};
+
+/// The body of a request, read as it arrives.
+///
+/// Reads end after the `Content-Length` announced by the client, which the
+/// server has already checked against the route's limit. A connection that
+/// closes early is an `UnexpectedEof` error. Whatever the handler leaves
+/// unread is discarded by closing the connection after the response.
+pub struct Body<'a> {
+    reader: &'a mut dyn Read,
+    remaining: u64,
+}
+
+impl<'a> Body<'a> {
+    pub(crate) fn new(reader: &'a mut dyn Read, length: u64) -> Body<'a> {
+        Body {
+            reader,
+            remaining: length,
+        }
+    }
+
+    /// The number of bytes still to be read.
+    pub fn remaining(&self) -> u64 {
+        self.remaining
+    }
+}
+
+impl Read for Body<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 || buf.is_empty() {
+            return Ok(0);
+        }
+        let max = buf
+            .len()
+            .min(usize::try_from(self.remaining).unwrap_or(usize::MAX));
+        let read = self.reader.read(&mut buf[..max])?;
+        if read == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!(
+                    "connection closed with {} body bytes unsent",
+                    self.remaining
+                ),
+            ));
+        }
+        self.remaining -= read as u64;
+        Ok(read)
+    }
+}
+
+/// Answers a request whose body it reads itself, registered with
+/// `Router::stream`.
+///
+/// # Example
+///
+/// An upload saved to disk without holding it in memory:
+///
+/// ```no_run
+/// # use std::{fs::File, io};
+/// # use harbor::server::{Response, Router};
+/// let router = Router::new().stream("PUT", "/upload", 1 << 30, |_, body| {
+///     let saved = File::create("upload.bin").and_then(|mut file| io::copy(body, &mut file));
+///     match saved {
+///         Ok(bytes) => Response::new(201).body(format!("{} bytes", bytes)),
+///         Err(_) => Response::new(500),
+///     }
+/// });
+/// ```
+pub trait BodyHandler: Send + Sync {
+    fn handle(&self, request: &HttpRequest, body: &mut Body<'_>) -> Response;
+}
+
+impl<F> BodyHandler for F
+where
+    F: Fn(&HttpRequest, &mut Body<'_>) -> Response + Send + Sync,
+{
+    fn handle(&self, request: &HttpRequest, body: &mut Body<'_>) -> Response {
+        self(request, body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn body_should_stop_at_the_announced_length() {
+        let mut source = &b"hello, world"[..];
+        let mut body = Body::new(&mut source, 5);
+
+        let mut read = String::new();
+        body.read_to_string(&mut read).unwrap();
+
+        assert_eq!(read, "hello");
+        assert_eq!(body.remaining(), 0);
+        assert_eq!(source, b", world");
+
+        let mut short = &b"abc"[..];
+        let error = io::copy(&mut Body::new(&mut short, 10), &mut io::sink()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,1124 @@
+//! A Brotli (RFC 7932) compressor, used for responses to clients sending
+//! `Accept-Encoding: br`.
+//!
+//! Repeats are found with a hash chain, and each meta-block is coded with
+//! a single prefix code per alphabet: no block switches, context modeling,
+//! distance cache or static dictionary. That costs some ratio against the
+//! reference encoder, but every decoder accepts the output.
+
+/// The window is 2^18 bytes, less the 16 Brotli reserves.
+const WINDOW_BITS: u32 = 18;
+const MAX_DISTANCE: usize = (1 << WINDOW_BITS) - 16;
+/// Uncompressed bytes per meta-block.
+const META_BLOCK_BYTES: usize = 1 << 20;
+const MIN_MATCH: usize = 4;
+/// How many earlier positions with the same hash are tried per match.
+const MAX_CHAIN: usize = 32;
+const HASH_BITS: u32 = 15;
+
+/// The first insert length of each insert length code, and its extra bits.
+const INSERT_LENGTHS: [(usize, u32); 24] = [
+    (0, 0),
+    (1, 0),
+    (2, 0),
+    (3, 0),
+    (4, 0),
+    (5, 0),
+    (6, 1),
+    (8, 1),
+    (10, 2),
+    (14, 2),
+    (18, 3),
+    (26, 3),
+    (34, 4),
+    (50, 4),
+    (66, 5),
+    (98, 5),
+    (130, 6),
+    (194, 7),
+    (322, 8),
+    (578, 9),
+    (1090, 10),
+    (2114, 12),
+    (6210, 14),
+    (22594, 24),
+];
+
+/// The first copy length of each copy length code, and its extra bits.
+const COPY_LENGTHS: [(usize, u32); 24] = [
+    (2, 0),
+    (3, 0),
+    (4, 0),
+    (5, 0),
+    (6, 0),
+    (7, 0),
+    (8, 0),
+    (9, 0),
+    (10, 1),
+    (12, 1),
+    (14, 2),
+    (18, 2),
+    (22, 3),
+    (30, 3),
+    (38, 4),
+    (54, 4),
+    (70, 5),
+    (102, 5),
+    (134, 6),
+    (198, 7),
+    (326, 8),
+    (582, 9),
+    (1094, 10),
+    (2118, 24),
+];
+
+/// The first insert-and-copy code for each pair of insert and copy length
+/// code ranges (0-7, 8-15, 16-23), among those with an explicit distance.
+const COMMAND_OFFSETS: [[usize; 3]; 3] = [[128, 192, 384], [256, 320, 512], [448, 576, 640]];
+
+const LITERAL_ALPHABET: usize = 256;
+const COMMAND_ALPHABET: usize = 704;
+/// Sixteen distance cache codes, never used here, then 48 distance codes.
+const DISTANCE_ALPHABET: usize = 64;
+
+/// The order code length code lengths are sent in.
+const CODE_LENGTH_ORDER: [usize; 18] =
+    [1, 2, 3, 4, 0, 5, 17, 6, 16, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+/// The fixed code for code length code lengths 0 to 5, as bits and length.
+const CODE_LENGTH_CODES: [(u64, u32); 6] = [(0, 2), (7, 4), (3, 3), (2, 2), (1, 2), (15, 4)];
+/// The code length symbol repeating a zero length 3 to 10 times.
+const REPEAT_ZERO: usize = 17;
+
+/// The Brotli stream decoding to `data`.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut out = BitWriter::default();
+    // WBITS: a set bit, then the window size less 17 in three bits.
+    out.write(1, 1);
+    out.write(3, u64::from(WINDOW_BITS - 17));
+
+    let mut matcher = Matcher::new();
+    let mut start = 0;
+    while start < data.len() {
+        let end = data.len().min(start + META_BLOCK_BYTES);
+        let commands = matcher.commands(data, start, end);
+        let block = compressed_meta_block(data, start, &commands);
+        if block.len_bits() < (end - start) * 8 {
+            out.append(&block);
+        } else {
+            uncompressed_meta_block(&mut out, &data[start..end]);
+        }
+        start = end;
+    }
+
+    // ISLAST and ISLASTEMPTY.
+    out.write(2, 0b11);
+    out.finish()
+}
+
+/// Inserts `insert` literals, then copies `copy` bytes from `distance`
+/// back. Only a meta-block's last command copies nothing.
+#[derive(Debug, Clone, Copy)]
+struct Command {
+    insert: usize,
+    copy: usize,
+    distance: usize,
+}
+
+/// Finds earlier occurrences of the bytes at a position, through chains
+/// of positions whose next four bytes hash alike.
+struct Matcher {
+    head: Vec<u32>,
+    /// The previous position in each position's chain, by its offset in
+    /// the window.
+    prev: Vec<u32>,
+    /// Positions below this one are in the chains.
+    inserted: usize,
+}
+
+impl Matcher {
+    const NONE: u32 = u32::MAX;
+
+    fn new() -> Matcher {
+        Matcher {
+            head: vec![Matcher::NONE; 1 << HASH_BITS],
+            prev: vec![Matcher::NONE; 1 << WINDOW_BITS],
+            inserted: 0,
+        }
+    }
+
+    /// The commands producing `data[start..end]`, greedily taking the
+    /// longest match unless the next position has a longer one.
+    fn commands(&mut self, data: &[u8], start: usize, end: usize) -> Vec<Command> {
+        let mut commands = Vec::new();
+        let (mut pos, mut literals) = (start, start);
+        while pos < end {
+            self.insert_up_to(data, pos);
+            let Some((copy, distance)) = self.longest_match(data, pos, end) else {
+                pos += 1;
+                continue;
+            };
+            self.insert_up_to(data, pos + 1);
+            if self
+                .longest_match(data, pos + 1, end)
+                .is_some_and(|(next, _)| next > copy)
+            {
+                pos += 1;
+                continue;
+            }
+            commands.push(Command {
+                insert: pos - literals,
+                copy,
+                distance,
+            });
+            pos += copy;
+            literals = pos;
+        }
+        if literals < end {
+            commands.push(Command {
+                insert: end - literals,
+                copy: 0,
+                distance: 0,
+            });
+        }
+        commands
+    }
+
+    /// The longest match for `data[pos..end]` as its length and distance.
+    fn longest_match(&self, data: &[u8], pos: usize, end: usize) -> Option<(usize, usize)> {
+        if pos + MIN_MATCH > end {
+            return None;
+        }
+        let mut best: Option<(usize, usize)> = None;
+        let mut candidate = self.head[hash(data, pos)];
+        for _ in 0..MAX_CHAIN {
+            if candidate == Matcher::NONE {
+                break;
+            }
+            let earlier = candidate as usize;
+            let distance = pos - earlier;
+            if distance > MAX_DISTANCE {
+                break;
+            }
+            let len = data[earlier..]
+                .iter()
+                .zip(&data[pos..end])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if len >= MIN_MATCH && best.is_none_or(|(best, _)| len > best) {
+                best = Some((len, distance));
+                if pos + len == end {
+                    break;
+                }
+            }
+            candidate = self.prev[earlier & ((1 << WINDOW_BITS) - 1)];
+        }
+        best
+    }
+
+    fn insert_up_to(&mut self, data: &[u8], pos: usize) {
+        while self.inserted < pos {
+            let at = self.inserted;
+            if at + MIN_MATCH <= data.len() {
+                let bucket = hash(data, at);
+                self.prev[at & ((1 << WINDOW_BITS) - 1)] = self.head[bucket];
+                self.head[bucket] = at as u32;
+            }
+            self.inserted += 1;
+        }
+    }
+}
+
+fn hash(data: &[u8], pos: usize) -> usize {
+    let word = u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]);
+    (word.wrapping_mul(0x1E35_A7BD) >> (32 - HASH_BITS)) as usize
+}
+
+/// The meta-block running `commands` from `start`, as bits to append to
+/// the stream.
+fn compressed_meta_block(data: &[u8], start: usize, commands: &[Command]) -> BitWriter {
+    let mut literal_counts = vec![0; LITERAL_ALPHABET];
+    let mut command_counts = vec![0; COMMAND_ALPHABET];
+    let mut distance_counts = vec![0; DISTANCE_ALPHABET];
+    let mut pos = start;
+    for command in commands {
+        for &byte in &data[pos..pos + command.insert] {
+            literal_counts[usize::from(byte)] += 1;
+        }
+        command_counts[command_code(command)] += 1;
+        if command.copy > 0 {
+            distance_counts[distance_code(command.distance).0] += 1;
+        }
+        pos += command.insert + command.copy;
+    }
+    let literal_code = PrefixCode::new(&literal_counts, 15);
+    let command_prefix_code = PrefixCode::new(&command_counts, 15);
+    let distance_prefix_code = PrefixCode::new(&distance_counts, 15);
+
+    let mut block = BitWriter::default();
+    // ISLAST, then MLEN, then ISUNCOMPRESSED.
+    block.write(1, 0);
+    write_meta_block_length(&mut block, pos - start);
+    block.write(1, 0);
+    // One block type each of literals, commands and distances.
+    block.write(3, 0);
+    // NPOSTFIX and NDIRECT, then the literal context mode, unused with a
+    // single literal code.
+    block.write(6, 0);
+    block.write(2, 0);
+    // One literal code and one distance code.
+    block.write(2, 0);
+    literal_code.write_definition(&mut block, 8);
+    command_prefix_code.write_definition(&mut block, 10);
+    distance_prefix_code.write_definition(&mut block, 6);
+
+    let mut pos = start;
+    for command in commands {
+        command_prefix_code.write(&mut block, command_code(command));
+        let (_, bits, extra) = length_code(&INSERT_LENGTHS, command.insert);
+        block.write(bits, extra);
+        let (_, bits, extra) = length_code(&COPY_LENGTHS, command.copy.max(2));
+        block.write(bits, extra);
+        for &byte in &data[pos..pos + command.insert] {
+            literal_code.write(&mut block, usize::from(byte));
+        }
+        // The meta-block ends after the last command's literals, before
+        // a distance is read.
+        if command.copy > 0 {
+            let (code, bits, extra) = distance_code(command.distance);
+            distance_prefix_code.write(&mut block, code);
+            block.write(bits, extra);
+        }
+        pos += command.insert + command.copy;
+    }
+    block
+}
+
+fn uncompressed_meta_block(out: &mut BitWriter, data: &[u8]) {
+    // ISLAST, then MLEN, then ISUNCOMPRESSED.
+    out.write(1, 0);
+    write_meta_block_length(out, data.len());
+    out.write(1, 1);
+    out.align();
+    out.bytes.extend_from_slice(data);
+}
+
+/// Writes MNIBBLES and MLEN - 1 in as few nibbles as hold it.
+fn write_meta_block_length(out: &mut BitWriter, len: usize) {
+    let value = len - 1;
+    let nibbles = match value {
+        0..0x1_0000 => 4,
+        0x1_0000..0x10_0000 => 5,
+        _ => 6,
+    };
+    out.write(2, nibbles - 4);
+    out.write(nibbles as u32 * 4, value as u64);
+}
+
+/// The insert-and-copy code of `command`, always with an explicit distance.
+fn command_code(command: &Command) -> usize {
+    let (insert, _, _) = length_code(&INSERT_LENGTHS, command.insert);
+    let (copy, _, _) = length_code(&COPY_LENGTHS, command.copy.max(2));
+    COMMAND_OFFSETS[insert >> 3][copy >> 3] + ((insert & 7) << 3) + (copy & 7)
+}
+
+/// The code for `len` in `table`, with the number and value of its extra
+/// bits.
+fn length_code(table: &[(usize, u32); 24], len: usize) -> (usize, u32, u64) {
+    let code = table.partition_point(|&(first, _)| first <= len) - 1;
+    let (first, bits) = table[code];
+    (code, bits, (len - first) as u64)
+}
+
+/// The distance code for `distance`, with the number and value of its
+/// extra bits (RFC 7932, section 4, with NPOSTFIX and NDIRECT of 0).
+fn distance_code(distance: usize) -> (usize, u32, u64) {
+    let value = distance + 3;
+    let bucket = value.ilog2() - 1;
+    let prefix = (value >> bucket) & 1;
+    let offset = (2 + prefix) << bucket;
+    (
+        16 + 2 * (bucket as usize - 1) + prefix,
+        bucket,
+        (value - offset) as u64,
+    )
+}
+
+/// A canonical prefix code over an alphabet, built from symbol counts.
+struct PrefixCode {
+    lengths: Vec<u8>,
+    /// Each symbol's code, bit-reversed to be written least significant
+    /// bit first.
+    codes: Vec<u16>,
+    /// The symbol of a code with fewer than two, which takes no bits.
+    single: Option<usize>,
+}
+
+impl PrefixCode {
+    fn new(counts: &[u32], max_length: u8) -> PrefixCode {
+        let used = counts.iter().filter(|&&count| count > 0).count();
+        let single = (used < 2).then(|| counts.iter().position(|&count| count > 0).unwrap_or(0));
+        let lengths = if single.is_some() {
+            vec![0; counts.len()]
+        } else {
+            code_lengths(counts, max_length)
+        };
+
+        let mut per_length = [0u16; 16];
+        for &len in lengths.iter().filter(|&&len| len > 0) {
+            per_length[usize::from(len)] += 1;
+        }
+        let mut next = [0u16; 16];
+        let mut code = 0;
+        for len in 1..16 {
+            code = (code + per_length[len - 1]) << 1;
+            next[len] = code;
+        }
+        let codes = lengths
+            .iter()
+            .map(|&len| {
+                if len == 0 {
+                    return 0;
+                }
+                let code = next[usize::from(len)];
+                next[usize::from(len)] += 1;
+                code.reverse_bits() >> (16 - len)
+            })
+            .collect();
+
+        PrefixCode {
+            lengths,
+            codes,
+            single,
+        }
+    }
+
+    fn write(&self, out: &mut BitWriter, symbol: usize) {
+        out.write(
+            u32::from(self.lengths[symbol]),
+            u64::from(self.codes[symbol]),
+        );
+    }
+
+    /// Writes the code itself, for an alphabet whose symbols take
+    /// `alphabet_bits` bits (RFC 7932, section 3.4 and 3.5).
+    fn write_definition(&self, out: &mut BitWriter, alphabet_bits: u32) {
+        if let Some(symbol) = self.single {
+            // HSKIP of 1 marks a simple code, here of a single symbol.
+            out.write(2, 1);
+            out.write(2, 0);
+            out.write(alphabet_bits, symbol as u64);
+            return;
+        }
+
+        let tokens = length_tokens(&self.lengths);
+        let mut counts = [0; 18];
+        for &(symbol, _) in &tokens {
+            counts[symbol] += 1;
+        }
+        let length_code = PrefixCode::new(&counts, 5);
+        // HSKIP of 0: no code length code lengths are skipped.
+        out.write(2, 0);
+        match length_code.single {
+            // The decoder reads all eighteen when only one is set.
+            Some(symbol) => {
+                for position in CODE_LENGTH_ORDER {
+                    let (bits, len) = CODE_LENGTH_CODES[usize::from(position == symbol)];
+                    out.write(len, bits);
+                }
+            }
+            // Otherwise it stops once the lengths make a complete code.
+            None => {
+                let last = CODE_LENGTH_ORDER
+                    .iter()
+                    .rposition(|&symbol| length_code.lengths[symbol] > 0)
+                    .unwrap_or(0);
+                for &symbol in &CODE_LENGTH_ORDER[..=last] {
+                    let (bits, len) = CODE_LENGTH_CODES[usize::from(length_code.lengths[symbol])];
+                    out.write(len, bits);
+                }
+            }
+        }
+        for (symbol, extra) in tokens {
+            length_code.write(out, symbol);
+            if symbol == REPEAT_ZERO {
+                out.write(3, extra);
+            }
+        }
+    }
+}
+
+/// Code lengths for `counts` no longer than `max_length`, from a Huffman
+/// tree over counts raised to a floor that doubles until the tree fits.
+fn code_lengths(counts: &[u32], max_length: u8) -> Vec<u8> {
+    let mut floor = 1;
+    loop {
+        let mut leaves: Vec<(u64, usize)> = counts
+            .iter()
+            .enumerate()
+            .filter(|&(_, &count)| count > 0)
+            .map(|(symbol, &count)| (u64::from(count.max(floor)), symbol))
+            .collect();
+        leaves.sort_unstable();
+
+        // Leaves come first, then the internal nodes in the order they are
+        // made, which is also by weight.
+        let count = leaves.len();
+        let mut weights: Vec<u64> = leaves.iter().map(|&(weight, _)| weight).collect();
+        let mut parents = vec![0; 2 * count - 1];
+        let (mut next_leaf, mut next_node) = (0, count);
+        for node in count..2 * count - 1 {
+            let mut lightest = || {
+                let leaf_first = next_leaf < count
+                    && (next_node == node || weights[next_leaf] <= weights[next_node]);
+                if leaf_first {
+                    next_leaf += 1;
+                    next_leaf - 1
+                } else {
+                    next_node += 1;
+                    next_node - 1
+                }
+            };
+            let (a, b) = (lightest(), lightest());
+            weights.push(weights[a] + weights[b]);
+            parents[a] = node;
+            parents[b] = node;
+        }
+        let mut depths = vec![0u8; 2 * count - 1];
+        for node in (0..2 * count - 2).rev() {
+            depths[node] = depths[parents[node]] + 1;
+        }
+
+        if depths[..count].iter().all(|&depth| depth <= max_length) {
+            let mut lengths = vec![0; counts.len()];
+            for (&(_, symbol), &depth) in leaves.iter().zip(&depths) {
+                lengths[symbol] = depth;
+            }
+            return lengths;
+        }
+        floor *= 2;
+    }
+}
+
+/// The code length symbols for `lengths` up to the last one set, with
+/// the extra bits of each, runs of zeros shortened to repeat codes.
+fn length_tokens(lengths: &[u8]) -> Vec<(usize, u64)> {
+    let last = lengths.iter().rposition(|&len| len > 0).unwrap_or(0);
+    let mut tokens = Vec::new();
+    let mut symbol = 0;
+    while symbol <= last {
+        if lengths[symbol] > 0 {
+            tokens.push((usize::from(lengths[symbol]), 0));
+            symbol += 1;
+            continue;
+        }
+        let zeros = lengths[symbol..=last]
+            .iter()
+            .take_while(|&&len| len == 0)
+            .count();
+        push_zeros(&mut tokens, zeros);
+        symbol += zeros;
+    }
+    tokens
+}
+
+/// Pushes a run of `zeros` zero lengths. Consecutive repeat codes multiply,
+/// each one after the first holding another three bits of the count.
+fn push_zeros(tokens: &mut Vec<(usize, u64)>, mut zeros: usize) {
+    if zeros < 3 {
+        tokens.extend((0..zeros).map(|_| (0, 0)));
+        return;
+    }
+    let first = tokens.len();
+    zeros -= 3;
+    loop {
+        tokens.push((REPEAT_ZERO, (zeros & 7) as u64));
+        zeros >>= 3;
+        if zeros == 0 {
+            break;
+        }
+        zeros -= 1;
+    }
+    tokens[first..].reverse();
+}
+
+/// Packs bits least significant first, as Brotli reads them.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    pending: u64,
+    pending_bits: u32,
+}
+
+impl BitWriter {
+    /// Writes the low `bits` bits of `value`; at most 32 at a time.
+    fn write(&mut self, bits: u32, value: u64) {
+        self.pending |= value << self.pending_bits;
+        self.pending_bits += bits;
+        while self.pending_bits >= 8 {
+            self.bytes.push(self.pending as u8);
+            self.pending >>= 8;
+            self.pending_bits -= 8;
+        }
+    }
+
+    /// Pads with zero bits to the next byte.
+    fn align(&mut self) {
+        if self.pending_bits > 0 {
+            self.write(8 - self.pending_bits, 0);
+        }
+    }
+
+    fn len_bits(&self) -> usize {
+        self.bytes.len() * 8 + self.pending_bits as usize
+    }
+
+    fn append(&mut self, other: &BitWriter) {
+        for &byte in &other.bytes {
+            self.write(8, u64::from(byte));
+        }
+        self.write(other.pending_bits, other.pending);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.align();
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A Brotli decoder written from RFC 7932 rather than from the encoder,
+    /// so a round trip checks the format and not just that the two agree.
+    /// It is strict where the format is: incomplete prefix codes, nonzero
+    /// padding, copies past the window and trailing bytes are errors. What
+    /// the encoder never sends (block switches, context maps, metadata,
+    /// the static dictionary) is refused as unsupported.
+    mod decoder {
+        /// A decoded stream, with what it took to decode.
+        #[derive(Debug, Default)]
+        pub struct Decoded {
+            pub data: Vec<u8>,
+            pub meta_blocks: usize,
+            pub uncompressed_meta_blocks: usize,
+            pub longest_distance: usize,
+        }
+
+        pub fn decompress(input: &[u8]) -> Result<Decoded, String> {
+            let mut reader = BitReader { input, pos: 0 };
+            let window = (1 << window_bits(&mut reader)?) - 16;
+            let inserts = lengths(
+                0,
+                &[
+                    0, 0, 0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 7, 8, 9, 10, 12, 14, 24,
+                ],
+            );
+            let copies = lengths(
+                2,
+                &[
+                    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 7, 8, 9, 10, 24,
+                ],
+            );
+            // The most recent distance first.
+            let mut last = [4, 11, 15, 16];
+            let mut decoded = Decoded::default();
+            loop {
+                let is_last = reader.bit()? == 1;
+                if is_last && reader.bit()? == 1 {
+                    break;
+                }
+                let nibbles = match reader.bits(2)? {
+                    3 => return Err("metadata meta-blocks are unsupported".to_string()),
+                    nibbles => nibbles as u32 + 4,
+                };
+                let len = reader.bits(nibbles * 4)?;
+                if nibbles > 4 && len >> ((nibbles - 1) * 4) == 0 {
+                    return Err("MLEN has a leading zero nibble".to_string());
+                }
+                let end = decoded.data.len() + len as usize + 1;
+                decoded.meta_blocks += 1;
+
+                if !is_last && reader.bit()? == 1 {
+                    reader.align()?;
+                    let start = reader.pos / 8;
+                    let bytes = input
+                        .get(start..start + end - decoded.data.len())
+                        .ok_or("uncompressed meta-block ends early")?;
+                    decoded.data.extend_from_slice(bytes);
+                    reader.pos += bytes.len() * 8;
+                    decoded.uncompressed_meta_blocks += 1;
+                    continue;
+                }
+
+                for _ in 0..3 {
+                    if var_len_uint8(&mut reader)? != 1 {
+                        return Err("block switching is unsupported".to_string());
+                    }
+                }
+                let postfix = reader.bits(2)? as usize;
+                let direct = (reader.bits(4)? as usize) << postfix;
+                // The context mode of the one literal block type.
+                reader.bits(2)?;
+                if var_len_uint8(&mut reader)? != 1 || var_len_uint8(&mut reader)? != 1 {
+                    return Err("context maps are unsupported".to_string());
+                }
+                let literals = PrefixCode::read(&mut reader, 256)?;
+                let commands = PrefixCode::read(&mut reader, 704)?;
+                let distances = PrefixCode::read(&mut reader, 16 + direct + (48 << postfix))?;
+
+                loop {
+                    let command = commands.symbol(&mut reader)?;
+                    let (insert_base, copy_base, implicit) = match command >> 6 {
+                        0 => (0, 0, true),
+                        1 => (0, 8, true),
+                        2 => (0, 0, false),
+                        3 => (0, 8, false),
+                        4 => (8, 0, false),
+                        5 => (8, 8, false),
+                        6 => (0, 16, false),
+                        7 => (16, 0, false),
+                        8 => (8, 16, false),
+                        9 => (16, 8, false),
+                        _ => (16, 16, false),
+                    };
+                    let (base, bits) = inserts[insert_base + ((command >> 3) & 7)];
+                    let insert = base + reader.bits(bits)? as usize;
+                    let (base, bits) = copies[copy_base + (command & 7)];
+                    let copy = base + reader.bits(bits)? as usize;
+
+                    if decoded.data.len() + insert > end {
+                        return Err("literals run past MLEN".to_string());
+                    }
+                    for _ in 0..insert {
+                        decoded.data.push(literals.symbol(&mut reader)? as u8);
+                    }
+                    // The copy of the command that completes a meta-block
+                    // is ignored.
+                    if decoded.data.len() == end {
+                        break;
+                    }
+
+                    let code = if implicit {
+                        0
+                    } else {
+                        distances.symbol(&mut reader)?
+                    };
+                    let distance = match code {
+                        0..16 => {
+                            let (earlier, delta) = match code {
+                                0..4 => (last[code], 0),
+                                4..10 => (last[0], [-1, 1, -2, 2, -3, 3][code - 4]),
+                                _ => (last[1], [-1, 1, -2, 2, -3, 3][code - 10]),
+                            };
+                            usize::try_from(earlier as i64 + delta)
+                                .ok()
+                                .filter(|&distance| distance > 0)
+                                .ok_or("distance cache gives a distance below 1")?
+                        }
+                        _ if code < 16 + direct => code - 15,
+                        _ => {
+                            let code = code - direct - 16;
+                            let extra_bits = 1 + (code >> (postfix + 1));
+                            let offset = ((2 + ((code >> postfix) & 1)) << extra_bits) - 4;
+                            let extra = reader.bits(extra_bits as u32)? as usize;
+                            ((offset + extra) << postfix)
+                                + (code & ((1 << postfix) - 1))
+                                + direct
+                                + 1
+                        }
+                    };
+                    if distance > window.min(decoded.data.len()) {
+                        return Err(format!(
+                            "distance {} reaches past the window or into the static dictionary",
+                            distance
+                        ));
+                    }
+                    if code != 0 {
+                        last = [distance, last[0], last[1], last[2]];
+                    }
+                    if decoded.data.len() + copy > end {
+                        return Err("copy runs past MLEN".to_string());
+                    }
+                    for _ in 0..copy {
+                        decoded
+                            .data
+                            .push(decoded.data[decoded.data.len() - distance]);
+                    }
+                    decoded.longest_distance = decoded.longest_distance.max(distance);
+                    if decoded.data.len() == end {
+                        break;
+                    }
+                }
+                if is_last {
+                    break;
+                }
+            }
+
+            reader.align()?;
+            if reader.pos / 8 != input.len() {
+                return Err("bytes after the last meta-block".to_string());
+            }
+            Ok(decoded)
+        }
+
+        /// The first length and extra bits of each length code, from the
+        /// extra bits alone: each code starts where the one before ends.
+        fn lengths(first: usize, extra_bits: &[u32; 24]) -> Vec<(usize, u32)> {
+            let mut base = first;
+            extra_bits
+                .iter()
+                .map(|&bits| {
+                    base += 1 << bits;
+                    (base - (1 << bits), bits)
+                })
+                .collect()
+        }
+
+        fn window_bits(reader: &mut BitReader) -> Result<u32, String> {
+            if reader.bit()? == 0 {
+                return Ok(16);
+            }
+            match reader.bits(3)? {
+                0 => match reader.bits(3)? {
+                    0 => Ok(17),
+                    1 => Err("large windows are unsupported".to_string()),
+                    bits => Ok(8 + bits as u32),
+                },
+                bits => Ok(17 + bits as u32),
+            }
+        }
+
+        fn var_len_uint8(reader: &mut BitReader) -> Result<u64, String> {
+            if reader.bit()? == 0 {
+                return Ok(1);
+            }
+            match reader.bits(3)? {
+                0 => Ok(2),
+                bits => Ok((1 << bits) + reader.bits(bits as u32)? + 1),
+            }
+        }
+
+        struct BitReader<'a> {
+            input: &'a [u8],
+            /// In bits.
+            pos: usize,
+        }
+
+        impl BitReader<'_> {
+            fn bit(&mut self) -> Result<u32, String> {
+                let byte = self.input.get(self.pos / 8).ok_or("stream ends early")?;
+                let bit = (byte >> (self.pos % 8)) & 1;
+                self.pos += 1;
+                Ok(u32::from(bit))
+            }
+
+            fn bits(&mut self, count: u32) -> Result<u64, String> {
+                let mut value = 0;
+                for bit in 0..count {
+                    value |= u64::from(self.bit()?) << bit;
+                }
+                Ok(value)
+            }
+
+            fn align(&mut self) -> Result<(), String> {
+                while !self.pos.is_multiple_of(8) {
+                    if self.bit()? != 0 {
+                        return Err("padding bits are not zero".to_string());
+                    }
+                }
+                Ok(())
+            }
+        }
+
+        /// A canonical prefix code, read one bit at a time from its first,
+        /// most significant, bit.
+        struct PrefixCode {
+            /// How many codes have each length.
+            per_length: [usize; 16],
+            /// Symbols by code length, then by value.
+            symbols: Vec<usize>,
+        }
+
+        impl PrefixCode {
+            fn from_lengths(lengths: &[u8]) -> PrefixCode {
+                let mut per_length = [0; 16];
+                let mut symbols: Vec<usize> = (0..lengths.len())
+                    .filter(|&symbol| lengths[symbol] > 0)
+                    .collect();
+                symbols.sort_by_key(|&symbol| lengths[symbol]);
+                // A lone symbol takes no bits.
+                if symbols.len() > 1 {
+                    for &symbol in &symbols {
+                        per_length[usize::from(lengths[symbol])] += 1;
+                    }
+                }
+                PrefixCode {
+                    per_length,
+                    symbols,
+                }
+            }
+
+            fn symbol(&self, reader: &mut BitReader) -> Result<usize, String> {
+                if self.symbols.len() == 1 {
+                    return Ok(self.symbols[0]);
+                }
+                let (mut code, mut first, mut index) = (0, 0, 0);
+                for &count in &self.per_length[1..] {
+                    code |= reader.bit()? as usize;
+                    if code < first + count {
+                        return Ok(self.symbols[index + code - first]);
+                    }
+                    index += count;
+                    first = (first + count) << 1;
+                    code <<= 1;
+                }
+                Err("bits match no code".to_string())
+            }
+
+            /// Reads a simple or complex prefix code over `alphabet` symbols
+            /// (RFC 7932, sections 3.4 and 3.5).
+            fn read(reader: &mut BitReader, alphabet: usize) -> Result<PrefixCode, String> {
+                let skip = reader.bits(2)? as usize;
+                let mut lengths = vec![0; alphabet];
+                if skip == 1 {
+                    let alphabet_bits = usize::BITS - (alphabet - 1).leading_zeros();
+                    let count = reader.bits(2)? as usize + 1;
+                    let mut symbols = Vec::new();
+                    for _ in 0..count {
+                        let symbol = reader.bits(alphabet_bits)? as usize;
+                        if symbol >= alphabet || symbols.contains(&symbol) {
+                            return Err(format!(
+                                "simple code symbol {} repeated or out of range",
+                                symbol
+                            ));
+                        }
+                        symbols.push(symbol);
+                    }
+                    let code_lengths: &[u8] = match count {
+                        1 => &[1],
+                        2 => &[1, 1],
+                        3 => &[1, 2, 2],
+                        _ if reader.bit()? == 0 => &[2, 2, 2, 2],
+                        _ => &[1, 2, 3, 3],
+                    };
+                    for (&symbol, &len) in symbols.iter().zip(code_lengths) {
+                        lengths[symbol] = len;
+                    }
+                    return Ok(PrefixCode::from_lengths(&lengths));
+                }
+
+                const ORDER: [usize; 18] =
+                    [1, 2, 3, 4, 0, 5, 17, 6, 16, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+                let mut length_lengths = [0; 18];
+                let (mut space, mut set) = (32, 0);
+                for &symbol in &ORDER[skip..] {
+                    // 00, 0111, 011, 10, 01 and 1111, read from the left.
+                    let len = match reader.bits(2)? {
+                        0 => 0,
+                        1 => 4,
+                        2 => 3,
+                        _ if reader.bit()? == 0 => 2,
+                        _ if reader.bit()? == 0 => 1,
+                        _ => 5,
+                    };
+                    length_lengths[symbol] = len;
+                    if len > 0 {
+                        space -= 32 >> len;
+                        set += 1;
+                        if space <= 0 {
+                            break;
+                        }
+                    }
+                }
+                if set != 1 && space != 0 {
+                    return Err("incomplete code length code".to_string());
+                }
+                let length_code = PrefixCode::from_lengths(&length_lengths);
+
+                let (mut symbol, mut space) = (0, 32768);
+                let mut previous = 8;
+                let mut repeat: Option<(usize, usize)> = None;
+                while symbol < alphabet && space > 0 {
+                    match length_code.symbol(reader)? {
+                        len @ 0..16 => {
+                            lengths[symbol] = len as u8;
+                            symbol += 1;
+                            if len > 0 {
+                                previous = len as u8;
+                                space -= 32768 >> len;
+                            }
+                            repeat = None;
+                        }
+                        code => {
+                            let extra_bits = if code == 16 { 2 } else { 3 };
+                            let extra = reader.bits(extra_bits)? as usize;
+                            let (before, count) = match repeat {
+                                Some((last, count)) if last == code => {
+                                    (count, ((count - 2) << extra_bits) + extra + 3)
+                                }
+                                _ => (0, extra + 3),
+                            };
+                            if symbol + count - before > alphabet {
+                                return Err("repeat runs past the alphabet".to_string());
+                            }
+                            let len = if code == 16 { previous } else { 0 };
+                            for _ in before..count {
+                                lengths[symbol] = len;
+                                symbol += 1;
+                                if len > 0 {
+                                    space -= 32768 >> len;
+                                }
+                            }
+                            repeat = Some((code, count));
+                        }
+                    }
+                }
+                if space != 0 {
+                    return Err("incomplete or oversubscribed prefix code".to_string());
+                }
+                Ok(PrefixCode::from_lengths(&lengths))
+            }
+        }
+    }
+
+    /// `compress(data)`, decoded again.
+    fn round_trip(data: &[u8]) -> decoder::Decoded {
+        let decoded = decoder::decompress(&compress(data)).unwrap();
+        assert!(decoded.data == data, "the stream decodes to other bytes");
+        decoded
+    }
+
+    /// Bytes in which no four in a row occur twice, so nothing in them
+    /// can be copied: the output of a maximal-length 32-bit LFSR, whose
+    /// 32-bit windows are all distinct.
+    fn unique_bytes(len: usize) -> Vec<u8> {
+        let mut state = 1u32;
+        (0..len)
+            .map(|_| {
+                let mut byte = 0;
+                for bit in 0..8 {
+                    let out = state & 1;
+                    state = (state >> 1) ^ (0u32.wrapping_sub(out) & 0x8020_0003);
+                    byte |= (out as u8) << bit;
+                }
+                byte
+            })
+            .collect()
+    }
+
+    /// Deterministic bytes from `alphabet`.
+    fn noise(len: usize, alphabet: &[u8], seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                alphabet[(state % alphabet.len() as u64) as usize]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn compress_should_end_an_empty_stream_at_once() {
+        // WBITS of 18, then an empty last meta-block.
+        assert_eq!(compress(b""), [0x33]);
+    }
+
+    #[test]
+    fn compress_should_code_repeats_and_store_what_does_not_shrink() {
+        let text = "<li>Harbor serves static files.</li>\n".repeat(200);
+        let compressed = compress(text.as_bytes());
+        assert!(compressed.len() < text.len() / 20, "{}", compressed.len());
+        assert_eq!(compress(b"x")[..], [0x03, 0x00, 0x80, b'x', 0x03][..]);
+
+        let noise: Vec<u8> = (0u32..4096)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect();
+        assert!(compress(&noise).len() <= noise.len() + 8);
+    }
+
+    #[test]
+    fn push_zeros_should_nest_repeat_codes_for_long_runs() {
+        let mut tokens = Vec::new();
+        push_zeros(&mut tokens, 2);
+        push_zeros(&mut tokens, 10);
+        assert_eq!(tokens, [(0, 0), (0, 0), (17, 7)]);
+
+        // The decoder counts 3 + 1 zeros, then raises that to
+        // (4 - 2) * 8 + 3 + 0.
+        tokens.clear();
+        push_zeros(&mut tokens, 19);
+        assert_eq!(tokens, [(17, 1), (17, 0)]);
+    }
+
+    #[test]
+    fn compress_should_round_trip_through_an_independent_decoder() {
+        let every_byte: Vec<u8> = (0..=255).collect();
+        for data in [
+            &b""[..],
+            b"x",
+            b"abababababababab",
+            &every_byte,
+            "<li>Harbor serves static files.</li>\n"
+                .repeat(200)
+                .as_bytes(),
+            &b"a".repeat(100_000),
+            &noise(4096, &every_byte, 1),
+            &noise(50_000, b"abcdefgh", 2),
+        ] {
+            round_trip(data);
+        }
+    }
+
+    #[test]
+    fn compress_should_round_trip_inputs_over_several_meta_blocks() {
+        // Incompressible, then compressible, so stored and coded meta-blocks
+        // follow each other, and matches reach back across them.
+        let text = "<p>Every job is tracked,   from queued to completed.</p>\n";
+        let mut data = unique_bytes(META_BLOCK_BYTES);
+        data.extend_from_slice(text.repeat(4000).as_bytes());
+        data.extend_from_slice(&noise(META_BLOCK_BYTES / 2, b"0123456789abcdef", 4));
+        data.extend_from_slice(text.repeat(4000).as_bytes());
+
+        let decoded = round_trip(&data);
+        assert_eq!(decoded.meta_blocks, data.len().div_ceil(META_BLOCK_BYTES));
+        assert_eq!(decoded.uncompressed_meta_blocks, 1);
+    }
+
+    #[test]
+    fn compress_should_copy_from_as_far_back_as_the_window_allows() {
+        // A marker no other bytes can match, repeated exactly the longest
+        // distance later, and once just past it.
+        let marker = noise(32, b"ABCDEFGHIJKLMNOP", 5);
+        let filler = noise(MAX_DISTANCE - marker.len(), b"abcdefghijklmnop", 6);
+        let at_limit = [&marker[..], &filler, &marker].concat();
+        assert_eq!(round_trip(&at_limit).longest_distance, MAX_DISTANCE);
+
+        let past_limit = [&marker[..], &filler, b"z", &marker].concat();
+        assert!(round_trip(&past_limit).longest_distance < MAX_DISTANCE);
+    }
+
+    #[test]
+    fn decoder_should_refuse_damaged_streams() {
+        // The reference encoder's empty stream, with a 64 KiB window.
+        assert!(decoder::decompress(&[0x06]).unwrap().data.is_empty());
+
+        let compressed = compress(
+            "<li>Harbor serves static files.</li>\n"
+                .repeat(50)
+                .as_bytes(),
+        );
+        assert!(decoder::decompress(&compressed[..compressed.len() - 1]).is_err());
+        assert!(decoder::decompress(&[&compressed[..], &[0]].concat()).is_err());
+        let damaged = (0..compressed.len() * 8).filter(|&bit| {
+            let mut damaged = compressed.clone();
+            damaged[bit / 8] ^= 1 << (bit % 8);
+            decoder::decompress(&damaged).is_err()
+        });
+        // Most flipped bits break the stream outright; the rest change
+        // what it decodes to.
+        assert!(damaged.count() > compressed.len() * 4);
+    }
+}
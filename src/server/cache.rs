@@ -0,0 +1,204 @@
+//! An in-memory LRU cache of file contents.
+
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::SystemTime,
+};
+
+/// Caches file contents up to a total size in bytes, evicting the least
+/// recently used files first.
+///
+/// Entries are revalidated against the file's modification time and length
+/// on every lookup, so edited files are picked up immediately.
+#[derive(Debug)]
+pub struct FileCache {
+    max_bytes: usize,
+    inner: Mutex<Inner>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    entries: HashMap<PathBuf, Entry>,
+    bytes: usize,
+    clock: u64,
+}
+
+#[derive(Debug)]
+struct Entry {
+    contents: Arc<Vec<u8>>,
+    modified: Option<SystemTime>,
+    last_used: u64,
+}
+
+/// A snapshot of a `FileCache`'s counters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+    pub bytes: usize,
+}
+
+impl CacheStats {
+    /// The fraction of lookups served from memory, between 0 and 1.
+    pub fn hit_rate(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            0.0
+        } else {
+            self.hits as f64 / total as f64
+        }
+    }
+}
+
+impl FileCache {
+    /// Creates a cache holding at most `max_bytes` of file contents.
+    /// A size of zero disables caching.
+    pub fn new(max_bytes: usize) -> FileCache {
+        FileCache {
+            max_bytes,
+            inner: Mutex::new(Inner::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the contents of `path`, from memory when the cached copy is
+    /// still current.
+    pub fn read(&self, path: &Path) -> io::Result<Arc<Vec<u8>>> {
+        let metadata = fs::metadata(path)?;
+        let modified = metadata.modified().ok();
+
+        {
+            let mut inner = self.inner.lock().unwrap();
+            inner.clock += 1;
+            let clock = inner.clock;
+            if let Some(entry) = inner.entries.get_mut(path)
+                && entry.modified == modified
+                && modified.is_some()
+                && entry.contents.len() as u64 == metadata.len()
+            {
+                entry.last_used = clock;
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(Arc::clone(&entry.contents));
+            }
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let contents = Arc::new(fs::read(path)?);
+        self.insert(path, Arc::clone(&contents), modified);
+        Ok(contents)
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let inner = self.inner.lock().unwrap();
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: inner.entries.len(),
+            bytes: inner.bytes,
+        }
+    }
+
+    fn insert(&self, path: &Path, contents: Arc<Vec<u8>>, modified: Option<SystemTime>) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(old) = inner.entries.remove(path) {
+            inner.bytes -= old.contents.len();
+        }
+        if contents.len() > self.max_bytes {
+            return;
+        }
+
+        while inner.bytes + contents.len() > self.max_bytes {
+            let oldest = inner
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(path, _)| path.clone());
+            match oldest.and_then(|path| inner.entries.remove(&path)) {
+                Some(evicted) => inner.bytes -= evicted.contents.len(),
+                None => break,
+            }
+        }
+
+        inner.bytes += contents.len();
+        let last_used = inner.clock;
+        inner.entries.insert(
+            path.to_path_buf(),
+            Entry {
+                contents,
+                modified,
+                last_used,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn fixture(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("harbor-cache-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn read_should_hit_until_the_file_changes() {
+        let dir = fixture("hits");
+        let file = dir.join("page.html");
+        fs::write(&file, "one").unwrap();
+        let cache = FileCache::new(1024);
+
+        assert_eq!(*cache.read(&file).unwrap(), b"one");
+        assert_eq!(*cache.read(&file).unwrap(), b"one");
+
+        let later = SystemTime::now() + Duration::from_secs(5);
+        fs::write(&file, "two!").unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&file)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+
+        assert_eq!(*cache.read(&file).unwrap(), b"two!");
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (1, 2));
+        assert_eq!(stats.hit_rate(), 1.0 / 3.0);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn read_should_evict_least_recently_used_files() {
+        let dir = fixture("evict");
+        for name in ["a", "b", "c"] {
+            fs::write(dir.join(name), "12345").unwrap();
+        }
+        let cache = FileCache::new(10);
+
+        cache.read(&dir.join("a")).unwrap();
+        cache.read(&dir.join("b")).unwrap();
+        cache.read(&dir.join("a")).unwrap();
+        cache.read(&dir.join("c")).unwrap();
+        cache.read(&dir.join("a")).unwrap();
+        cache.read(&dir.join("b")).unwrap();
+
+        let stats = cache.stats();
+        assert_eq!(stats.bytes, 10);
+        assert_eq!((stats.hits, stats.misses), (2, 4));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
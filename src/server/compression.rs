@@ -0,0 +1,168 @@
+//! Compressing response bodies for clients that accept it.
+
+use super::{
+    brotli, encoding,
+    middleware::{Middleware, Next},
+    request::HttpRequest,
+    response::Response,
+};
+
+/// Bodies smaller than this are sent as they are by default, as framing
+/// and headers outweigh what compression saves.
+pub const MIN_SIZE: usize = 256;
+
+/// Middleware compressing text responses with Brotli (`br`) for clients
+/// whose `Accept-Encoding` allows it, adding `Vary: Accept-Encoding` so
+/// caches keep the variants apart.
+///
+/// Responses already carrying a `Content-Encoding`, such as pre-compressed
+/// static files, streamed file bodies and media types that are compressed
+/// already are left alone, as is a body the coding doesn't shrink.
+///
+/// # Example
+///
+/// ```
+/// # use harbor::server::{Compression, Server};
+/// let server = Server::new("127.0.0.1:7878").middleware(Compression::new().min_size(1024));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Compression {
+    min_size: usize,
+}
+
+impl Compression {
+    pub fn new() -> Compression {
+        Compression::default()
+    }
+
+    /// Leaves bodies smaller than `bytes` uncompressed, in place of
+    /// `MIN_SIZE`.
+    pub fn min_size(mut self, bytes: usize) -> Compression {
+        self.min_size = bytes;
+        self
+    }
+}
+
+impl Default for Compression {
+    fn default() -> Compression {
+        Compression { min_size: MIN_SIZE }
+    }
+}
+
+impl Middleware for Compression {
+    fn handle(&self, request: &mut HttpRequest, next: Next<'_>) -> Response {
+        let response = next.run(request);
+        if !compressible(&response) {
+            return response;
+        }
+        let response = vary_on_encoding(response);
+        if response.get_body().len() < self.min_size {
+            return response;
+        }
+
+        match encoding::negotiate(request.header("Accept-Encoding"), encoding::SUPPORTED) {
+            Some("br") => {
+                let compressed = brotli::compress(response.get_body());
+                if compressed.len() < response.get_body().len() {
+                    response.header("Content-Encoding", "br").body(compressed)
+                } else {
+                    response
+                }
+            }
+            _ => response,
+        }
+    }
+}
+
+/// Whether `response` has a body held in memory, not yet encoded, of a
+/// media type that compresses well.
+fn compressible(response: &Response) -> bool {
+    if matches!(response.status(), 100..=199 | 204 | 206 | 304)
+        || response.get_header("Content-Encoding").is_some()
+        || response.get_body().len() as u64 != response.body_len()
+    {
+        return false;
+    }
+    let Some(content_type) = response.get_header("Content-Type") else {
+        return false;
+    };
+    let media_type = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    media_type.starts_with("text/")
+        || media_type.ends_with("+json")
+        || media_type.ends_with("+xml")
+        || matches!(
+            media_type.as_str(),
+            "application/json"
+                | "application/javascript"
+                | "application/xml"
+                | "application/wasm"
+                | "image/svg+xml"
+        )
+}
+
+/// Adds `Accept-Encoding` to the response's `Vary` header, unless it is
+/// there already. A second `Vary` line adds to the first.
+fn vary_on_encoding(response: Response) -> Response {
+    let varies = response.get_header("Vary").is_some_and(|vary| {
+        vary.split(',').any(|name| {
+            let name = name.trim();
+            name == "*" || name.eq_ignore_ascii_case("Accept-Encoding")
+        })
+    });
+    if varies {
+        response
+    } else {
+        response.header("Vary", "Accept-Encoding")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::middleware::MiddlewareStack;
+
+    fn run(accept_encoding: &str, content_type: &str, body: &str) -> Response {
+        let mut stack = MiddlewareStack::new();
+        stack.push(Compression::new());
+        let head = format!(
+            "GET / HTTP/1.1\r\nAccept-Encoding: {}\r\n\r\n",
+            accept_encoding
+        );
+        let mut request = HttpRequest::parse(&mut head.as_bytes()).unwrap().unwrap();
+        stack.run(&mut request, &|_| {
+            Response::new(200)
+                .header("Content-Type", content_type)
+                .body(body)
+        })
+    }
+
+    #[test]
+    fn compression_should_encode_text_for_clients_accepting_brotli() {
+        let page = "<p>Hello, harbor!</p>\n".repeat(100);
+
+        let response = run("gzip, br", "text/html; charset=utf-8", &page);
+        assert_eq!(response.get_header("Content-Encoding"), Some("br"));
+        assert_eq!(response.get_header("Vary"), Some("Accept-Encoding"));
+        assert!(response.get_body().len() < page.len() / 10);
+
+        let response = run("gzip", "text/html; charset=utf-8", &page);
+        assert_eq!(response.get_header("Content-Encoding"), None);
+        assert_eq!(response.get_header("Vary"), Some("Accept-Encoding"));
+        assert_eq!(response.get_body(), page.as_bytes());
+    }
+
+    #[test]
+    fn compression_should_skip_small_and_binary_bodies() {
+        let response = run("br", "text/html", "<p>short</p>");
+        assert_eq!(response.get_header("Content-Encoding"), None);
+
+        let response = run("br", "image/png", &"\0".repeat(4096));
+        assert_eq!(response.get_header("Content-Encoding"), None);
+        assert_eq!(response.get_header("Vary"), None);
+    }
+}
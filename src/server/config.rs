@@ -0,0 +1,1282 @@
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use super::{
+    access_log::{AccessLogFormat, Rotation},
+    forwarded::Cidr,
+    response::FlushPolicy,
+    rewrite::Rewrite,
+    static_files::{CacheRule, TrailingSlash},
+};
+pub use crate::log::Level as LogLevel;
+use crate::{
+    json,
+    registry::JobDescriptor,
+    toml::{self, Table, Value},
+};
+
+/// The methods `ServerConfig::allowed_methods` holds by default: the
+/// standard ones but `TRACE`, which reflects requests back, and `CONNECT`,
+/// which only proxies serve.
+pub const DEFAULT_ALLOWED_METHODS: [&str; 7] =
+    ["GET", "HEAD", "POST", "PUT", "DELETE", "PATCH", "OPTIONS"];
+
+/// Settings controlling how a `Server` listens and serves requests.
+///
+/// Can be built in code or loaded from a TOML file with `from_file`:
+///
+/// ```toml
+/// address = "0.0.0.0:8080"
+/// additional_addresses = ["[::1]:8080", "unix:/run/harbor.sock"]
+/// workers = 8
+/// acceptors = 2
+/// queue_wait_threshold_ms = 250
+/// max_job_result_bytes = 65536
+/// evented = false
+/// keep_alive = true
+/// flush_policy = "response"
+/// max_requests_per_connection = 100
+/// max_body_bytes = 10485760
+/// max_connections = 1024
+/// max_connections_per_ip = 16
+/// max_queued_connections = 256
+/// accept_high_water = 512
+/// accept_low_water = 128
+/// allowed_hosts = ["example.com", "*.example.com"]
+/// allowed_methods = ["GET", "HEAD", "POST"]
+/// proxy_protocol = false
+/// trusted_proxies = ["10.0.0.0/8", "::1"]
+/// https_redirect = 443
+/// document_root = "public"
+/// directory_listing = false
+/// precompressed = false
+/// trailing_slash = "add"
+/// file_cache_bytes = 8388608
+/// job_dashboard = false
+/// server_header = "harbor"
+/// watch_config = false
+/// upgrade_socket = "/run/harbor-upgrade.sock"
+///
+/// [pools]
+/// slow = 2
+///
+/// [socket]
+/// tcp_nodelay = true
+/// backlog = 1024
+/// linger_secs = 5
+///
+/// [mime_types]
+/// webmanifest = "application/manifest+json"
+///
+/// [[redirects]]
+/// from = "/old"
+/// to = "/new"
+/// permanent = true
+///
+/// [[rewrites]]
+/// from = "/blog/(.*)"
+/// to = "/posts/$1"
+///
+/// [[rewrites]]
+/// from = "/app/(.*)"
+/// to = "/app/index.html"
+/// fallback = true
+///
+/// [[jobs]]
+/// job = "warm_cache"
+/// args = { pages = 100 }
+///
+/// [[cache_control]]
+/// path = "/assets/*"
+/// value = "public, max-age=31536000, immutable"
+///
+/// [[cache_control]]
+/// path = "*.html"
+/// value = "no-cache"
+///
+/// [timeouts]
+/// read_secs = 30
+/// write_secs = 30
+/// header_secs = 10
+/// keep_alive_secs = 5
+/// handler_secs = 30
+/// grace_period_secs = 30
+///
+/// [tls]
+/// certificate = "cert.pem"
+/// private_key = "key.pem"
+///
+/// [log]
+/// level = "info"
+/// access_log = true
+/// file = "logs/access.log"
+/// format = "combined"
+/// rotation = "size"        # "never", "daily" or "size"
+/// rotate_bytes = 10485760
+/// max_files = 5
+/// ```
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// Address the listener binds to: `host:port`, or `unix:/path` for a
+    /// Unix domain socket.
+    pub address: String,
+    /// More addresses served alongside `address`, each by its own accept
+    /// loop feeding the same pool.
+    pub additional_addresses: Vec<String>,
+    /// Number of worker threads handling connections.
+    pub workers: usize,
+    /// More worker pools, by name and number of threads, for the routes
+    /// pinned to them with `Router::pool`.
+    pub pools: BTreeMap<String, usize>,
+    /// Warn when a job waits longer than this for a worker, a sign the
+    /// pools are too small. No warnings when `None`.
+    pub queue_wait_threshold: Option<Duration>,
+    /// Most bytes of a job's result the pools keep and log; longer ones
+    /// are truncated. Unlimited when `None`.
+    pub max_job_result_bytes: Option<usize>,
+    /// Serve several requests per connection, including pipelined ones,
+    /// instead of closing it after the first response.
+    pub keep_alive: bool,
+    /// When the threaded backend writes responses out.
+    pub flush_policy: FlushPolicy,
+    /// Most requests served on one connection; the last response carries
+    /// `Connection: close`. Unlimited when `None`.
+    pub max_requests_per_connection: Option<usize>,
+    /// Largest request body accepted; bigger ones get `413` before their
+    /// body is read. Unlimited when `None`.
+    pub max_body_bytes: Option<usize>,
+    /// Most connections open at once; more are closed as soon as they are
+    /// accepted. Unlimited when `None`.
+    pub max_connections: Option<usize>,
+    /// Most connections open at once from one client address; more are
+    /// answered `429` and closed. The address is the socket's peer, or the
+    /// one a PROXY protocol header carries; forwarding headers are not
+    /// counted. Unlimited when `None`.
+    pub max_connections_per_ip: Option<usize>,
+    /// Most connections allowed to wait for a free worker; more are
+    /// answered `503` at once. Unlimited when `None`.
+    pub max_queued_connections: Option<usize>,
+    /// Stop accepting connections while more jobs than this wait for a
+    /// worker, leaving new ones in the kernel's backlog. Always accepting
+    /// when `None`.
+    pub accept_high_water: Option<usize>,
+    /// Accept again once at most this many jobs wait; half the high-water
+    /// mark when `None`.
+    pub accept_low_water: Option<usize>,
+    /// Host names requests may be addressed to; a leading `*.` matches any
+    /// subdomain. Others get `421`, and HTTP/1.1 requests without a `Host`
+    /// header `400`. Any host is accepted when empty.
+    pub allowed_hosts: Vec<String>,
+    /// Request methods the server answers, `DEFAULT_ALLOWED_METHODS`
+    /// unless set. Other standard methods, such as `TRACE` and `CONNECT`,
+    /// get `405` and unknown ones `501`. Any method is accepted when empty.
+    pub allowed_methods: Vec<String>,
+    /// Require a PROXY protocol (v1 or v2) header on every connection and
+    /// take the client address from it, for servers behind a load
+    /// balancer.
+    pub proxy_protocol: bool,
+    /// Reverse proxies whose `Forwarded` and `X-Forwarded-For` headers are
+    /// believed when finding the client's address. The headers are ignored
+    /// on connections from anywhere else.
+    pub trusted_proxies: Vec<Cidr>,
+    /// Answer every request with a `301` to the same host, path and query
+    /// on `https://` at this port, instead of serving it. For the plain
+    /// HTTP listener that runs next to an HTTPS one.
+    pub https_redirect: Option<u16>,
+    /// Number of threads accepting connections on each address. With the
+    /// `reuseport` feature on Linux each gets its own `SO_REUSEPORT` socket.
+    pub acceptors: usize,
+    /// Serve connections from a single event loop and only hand requests to
+    /// the workers, so idle connections don't hold a worker. Needs the
+    /// `evented` feature on Linux.
+    pub evented: bool,
+    /// Disable Nagle's algorithm on accepted TCP connections, so small
+    /// responses aren't held back waiting for more data. On by default.
+    pub tcp_nodelay: bool,
+    /// Length of each listener's accept queue. The platform default when
+    /// `None`. Needs the `sockopt` feature on Unix.
+    pub backlog: Option<u32>,
+    /// How long closing a connection waits for unsent data (`SO_LINGER`);
+    /// zero resets it instead. The platform default when `None`. Needs
+    /// the `sockopt` feature on Unix.
+    pub linger: Option<Duration>,
+    /// Directory served as static files. Static serving is off when `None`.
+    pub document_root: Option<PathBuf>,
+    /// Render an HTML listing for directories without an `index.html`.
+    pub directory_listing: bool,
+    /// Serve `file.br` or `file.gz` in place of `file` to clients
+    /// accepting Brotli or gzip.
+    pub precompressed: bool,
+    /// Where static paths want a trailing slash; the other form is
+    /// redirected.
+    pub trailing_slash: TrailingSlash,
+    /// Content types for static file extensions, overriding the built-in
+    /// `mime` table.
+    pub mime_types: BTreeMap<String, String>,
+    /// `Cache-Control` values for static files, tried in order.
+    pub cache_control: Vec<CacheRule>,
+    /// Paths answered with a redirect instead of their content.
+    pub redirects: Vec<Redirect>,
+    /// Paths served as if the request had been for another, tried in order.
+    pub rewrites: Vec<Rewrite>,
+    /// Jobs of the server's `JobRegistry` queued on the default pool when
+    /// it starts, such as warming a cache.
+    pub jobs: Vec<JobDescriptor>,
+    /// Maximum bytes of file contents kept in memory. Zero disables caching.
+    pub file_cache_bytes: usize,
+    /// Serve a page at `/admin/jobs` listing the pool's jobs, which exposes
+    /// their results to anyone who can reach the server.
+    pub job_dashboard: bool,
+    /// The `Server` header sent with responses that don't set one. `None`,
+    /// or an empty string in the file, leaves it out.
+    pub server_header: Option<String>,
+    /// Reload the configuration file whenever it changes, for servers
+    /// created with `Server::from_config`.
+    pub watch_config: bool,
+    /// A Unix socket through which a new process started with the same
+    /// setting takes over the listening sockets, while this one drains and
+    /// exits. Needs the `upgrade` feature on Linux.
+    pub upgrade_socket: Option<PathBuf>,
+    /// Socket read timeout for accepted connections.
+    pub read_timeout: Option<Duration>,
+    /// Socket write timeout for accepted connections.
+    pub write_timeout: Option<Duration>,
+    /// Total time allowed for receiving a request head; slower clients get
+    /// `408`. Unlimited when `None`.
+    pub header_timeout: Option<Duration>,
+    /// How long a kept-alive connection may sit idle between requests
+    /// before it is closed. Unlimited when `None`.
+    pub keep_alive_timeout: Option<Duration>,
+    /// Longest a handler may run before the client is answered `504`
+    /// without it. Unlimited when `None`. `Router::stream` handlers are
+    /// never timed.
+    pub handler_timeout: Option<Duration>,
+    /// How long in-flight connections may run once shutdown begins.
+    pub grace_period: Duration,
+    /// Certificate and key for HTTPS.
+    pub tls: Option<TlsConfig>,
+    pub log: LogConfig,
+}
+
+/// A path that redirects `GET` and `HEAD` requests elsewhere.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Redirect {
+    pub from: String,
+    pub to: String,
+    /// Answer `301 Moved Permanently` instead of `302 Found`.
+    pub permanent: bool,
+}
+
+/// Paths to the PEM files used for HTTPS.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TlsConfig {
+    pub certificate: PathBuf,
+    pub private_key: PathBuf,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogConfig {
+    /// Most verbose level of server diagnostics that is printed.
+    pub level: LogLevel,
+    /// Print a line for every request served.
+    pub access_log: bool,
+    /// File that access log lines are appended to.
+    pub access_log_file: Option<PathBuf>,
+    /// With `AccessLogFormat::Json` the server's diagnostics are written
+    /// as JSON objects too, through `log::JsonLogger`.
+    pub access_log_format: AccessLogFormat,
+    pub rotation: Rotation,
+    /// Number of rotated access log files to keep.
+    pub max_files: usize,
+}
+
+impl Default for ServerConfig {
+    fn default() -> ServerConfig {
+        ServerConfig {
+            address: "127.0.0.1:7878".to_string(),
+            additional_addresses: Vec::new(),
+            workers: 4,
+            pools: BTreeMap::new(),
+            queue_wait_threshold: None,
+            max_job_result_bytes: None,
+            acceptors: 1,
+            evented: false,
+            tcp_nodelay: true,
+            backlog: None,
+            linger: None,
+            keep_alive: false,
+            flush_policy: FlushPolicy::Response,
+            max_requests_per_connection: None,
+            max_body_bytes: None,
+            max_connections: None,
+            max_connections_per_ip: None,
+            max_queued_connections: None,
+            accept_high_water: None,
+            accept_low_water: None,
+            allowed_hosts: Vec::new(),
+            allowed_methods: DEFAULT_ALLOWED_METHODS
+                .iter()
+                .map(|method| method.to_string())
+                .collect(),
+            proxy_protocol: false,
+            trusted_proxies: Vec::new(),
+            https_redirect: None,
+            document_root: None,
+            directory_listing: false,
+            precompressed: false,
+            trailing_slash: TrailingSlash::Add,
+            mime_types: BTreeMap::new(),
+            cache_control: Vec::new(),
+            redirects: Vec::new(),
+            rewrites: Vec::new(),
+            jobs: Vec::new(),
+            file_cache_bytes: 8 * 1024 * 1024,
+            job_dashboard: false,
+            server_header: Some("harbor".to_string()),
+            watch_config: false,
+            upgrade_socket: None,
+            read_timeout: None,
+            write_timeout: None,
+            header_timeout: Some(Duration::from_secs(10)),
+            keep_alive_timeout: Some(Duration::from_secs(5)),
+            handler_timeout: None,
+            grace_period: Duration::from_secs(30),
+            tls: None,
+            log: LogConfig::default(),
+        }
+    }
+}
+
+impl Default for LogConfig {
+    fn default() -> LogConfig {
+        LogConfig {
+            level: LogLevel::Info,
+            access_log: false,
+            access_log_file: None,
+            access_log_format: AccessLogFormat::Common,
+            rotation: Rotation::Never,
+            max_files: 5,
+        }
+    }
+}
+
+/// Why a configuration could not be loaded.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The file could not be read.
+    Io {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    /// The file is not valid TOML.
+    Parse(toml::ParseError),
+    /// A setting is unknown or has an invalid value.
+    Invalid { key: String, message: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io { path, error } => {
+                write!(f, "cannot read {}: {}", path.display(), error)
+            }
+            ConfigError::Parse(error) => write!(f, "invalid TOML at {}", error),
+            ConfigError::Invalid { key, message } => write!(f, "invalid `{}`: {}", key, message),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl ServerConfig {
+    /// Loads the configuration from a TOML file. Missing settings keep
+    /// their default values.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<ServerConfig, ConfigError> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path).map_err(|error| ConfigError::Io {
+            path: path.to_path_buf(),
+            error,
+        })?;
+        ServerConfig::from_toml(&source)
+    }
+
+    /// Parses the configuration from TOML source.
+    pub fn from_toml(source: &str) -> Result<ServerConfig, ConfigError> {
+        let table = toml::parse(source).map_err(ConfigError::Parse)?;
+        let mut config = ServerConfig::default();
+
+        for (key, value) in &table {
+            match key.as_str() {
+                "address" => config.address = string(key, value)?,
+                "additional_addresses" => config.additional_addresses = strings(key, value)?,
+                "workers" => config.workers = integer(key, value, 1)?,
+                "acceptors" => config.acceptors = integer(key, value, 1)?,
+                "queue_wait_threshold_ms" => {
+                    let millis = integer(key, value, 0)? as u64;
+                    config.queue_wait_threshold =
+                        Some(Duration::from_millis(millis)).filter(|d| !d.is_zero());
+                }
+                "max_job_result_bytes" => {
+                    config.max_job_result_bytes = Some(integer(key, value, 0)?)
+                }
+                "evented" => config.evented = boolean(key, value)?,
+                "keep_alive" => config.keep_alive = boolean(key, value)?,
+                "flush_policy" => {
+                    let name = string(key, value)?;
+                    config.flush_policy = FlushPolicy::parse(&name).ok_or_else(|| {
+                        invalid(
+                            key,
+                            &format!("unknown value \"{}\", expected response or pipeline", name),
+                        )
+                    })?;
+                }
+                "max_requests_per_connection" => {
+                    config.max_requests_per_connection = Some(integer(key, value, 1)?)
+                }
+                "max_body_bytes" => config.max_body_bytes = Some(integer(key, value, 0)?),
+                "max_connections" => config.max_connections = Some(integer(key, value, 1)?),
+                "max_connections_per_ip" => {
+                    config.max_connections_per_ip = Some(integer(key, value, 1)?)
+                }
+                "max_queued_connections" => {
+                    config.max_queued_connections = Some(integer(key, value, 0)?)
+                }
+                "accept_high_water" => config.accept_high_water = Some(integer(key, value, 1)?),
+                "accept_low_water" => config.accept_low_water = Some(integer(key, value, 0)?),
+                "allowed_hosts" => config.allowed_hosts = strings(key, value)?,
+                "allowed_methods" => config.allowed_methods = strings(key, value)?,
+                "proxy_protocol" => config.proxy_protocol = boolean(key, value)?,
+                "trusted_proxies" => config.trusted_proxies = cidrs(key, value)?,
+                "https_redirect" => config.https_redirect = Some(port(key, value)?),
+                "document_root" => config.document_root = Some(PathBuf::from(string(key, value)?)),
+                "directory_listing" => config.directory_listing = boolean(key, value)?,
+                "precompressed" => config.precompressed = boolean(key, value)?,
+                "trailing_slash" => {
+                    let name = string(key, value)?;
+                    config.trailing_slash = TrailingSlash::parse(&name).ok_or_else(|| {
+                        invalid(
+                            key,
+                            &format!("unknown value \"{}\", expected add, remove or ignore", name),
+                        )
+                    })?;
+                }
+                "file_cache_bytes" => config.file_cache_bytes = integer(key, value, 0)?,
+                "job_dashboard" => config.job_dashboard = boolean(key, value)?,
+                "server_header" => {
+                    config.server_header = Some(string(key, value)?).filter(|name| !name.is_empty())
+                }
+                "watch_config" => config.watch_config = boolean(key, value)?,
+                "upgrade_socket" => {
+                    config.upgrade_socket = Some(PathBuf::from(string(key, value)?))
+                }
+                "pools" => {
+                    for (name, workers) in table_of(key, value)? {
+                        let full_key = format!("pools.{}", name);
+                        config
+                            .pools
+                            .insert(name.clone(), integer(&full_key, workers, 1)?);
+                    }
+                }
+                "mime_types" => {
+                    for (extension, content_type) in table_of(key, value)? {
+                        let full_key = format!("mime_types.{}", extension);
+                        let content_type = string(&full_key, content_type)?;
+                        config.mime_types.insert(extension.clone(), content_type);
+                    }
+                }
+                "redirects" => config.redirects = redirects(key, value)?,
+                "rewrites" => config.rewrites = rewrites(key, value)?,
+                "jobs" => config.jobs = jobs(key, value)?,
+                "cache_control" => config.cache_control = cache_rules(key, value)?,
+                "timeouts" => config.apply_timeouts(table_of(key, value)?)?,
+                "socket" => config.apply_socket(table_of(key, value)?)?,
+                "tls" => config.tls = Some(tls(table_of(key, value)?)?),
+                "log" => config.log = log(table_of(key, value)?)?,
+                _ => return Err(unknown(key)),
+            }
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// The settings that differ in `other` but only take effect on a
+    /// restart: listeners, threads, the file cache, TLS and access logs.
+    /// Everything else is applied when a running server reloads.
+    pub fn restart_required(&self, other: &ServerConfig) -> Vec<&'static str> {
+        let log = (
+            self.log.access_log,
+            &self.log.access_log_file,
+            self.log.access_log_format,
+            self.log.rotation,
+            self.log.max_files,
+        );
+        let other_log = (
+            other.log.access_log,
+            &other.log.access_log_file,
+            other.log.access_log_format,
+            other.log.rotation,
+            other.log.max_files,
+        );
+        [
+            ("address", self.address != other.address),
+            (
+                "additional_addresses",
+                self.additional_addresses != other.additional_addresses,
+            ),
+            ("workers", self.workers != other.workers),
+            ("pools", self.pools != other.pools),
+            (
+                "queue_wait_threshold_ms",
+                self.queue_wait_threshold != other.queue_wait_threshold,
+            ),
+            (
+                "max_job_result_bytes",
+                self.max_job_result_bytes != other.max_job_result_bytes,
+            ),
+            ("acceptors", self.acceptors != other.acceptors),
+            ("evented", self.evented != other.evented),
+            ("socket.backlog", self.backlog != other.backlog),
+            (
+                "file_cache_bytes",
+                self.file_cache_bytes != other.file_cache_bytes,
+            ),
+            (
+                "timeouts.grace_period_secs",
+                self.grace_period != other.grace_period,
+            ),
+            ("jobs", self.jobs != other.jobs),
+            (
+                "upgrade_socket",
+                self.upgrade_socket != other.upgrade_socket,
+            ),
+            ("tls", self.tls != other.tls),
+            ("log", log != other_log),
+        ]
+        .into_iter()
+        .filter_map(|(setting, changed)| changed.then_some(setting))
+        .collect()
+    }
+
+    fn apply_socket(&mut self, table: &Table) -> Result<(), ConfigError> {
+        for (key, value) in table {
+            let full_key = format!("socket.{}", key);
+            match key.as_str() {
+                "tcp_nodelay" => self.tcp_nodelay = boolean(&full_key, value)?,
+                "backlog" => {
+                    let backlog = integer(&full_key, value, 1)?;
+                    self.backlog = Some(u32::try_from(backlog).unwrap_or(u32::MAX));
+                }
+                "linger_secs" => {
+                    let secs = integer(&full_key, value, 0)? as u64;
+                    self.linger = Some(Duration::from_secs(secs));
+                }
+                _ => return Err(unknown(&full_key)),
+            }
+        }
+        Ok(())
+    }
+
+    fn apply_timeouts(&mut self, table: &Table) -> Result<(), ConfigError> {
+        for (key, value) in table {
+            let full_key = format!("timeouts.{}", key);
+            let duration = Duration::from_secs(integer(&full_key, value, 0)? as u64);
+            match key.as_str() {
+                "read_secs" => self.read_timeout = Some(duration).filter(|d| !d.is_zero()),
+                "write_secs" => self.write_timeout = Some(duration).filter(|d| !d.is_zero()),
+                "header_secs" => self.header_timeout = Some(duration).filter(|d| !d.is_zero()),
+                "handler_secs" => self.handler_timeout = Some(duration).filter(|d| !d.is_zero()),
+                "keep_alive_secs" => {
+                    self.keep_alive_timeout = Some(duration).filter(|d| !d.is_zero())
+                }
+                "grace_period_secs" => self.grace_period = duration,
+                _ => return Err(unknown(&full_key)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks settings that can be wrong even when well typed.
+    fn validate(&self) -> Result<(), ConfigError> {
+        check_address("address", &self.address)?;
+        for (index, address) in self.additional_addresses.iter().enumerate() {
+            check_address(&format!("additional_addresses[{}]", index), address)?;
+        }
+
+        if let Some(root) = &self.document_root
+            && !root.is_dir()
+        {
+            return Err(invalid(
+                "document_root",
+                &format!("{} is not a directory", root.display()),
+            ));
+        }
+
+        if let Some(tls) = &self.tls {
+            for (key, path) in [
+                ("tls.certificate", &tls.certificate),
+                ("tls.private_key", &tls.private_key),
+            ] {
+                if !path.is_file() {
+                    return Err(invalid(key, &format!("{} does not exist", path.display())));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn check_address(key: &str, address: &str) -> Result<(), ConfigError> {
+    if address.starts_with("unix:/") {
+        return Ok(());
+    }
+    let port = address
+        .rsplit_once(':')
+        .filter(|(host, _)| !host.contains(':') || host.starts_with('['))
+        .and_then(|(_, port)| port.parse::<u16>().ok());
+    if port.is_none() {
+        return Err(invalid(
+            key,
+            "expected `host:port`, e.g. \"127.0.0.1:7878\" or \"[::]:7878\", or `unix:/path/to.sock`",
+        ));
+    }
+    Ok(())
+}
+
+fn redirects(key: &str, value: &Value) -> Result<Vec<Redirect>, ConfigError> {
+    let Value::Array(entries) = value else {
+        return Err(wrong_type(key, "an array of tables", value));
+    };
+    entries
+        .iter()
+        .enumerate()
+        .map(|(index, entry)| {
+            let prefix = format!("{}[{}]", key, index);
+            let (mut from, mut to, mut permanent) = (None, None, false);
+            for (name, value) in table_of(&prefix, entry)? {
+                let full_key = format!("{}.{}", prefix, name);
+                match name.as_str() {
+                    "from" => from = Some(string(&full_key, value)?),
+                    "to" => to = Some(string(&full_key, value)?),
+                    "permanent" => permanent = boolean(&full_key, value)?,
+                    _ => return Err(unknown(&full_key)),
+                }
+            }
+            match (from, to) {
+                (Some(from), Some(to)) if from.starts_with('/') => Ok(Redirect {
+                    from,
+                    to,
+                    permanent,
+                }),
+                (Some(_), Some(_)) => Err(invalid(
+                    &format!("{}.from", prefix),
+                    "must be a path starting with '/'",
+                )),
+                _ => Err(invalid(&prefix, "both `from` and `to` are required")),
+            }
+        })
+        .collect()
+}
+
+fn cidrs(key: &str, value: &Value) -> Result<Vec<Cidr>, ConfigError> {
+    strings(key, value)?
+        .iter()
+        .enumerate()
+        .map(|(index, block)| {
+            block
+                .parse()
+                .map_err(|message: String| invalid(&format!("{}[{}]", key, index), &message))
+        })
+        .collect()
+}
+
+fn jobs(key: &str, value: &Value) -> Result<Vec<JobDescriptor>, ConfigError> {
+    let Value::Array(entries) = value else {
+        return Err(wrong_type(key, "an array of tables", value));
+    };
+    entries
+        .iter()
+        .enumerate()
+        .map(|(index, entry)| {
+            let prefix = format!("{}[{}]", key, index);
+            let (mut job, mut args) = (None, json::Value::Null);
+            for (name, value) in table_of(&prefix, entry)? {
+                let full_key = format!("{}.{}", prefix, name);
+                match name.as_str() {
+                    "job" => job = Some(string(&full_key, value)?),
+                    "args" => args = to_json(value),
+                    _ => return Err(unknown(&full_key)),
+                }
+            }
+            match job {
+                Some(job) => Ok(JobDescriptor::new(&job, args)),
+                None => Err(invalid(&prefix, "`job` is required")),
+            }
+        })
+        .collect()
+}
+
+/// The JSON a job's factory receives for TOML `args`.
+fn to_json(value: &Value) -> json::Value {
+    match value {
+        Value::String(value) => json::Value::String(value.clone()),
+        Value::Integer(value) => json::Value::Number(*value as f64),
+        Value::Float(value) => json::Value::Number(*value),
+        Value::Boolean(value) => json::Value::Bool(*value),
+        Value::Array(values) => json::Value::Array(values.iter().map(to_json).collect()),
+        Value::Table(table) => json::Value::Object(
+            table
+                .iter()
+                .map(|(key, value)| (key.clone(), to_json(value)))
+                .collect(),
+        ),
+    }
+}
+
+fn rewrites(key: &str, value: &Value) -> Result<Vec<Rewrite>, ConfigError> {
+    let Value::Array(entries) = value else {
+        return Err(wrong_type(key, "an array of tables", value));
+    };
+    entries
+        .iter()
+        .enumerate()
+        .map(|(index, entry)| {
+            let prefix = format!("{}[{}]", key, index);
+            let (mut from, mut to, mut fallback) = (None, None, false);
+            for (name, value) in table_of(&prefix, entry)? {
+                let full_key = format!("{}.{}", prefix, name);
+                match name.as_str() {
+                    "from" => from = Some(string(&full_key, value)?),
+                    "to" => to = Some(string(&full_key, value)?),
+                    "fallback" => fallback = boolean(&full_key, value)?,
+                    _ => return Err(unknown(&full_key)),
+                }
+            }
+            let (Some(from), Some(to)) = (from, to) else {
+                return Err(invalid(&prefix, "both `from` and `to` are required"));
+            };
+            let rewrite = Rewrite::new(&from, &to).map_err(|err| invalid(&prefix, &err))?;
+            Ok(if fallback {
+                rewrite.fallback()
+            } else {
+                rewrite
+            })
+        })
+        .collect()
+}
+
+fn cache_rules(key: &str, value: &Value) -> Result<Vec<CacheRule>, ConfigError> {
+    let Value::Array(entries) = value else {
+        return Err(wrong_type(key, "an array of tables", value));
+    };
+    entries
+        .iter()
+        .enumerate()
+        .map(|(index, entry)| {
+            let prefix = format!("{}[{}]", key, index);
+            let (mut path, mut header) = (None, None);
+            for (name, value) in table_of(&prefix, entry)? {
+                let full_key = format!("{}.{}", prefix, name);
+                match name.as_str() {
+                    "path" => path = Some(string(&full_key, value)?),
+                    "value" => header = Some(string(&full_key, value)?),
+                    _ => return Err(unknown(&full_key)),
+                }
+            }
+            let (Some(path), Some(header)) = (path, header) else {
+                return Err(invalid(&prefix, "both `path` and `value` are required"));
+            };
+            if !path.starts_with('/') && !path.starts_with("*.") {
+                return Err(invalid(
+                    &format!("{}.path", prefix),
+                    "must be a path starting with '/' or an extension like \"*.html\"",
+                ));
+            }
+            if header.is_empty() || header.chars().any(char::is_control) {
+                return Err(invalid(
+                    &format!("{}.value", prefix),
+                    "must be a non-empty header value",
+                ));
+            }
+            Ok(CacheRule::new(&path, &header))
+        })
+        .collect()
+}
+
+fn tls(table: &Table) -> Result<TlsConfig, ConfigError> {
+    let mut certificate = None;
+    let mut private_key = None;
+    for (key, value) in table {
+        let full_key = format!("tls.{}", key);
+        match key.as_str() {
+            "certificate" => certificate = Some(PathBuf::from(string(&full_key, value)?)),
+            "private_key" => private_key = Some(PathBuf::from(string(&full_key, value)?)),
+            _ => return Err(unknown(&full_key)),
+        }
+    }
+    match (certificate, private_key) {
+        (Some(certificate), Some(private_key)) => Ok(TlsConfig {
+            certificate,
+            private_key,
+        }),
+        _ => Err(invalid(
+            "tls",
+            "both `certificate` and `private_key` are required",
+        )),
+    }
+}
+
+fn log(table: &Table) -> Result<LogConfig, ConfigError> {
+    let mut log = LogConfig::default();
+    let mut rotation = None;
+    let mut rotate_bytes = None;
+    for (key, value) in table {
+        let full_key = format!("log.{}", key);
+        match key.as_str() {
+            "level" => {
+                let name = string(&full_key, value)?;
+                log.level = LogLevel::parse(&name).ok_or_else(|| {
+                    invalid(
+                        &full_key,
+                        &format!(
+                            "unknown level \"{}\", expected error, warn, info, debug or trace",
+                            name
+                        ),
+                    )
+                })?;
+            }
+            "access_log" => log.access_log = boolean(&full_key, value)?,
+            "file" => log.access_log_file = Some(PathBuf::from(string(&full_key, value)?)),
+            "format" => {
+                let name = string(&full_key, value)?;
+                log.access_log_format = AccessLogFormat::parse(&name).ok_or_else(|| {
+                    invalid(
+                        &full_key,
+                        &format!(
+                            "unknown format \"{}\", expected common, combined or json",
+                            name
+                        ),
+                    )
+                })?;
+            }
+            "rotation" => rotation = Some(string(&full_key, value)?),
+            "rotate_bytes" => rotate_bytes = Some(integer(&full_key, value, 1)? as u64),
+            "max_files" => log.max_files = integer(&full_key, value, 0)?,
+            _ => return Err(unknown(&full_key)),
+        }
+    }
+
+    log.rotation = match (rotation.as_deref(), rotate_bytes) {
+        (None | Some("never"), None) => Rotation::Never,
+        (Some("daily"), None) => Rotation::Daily,
+        (Some("size"), Some(bytes)) => Rotation::Size(bytes),
+        (Some("size"), None) => {
+            return Err(invalid(
+                "log.rotate_bytes",
+                "required when rotation is \"size\"",
+            ));
+        }
+        (None | Some("never" | "daily"), Some(_)) => {
+            return Err(invalid(
+                "log.rotate_bytes",
+                "only allowed when rotation is \"size\"",
+            ));
+        }
+        (Some(other), _) => {
+            return Err(invalid(
+                "log.rotation",
+                &format!(
+                    "unknown rotation \"{}\", expected never, daily or size",
+                    other
+                ),
+            ));
+        }
+    };
+    Ok(log)
+}
+
+fn string(key: &str, value: &Value) -> Result<String, ConfigError> {
+    match value {
+        Value::String(value) => Ok(value.clone()),
+        other => Err(wrong_type(key, "a string", other)),
+    }
+}
+
+fn strings(key: &str, value: &Value) -> Result<Vec<String>, ConfigError> {
+    match value {
+        Value::Array(values) => values
+            .iter()
+            .enumerate()
+            .map(|(index, value)| string(&format!("{}[{}]", key, index), value))
+            .collect(),
+        other => Err(wrong_type(key, "an array", other)),
+    }
+}
+
+fn boolean(key: &str, value: &Value) -> Result<bool, ConfigError> {
+    match value {
+        Value::Boolean(value) => Ok(*value),
+        other => Err(wrong_type(key, "a boolean", other)),
+    }
+}
+
+fn port(key: &str, value: &Value) -> Result<u16, ConfigError> {
+    u16::try_from(integer(key, value, 1)?)
+        .map_err(|_| invalid(key, "must be a port number from 1 to 65535"))
+}
+
+fn integer(key: &str, value: &Value, min: i64) -> Result<usize, ConfigError> {
+    match value {
+        Value::Integer(value) if *value >= min => Ok(*value as usize),
+        Value::Integer(value) => Err(invalid(
+            key,
+            &format!("must be at least {}, got {}", min, value),
+        )),
+        other => Err(wrong_type(key, "an integer", other)),
+    }
+}
+
+fn table_of<'a>(key: &str, value: &'a Value) -> Result<&'a Table, ConfigError> {
+    match value {
+        Value::Table(table) => Ok(table),
+        other => Err(wrong_type(key, "a table", other)),
+    }
+}
+
+fn wrong_type(key: &str, expected: &str, found: &Value) -> ConfigError {
+    invalid(
+        key,
+        &format!("expected {}, found {}", expected, found.type_name()),
+    )
+}
+
+fn unknown(key: &str) -> ConfigError {
+    invalid(key, "unknown setting")
+}
+
+fn invalid(key: &str, message: &str) -> ConfigError {
+    ConfigError::Invalid {
+        key: key.to_string(),
+        message: message.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_toml_should_override_defaults() {
+        let config = ServerConfig::from_toml(
+            r#"
+            address = "0.0.0.0:8080"
+            workers = 8
+            queue_wait_threshold_ms = 250
+            max_job_result_bytes = 1024
+            max_connections = 100
+            max_connections_per_ip = 4
+            max_queued_connections = 16
+            accept_high_water = 64
+            max_requests_per_connection = 50
+            allowed_hosts = ["localhost"]
+            allowed_methods = ["GET", "PROPFIND"]
+            trailing_slash = "remove"
+            flush_policy = "pipeline"
+
+            [pools]
+            slow = 2
+
+            [socket]
+            tcp_nodelay = false
+            backlog = 512
+            linger_secs = 0
+
+            [mime_types]
+            log = "text/plain"
+
+            [timeouts]
+            read_secs = 5
+            header_secs = 0
+            keep_alive_secs = 0
+            handler_secs = 3
+            grace_period_secs = 2
+
+            [log]
+            level = "debug"
+            access_log = true
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.address, "0.0.0.0:8080");
+        assert_eq!(config.workers, 8);
+        assert_eq!(
+            config.queue_wait_threshold,
+            Some(Duration::from_millis(250))
+        );
+        assert_eq!(config.max_job_result_bytes, Some(1024));
+        assert_eq!(config.pools["slow"], 2);
+        assert!(!config.tcp_nodelay);
+        assert_eq!(config.backlog, Some(512));
+        assert_eq!(config.linger, Some(Duration::ZERO));
+        assert_eq!(config.max_connections, Some(100));
+        assert_eq!(config.max_connections_per_ip, Some(4));
+        assert_eq!(config.max_queued_connections, Some(16));
+        assert_eq!(config.accept_high_water, Some(64));
+        assert_eq!(config.accept_low_water, None);
+        assert_eq!(config.max_requests_per_connection, Some(50));
+        assert_eq!(config.allowed_hosts, vec!["localhost"]);
+        assert_eq!(config.allowed_methods, vec!["GET", "PROPFIND"]);
+        assert_eq!(config.trailing_slash, TrailingSlash::Remove);
+        assert_eq!(config.flush_policy, FlushPolicy::Pipeline);
+        assert_eq!(config.mime_types["log"], "text/plain");
+        assert_eq!(config.read_timeout, Some(Duration::from_secs(5)));
+        assert_eq!(config.write_timeout, None);
+        assert_eq!(config.header_timeout, None);
+        assert_eq!(config.keep_alive_timeout, None);
+        assert_eq!(config.handler_timeout, Some(Duration::from_secs(3)));
+        assert_eq!(config.grace_period, Duration::from_secs(2));
+        assert_eq!(config.log.level, LogLevel::Debug);
+        assert!(config.log.access_log);
+        assert_eq!(config.log.rotation, Rotation::Never);
+        assert_eq!(
+            config.file_cache_bytes,
+            ServerConfig::default().file_cache_bytes
+        );
+    }
+
+    #[test]
+    fn from_toml_should_read_redirects() {
+        let config = ServerConfig::from_toml(
+            "[[redirects]]\nfrom = \"/old\"\nto = \"/new\"\n\n[[redirects]]\nfrom = \"/blog\"\nto = \"https://blog.example.com/\"\npermanent = true",
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.redirects,
+            vec![
+                Redirect {
+                    from: "/old".to_string(),
+                    to: "/new".to_string(),
+                    permanent: false,
+                },
+                Redirect {
+                    from: "/blog".to_string(),
+                    to: "https://blog.example.com/".to_string(),
+                    permanent: true,
+                },
+            ]
+        );
+        assert_eq!(
+            ServerConfig::from_toml("[[redirects]]\nfrom = \"old\"\nto = \"/new\"")
+                .unwrap_err()
+                .to_string(),
+            "invalid `redirects[0].from`: must be a path starting with '/'"
+        );
+    }
+
+    #[test]
+    fn from_toml_should_read_jobs() {
+        let config = ServerConfig::from_toml(
+            "[[jobs]]\njob = \"warm_cache\"\nargs = { pages = 100, paths = [\"/\"] }\n\n[[jobs]]\njob = \"ping\"",
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.jobs,
+            vec![
+                JobDescriptor::parse(
+                    r#"{"job": "warm_cache", "args": {"pages": 100, "paths": ["/"]}}"#
+                )
+                .unwrap(),
+                JobDescriptor::new("ping", json::Value::Null),
+            ]
+        );
+        assert_eq!(
+            ServerConfig::from_toml("[[jobs]]\nargs = {}")
+                .unwrap_err()
+                .to_string(),
+            "invalid `jobs[0]`: `job` is required"
+        );
+    }
+
+    #[test]
+    fn from_toml_should_read_rewrites() {
+        let config = ServerConfig::from_toml(
+            "[[rewrites]]\nfrom = \"/old/(.*)\"\nto = \"/new/$1\"\n\n[[rewrites]]\nfrom = \"/app/(.*)\"\nto = \"/app/index.html\"\nfallback = true",
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.rewrites,
+            vec![
+                Rewrite::new("/old/(.*)", "/new/$1").unwrap(),
+                Rewrite::new("/app/(.*)", "/app/index.html")
+                    .unwrap()
+                    .fallback(),
+            ]
+        );
+        assert_eq!(
+            ServerConfig::from_toml("[[rewrites]]\nfrom = \"/old\"\nto = \"/new/$1\"")
+                .unwrap_err()
+                .to_string(),
+            "invalid `rewrites[0]`: \"/new/$1\" refers to a capture that \"/old\" doesn't have"
+        );
+    }
+
+    #[test]
+    fn restart_required_should_name_settings_that_cannot_reload() {
+        let current = ServerConfig::default();
+        let reloaded = ServerConfig::from_toml(
+            "address = \"0.0.0.0:80\"\nworkers = 16\nevented = true\nkeep_alive = true\nallowed_hosts = [\"example.com\"]\n\n[log]\nlevel = \"debug\"\nformat = \"combined\"",
+        )
+        .unwrap();
+
+        assert_eq!(
+            current.restart_required(&reloaded),
+            vec!["address", "workers", "evented", "log"]
+        );
+        assert!(current.restart_required(&current.clone()).is_empty());
+    }
+
+    #[test]
+    fn from_toml_should_read_cache_control_rules() {
+        let config = ServerConfig::from_toml(
+            "[[cache_control]]\npath = \"/assets/*\"\nvalue = \"max-age=60\"\n\n[[cache_control]]\npath = \"*.html\"\nvalue = \"no-cache\"",
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.cache_control,
+            vec![
+                CacheRule::new("/assets/*", "max-age=60"),
+                CacheRule::new("*.html", "no-cache"),
+            ]
+        );
+        assert_eq!(
+            ServerConfig::from_toml("[[cache_control]]\npath = \"assets\"\nvalue = \"no-cache\"")
+                .unwrap_err()
+                .to_string(),
+            "invalid `cache_control[0].path`: must be a path starting with '/' or an extension like \"*.html\""
+        );
+    }
+
+    #[test]
+    fn from_toml_should_read_trusted_proxies() {
+        let config =
+            ServerConfig::from_toml("trusted_proxies = [\"10.0.0.0/8\", \"::1\"]").unwrap();
+
+        assert_eq!(
+            config.trusted_proxies,
+            vec!["10.0.0.0/8".parse().unwrap(), "::1/128".parse().unwrap()]
+        );
+        assert_eq!(
+            ServerConfig::from_toml("trusted_proxies = [\"10.0.0.0/8\", \"proxy\"]")
+                .unwrap_err()
+                .to_string(),
+            "invalid `trusted_proxies[1]`: not an IP address: \"proxy\""
+        );
+    }
+
+    #[test]
+    fn from_toml_should_read_the_https_redirect_port() {
+        let config = ServerConfig::from_toml("https_redirect = 8443").unwrap();
+
+        assert_eq!(config.https_redirect, Some(8443));
+        assert_eq!(
+            ServerConfig::from_toml("https_redirect = 70000")
+                .unwrap_err()
+                .to_string(),
+            "invalid `https_redirect`: must be a port number from 1 to 65535"
+        );
+    }
+
+    #[test]
+    fn from_toml_should_read_access_log_rotation() {
+        let config = ServerConfig::from_toml(
+            "[log]\nfile = \"access.log\"\nformat = \"combined\"\nrotation = \"size\"\nrotate_bytes = 1024\nmax_files = 3",
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.log.access_log_file,
+            Some(PathBuf::from("access.log"))
+        );
+        assert_eq!(config.log.access_log_format, AccessLogFormat::Combined);
+        assert_eq!(config.log.rotation, Rotation::Size(1024));
+        assert_eq!(config.log.max_files, 3);
+    }
+
+    #[test]
+    fn from_toml_should_explain_invalid_values() {
+        let cases = [
+            (
+                "workers = 0",
+                "invalid `workers`: must be at least 1, got 0",
+            ),
+            (
+                "workers = \"4\"",
+                "invalid `workers`: expected an integer, found string",
+            ),
+            ("adress = \"x\"", "invalid `adress`: unknown setting"),
+            (
+                "address = \"localhost\"",
+                "invalid `address`: expected `host:port`, e.g. \"127.0.0.1:7878\" or \"[::]:7878\", or `unix:/path/to.sock`",
+            ),
+            (
+                "address = \"::1:7878\"",
+                "invalid `address`: expected `host:port`, e.g. \"127.0.0.1:7878\" or \"[::]:7878\", or `unix:/path/to.sock`",
+            ),
+            (
+                "additional_addresses = [\"[::1]:8080\", 8081]",
+                "invalid `additional_addresses[1]`: expected a string, found integer",
+            ),
+            (
+                "additional_addresses = [\"nowhere\"]",
+                "invalid `additional_addresses[0]`: expected `host:port`, e.g. \"127.0.0.1:7878\" or \"[::]:7878\", or `unix:/path/to.sock`",
+            ),
+            (
+                "[log]\nlevel = \"loud\"",
+                "invalid `log.level`: unknown level \"loud\", expected error, warn, info, debug or trace",
+            ),
+            (
+                "[tls]\ncertificate = \"c.pem\"",
+                "invalid `tls`: both `certificate` and `private_key` are required",
+            ),
+            ("workers = ", "invalid TOML at line 1: expected a value"),
+            (
+                "[log]\nrotation = \"size\"",
+                "invalid `log.rotate_bytes`: required when rotation is \"size\"",
+            ),
+            (
+                "[log]\nformat = \"xml\"",
+                "invalid `log.format`: unknown format \"xml\", expected common, combined or json",
+            ),
+            (
+                "trailing_slash = \"keep\"",
+                "invalid `trailing_slash`: unknown value \"keep\", expected add, remove or ignore",
+            ),
+        ];
+
+        for (source, message) in cases {
+            let error = ServerConfig::from_toml(source).unwrap_err();
+            assert_eq!(error.to_string(), message);
+        }
+    }
+
+    #[test]
+    fn from_file_should_report_missing_files() {
+        let error = ServerConfig::from_file("does-not-exist.toml").unwrap_err();
+
+        assert!(matches!(error, ConfigError::Io { .. }));
+        assert!(
+            error
+                .to_string()
+                .starts_with("cannot read does-not-exist.toml")
+        );
+    }
+}
@@ -0,0 +1,258 @@
+//! Counting open connections and enforcing a maximum, overall and per
+//! client address.
+
+use std::{
+    collections::HashMap,
+    io,
+    net::IpAddr,
+    sync::{
+        Arc, Mutex, MutexGuard, PoisonError,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+};
+
+/// Tracks how many connections a server has open.
+///
+/// Each accepted connection holds a `ConnectionGuard` for as long as it is
+/// served; dropping the guard releases its slot.
+#[derive(Debug, Default)]
+pub struct Connections {
+    open: AtomicUsize,
+    accepted: AtomicU64,
+    rejected: AtomicU64,
+    rejected_per_ip: AtomicU64,
+    accept_errors: AtomicU64,
+    client_disconnects: AtomicU64,
+    handler_timeouts: AtomicU64,
+    abandoned_handlers: AtomicUsize,
+    /// Open connections by client address, for those claimed with
+    /// `ConnectionGuard::claim_ip`.
+    per_ip: Mutex<HashMap<IpAddr, usize>>,
+}
+
+/// A snapshot of a `Connections`' counters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionStats {
+    /// Connections currently being served or waiting for a worker.
+    pub open: usize,
+    /// Connections accepted since the server started.
+    pub accepted: u64,
+    /// Connections closed straight away because `open` was at the limit.
+    pub rejected: u64,
+    /// Connections refused because their client address had as many open
+    /// as `max_connections_per_ip` allows.
+    pub rejected_per_ip: u64,
+    /// Failed attempts to accept a connection, such as when the process
+    /// ran out of file descriptors.
+    pub accept_errors: u64,
+    /// Connections the client closed or reset while a response was being
+    /// written to it.
+    pub client_disconnects: u64,
+    /// Requests answered `504` because their handler ran past its timeout.
+    pub handler_timeouts: u64,
+    /// Handlers answered `504` for that have not returned yet, each still
+    /// holding a thread of its own.
+    pub abandoned_handlers: usize,
+}
+
+/// Holds one open connection slot until dropped.
+#[derive(Debug)]
+pub struct ConnectionGuard {
+    connections: Arc<Connections>,
+    /// The client address whose slot the guard also holds.
+    ip: Option<IpAddr>,
+}
+
+impl Connections {
+    pub fn new() -> Connections {
+        Connections::default()
+    }
+
+    /// Claims a slot for a new connection, unless `limit` connections are
+    /// already open. Without a limit this always succeeds.
+    pub fn try_acquire(self: &Arc<Self>, limit: Option<usize>) -> Option<ConnectionGuard> {
+        let acquired = self
+            .open
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |open| match limit {
+                Some(limit) if open >= limit => None,
+                _ => Some(open + 1),
+            })
+            .is_ok();
+
+        if acquired {
+            self.accepted.fetch_add(1, Ordering::Relaxed);
+            Some(ConnectionGuard {
+                connections: Arc::clone(self),
+                ip: None,
+            })
+        } else {
+            self.rejected.fetch_add(1, Ordering::Relaxed);
+            None
+        }
+    }
+
+    /// Counts a failed attempt to accept a connection.
+    pub fn accept_failed(&self) {
+        self.accept_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a client that went away before its response was written.
+    pub fn client_disconnected(&self) {
+        self.client_disconnects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a request answered `504` because its handler overran.
+    pub(crate) fn handler_timed_out(&self) {
+        self.handler_timeouts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a handler left running after its timeout, unless `limit`
+    /// of them are running already. Returns whether it was counted.
+    pub(crate) fn try_abandon_handler(&self, limit: usize) -> bool {
+        self.abandoned_handlers
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |abandoned| {
+                (abandoned < limit).then_some(abandoned + 1)
+            })
+            .is_ok()
+    }
+
+    /// Counts an abandoned handler that has returned after all.
+    pub(crate) fn abandoned_handler_returned(&self) {
+        self.abandoned_handlers.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    pub fn stats(&self) -> ConnectionStats {
+        ConnectionStats {
+            open: self.open.load(Ordering::SeqCst),
+            accepted: self.accepted.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+            rejected_per_ip: self.rejected_per_ip.load(Ordering::Relaxed),
+            accept_errors: self.accept_errors.load(Ordering::Relaxed),
+            client_disconnects: self.client_disconnects.load(Ordering::Relaxed),
+            handler_timeouts: self.handler_timeouts.load(Ordering::Relaxed),
+            abandoned_handlers: self.abandoned_handlers.load(Ordering::SeqCst),
+        }
+    }
+
+    /// Counts are updated in one step under the lock, so a poisoned one
+    /// still guards consistent counts.
+    fn lock_per_ip(&self) -> MutexGuard<'_, HashMap<IpAddr, usize>> {
+        self.per_ip.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Whether `err`, from writing to a client, means the client closed the
+/// connection or reset it: an everyday event, not a server fault.
+pub(crate) fn is_disconnect(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::BrokenPipe
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::NotConnected
+    )
+}
+
+impl ConnectionGuard {
+    /// Also claims one of the `limit` slots of the client address `ip`,
+    /// unless that many of its connections are open already. Returns
+    /// whether it did. Without a limit nothing is counted; connections
+    /// accepted before a limit was set don't count towards it.
+    pub fn claim_ip(&mut self, ip: IpAddr, limit: Option<usize>) -> bool {
+        let Some(limit) = limit else {
+            return true;
+        };
+        let mut per_ip = self.connections.lock_per_ip();
+        let open = per_ip.entry(ip).or_insert(0);
+        if *open >= limit {
+            drop(per_ip);
+            self.connections
+                .rejected_per_ip
+                .fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        *open += 1;
+        self.ip = Some(ip);
+        true
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        if let Some(ip) = self.ip {
+            let mut per_ip = self.connections.lock_per_ip();
+            if let Some(open) = per_ip.get_mut(&ip) {
+                *open -= 1;
+                if *open == 0 {
+                    per_ip.remove(&ip);
+                }
+            }
+        }
+        self.connections.open.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn try_acquire_should_refuse_connections_over_the_limit() {
+        let connections = Arc::new(Connections::new());
+
+        let first = connections.try_acquire(Some(2));
+        let second = connections.try_acquire(Some(2));
+        assert!(first.is_some() && second.is_some());
+        assert!(connections.try_acquire(Some(2)).is_none());
+
+        drop(first);
+        assert!(connections.try_acquire(Some(2)).is_some());
+        connections.accept_failed();
+        connections.client_disconnected();
+        connections.handler_timed_out();
+        connections.handler_timed_out();
+        assert!(connections.try_abandon_handler(2));
+        assert!(connections.try_abandon_handler(2));
+        assert!(!connections.try_abandon_handler(2));
+        connections.abandoned_handler_returned();
+        assert_eq!(
+            connections.stats(),
+            ConnectionStats {
+                open: 1,
+                accepted: 3,
+                rejected: 1,
+                rejected_per_ip: 0,
+                accept_errors: 1,
+                client_disconnects: 1,
+                handler_timeouts: 2,
+                abandoned_handlers: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn claim_ip_should_refuse_clients_over_their_limit() {
+        let connections = Arc::new(Connections::new());
+        let client: IpAddr = "192.0.2.1".parse().unwrap();
+        let other: IpAddr = "192.0.2.2".parse().unwrap();
+
+        let mut first = connections.try_acquire(None).unwrap();
+        let mut second = connections.try_acquire(None).unwrap();
+        let mut third = connections.try_acquire(None).unwrap();
+        assert!(first.claim_ip(client, Some(1)));
+        assert!(!second.claim_ip(client, Some(1)));
+        assert!(third.claim_ip(other, Some(1)));
+
+        drop(first);
+        assert!(second.claim_ip(client, Some(1)));
+        assert!(
+            connections
+                .try_acquire(None)
+                .unwrap()
+                .claim_ip(client, None)
+        );
+        assert_eq!(connections.stats().rejected_per_ip, 1);
+        drop((second, third));
+        assert!(connections.lock_per_ip().is_empty());
+    }
+}
@@ -0,0 +1,248 @@
+//! `Cookie` request headers and `Set-Cookie` response headers (RFC 6265).
+
+use std::{collections::HashMap, fmt, time::Duration};
+
+use super::request::is_token;
+
+/// Parses the value of a `Cookie` header into a map of names to values.
+///
+/// Pairs without a `=` or with an invalid name are skipped. When a name
+/// appears twice the first value wins, as browsers send the most specific
+/// cookie first. Double quotes around a value are removed.
+pub fn parse(header: &str) -> HashMap<String, String> {
+    let mut cookies = HashMap::new();
+    for pair in header.split(';') {
+        let Some((name, value)) = pair.split_once('=') else {
+            continue;
+        };
+        let name = name.trim();
+        if !is_token(name) {
+            continue;
+        }
+        let value = value.trim();
+        let value = value
+            .strip_prefix('"')
+            .and_then(|value| value.strip_suffix('"'))
+            .unwrap_or(value);
+        cookies
+            .entry(name.to_string())
+            .or_insert_with(|| value.to_string());
+    }
+    cookies
+}
+
+/// Whether a cookie is sent along with cross-site requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    /// Sent with every request. Browsers require `Secure` as well.
+    None,
+}
+
+impl SameSite {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        }
+    }
+}
+
+/// A cookie to set on the client with `Response::cookie`.
+///
+/// # Example
+///
+/// ```
+/// # use std::time::Duration;
+/// # use harbor::server::cookie::{Cookie, SameSite};
+/// let cookie = Cookie::new("theme", "dark")
+///     .unwrap()
+///     .path("/")
+///     .max_age(Duration::from_secs(3600))
+///     .http_only(true)
+///     .same_site(SameSite::Lax);
+/// assert_eq!(
+///     cookie.to_string(),
+///     "theme=dark; Path=/; Max-Age=3600; HttpOnly; SameSite=Lax"
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cookie {
+    name: String,
+    value: String,
+    path: Option<String>,
+    domain: Option<String>,
+    max_age: Option<Duration>,
+    http_only: bool,
+    secure: bool,
+    same_site: Option<SameSite>,
+}
+
+impl Cookie {
+    /// Creates a session cookie. Fails if `name` is not a token or `value`
+    /// holds characters cookies cannot carry (spaces, `"`, `,`, `;`, `\`
+    /// or control characters).
+    pub fn new(name: &str, value: &str) -> Result<Cookie, String> {
+        if !is_token(name) {
+            return Err(format!("Invalid cookie name: {:?}", name));
+        }
+        if !value.bytes().all(is_cookie_octet) {
+            return Err(format!("Invalid value for cookie {}: {:?}", name, value));
+        }
+        Ok(Cookie {
+            name: name.to_string(),
+            value: value.to_string(),
+            path: None,
+            domain: None,
+            max_age: None,
+            http_only: false,
+            secure: false,
+            same_site: None,
+        })
+    }
+
+    /// A cookie that tells the client to delete `name` right away.
+    pub fn removal(name: &str) -> Result<Cookie, String> {
+        Ok(Cookie::new(name, "")?.max_age(Duration::ZERO))
+    }
+
+    /// Limits the cookie to paths under `path`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `path` contains `;` or control characters.
+    pub fn path(mut self, path: &str) -> Cookie {
+        assert!(is_attribute_value(path), "invalid cookie path: {:?}", path);
+        self.path = Some(path.to_string());
+        self
+    }
+
+    /// Sends the cookie to `domain` and its subdomains too.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `domain` contains `;` or control characters.
+    pub fn domain(mut self, domain: &str) -> Cookie {
+        assert!(
+            is_attribute_value(domain),
+            "invalid cookie domain: {:?}",
+            domain
+        );
+        self.domain = Some(domain.to_string());
+        self
+    }
+
+    /// Keeps the cookie for `max_age`, in whole seconds, instead of until
+    /// the browser closes.
+    pub fn max_age(mut self, max_age: Duration) -> Cookie {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Hides the cookie from scripts in the page.
+    pub fn http_only(mut self, http_only: bool) -> Cookie {
+        self.http_only = http_only;
+        self
+    }
+
+    /// Only sends the cookie over HTTPS.
+    pub fn secure(mut self, secure: bool) -> Cookie {
+        self.secure = secure;
+        self
+    }
+
+    pub fn same_site(mut self, same_site: SameSite) -> Cookie {
+        self.same_site = Some(same_site);
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+}
+
+/// Formats the cookie as the value of a `Set-Cookie` header.
+impl fmt::Display for Cookie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+        if let Some(path) = &self.path {
+            write!(f, "; Path={}", path)?;
+        }
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={}", domain)?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age.as_secs())?;
+        }
+        if self.http_only {
+            f.write_str("; HttpOnly")?;
+        }
+        if self.secure {
+            f.write_str("; Secure")?;
+        }
+        if let Some(same_site) = self.same_site {
+            write!(f, "; SameSite={}", same_site.as_str())?;
+        }
+        Ok(())
+    }
+}
+
+/// RFC 6265 `cookie-octet`: printable ASCII except `"`, `,`, `;` and `\`.
+fn is_cookie_octet(byte: u8) -> bool {
+    matches!(byte, 0x21 | 0x23..=0x2B | 0x2D..=0x3A | 0x3C..=0x5B | 0x5D..=0x7E)
+}
+
+fn is_attribute_value(text: &str) -> bool {
+    !text.is_empty()
+        && text
+            .bytes()
+            .all(|byte| byte != b';' && !byte.is_ascii_control())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_should_split_pairs_and_keep_the_first_duplicate() {
+        let cookies = parse("session=abc123; theme=\"dark\"; session=old;flag; =x; empty=");
+
+        assert_eq!(cookies.len(), 3);
+        assert_eq!(cookies["session"], "abc123");
+        assert_eq!(cookies["theme"], "dark");
+        assert_eq!(cookies["empty"], "");
+    }
+
+    #[test]
+    fn new_should_reject_invalid_names_and_values() {
+        assert!(Cookie::new("bad name", "x").is_err());
+        assert!(Cookie::new("", "x").is_err());
+        assert!(Cookie::new("ok", "a;b").is_err());
+        assert!(Cookie::new("ok", "with space").is_err());
+        assert!(Cookie::new("ok", "base64+/=").is_ok());
+    }
+
+    #[test]
+    fn display_should_format_every_attribute() {
+        let cookie = Cookie::new("id", "42")
+            .unwrap()
+            .path("/app")
+            .domain("example.com")
+            .max_age(Duration::from_secs(60))
+            .http_only(true)
+            .secure(true)
+            .same_site(SameSite::None);
+
+        assert_eq!(
+            cookie.to_string(),
+            "id=42; Path=/app; Domain=example.com; Max-Age=60; HttpOnly; Secure; SameSite=None"
+        );
+        assert_eq!(Cookie::removal("id").unwrap().to_string(), "id=; Max-Age=0");
+    }
+}
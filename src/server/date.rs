@@ -0,0 +1,261 @@
+//! UTC calendar formatting for log lines, listings and headers.
+
+use std::{
+    cell::RefCell,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Starting from Thursday, the weekday of 1970-01-01.
+const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+
+/// A point in time broken down into UTC calendar fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+    /// Days since 1970-01-01.
+    pub days: i64,
+}
+
+impl DateTime {
+    /// Converts `time` to UTC. Times before the epoch clamp to the epoch.
+    pub fn from_system_time(time: SystemTime) -> DateTime {
+        let secs = time
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+        let (days, rem) = ((secs / 86_400) as i64, secs % 86_400);
+
+        // Civil-from-days conversion from Howard Hinnant's date algorithms.
+        let z = days + 719_468;
+        let era = z / 146_097;
+        let doe = z - era * 146_097;
+        let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + i64::from(month <= 2);
+
+        DateTime {
+            year,
+            month: month as u32,
+            day: day as u32,
+            hour: (rem / 3600) as u32,
+            minute: (rem % 3600 / 60) as u32,
+            second: (rem % 60) as u32,
+            days,
+        }
+    }
+
+    fn month_name(&self) -> &'static str {
+        MONTHS[(self.month as usize).saturating_sub(1) % 12]
+    }
+
+    fn weekday_name(&self) -> &'static str {
+        WEEKDAYS[self.days.rem_euclid(7) as usize]
+    }
+}
+
+/// Formats `time` as `YYYY-MM-DD HH:MM:SS`.
+pub fn format_timestamp(time: SystemTime) -> String {
+    let t = DateTime::from_system_time(time);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        t.year, t.month, t.day, t.hour, t.minute, t.second
+    )
+}
+
+/// Formats `time` as an RFC 3339 UTC timestamp with milliseconds, as log
+/// collectors expect: `2000-10-10T13:55:36.123Z`.
+pub fn format_rfc3339(time: SystemTime) -> String {
+    let t = DateTime::from_system_time(time);
+    let millis = time
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.subsec_millis())
+        .unwrap_or(0);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        t.year, t.month, t.day, t.hour, t.minute, t.second, millis
+    )
+}
+
+/// Formats `time` as `YYYY-MM-DD`.
+pub fn format_date(time: SystemTime) -> String {
+    let t = DateTime::from_system_time(time);
+    format!("{:04}-{:02}-{:02}", t.year, t.month, t.day)
+}
+
+/// Formats `time` as used by the Common Log Format:
+/// `10/Oct/2000:13:55:36 +0000`.
+pub fn format_clf(time: SystemTime) -> String {
+    let t = DateTime::from_system_time(time);
+    format!(
+        "{:02}/{}/{:04}:{:02}:{:02}:{:02} +0000",
+        t.day,
+        t.month_name(),
+        t.year,
+        t.hour,
+        t.minute,
+        t.second
+    )
+}
+
+/// Formats `time` as an HTTP date (RFC 9110 `IMF-fixdate`):
+/// `Sun, 06 Nov 1994 08:49:37 GMT`.
+pub fn format_http_date(time: SystemTime) -> String {
+    let t = DateTime::from_system_time(time);
+    format!(
+        "{}, {:02} {} {:04} {:02}:{:02}:{:02} GMT",
+        t.weekday_name(),
+        t.day,
+        t.month_name(),
+        t.year,
+        t.hour,
+        t.minute,
+        t.second
+    )
+}
+
+/// The current time as an HTTP date, for `Date` headers. Formatted at
+/// most once a second per thread.
+pub fn http_date_now() -> String {
+    thread_local! {
+        static CACHED: RefCell<(u64, String)> = const { RefCell::new((u64::MAX, String::new())) };
+    }
+    let now = SystemTime::now();
+    let secs = now
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+    CACHED.with_borrow_mut(|(cached_secs, date)| {
+        if *cached_secs != secs {
+            *cached_secs = secs;
+            *date = format_http_date(now);
+        }
+        date.clone()
+    })
+}
+
+/// Parses an HTTP date in any of the three formats RFC 9110 requires
+/// recipients to accept: `Sun, 06 Nov 1994 08:49:37 GMT`,
+/// `Sunday, 06-Nov-94 08:49:37 GMT` and `Sun Nov  6 08:49:37 1994`.
+pub fn parse_http_date(text: &str) -> Option<SystemTime> {
+    let text = text.trim();
+    let (day, month, year, time) = match text.split_once(", ") {
+        Some((_, rest)) => {
+            let (date, time) = rest.strip_suffix(" GMT")?.rsplit_once(' ')?;
+            let mut fields = date.split([' ', '-']);
+            let (day, month, year) = (fields.next()?, fields.next()?, fields.next()?);
+            if fields.next().is_some() {
+                return None;
+            }
+            let year: i64 = year.parse().ok()?;
+            // RFC 850 dates carry two-digit years.
+            let year = match year {
+                0..=69 => year + 2000,
+                70..=99 => year + 1900,
+                _ => year,
+            };
+            (day, month, year, time)
+        }
+        None => {
+            let mut fields = text.split_whitespace().skip(1);
+            let (month, day, time, year) = (
+                fields.next()?,
+                fields.next()?,
+                fields.next()?,
+                fields.next()?,
+            );
+            if fields.next().is_some() {
+                return None;
+            }
+            (day, month, year.parse().ok()?, time)
+        }
+    };
+
+    let month = MONTHS.iter().position(|name| *name == month)? as i64 + 1;
+    let day: i64 = day.parse().ok().filter(|day| (1..=31).contains(day))?;
+    let mut clock = time.split(':').map(|field| field.parse::<u64>().ok());
+    let (hour, minute, second) = (clock.next()??, clock.next()??, clock.next()??);
+    if clock.next().is_some() || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+
+    let days = u64::try_from(days_from_civil(year, month, day)).ok()?;
+    let secs = days * 86_400 + hour * 3600 + minute * 60 + second;
+    UNIX_EPOCH.checked_add(Duration::from_secs(secs))
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date; the inverse of
+/// the conversion in `DateTime::from_system_time`.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn formatters_should_render_utc_dates() {
+        let time = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        assert_eq!(format_timestamp(time), "2023-11-14 22:13:20");
+        assert_eq!(format_timestamp(UNIX_EPOCH), "1970-01-01 00:00:00");
+        assert_eq!(format_date(time), "2023-11-14");
+        assert_eq!(
+            format_rfc3339(time + Duration::from_millis(42)),
+            "2023-11-14T22:13:20.042Z"
+        );
+        assert_eq!(format_clf(time), "14/Nov/2023:22:13:20 +0000");
+        assert_eq!(format_http_date(time), "Tue, 14 Nov 2023 22:13:20 GMT");
+        assert_eq!(
+            format_http_date(UNIX_EPOCH),
+            "Thu, 01 Jan 1970 00:00:00 GMT"
+        );
+    }
+
+    #[test]
+    fn parse_http_date_should_accept_all_three_formats() {
+        let time = UNIX_EPOCH + Duration::from_secs(784_111_777);
+
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"), Some(time));
+        assert_eq!(
+            parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"),
+            Some(time)
+        );
+        assert_eq!(parse_http_date("Sun Nov  6 08:49:37 1994"), Some(time));
+        assert_eq!(
+            parse_http_date(&format_http_date(
+                UNIX_EPOCH + Duration::from_secs(951_782_400)
+            )),
+            Some(UNIX_EPOCH + Duration::from_secs(951_782_400))
+        );
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 25:49:37 GMT"), None);
+        assert_eq!(parse_http_date("yesterday"), None);
+    }
+
+    #[test]
+    fn from_system_time_should_handle_leap_days() {
+        let leap_day = UNIX_EPOCH + Duration::from_secs(951_782_400);
+
+        let t = DateTime::from_system_time(leap_day);
+        assert_eq!((t.year, t.month, t.day), (2000, 2, 29));
+    }
+}
@@ -0,0 +1,171 @@
+//! A total time budget for reading a request head.
+//!
+//! Socket read timeouts only bound the gap between two reads, so a client
+//! trickling one byte every few seconds never trips them. `Deadline` wraps
+//! the stream and shrinks the read timeout as the budget runs out.
+
+use std::{
+    io::{self, Read, Write},
+    net::TcpStream,
+    time::{Duration, Instant},
+};
+
+use super::listener::Stream;
+
+/// Streams whose read timeout can be changed between reads.
+pub trait ReadTimeout {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl ReadTimeout for TcpStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+}
+
+impl ReadTimeout for Stream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_read_timeout(timeout),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.set_read_timeout(timeout),
+        }
+    }
+}
+
+/// Wraps a stream, failing reads with `TimedOut` once the deadline set by
+/// `start` has passed.
+#[derive(Debug)]
+pub struct Deadline<S> {
+    stream: S,
+    /// The socket's own read timeout, restored by `clear`.
+    read_timeout: Option<Duration>,
+    deadline: Option<Instant>,
+    received: usize,
+    expired: bool,
+}
+
+impl<S: Read + ReadTimeout> Deadline<S> {
+    pub fn new(stream: S, read_timeout: Option<Duration>) -> Deadline<S> {
+        Deadline {
+            stream,
+            read_timeout,
+            deadline: None,
+            received: 0,
+            expired: false,
+        }
+    }
+
+    /// Gives the reads from now on `budget` in total. `None` removes the
+    /// limit, like `clear`.
+    pub fn start(&mut self, budget: Option<Duration>) {
+        self.deadline = budget.map(|budget| Instant::now() + budget);
+        self.received = 0;
+        self.expired = false;
+    }
+
+    /// Removes the deadline and restores the socket's read timeout.
+    pub fn clear(&mut self) -> io::Result<()> {
+        if self.deadline.take().is_some() {
+            self.stream.set_read_timeout(self.read_timeout)?;
+        }
+        Ok(())
+    }
+
+    /// Whether a read failed because the deadline passed.
+    pub fn expired(&self) -> bool {
+        self.expired
+    }
+
+    /// Bytes read since `start`.
+    pub fn received(&self) -> usize {
+        self.received
+    }
+
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+impl<S: Read + ReadTimeout> Read for Deadline<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let Some(deadline) = self.deadline else {
+            return self.stream.read(buf);
+        };
+
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            self.expired = true;
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        let timeout = match self.read_timeout {
+            Some(read_timeout) => read_timeout.min(remaining),
+            None => remaining,
+        };
+        self.stream.set_read_timeout(Some(timeout))?;
+
+        match self.stream.read(buf) {
+            Ok(read) => {
+                self.received += read;
+                Ok(read)
+            }
+            Err(err) => {
+                if Instant::now() >= deadline {
+                    self.expired = true;
+                }
+                Err(err)
+            }
+        }
+    }
+}
+
+impl<S: Write> Write for Deadline<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{net::TcpListener, thread};
+
+    #[test]
+    fn read_should_time_out_once_the_budget_is_spent() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            for _ in 0..20 {
+                if stream.write_all(b"G").is_err() {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(50));
+            }
+        });
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = Deadline::new(stream, Some(Duration::from_secs(5)));
+        reader.start(Some(Duration::from_millis(200)));
+
+        let started = Instant::now();
+        let mut buf = [0; 16];
+        let result = loop {
+            match reader.read(&mut buf) {
+                Ok(0) => break Ok(()),
+                Ok(_) => continue,
+                Err(err) => break Err(err),
+            }
+        };
+
+        assert!(result.is_err());
+        assert!(reader.expired());
+        assert!(reader.received() > 0);
+        assert!(started.elapsed() < Duration::from_secs(1));
+        drop(reader);
+        client.join().unwrap();
+    }
+}
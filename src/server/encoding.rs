@@ -0,0 +1,132 @@
+//! `Accept-Encoding` negotiation (RFC 9110, section 12.5.3).
+
+/// The "no transformation" coding, acceptable unless a client rules it out.
+pub const IDENTITY: &str = "identity";
+
+/// Content codings the server can apply to responses, in order of
+/// preference: only `br`, with the `brotli` feature, which the
+/// `Compression` middleware applies. There is no gzip or deflate encoder;
+/// those reach clients only as precompressed static files.
+#[cfg(feature = "brotli")]
+pub const SUPPORTED: &[&str] = &["br"];
+/// Content codings the server can apply to responses. Without the
+/// `brotli` feature only `identity` is offered.
+#[cfg(not(feature = "brotli"))]
+pub const SUPPORTED: &[&str] = &[];
+
+/// Parses an `Accept-Encoding` value into codings, lowercased, with their
+/// quality in thousandths. Entries with a malformed `q` are skipped.
+pub fn parse(header: &str) -> Vec<(String, u16)> {
+    header
+        .split(',')
+        .filter_map(|entry| {
+            let mut parts = entry.split(';');
+            let coding = parts.next()?.trim().to_ascii_lowercase();
+            if coding.is_empty() {
+                return None;
+            }
+            let mut quality = 1000;
+            for parameter in parts {
+                let (name, value) = parameter.split_once('=')?;
+                if name.trim().eq_ignore_ascii_case("q") {
+                    quality = parse_quality(value.trim())?;
+                }
+            }
+            Some((coding, quality))
+        })
+        .collect()
+}
+
+/// Picks the coding for a response from `supported` (in the server's
+/// order of preference) and `identity`, given the request's
+/// `Accept-Encoding` header.
+///
+/// The client's highest-ranked coding wins, earlier `supported` entries
+/// breaking ties. `identity` is acceptable unless the header excludes it
+/// with `identity;q=0` or `*;q=0`, but ranks below anything the client
+/// names. Without a header only `identity` is used. `None` means nothing
+/// is acceptable; the server answers `406 Not Acceptable` when it has a
+/// body it could only send as `identity`.
+pub fn negotiate(header: Option<&str>, supported: &[&'static str]) -> Option<&'static str> {
+    let Some(header) = header else {
+        return Some(IDENTITY);
+    };
+    let accepted = parse(header);
+    let quality = |coding: &str| {
+        accepted
+            .iter()
+            .find(|(name, _)| name == coding)
+            .or_else(|| accepted.iter().find(|(name, _)| name == "*"))
+            .map(|(_, quality)| *quality)
+    };
+
+    let mut best: Option<(&'static str, u16)> = None;
+    let candidates = supported
+        .iter()
+        .map(|coding| (*coding, quality(coding).unwrap_or(0)))
+        .chain([(IDENTITY, quality(IDENTITY).unwrap_or(1))]);
+    for (coding, quality) in candidates {
+        if quality > 0 && best.is_none_or(|(_, best)| quality > best) {
+            best = Some((coding, quality));
+        }
+    }
+    best.map(|(coding, _)| coding)
+}
+
+/// Parses a `qvalue`: `0` to `1` with at most three decimals.
+fn parse_quality(value: &str) -> Option<u16> {
+    let (whole, fraction) = value.split_once('.').unwrap_or((value, ""));
+    if fraction.len() > 3 || !fraction.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    let thousandths = format!("{:0<3}", fraction).parse::<u16>().ok()?;
+    match whole {
+        "0" => Some(thousandths),
+        "1" if thousandths == 0 => Some(1000),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CODINGS: &[&str] = &["gzip", "deflate"];
+
+    #[test]
+    fn parse_should_read_quality_values() {
+        assert_eq!(
+            parse("gzip;q=0.8, DEFLATE, br;q=1.5, *;q=0"),
+            vec![
+                ("gzip".to_string(), 800),
+                ("deflate".to_string(), 1000),
+                ("*".to_string(), 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn negotiate_should_prefer_the_highest_ranked_supported_coding() {
+        assert_eq!(negotiate(None, CODINGS), Some("identity"));
+        assert_eq!(negotiate(Some("gzip, deflate, br"), CODINGS), Some("gzip"));
+        assert_eq!(
+            negotiate(Some("gzip;q=0.5, deflate;q=0.9"), CODINGS),
+            Some("deflate")
+        );
+        assert_eq!(negotiate(Some("br"), CODINGS), Some("identity"));
+        assert_eq!(negotiate(Some(""), CODINGS), Some("identity"));
+        assert_eq!(negotiate(Some("*;q=0.1"), CODINGS), Some("gzip"));
+        assert_eq!(
+            negotiate(Some("identity;q=1, gzip;q=0.5"), CODINGS),
+            Some("identity")
+        );
+    }
+
+    #[test]
+    fn negotiate_should_fail_when_identity_is_excluded() {
+        assert_eq!(negotiate(Some("br, identity;q=0"), CODINGS), None);
+        assert_eq!(negotiate(Some("*;q=0"), CODINGS), None);
+        assert_eq!(negotiate(Some("deflate, *;q=0"), CODINGS), Some("deflate"));
+        assert_eq!(negotiate(Some("gzip, identity;q=0"), &[]), None);
+    }
+}
@@ -0,0 +1,869 @@
+//! The evented backend: one thread reads and writes every connection
+//! without blocking, and only complete requests go to the workers.
+//!
+//! A connection is `Reading` until a whole request has arrived. The request
+//! is then `Handling` on a worker, which sends the response back as bytes
+//! while the socket is left alone, and `Writing` sends them as fast as the
+//! client takes them before the connection reads again or closes.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, Read, Write},
+    net::SocketAddr,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    sync::{Arc, RwLock, mpsc},
+    time::{Duration, Instant},
+};
+
+use super::{
+    ACCEPT_POLL_INTERVAL, Backpressure, CONTINUE, Context, Server,
+    body::Body,
+    check_head,
+    connections::{ConnectionGuard, is_disconnect},
+    exchange,
+    listener::{Listener, SpareDescriptor, Stream},
+    reject_overloaded,
+    request::{HttpRequest, MAX_HEAD_BYTES},
+    response::Response,
+    set_socket_options,
+    takeover::OnUpgrade,
+    timing::{Pending, Phases},
+};
+use crate::{ThreadPool, debug, error, info, warn};
+
+/// How long the loop waits for events, at most, before checking timeouts
+/// and shutdown again.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Events handled per wakeup.
+const EVENTS: usize = 256;
+
+/// The waker's token. Listeners come next, numbered from 1, and then the
+/// connections.
+const WAKER: u64 = 0;
+
+const READABLE: u32 = (libc::EPOLLIN | libc::EPOLLRDHUP) as u32;
+const WRITABLE: u32 = libc::EPOLLOUT as u32;
+
+impl Server {
+    /// Serves the connections accepted from `listeners` on this thread,
+    /// running only the handlers on `pool`, until shutdown is requested and
+    /// the open connections finish or the grace period runs out.
+    pub(super) fn event_loop(
+        &self,
+        listeners: &[Arc<Listener>],
+        pool: &ThreadPool,
+        live: &RwLock<Arc<Context>>,
+    ) {
+        // Acceptors sharing a socket have one `Arc` each, side by side.
+        let mut listeners = listeners.to_vec();
+        listeners.dedup_by(|a, b| Arc::ptr_eq(a, b));
+        match EventLoop::new(self, listeners, pool, live) {
+            Ok(mut event_loop) => event_loop.run(),
+            Err(err) => error!("Failed to start the event loop: {}", err),
+        }
+    }
+}
+
+struct EventLoop<'a> {
+    server: &'a Server,
+    pool: &'a ThreadPool,
+    live: &'a RwLock<Arc<Context>>,
+    listeners: Vec<Arc<Listener>>,
+    poller: Poller,
+    waker: Arc<Waker>,
+    connections: HashMap<u64, Connection>,
+    next_token: u64,
+    sender: mpsc::Sender<Handled>,
+    receiver: mpsc::Receiver<Handled>,
+    /// Set once shutdown was requested: no more connections are accepted
+    /// and none is kept alive.
+    stopping: bool,
+    /// When accepting resumes after an error that is likely to repeat.
+    accept_paused_until: Option<Instant>,
+    backpressure: Backpressure,
+    spare: SpareDescriptor,
+}
+
+/// A response built by a worker for the connection `token`.
+struct Handled {
+    token: u64,
+    response: io::Result<Vec<u8>>,
+    keep_alive: bool,
+    pending: Option<Pending>,
+    upgrade: Option<OnUpgrade>,
+}
+
+impl<'a> EventLoop<'a> {
+    fn new(
+        server: &'a Server,
+        listeners: Vec<Arc<Listener>>,
+        pool: &'a ThreadPool,
+        live: &'a RwLock<Arc<Context>>,
+    ) -> io::Result<EventLoop<'a>> {
+        let poller = Poller::new()?;
+        let waker = Arc::new(Waker::new()?);
+        poller.add(waker.file.as_raw_fd(), WAKER, READABLE)?;
+        for (token, listener) in (1..).zip(&listeners) {
+            listener.set_nonblocking(true)?;
+            poller.add(listener_fd(listener), token, READABLE)?;
+        }
+        let (sender, receiver) = mpsc::channel();
+        Ok(EventLoop {
+            server,
+            pool,
+            live,
+            next_token: listeners.len() as u64 + 1,
+            listeners,
+            poller,
+            waker,
+            connections: HashMap::new(),
+            sender,
+            receiver,
+            stopping: false,
+            accept_paused_until: None,
+            backpressure: Backpressure::default(),
+            spare: SpareDescriptor::new(),
+        })
+    }
+
+    fn run(&mut self) {
+        let mut events = vec![libc::epoll_event { events: 0, u64: 0 }; EVENTS];
+        let mut deadline = None;
+        let mut last_sweep = Instant::now();
+        loop {
+            if deadline.is_none() && self.server.shutdown_requested() {
+                deadline = Some(Instant::now() + self.server.config.grace_period);
+                self.stop_accepting();
+            }
+            if let Some(deadline) = deadline
+                && (self.connections.is_empty() || Instant::now() >= deadline)
+            {
+                if !self.connections.is_empty() {
+                    warn!(
+                        "Grace period expired with {} connections still open.",
+                        self.connections.len()
+                    );
+                }
+                return;
+            }
+
+            let ready = match self.poller.wait(&mut events, POLL_INTERVAL) {
+                Ok(ready) => ready,
+                Err(err) => {
+                    error!("Event loop failed: {}", err);
+                    return;
+                }
+            };
+            for event in &events[..ready] {
+                // Copied out: the kernel's struct is packed.
+                let token = event.u64;
+                match token {
+                    WAKER => self.waker.reset(),
+                    token if token <= self.listeners.len() as u64 => {
+                        let listener = Arc::clone(&self.listeners[token as usize - 1]);
+                        self.accept(&listener);
+                    }
+                    token => self.ready(token),
+                }
+            }
+            while let Ok(handled) = self.receiver.try_recv() {
+                self.handled(handled);
+            }
+
+            let now = Instant::now();
+            if let Some(until) = self.accept_paused_until
+                && now >= until
+            {
+                self.resume_accepting();
+            }
+            if now - last_sweep >= POLL_INTERVAL {
+                self.expire(now);
+                last_sweep = now;
+            }
+        }
+    }
+
+    /// Accepts every connection waiting on `listener`.
+    fn accept(&mut self, listener: &Listener) {
+        loop {
+            let marks = self.live.read().unwrap().accept_water_marks;
+            if self.backpressure.holds(self.pool.queue_depth(), marks) {
+                self.pause_accepting(ACCEPT_POLL_INTERVAL);
+                return;
+            }
+            let (stream, peer) = match listener.accept() {
+                Ok(accepted) => accepted,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return,
+                Err(err) => match self.server.accept_failed(&err, listener, &mut self.spare) {
+                    Some(backoff) => {
+                        self.pause_accepting(backoff);
+                        return;
+                    }
+                    None => continue,
+                },
+            };
+            let context = Arc::clone(&self.live.read().unwrap());
+            let Some((stream, guard)) = self.server.admit(stream, peer, &context) else {
+                continue;
+            };
+            if context.proxy_protocol {
+                warn!(
+                    "Closing connection from {:?}: the evented backend does not read PROXY protocol headers",
+                    peer
+                );
+                continue;
+            }
+            if let Some(limit) = context.max_queued_connections
+                && self.pool.queue_depth() > limit
+            {
+                reject_overloaded(stream, peer, &context);
+                continue;
+            }
+            if let Err(err) = stream.set_nonblocking(true) {
+                warn!("Failed to make the connection non-blocking: {}", err);
+                continue;
+            }
+            if let Err(err) = set_socket_options(&stream, &context) {
+                warn!("Failed to set socket options: {}", err);
+            }
+
+            let token = self.next_token;
+            self.next_token += 1;
+            self.connections
+                .insert(token, Connection::new(stream, peer, context, guard));
+            self.drive(token);
+        }
+    }
+
+    /// Stops watching the listeners for `backoff`, as waiting connections
+    /// would wake the loop again straight away, after an error or while
+    /// the queue is too deep.
+    fn pause_accepting(&mut self, backoff: Duration) {
+        if self.accept_paused_until.is_none() {
+            for listener in &self.listeners {
+                let _ = self.poller.delete(listener_fd(listener));
+            }
+        }
+        self.accept_paused_until = Some(Instant::now() + backoff);
+    }
+
+    fn resume_accepting(&mut self) {
+        self.accept_paused_until = None;
+        if self.stopping {
+            return;
+        }
+        for (token, listener) in (1..).zip(&self.listeners) {
+            if let Err(err) = self.poller.add(listener_fd(listener), token, READABLE) {
+                error!("Failed to watch a listener again: {}", err);
+            }
+        }
+    }
+
+    /// Closes the listeners to new connections, and the connections that
+    /// are waiting for another request.
+    fn stop_accepting(&mut self) {
+        self.stopping = true;
+        for listener in &self.listeners {
+            let _ = self.poller.delete(listener_fd(listener));
+        }
+        self.connections
+            .retain(|_, connection| !connection.is_idle());
+    }
+
+    /// Handles an event on the connection `token`.
+    fn ready(&mut self, token: u64) {
+        let Some(connection) = self.connections.get_mut(&token) else {
+            return;
+        };
+        if connection.state == State::Reading && !connection.receive() {
+            self.connections.remove(&token);
+            return;
+        }
+        self.drive(token);
+    }
+
+    /// Starts sending the response a worker built.
+    fn handled(&mut self, handled: Handled) {
+        let Some(connection) = self.connections.get_mut(&handled.token) else {
+            return;
+        };
+        match handled.response {
+            Ok(output) => {
+                connection.output = output;
+                connection.written = 0;
+                // Pipelined requests are still answered after the client
+                // closes its side; `next_request` closes once they are done.
+                connection.keep_alive = handled.keep_alive;
+                connection.state = State::Writing;
+                connection.active = Instant::now();
+                connection.pending = handled.pending.map(|pending| (pending, connection.active));
+                connection.upgrade = handled.upgrade;
+                self.drive(handled.token);
+            }
+            Err(err) => {
+                warn!("{}", err);
+                self.connections.remove(&handled.token);
+            }
+        }
+    }
+
+    /// Moves the connection `token` along as far as it can go without
+    /// waiting: answers, sends and reads pipelined requests until it needs
+    /// the socket, a worker, or is done.
+    fn drive(&mut self, token: u64) {
+        loop {
+            let Some(connection) = self.connections.get_mut(&token) else {
+                return;
+            };
+            let interest = match connection.state {
+                State::Handling => return,
+                State::Reading => match connection.next_request() {
+                    Next::Wait => READABLE,
+                    Next::Close => break,
+                    Next::Refuse(response) => {
+                        connection.refuse(response);
+                        continue;
+                    }
+                    Next::Handle(request, body, streamed) => {
+                        if let Err(err) = connection.watch(&self.poller, token, None) {
+                            warn!("{}", err);
+                            break;
+                        }
+                        self.dispatch(token, request, body, streamed);
+                        return;
+                    }
+                },
+                State::Writing => match connection.flush() {
+                    Ok(false) => WRITABLE,
+                    Ok(true) if connection.upgrade.is_some() => {
+                        self.hand_over(token);
+                        return;
+                    }
+                    Ok(true) if connection.keep_alive && !self.stopping => {
+                        connection.read_next();
+                        continue;
+                    }
+                    Ok(true) => break,
+                    Err(err) => {
+                        debug!("{}", err);
+                        if is_disconnect(&err) {
+                            self.server.connections.client_disconnected();
+                        }
+                        break;
+                    }
+                },
+            };
+            if let Err(err) = connection.watch(&self.poller, token, Some(interest)) {
+                warn!("{}", err);
+                break;
+            }
+            return;
+        }
+        self.connections.remove(&token);
+    }
+
+    /// Has a worker answer `request`, whose body has arrived in full.
+    fn dispatch(&mut self, token: u64, request: Box<HttpRequest>, body: Vec<u8>, streamed: u64) {
+        let Some(connection) = self.connections.get_mut(&token) else {
+            return;
+        };
+        connection.state = State::Handling;
+        connection.served += 1;
+
+        let context = Arc::clone(&connection.context);
+        let (peer, served) = (connection.peer, connection.served);
+        let parse = connection.since.elapsed();
+        let dispatched = Instant::now();
+        let sender = self.sender.clone();
+        let waker = Arc::clone(&self.waker);
+        let pool = context.pool_for(&request).map(|(_, pool)| pool.clone());
+        let job = move || {
+            let mut request = *request;
+            request.set_phases(Phases {
+                parse,
+                queue: dispatched.elapsed(),
+                ..Phases::default()
+            });
+            let body = if streamed == 0 {
+                request.set_body(body);
+                Vec::new()
+            } else {
+                body
+            };
+            let mut reader = body.as_slice();
+            let mut body = Body::new(&mut reader, streamed);
+            let (mut response, keep_alive) =
+                exchange(&mut request, &mut body, peer, served, &context);
+            let pending = Pending::new(&request, &response);
+            let upgrade = response.take_upgrade();
+
+            let mut output = Vec::new();
+            let response = response.write_to(&mut output).map(|_| output);
+            let result = match &response {
+                Ok(_) => Ok("Request handled successfully".to_string()),
+                Err(err) => Err(format!("Error building response: {}", err)),
+            };
+            let _ = sender.send(Handled {
+                token,
+                response,
+                keep_alive,
+                pending,
+                upgrade,
+            });
+            waker.wake();
+            result
+        };
+        // Routes pinned with `Router::pool` are answered on their pool.
+        match pool {
+            Some(pool) => pool.execute(job),
+            None => self.pool.execute(job),
+        };
+    }
+
+    /// Runs the upgrade of the connection `token`, whose `101` has been
+    /// sent, on a worker that owns the socket from now on.
+    fn hand_over(&mut self, token: u64) {
+        let Some(mut connection) = self.connections.remove(&token) else {
+            return;
+        };
+        if let Err(err) = connection.watch(&self.poller, token, None) {
+            warn!("{}", err);
+            return;
+        }
+        let Some(upgrade) = connection.upgrade.take() else {
+            return;
+        };
+        self.pool.execute(move || {
+            let Connection {
+                stream,
+                peer,
+                input,
+                _guard,
+                ..
+            } = connection;
+            upgrade.run(stream, input, peer);
+            Ok("Upgraded connection handled successfully".to_string())
+        });
+    }
+
+    /// Closes the connections that ran out of time, refusing incomplete
+    /// heads with `408`.
+    fn expire(&mut self, now: Instant) {
+        let expired: Vec<(u64, Timeout)> = self
+            .connections
+            .iter()
+            .filter_map(|(&token, connection)| Some((token, connection.timeout(now)?)))
+            .collect();
+        for (token, timeout) in expired {
+            match timeout {
+                Timeout::Idle => {
+                    debug!("Closing idle connection");
+                    self.connections.remove(&token);
+                }
+                Timeout::Head => {
+                    info!("Request head not received before the header timeout");
+                    if let Some(connection) = self.connections.get_mut(&token) {
+                        connection.refuse(Response::error_page(408));
+                    }
+                    self.drive(token);
+                }
+                Timeout::Stalled => {
+                    debug!("Closing connection that stopped reading or writing");
+                    self.connections.remove(&token);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Reading,
+    Handling,
+    Writing,
+}
+
+/// What a `Reading` connection does next.
+enum Next {
+    /// Wait for more of the request.
+    Wait,
+    /// Answer the request with its body, and the body's length if the
+    /// handler streams it.
+    Handle(Box<HttpRequest>, Vec<u8>, u64),
+    /// Send the response and close.
+    Refuse(Response),
+    /// Close without answering.
+    Close,
+}
+
+/// Why a connection ran out of time.
+enum Timeout {
+    /// It sent nothing.
+    Idle,
+    /// Its request head was not complete in time.
+    Head,
+    /// It stopped sending its body or reading the response.
+    Stalled,
+}
+
+struct Connection {
+    stream: Stream,
+    peer: Option<SocketAddr>,
+    context: Arc<Context>,
+    _guard: ConnectionGuard,
+    state: State,
+    /// The events the poller watches for, if it watches the socket.
+    interest: Option<u32>,
+    /// Received bytes that are not part of a dispatched request yet.
+    input: Vec<u8>,
+    /// The client has closed its side of the connection.
+    eof: bool,
+    /// `100 Continue` was sent for the request being read.
+    continued: bool,
+    /// The response being sent, of which `written` bytes have been.
+    output: Vec<u8>,
+    written: usize,
+    /// The timed request being sent, and when sending started.
+    pending: Option<(Pending, Instant)>,
+    keep_alive: bool,
+    /// Takes the connection over once the response is sent.
+    upgrade: Option<OnUpgrade>,
+    served: usize,
+    /// When the connection started waiting for the next request, or when
+    /// its first byte arrived.
+    since: Instant,
+    /// When bytes last moved either way.
+    active: Instant,
+}
+
+impl Connection {
+    fn new(
+        stream: Stream,
+        peer: Option<SocketAddr>,
+        context: Arc<Context>,
+        guard: ConnectionGuard,
+    ) -> Connection {
+        let now = Instant::now();
+        Connection {
+            stream,
+            peer,
+            context,
+            _guard: guard,
+            state: State::Reading,
+            interest: None,
+            input: Vec::new(),
+            eof: false,
+            continued: false,
+            output: Vec::new(),
+            written: 0,
+            pending: None,
+            keep_alive: false,
+            upgrade: None,
+            served: 0,
+            since: now,
+            active: now,
+        }
+    }
+
+    /// Reads everything that has arrived. Returns `false` if reading
+    /// failed; a client closing its side only sets `eof`.
+    fn receive(&mut self) -> bool {
+        let mut buffer = [0; 16 * 1024];
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => {
+                    self.eof = true;
+                    return true;
+                }
+                Ok(read) => {
+                    let now = Instant::now();
+                    if self.input.is_empty() {
+                        self.since = now;
+                    }
+                    self.active = now;
+                    self.input.extend_from_slice(&buffer[..read]);
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return true,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => {
+                    debug!("{}", err);
+                    return false;
+                }
+            }
+        }
+    }
+
+    /// Takes the next complete request out of `input`, checking it like
+    /// the default backend does.
+    fn next_request(&mut self) -> Next {
+        let waiting = if self.eof { Next::Close } else { Next::Wait };
+        let end = match head_end(&self.input) {
+            Some(end) if end <= MAX_HEAD_BYTES => end,
+            _ if self.input.len() > MAX_HEAD_BYTES => {
+                info!("Request head over {} bytes", MAX_HEAD_BYTES);
+                return Next::Refuse(Response::error_page(431));
+            }
+            _ => return waiting,
+        };
+        let request = match HttpRequest::parse_head(&mut &self.input[..end]) {
+            Ok(Some(request)) => request,
+            Ok(None) => return Next::Close,
+            Err(err) => {
+                info!("Invalid HTTP request: {}", err);
+                return Next::Refuse(Response::error_page(err.status()));
+            }
+        };
+        #[cfg(feature = "http2")]
+        if super::http2::is_preface(&request) {
+            warn!("HTTP/2 is only served by the default backend");
+            return Next::Refuse(Response::error_page(505));
+        }
+        let expected = match check_head(&request, &self.context) {
+            Ok(expected) => expected,
+            Err(response) => return Next::Refuse(response),
+        };
+
+        if self.input.len() < end + expected.length {
+            if expected.expects_continue && !self.continued {
+                // A response this short fits in any socket buffer.
+                let _ = self.stream.write_all(CONTINUE);
+                self.continued = true;
+            }
+            return waiting;
+        }
+        let mut body = self.input.split_off(end);
+        self.input = body.split_off(expected.length);
+        self.continued = false;
+        let streamed = if expected.streamed {
+            expected.length as u64
+        } else {
+            0
+        };
+        Next::Handle(Box::new(request), body, streamed)
+    }
+
+    /// Sends `response` and closes the connection afterwards.
+    fn refuse(&mut self, response: Response) {
+        self.output.clear();
+        self.written = 0;
+        // Refusals are built-in pages, which never fail to serialize.
+        let _ = self
+            .context
+            .identified(response)
+            .header("Connection", "close")
+            .write_to(&mut self.output);
+        self.keep_alive = false;
+        self.state = State::Writing;
+    }
+
+    /// Writes as much of the response as the socket takes. Returns whether
+    /// all of it has been sent.
+    fn flush(&mut self) -> io::Result<bool> {
+        while self.written < self.output.len() {
+            match self.stream.write(&self.output[self.written..]) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(written) => {
+                    self.written += written;
+                    self.active = Instant::now();
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        if let Some((pending, started)) = self.pending.take() {
+            pending.written(started.elapsed());
+        }
+        Ok(true)
+    }
+
+    /// Waits for the next request on a kept-alive connection.
+    fn read_next(&mut self) {
+        self.output = Vec::new();
+        self.written = 0;
+        self.state = State::Reading;
+        self.since = Instant::now();
+    }
+
+    /// Waiting for a request that hasn't started to arrive.
+    fn is_idle(&self) -> bool {
+        self.state == State::Reading && self.input.is_empty()
+    }
+
+    /// Has `poller` watch the socket for `interest`, or stop watching it.
+    fn watch(&mut self, poller: &Poller, token: u64, interest: Option<u32>) -> io::Result<()> {
+        let fd = stream_fd(&self.stream);
+        match (self.interest, interest) {
+            (current, wanted) if current == wanted => {}
+            (None, Some(events)) => poller.add(fd, token, events)?,
+            (Some(_), Some(events)) => poller.modify(fd, token, events)?,
+            (Some(_), None) => poller.delete(fd)?,
+            (None, None) => {}
+        }
+        self.interest = interest;
+        Ok(())
+    }
+
+    /// Whether the connection has waited longer than its timeouts allow.
+    fn timeout(&self, now: Instant) -> Option<Timeout> {
+        let context = &self.context;
+        let past = |start: Instant, limit: Option<Duration>| {
+            limit.is_some_and(|limit| now.duration_since(start) >= limit)
+        };
+        match self.state {
+            State::Handling => None,
+            State::Writing => past(self.active, context.write_timeout).then_some(Timeout::Stalled),
+            State::Reading if self.input.is_empty() => {
+                let idle = if self.served == 0 {
+                    context.header_timeout
+                } else {
+                    context.keep_alive_timeout
+                };
+                past(self.since, idle.or(context.read_timeout)).then_some(Timeout::Idle)
+            }
+            State::Reading
+                if head_end(&self.input).is_none() && past(self.since, context.header_timeout) =>
+            {
+                Some(Timeout::Head)
+            }
+            State::Reading => past(self.active, context.read_timeout).then_some(Timeout::Stalled),
+        }
+    }
+}
+
+/// The length of the request head at the start of `input`, up to and
+/// including the empty line that ends it, once all of it has arrived.
+fn head_end(input: &[u8]) -> Option<usize> {
+    input.iter().enumerate().find_map(|(at, &byte)| {
+        if byte != b'\n' {
+            return None;
+        }
+        match &input[at + 1..] {
+            [b'\n', ..] => Some(at + 2),
+            [b'\r', b'\n', ..] => Some(at + 3),
+            _ => None,
+        }
+    })
+}
+
+fn stream_fd(stream: &Stream) -> RawFd {
+    match stream {
+        Stream::Tcp(stream) => stream.as_raw_fd(),
+        Stream::Unix(stream) => stream.as_raw_fd(),
+    }
+}
+
+fn listener_fd(listener: &Listener) -> RawFd {
+    match listener {
+        Listener::Tcp(listener) => listener.as_raw_fd(),
+        Listener::Unix(listener, _) => listener.as_raw_fd(),
+    }
+}
+
+/// An epoll instance, reporting which watched sockets are ready.
+struct Poller {
+    fd: OwnedFd,
+}
+
+impl Poller {
+    fn new() -> io::Result<Poller> {
+        let fd = unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Poller {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+        })
+    }
+
+    fn add(&self, fd: RawFd, token: u64, events: u32) -> io::Result<()> {
+        self.control(libc::EPOLL_CTL_ADD, fd, token, events)
+    }
+
+    fn modify(&self, fd: RawFd, token: u64, events: u32) -> io::Result<()> {
+        self.control(libc::EPOLL_CTL_MOD, fd, token, events)
+    }
+
+    fn delete(&self, fd: RawFd) -> io::Result<()> {
+        self.control(libc::EPOLL_CTL_DEL, fd, 0, 0)
+    }
+
+    fn control(
+        &self,
+        operation: libc::c_int,
+        fd: RawFd,
+        token: u64,
+        events: u32,
+    ) -> io::Result<()> {
+        let mut event = libc::epoll_event { events, u64: token };
+        if unsafe { libc::epoll_ctl(self.fd.as_raw_fd(), operation, fd, &mut event) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Waits up to `timeout` for events, returning how many were stored at
+    /// the start of `events`.
+    fn wait(&self, events: &mut [libc::epoll_event], timeout: Duration) -> io::Result<usize> {
+        let ready = unsafe {
+            libc::epoll_wait(
+                self.fd.as_raw_fd(),
+                events.as_mut_ptr(),
+                events.len() as libc::c_int,
+                timeout.as_millis() as libc::c_int,
+            )
+        };
+        if ready < 0 {
+            let err = io::Error::last_os_error();
+            // A signal arrived; the loop checks for shutdown anyway.
+            if err.kind() == io::ErrorKind::Interrupted {
+                return Ok(0);
+            }
+            return Err(err);
+        }
+        Ok(ready as usize)
+    }
+}
+
+/// An eventfd the workers use to interrupt the poller once a response is
+/// ready.
+struct Waker {
+    file: File,
+}
+
+impl Waker {
+    fn new() -> io::Result<Waker> {
+        let fd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Waker {
+            file: File::from(unsafe { OwnedFd::from_raw_fd(fd) }),
+        })
+    }
+
+    fn wake(&self) {
+        // Only fails if the counter is already far from zero.
+        let _ = (&self.file).write(&1u64.to_ne_bytes());
+    }
+
+    fn reset(&self) {
+        let mut count = [0; 8];
+        let _ = (&self.file).read(&mut count);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn head_end_should_find_the_empty_line() {
+        assert_eq!(head_end(b"GET / HTTP/1.1\r\nHost: a\r\n\r\nbody"), Some(27));
+        assert_eq!(head_end(b"GET / HTTP/1.1\n\nbody"), Some(16));
+        assert_eq!(head_end(b"GET / HTTP/1.1\r\nHost: a\r\n"), None);
+        assert_eq!(head_end(b""), None);
+    }
+}
@@ -0,0 +1,210 @@
+//! Finding the client behind reverse proxies from the `Forwarded`
+//! (RFC 7239) and `X-Forwarded-For` headers.
+//!
+//! Anyone can send those headers, so they are only believed when the
+//! connection comes from a trusted proxy, and only as far back as the
+//! chain of trusted proxies goes.
+
+use std::{fmt, net::IpAddr, str::FromStr};
+
+use super::request::HttpRequest;
+
+/// A block of IP addresses such as `10.0.0.0/8` or `2001:db8::/32`. A bare
+/// address is a block of one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                masked(u32::from(network).into(), self.prefix, 32)
+                    == masked(u32::from(ip).into(), self.prefix, 32)
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                masked(network.into(), self.prefix, 128) == masked(ip.into(), self.prefix, 128)
+            }
+            (IpAddr::V4(_), IpAddr::V6(ip)) => ip
+                .to_ipv4_mapped()
+                .is_some_and(|ip| self.contains(IpAddr::V4(ip))),
+            (IpAddr::V6(_), IpAddr::V4(_)) => false,
+        }
+    }
+}
+
+/// The top `prefix` bits of an address `width` bits wide.
+fn masked(bits: u128, prefix: u8, width: u32) -> u128 {
+    match u32::from(prefix) {
+        0 => 0,
+        prefix => bits >> (width - prefix),
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Cidr, String> {
+        let (address, prefix) = match text.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (text, None),
+        };
+        let network: IpAddr = address
+            .parse()
+            .map_err(|_| format!("not an IP address: {:?}", address))?;
+        let width = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse()
+                .ok()
+                .filter(|prefix| *prefix <= width)
+                .ok_or_else(|| format!("prefix length must be 0 to {}: {:?}", width, prefix))?,
+            None => width,
+        };
+        Ok(Cidr { network, prefix })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+/// The address of the client that sent `request` through `peer`.
+///
+/// When `peer` is one of the `trusted` proxies, the forwarding headers are
+/// walked from the nearest hop outwards and the first address that is not
+/// a trusted proxy is the client. `Forwarded` wins over `X-Forwarded-For`
+/// when both are present. Otherwise, or when the headers can't be read,
+/// the answer is the last address that could be vouched for.
+pub fn client_ip(peer: IpAddr, request: &HttpRequest, trusted: &[Cidr]) -> IpAddr {
+    let is_trusted = |ip: IpAddr| trusted.iter().any(|cidr| cidr.contains(ip));
+    if !is_trusted(peer) {
+        return peer;
+    }
+
+    let mut client = peer;
+    for hop in forwarded_for(request).iter().rev() {
+        let Some(hop) = *hop else {
+            break;
+        };
+        client = hop;
+        if !is_trusted(hop) {
+            break;
+        }
+    }
+    client
+}
+
+/// The addresses the forwarding headers list, the original client first.
+/// Entries that are not IP addresses, like `unknown`, are `None`.
+fn forwarded_for(request: &HttpRequest) -> Vec<Option<IpAddr>> {
+    let forwarded: Vec<Option<IpAddr>> = request
+        .header_values("Forwarded")
+        .flat_map(|value| value.split(','))
+        .filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (name, value) = pair.split_once('=')?;
+                name.trim()
+                    .eq_ignore_ascii_case("for")
+                    .then(|| parse_node(value.trim().trim_matches('"')))
+            })
+        })
+        .collect();
+    if !forwarded.is_empty() {
+        return forwarded;
+    }
+    request
+        .header_values("X-Forwarded-For")
+        .flat_map(|value| value.split(','))
+        .map(|hop| parse_node(hop.trim()))
+        .collect()
+}
+
+/// Parses an address that may carry a port: `192.0.2.1`, `192.0.2.1:80`,
+/// `2001:db8::1` or `[2001:db8::1]:80`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    if let Some(rest) = node.strip_prefix('[') {
+        return rest.split_once(']')?.0.parse().ok();
+    }
+    node.parse().ok().or_else(|| {
+        let (address, port) = node.rsplit_once(':')?;
+        port.parse::<u16>().ok()?;
+        address.parse().ok().filter(IpAddr::is_ipv4)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(headers: &str) -> HttpRequest {
+        let raw = format!("GET / HTTP/1.1\r\n{}\r\n", headers);
+        HttpRequest::parse(&mut raw.as_bytes()).unwrap().unwrap()
+    }
+
+    fn cidrs(blocks: &[&str]) -> Vec<Cidr> {
+        blocks.iter().map(|block| block.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn cidr_should_match_addresses_in_the_block() {
+        let private: Cidr = "10.0.0.0/8".parse().unwrap();
+        let host: Cidr = "192.0.2.1".parse().unwrap();
+        let v6: Cidr = "2001:db8::/32".parse().unwrap();
+
+        assert!(private.contains("10.200.3.4".parse().unwrap()));
+        assert!(!private.contains("11.0.0.1".parse().unwrap()));
+        assert!(private.contains("::ffff:10.1.2.3".parse().unwrap()));
+        assert!(host.contains("192.0.2.1".parse().unwrap()));
+        assert!(!host.contains("192.0.2.2".parse().unwrap()));
+        assert!(v6.contains("2001:db8:1::5".parse().unwrap()));
+        assert!(
+            "0.0.0.0/0"
+                .parse::<Cidr>()
+                .unwrap()
+                .contains("8.8.8.8".parse().unwrap())
+        );
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("example.com".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn client_ip_should_only_believe_trusted_proxies() {
+        let trusted = cidrs(&["10.0.0.0/8"]);
+        let proxy: IpAddr = "10.0.0.2".parse().unwrap();
+        let stranger: IpAddr = "198.51.100.9".parse().unwrap();
+        let forwarded = request("X-Forwarded-For: 1.2.3.4, 203.0.113.5, 10.0.0.7\r\n");
+
+        assert_eq!(
+            client_ip(proxy, &forwarded, &trusted),
+            "203.0.113.5".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(client_ip(stranger, &forwarded, &trusted), stranger);
+        assert_eq!(client_ip(proxy, &request(""), &trusted), proxy);
+        assert_eq!(
+            client_ip(
+                proxy,
+                &request("X-Forwarded-For: 203.0.113.5, unknown\r\n"),
+                &trusted
+            ),
+            proxy
+        );
+    }
+
+    #[test]
+    fn client_ip_should_prefer_the_forwarded_header() {
+        let trusted = cidrs(&["10.0.0.0/8"]);
+        let forwarded = request(
+            "X-Forwarded-For: 9.9.9.9\r\nForwarded: for=\"[2001:db8:cafe::17]:4711\";proto=https, for=10.0.0.3:80;by=10.0.0.2\r\n",
+        );
+
+        assert_eq!(
+            client_ip("10.0.0.2".parse().unwrap(), &forwarded, &trusted),
+            "2001:db8:cafe::17".parse::<IpAddr>().unwrap()
+        );
+    }
+}
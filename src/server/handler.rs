@@ -0,0 +1,83 @@
+//! Request handlers: anything that turns a request into a response.
+
+use std::{
+    any::{self, Any},
+    marker::PhantomData,
+    sync::Arc,
+};
+
+use super::{request::HttpRequest, response::Response};
+
+/// The value given to `Server::with_state`, shared by every request.
+pub(crate) type State = Arc<dyn Any + Send + Sync>;
+
+/// Answers a request.
+///
+/// Closures taking `&HttpRequest` are handlers. Implement the trait on a
+/// struct to give a handler state of its own, such as a connection pool or
+/// a cache, that it can be built and tested with.
+///
+/// # Example
+///
+/// ```
+/// # use std::sync::atomic::{AtomicUsize, Ordering};
+/// # use harbor::server::{Handler, HttpRequest, Response, Router};
+/// struct Counter {
+///     hits: AtomicUsize,
+/// }
+///
+/// impl Handler for Counter {
+///     fn handle(&self, _: &HttpRequest) -> Response {
+///         let hits = self.hits.fetch_add(1, Ordering::Relaxed) + 1;
+///         Response::new(200).body(hits.to_string())
+///     }
+/// }
+///
+/// let router = Router::new().handler("GET", "/hits", Counter { hits: AtomicUsize::new(0) });
+/// ```
+pub trait Handler: Send + Sync {
+    fn handle(&self, request: &HttpRequest) -> Response;
+}
+
+impl<F> Handler for F
+where
+    F: Fn(&HttpRequest) -> Response + Send + Sync,
+{
+    fn handle(&self, request: &HttpRequest) -> Response {
+        self(request)
+    }
+}
+
+/// A handler taking the server's state of type `T`, registered with
+/// `Router::stateful`.
+pub(crate) struct Stateful<T, F> {
+    handler: F,
+    state: PhantomData<fn(&T)>,
+}
+
+impl<T, F> Stateful<T, F> {
+    pub(crate) fn new(handler: F) -> Stateful<T, F> {
+        Stateful {
+            handler,
+            state: PhantomData,
+        }
+    }
+}
+
+impl<T, F> Handler for Stateful<T, F>
+where
+    T: Send + Sync + 'static,
+    F: Fn(&HttpRequest, &T) -> Response + Send + Sync,
+{
+    /// Panics, which the server answers with `500`, if the server was not
+    /// given a `T` with `Server::with_state`.
+    fn handle(&self, request: &HttpRequest) -> Response {
+        let Some(state) = request.state::<T>() else {
+            panic!(
+                "no {} state; pass one to Server::with_state",
+                any::type_name::<T>()
+            );
+        };
+        (self.handler)(request, state)
+    }
+}
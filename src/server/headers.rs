@@ -0,0 +1,84 @@
+//! The header fields of a request, looked up by name without regard to
+//! case.
+
+/// Header fields in the order the client sent them.
+///
+/// Names keep the case they arrived in, for logging and forwarding, and
+/// every lookup ignores it: `get("content-length")` finds a
+/// `Content-Length` header. A name may appear more than once; `get`
+/// returns the first value and `get_all` each of them. Requests carry few
+/// enough headers that a scan beats hashing their names.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Headers {
+    fields: Vec<(String, String)>,
+}
+
+impl Headers {
+    pub fn new() -> Headers {
+        Headers::default()
+    }
+
+    /// The value of the first header called `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The values of every header called `name`, in the order received,
+    /// e.g. each `Cookie` line.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> {
+        self.fields
+            .iter()
+            .filter(move |(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Every header as a name and value, in the order received.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.fields
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    /// The number of header lines, counting repeated names each time.
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    pub(crate) fn push(&mut self, name: String, value: String) {
+        self.fields.push((name, value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn get_should_ignore_case_and_get_all_should_keep_every_value() {
+        let mut headers = Headers::new();
+        headers.push("Set-Cookie".to_string(), "a=1".to_string());
+        headers.push("Host".to_string(), "example.com".to_string());
+        headers.push("set-cookie".to_string(), "b=2".to_string());
+
+        assert_eq!(headers.get("HOST"), Some("example.com"));
+        assert_eq!(headers.get("set-cookie"), Some("a=1"));
+        assert_eq!(
+            headers.get_all("SET-COOKIE").collect::<Vec<_>>(),
+            ["a=1", "b=2"]
+        );
+        assert!(!headers.contains("Content-Length"));
+        assert_eq!(headers.len(), 3);
+        assert_eq!(headers.iter().next(), Some(("Set-Cookie", "a=1")));
+    }
+}
@@ -0,0 +1,506 @@
+//! HPACK (RFC 7541), the header compression of HTTP/2.
+//!
+//! The decoder supports everything a client may send. The encoder keeps
+//! to literals that are never added to the client's dynamic table and
+//! never Huffman-coded, which every decoder accepts.
+
+use std::{collections::VecDeque, sync::OnceLock};
+
+use super::request::{MAX_HEAD_BYTES, MAX_HEADERS};
+
+/// The largest header list a block may decode to, counted as RFC 7541
+/// sizes table entries and announced as `SETTINGS_MAX_HEADER_LIST_SIZE`.
+/// Like `MAX_HEADERS`, it keeps HTTP/2 requests within the HTTP/1 limits.
+pub const MAX_HEADER_LIST_SIZE: usize = MAX_HEAD_BYTES;
+
+/// The static table, indexed from 1.
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+/// The length in bits of each symbol's Huffman code (Appendix B), symbol
+/// 256 being end-of-string. The code is canonical, so the lengths are
+/// all it takes to rebuild it.
+const CODE_LENGTHS: [u8; 257] = [
+    13, 23, 28, 28, 28, 28, 28, 28, 28, 24, 30, 28, 28, 30, 28, 28, //
+    28, 28, 28, 28, 28, 28, 30, 28, 28, 28, 28, 28, 28, 28, 28, 28, //
+    6, 10, 10, 12, 13, 6, 8, 11, 10, 10, 8, 11, 8, 6, 6, 6, //
+    5, 5, 5, 6, 6, 6, 6, 6, 6, 6, 7, 8, 15, 6, 12, 10, //
+    13, 6, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, //
+    7, 7, 7, 7, 7, 7, 7, 7, 8, 7, 8, 13, 19, 13, 14, 6, //
+    15, 5, 6, 5, 6, 5, 6, 6, 6, 5, 7, 7, 6, 6, 6, 5, //
+    6, 7, 6, 5, 5, 6, 7, 7, 7, 7, 7, 15, 11, 14, 13, 28, //
+    20, 22, 20, 20, 22, 22, 22, 23, 22, 23, 23, 23, 23, 23, 24, 23, //
+    24, 24, 22, 23, 24, 23, 23, 23, 23, 21, 22, 23, 22, 23, 23, 24, //
+    22, 21, 20, 22, 22, 23, 23, 21, 23, 22, 22, 24, 21, 22, 23, 23, //
+    21, 21, 22, 21, 23, 22, 23, 23, 20, 22, 22, 22, 23, 22, 22, 23, //
+    26, 26, 20, 19, 22, 23, 22, 25, 26, 26, 26, 27, 27, 26, 24, 25, //
+    19, 21, 26, 27, 27, 26, 27, 24, 21, 21, 26, 26, 28, 27, 27, 27, //
+    20, 24, 20, 21, 22, 21, 21, 23, 22, 22, 25, 25, 24, 24, 26, 23, //
+    26, 27, 26, 26, 27, 27, 27, 27, 27, 28, 27, 27, 27, 27, 27, 26, //
+    30,
+];
+
+const MAX_CODE_LENGTH: usize = 30;
+const END_OF_STRING: u16 = 256;
+
+/// Decodes header blocks, keeping the dynamic table they build up.
+#[derive(Debug)]
+pub struct Decoder {
+    table: VecDeque<(String, String)>,
+    size: usize,
+    max_size: usize,
+    /// The most the client may resize the table to, from our settings.
+    limit: usize,
+}
+
+impl Decoder {
+    /// A decoder allowing a dynamic table of `limit` bytes, as announced in
+    /// `SETTINGS_HEADER_TABLE_SIZE`.
+    pub fn new(limit: usize) -> Decoder {
+        Decoder {
+            table: VecDeque::new(),
+            size: 0,
+            max_size: limit,
+            limit,
+        }
+    }
+
+    /// Decodes a complete header block into names and values.
+    ///
+    /// Fails as soon as the list grows over `MAX_HEADER_LIST_SIZE` or
+    /// `MAX_HEADERS` fields besides the pseudo-headers, since a small block
+    /// can refer to one table entry many times over. The table is then
+    /// out of step with the client's, so the connection has to end.
+    pub fn decode(&mut self, block: &[u8]) -> Result<Vec<(String, String)>, String> {
+        let mut headers = HeaderList::default();
+        let mut position = 0;
+        while let Some(&first) = block.get(position) {
+            if first & 0x80 != 0 {
+                let index = integer(block, &mut position, 7)?;
+                let (name, value) = self.entry(index)?;
+                headers.push(name, value)?;
+            } else if first & 0xe0 == 0x20 {
+                if !headers.fields.is_empty() {
+                    return Err("Table size update after the first header".to_string());
+                }
+                let size = integer(block, &mut position, 5)?;
+                if size > self.limit {
+                    return Err(format!("Table size {} is over the limit", size));
+                }
+                self.max_size = size;
+                self.evict(0);
+            } else {
+                // Literals: with incremental indexing (01), without (0000)
+                // or never indexed (0001).
+                let indexed = first & 0xc0 == 0x40;
+                let index = integer(block, &mut position, if indexed { 6 } else { 4 })?;
+                let name = if index == 0 {
+                    string(block, &mut position)?
+                } else {
+                    self.entry(index)?.0
+                };
+                let value = string(block, &mut position)?;
+                if indexed {
+                    self.insert(name.clone(), value.clone());
+                }
+                headers.push(name, value)?;
+            }
+        }
+        Ok(headers.fields)
+    }
+
+    fn entry(&self, index: usize) -> Result<(String, String), String> {
+        let entry = match index {
+            0 => None,
+            1..=61 => {
+                let (name, value) = STATIC_TABLE[index - 1];
+                Some((name.to_string(), value.to_string()))
+            }
+            _ => self.table.get(index - 62).cloned(),
+        };
+        entry.ok_or_else(|| format!("Invalid header table index {}", index))
+    }
+
+    fn insert(&mut self, name: String, value: String) {
+        let size = entry_size(&name, &value);
+        self.evict(size);
+        if size <= self.max_size {
+            self.size += size;
+            self.table.push_front((name, value));
+        }
+    }
+
+    /// Drops the oldest entries until `room` more bytes fit.
+    fn evict(&mut self, room: usize) {
+        while self.size + room > self.max_size {
+            match self.table.pop_back() {
+                Some((name, value)) => self.size -= entry_size(&name, &value),
+                None => {
+                    self.size = 0;
+                    break;
+                }
+            }
+        }
+    }
+}
+
+fn entry_size(name: &str, value: &str) -> usize {
+    name.len() + value.len() + 32
+}
+
+/// The header list a block decodes to, so far.
+#[derive(Default)]
+struct HeaderList {
+    fields: Vec<(String, String)>,
+    size: usize,
+    headers: usize,
+}
+
+impl HeaderList {
+    fn push(&mut self, name: String, value: String) -> Result<(), String> {
+        self.size += entry_size(&name, &value);
+        if self.size > MAX_HEADER_LIST_SIZE {
+            return Err(format!("Header list over {} bytes", MAX_HEADER_LIST_SIZE));
+        }
+        if !name.starts_with(':') {
+            self.headers += 1;
+            if self.headers > MAX_HEADERS {
+                return Err(format!("More than {} headers", MAX_HEADERS));
+            }
+        }
+        self.fields.push((name, value));
+        Ok(())
+    }
+}
+
+/// Encodes `headers`, whose names must be lowercase, as a header block.
+pub fn encode<'a>(headers: impl IntoIterator<Item = (&'a str, &'a str)>) -> Vec<u8> {
+    let mut block = Vec::new();
+    for (name, value) in headers {
+        match STATIC_TABLE.iter().position(|(known, _)| *known == name) {
+            Some(index) => encode_integer(&mut block, 0x00, 4, index + 1),
+            None => {
+                block.push(0x00);
+                encode_string(&mut block, name);
+            }
+        }
+        encode_string(&mut block, value);
+    }
+    block
+}
+
+fn encode_string(block: &mut Vec<u8>, text: &str) {
+    encode_integer(block, 0x00, 7, text.len());
+    block.extend_from_slice(text.as_bytes());
+}
+
+/// Writes `value` with an `prefix_bits`-bit prefix after the `flags` bits.
+fn encode_integer(block: &mut Vec<u8>, flags: u8, prefix_bits: u32, value: usize) {
+    let max = (1 << prefix_bits) - 1;
+    if value < max {
+        block.push(flags | value as u8);
+        return;
+    }
+    block.push(flags | max as u8);
+    let mut rest = value - max;
+    while rest >= 0x80 {
+        block.push((rest & 0x7f) as u8 | 0x80);
+        rest >>= 7;
+    }
+    block.push(rest as u8);
+}
+
+/// Reads an integer with a `prefix_bits`-bit prefix starting at `position`.
+fn integer(block: &[u8], position: &mut usize, prefix_bits: u32) -> Result<usize, String> {
+    let truncated = || "Header block is truncated".to_string();
+    let max = (1 << prefix_bits) - 1;
+    let first = *block.get(*position).ok_or_else(truncated)?;
+    *position += 1;
+    let mut value = usize::from(first) & max;
+    if value < max {
+        return Ok(value);
+    }
+    for shift in (0..28).step_by(7) {
+        let byte = *block.get(*position).ok_or_else(truncated)?;
+        *position += 1;
+        value += usize::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err("Header block integer is too large".to_string())
+}
+
+/// Reads a string literal, decoding Huffman-coded ones.
+fn string(block: &[u8], position: &mut usize) -> Result<String, String> {
+    let huffman = block.get(*position).is_some_and(|first| first & 0x80 != 0);
+    let length = integer(block, position, 7)?;
+    let bytes = block
+        .get(*position..*position + length)
+        .ok_or("Header block is truncated")?;
+    *position += length;
+    let bytes = if huffman {
+        huffman_decode(bytes)?
+    } else {
+        bytes.to_vec()
+    };
+    String::from_utf8(bytes).map_err(|_| "Header is not valid UTF-8".to_string())
+}
+
+/// The canonical Huffman code rebuilt from `CODE_LENGTHS`: for each length,
+/// its first code and where its symbols start in the symbols sorted by
+/// code.
+struct Code {
+    first_code: [u32; MAX_CODE_LENGTH + 1],
+    first_index: [usize; MAX_CODE_LENGTH + 1],
+    counts: [u32; MAX_CODE_LENGTH + 1],
+    symbols: Vec<u16>,
+}
+
+fn code() -> &'static Code {
+    static CODE: OnceLock<Code> = OnceLock::new();
+    CODE.get_or_init(|| {
+        let mut counts = [0; MAX_CODE_LENGTH + 1];
+        for length in CODE_LENGTHS {
+            counts[usize::from(length)] += 1;
+        }
+        let mut symbols: Vec<u16> = (0..=END_OF_STRING).collect();
+        symbols.sort_by_key(|symbol| CODE_LENGTHS[usize::from(*symbol)]);
+
+        let mut first_code = [0; MAX_CODE_LENGTH + 1];
+        let mut first_index = [0; MAX_CODE_LENGTH + 1];
+        let (mut next_code, mut next_index) = (0, 0);
+        for length in 1..=MAX_CODE_LENGTH {
+            next_code = (next_code + counts[length - 1]) << 1;
+            next_index += counts[length - 1] as usize;
+            first_code[length] = next_code;
+            first_index[length] = next_index;
+        }
+        Code {
+            first_code,
+            first_index,
+            counts,
+            symbols,
+        }
+    })
+}
+
+fn huffman_decode(bytes: &[u8]) -> Result<Vec<u8>, String> {
+    let code = code();
+    let mut decoded = Vec::with_capacity(bytes.len() * 8 / 5);
+    let (mut bits, mut length) = (0u32, 0usize);
+    for byte in bytes {
+        for shift in (0..8).rev() {
+            bits = (bits << 1) | u32::from(byte >> shift & 1);
+            length += 1;
+            let offset = bits.wrapping_sub(code.first_code[length]);
+            if bits >= code.first_code[length] && offset < code.counts[length] {
+                let symbol = code.symbols[code.first_index[length] + offset as usize];
+                if symbol == END_OF_STRING {
+                    return Err("Huffman string contains end-of-string".to_string());
+                }
+                decoded.push(symbol as u8);
+                (bits, length) = (0, 0);
+            } else if length == MAX_CODE_LENGTH {
+                return Err("Invalid Huffman code".to_string());
+            }
+        }
+    }
+    // Padding is the start of end-of-string: fewer than 8 one bits.
+    if length > 7 || bits != (1 << length) - 1 {
+        return Err("Invalid Huffman padding".to_string());
+    }
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(text: &str) -> Vec<u8> {
+        let digits: Vec<u8> = text.bytes().filter(u8::is_ascii_hexdigit).collect();
+        digits
+            .chunks(2)
+            .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).unwrap(), 16).unwrap())
+            .collect()
+    }
+
+    fn pairs(headers: &[(&str, &str)]) -> Vec<(String, String)> {
+        headers
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn code_lengths_should_form_a_complete_prefix_code() {
+        let kraft: u64 = CODE_LENGTHS
+            .iter()
+            .map(|length| 1u64 << (MAX_CODE_LENGTH - usize::from(*length)))
+            .sum();
+
+        assert_eq!(kraft, 1 << MAX_CODE_LENGTH);
+    }
+
+    #[test]
+    fn decode_should_follow_the_rfc_request_examples() {
+        // RFC 7541, appendix C.3 and, Huffman-coded, C.4.
+        for blocks in [
+            [
+                "8286 8441 0f77 7777 2e65 7861 6d70 6c65 2e63 6f6d",
+                "8286 84be 5808 6e6f 2d63 6163 6865",
+                "8287 85bf 400a 6375 7374 6f6d 2d6b 6579 0c63 7573 746f 6d2d 7661 6c75 65",
+            ],
+            [
+                "8286 8441 8cf1 e3c2 e5f2 3a6b a0ab 90f4 ff",
+                "8286 84be 5886 a8eb 1064 9cbf",
+                "8287 85bf 4088 25a8 49e9 5ba9 7d7f 8925 a849 e95b b8e8 b4bf",
+            ],
+        ] {
+            let mut decoder = Decoder::new(4096);
+            let decoded: Vec<_> = blocks
+                .iter()
+                .map(|block| decoder.decode(&hex(block)).unwrap())
+                .collect();
+
+            assert_eq!(
+                decoded[0],
+                pairs(&[
+                    (":method", "GET"),
+                    (":scheme", "http"),
+                    (":path", "/"),
+                    (":authority", "www.example.com"),
+                ])
+            );
+            assert_eq!(decoded[1][4], pairs(&[("cache-control", "no-cache")])[0]);
+            assert_eq!(
+                decoded[2],
+                pairs(&[
+                    (":method", "GET"),
+                    (":scheme", "https"),
+                    (":path", "/index.html"),
+                    (":authority", "www.example.com"),
+                    ("custom-key", "custom-value"),
+                ])
+            );
+            assert_eq!(decoder.size, 164);
+        }
+    }
+
+    #[test]
+    fn decode_should_evict_and_reject_bad_blocks() {
+        let mut decoder = Decoder::new(64);
+        // Each entry takes 32 + 6 bytes, so only the newest fits.
+        decoder.decode(&hex("4003 6f6e 6503 6f6e 65")).unwrap();
+        decoder.decode(&hex("4003 7477 6f03 7477 6f")).unwrap();
+
+        assert_eq!(
+            decoder.decode(&hex("be")).unwrap(),
+            pairs(&[("two", "two")])
+        );
+        assert!(decoder.decode(&hex("bf")).is_err());
+        assert!(decoder.decode(&hex("80")).is_err());
+        assert!(decoder.decode(&hex("3fe1 1f")).is_err());
+        assert!(decoder.decode(&hex("0003 6f6e")).is_err());
+        // "www" followed by eight bits of padding.
+        assert!(decoder.decode(&hex("0003 6f6e 6584 f1e3 c7ff")).is_err());
+    }
+
+    #[test]
+    fn decode_should_refuse_blocks_that_decode_over_the_limits() {
+        // One large entry added to the table, then referred to over and
+        // over: a block of 64 KiB would decode to 256 MB.
+        let mut block = vec![0x40];
+        encode_string(&mut block, "x-big");
+        encode_string(&mut block, &"x".repeat(4000));
+        block.resize(block.len() + 65_000, 0xbe);
+        let error = Decoder::new(4096).decode(&block).unwrap_err();
+        assert!(error.contains("Header list over"), "{}", error);
+
+        // `accept-encoding: gzip, deflate` from the static table.
+        let mut most = hex("8284 86");
+        most.resize(most.len() + MAX_HEADERS, 0x90);
+        assert_eq!(
+            Decoder::new(4096).decode(&most).unwrap().len(),
+            MAX_HEADERS + 3
+        );
+        most.push(0x90);
+        assert!(Decoder::new(4096).decode(&most).is_err());
+    }
+
+    #[test]
+    fn encode_should_round_trip_through_the_decoder() {
+        let long = "x".repeat(300);
+        let headers = [
+            (":status", "200"),
+            ("content-type", "text/html; charset=utf-8"),
+            ("x-request-id", long.as_str()),
+        ];
+
+        let block = encode(headers);
+
+        assert_eq!(&block[..2], &[0x08, 0x03]);
+        assert_eq!(Decoder::new(4096).decode(&block).unwrap(), pairs(&headers));
+    }
+}
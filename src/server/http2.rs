@@ -0,0 +1,844 @@
+//! Experimental HTTP/2 (RFC 9113) for clients that open a cleartext
+//! connection with the HTTP/2 preface ("prior knowledge").
+//!
+//! Streams are served one at a time: the server announces
+//! `SETTINGS_MAX_CONCURRENT_STREAMS` of 1 and refuses any stream opened
+//! while another is still in progress. Header lists are held to the
+//! HTTP/1 limits, announced as `SETTINGS_MAX_HEADER_LIST_SIZE`; a block
+//! decoding to more ends the connection. Request bodies are buffered before
+//! the handler runs; response bodies, including files, are sent as they
+//! are read, within the client's flow-control windows. Negotiating HTTP/2
+//! with ALPN waits on TLS support.
+
+use std::{
+    io::{self, BufReader, Read, Write},
+    net::SocketAddr,
+    sync::Arc,
+    time::Instant,
+};
+
+use super::{
+    Body, Context, Response, answer, check_host, check_method, date,
+    deadline::{Deadline, ReadTimeout},
+    hpack::{self, Decoder},
+    request::HttpRequest,
+    server_error, set_peer,
+    timing::Pending,
+};
+use crate::{HarborError, warn};
+
+/// What follows `PRI * HTTP/2.0\r\n\r\n`, which is read as an HTTP/1 head.
+const PREFACE_REST: &[u8] = b"SM\r\n\r\n";
+
+const DATA: u8 = 0x0;
+const HEADERS: u8 = 0x1;
+const RST_STREAM: u8 = 0x3;
+const SETTINGS: u8 = 0x4;
+const PUSH_PROMISE: u8 = 0x5;
+const PING: u8 = 0x6;
+const GOAWAY: u8 = 0x7;
+const WINDOW_UPDATE: u8 = 0x8;
+const CONTINUATION: u8 = 0x9;
+
+const END_STREAM: u8 = 0x1;
+const ACK: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+const PADDED: u8 = 0x8;
+const PRIORITY_FLAG: u8 = 0x20;
+
+const NO_ERROR: u32 = 0x0;
+const PROTOCOL_ERROR: u32 = 0x1;
+const INTERNAL_ERROR: u32 = 0x2;
+const FLOW_CONTROL_ERROR: u32 = 0x3;
+const FRAME_SIZE_ERROR: u32 = 0x6;
+const REFUSED_STREAM: u32 = 0x7;
+const COMPRESSION_ERROR: u32 = 0x9;
+const ENHANCE_YOUR_CALM: u32 = 0xb;
+
+const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+const SETTINGS_MAX_HEADER_LIST_SIZE: u16 = 0x6;
+
+/// The frame size both sides start with, and the most the server accepts.
+const DEFAULT_FRAME_SIZE: usize = 16_384;
+const DEFAULT_WINDOW: i64 = 65_535;
+const MAX_WINDOW: i64 = (1 << 31) - 1;
+/// Header blocks larger than this end the connection.
+const MAX_HEADER_BLOCK: usize = 64 * 1024;
+const HEADER_TABLE_SIZE: usize = 4096;
+
+/// Whether `request` is the start of the HTTP/2 connection preface.
+pub(super) fn is_preface(request: &HttpRequest) -> bool {
+    request.method() == "PRI" && request.target() == "*" && request.version() == "HTTP/2.0"
+}
+
+/// Serves an HTTP/2 connection whose preface has been read up to
+/// `PREFACE_REST`, until the client closes it or sends `GOAWAY`.
+pub(super) fn serve<S: Read + Write + ReadTimeout>(
+    mut reader: BufReader<Deadline<S>>,
+    peer: Option<SocketAddr>,
+    context: &Arc<Context>,
+) -> Result<(), HarborError> {
+    let mut rest = [0; PREFACE_REST.len()];
+    reader.read_exact(&mut rest)?;
+    if rest != PREFACE_REST {
+        return Err(HarborError::Http(
+            "Invalid HTTP/2 connection preface".to_string(),
+        ));
+    }
+
+    let mut connection = Connection {
+        reader,
+        decoder: Decoder::new(HEADER_TABLE_SIZE),
+        max_frame_size: DEFAULT_FRAME_SIZE,
+        initial_window: DEFAULT_WINDOW,
+        window: DEFAULT_WINDOW,
+        last_stream: 0,
+        sending: None,
+    };
+    let mut settings = setting(SETTINGS_MAX_CONCURRENT_STREAMS, 1);
+    settings.extend(setting(
+        SETTINGS_MAX_HEADER_LIST_SIZE,
+        hpack::MAX_HEADER_LIST_SIZE as u32,
+    ));
+    let result = connection
+        .write_frame(SETTINGS, 0, 0, &settings)
+        .and_then(|()| connection.run(peer, context));
+    let (code, outcome) = match result {
+        Ok(()) => (NO_ERROR, Ok(())),
+        Err(err) => {
+            warn!("Closing HTTP/2 connection from {:?}: {}", peer, err.message);
+            (err.code, Err(HarborError::Http(err.message)))
+        }
+    };
+    let mut payload = connection.last_stream.to_be_bytes().to_vec();
+    payload.extend_from_slice(&code.to_be_bytes());
+    // The client may already be gone; there is nothing left to tell it.
+    let _ = connection.write_frame(GOAWAY, 0, 0, &payload);
+    outcome
+}
+
+/// A connection error: the `GOAWAY` code and what went wrong.
+#[derive(Debug)]
+struct Error {
+    code: u32,
+    message: String,
+}
+
+impl Error {
+    fn new(code: u32, message: impl Into<String>) -> Error {
+        Error {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::new(INTERNAL_ERROR, err.to_string())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Frame {
+    kind: u8,
+    flags: u8,
+    stream: u32,
+    payload: Vec<u8>,
+}
+
+/// A request whose headers have arrived.
+struct Stream {
+    id: u32,
+    /// The request, or the response refusing it once it is complete.
+    request: Result<HttpRequest, Response>,
+    head: bool,
+    body: Vec<u8>,
+    limit: Option<u64>,
+}
+
+/// The response being sent and its flow-control window.
+struct Sending {
+    id: u32,
+    window: i64,
+    reset: bool,
+}
+
+struct Connection<S> {
+    reader: BufReader<Deadline<S>>,
+    decoder: Decoder,
+    /// The largest frame the client accepts.
+    max_frame_size: usize,
+    /// The client's window for new streams.
+    initial_window: i64,
+    /// The client's window for the whole connection.
+    window: i64,
+    last_stream: u32,
+    sending: Option<Sending>,
+}
+
+impl<S: Read + Write + ReadTimeout> Connection<S> {
+    fn run(&mut self, peer: Option<SocketAddr>, context: &Arc<Context>) -> Result<(), Error> {
+        let mut open: Option<Stream> = None;
+        while let Some(frame) = self.read_frame()? {
+            match frame.kind {
+                HEADERS => {
+                    let end_stream = frame.flags & END_STREAM != 0;
+                    let id = frame.stream;
+                    let block = self.header_block(frame)?;
+                    let headers = self
+                        .decoder
+                        .decode(&block)
+                        .map_err(|err| Error::new(COMPRESSION_ERROR, err))?;
+                    if open.as_ref().is_some_and(|stream| stream.id == id) {
+                        // Trailers: they must end the request and are dropped.
+                        if !end_stream {
+                            return Err(Error::new(PROTOCOL_ERROR, "trailers without END_STREAM"));
+                        }
+                        let stream = open.take().expect("the stream is open");
+                        self.finish(stream, peer, context)?;
+                        continue;
+                    }
+                    if id % 2 == 0 || id <= self.last_stream {
+                        return Err(Error::new(PROTOCOL_ERROR, format!("bad stream id {}", id)));
+                    }
+                    self.last_stream = id;
+                    if open.is_some() {
+                        self.reset(id, REFUSED_STREAM)?;
+                        continue;
+                    }
+                    let stream = new_stream(id, headers, context);
+                    if end_stream {
+                        self.finish(stream, peer, context)?;
+                    } else if stream.request.is_err() {
+                        // Refused from its headers; the body isn't wanted.
+                        self.finish(stream, peer, context)?;
+                        self.reset(id, NO_ERROR)?;
+                    } else {
+                        open = Some(stream);
+                    }
+                }
+                DATA => {
+                    let length = frame.payload.len();
+                    let data = unpad(&frame)?;
+                    if frame.stream == 0 || frame.stream > self.last_stream {
+                        return Err(Error::new(PROTOCOL_ERROR, "DATA on an idle stream"));
+                    }
+                    self.release(0, length)?;
+                    // Frames already in flight for a closed stream are dropped.
+                    let Some(stream) = open.as_mut().filter(|open| open.id == frame.stream) else {
+                        continue;
+                    };
+                    stream.body.extend_from_slice(data);
+                    if let Some(limit) = stream.limit
+                        && stream.body.len() as u64 > limit
+                    {
+                        warn!(
+                            "Request body of over {} bytes exceeds the {} byte limit",
+                            stream.body.len(),
+                            limit
+                        );
+                        let id = stream.id;
+                        open = None;
+                        let response = Response::error_page(413);
+                        self.send(id, &response, false)?;
+                        // Answered early; the rest of the body isn't wanted.
+                        self.reset(id, NO_ERROR)?;
+                    } else if frame.flags & END_STREAM != 0 {
+                        let stream = open.take().expect("the stream is open");
+                        self.finish(stream, peer, context)?;
+                    } else {
+                        self.release(frame.stream, length)?;
+                    }
+                }
+                RST_STREAM => {
+                    if open.as_ref().is_some_and(|open| open.id == frame.stream) {
+                        open = None;
+                    }
+                }
+                GOAWAY => return Ok(()),
+                _ => self.control(frame)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Handles the frames that don't belong to a request: settings, pings
+    /// and window updates.
+    fn control(&mut self, frame: Frame) -> Result<(), Error> {
+        match frame.kind {
+            SETTINGS => {
+                if frame.stream != 0 || !frame.payload.len().is_multiple_of(6) {
+                    return Err(Error::new(FRAME_SIZE_ERROR, "malformed SETTINGS"));
+                }
+                if frame.flags & ACK != 0 {
+                    return Ok(());
+                }
+                for setting in frame.payload.chunks(6) {
+                    let value =
+                        u32::from_be_bytes([setting[2], setting[3], setting[4], setting[5]]);
+                    match u16::from_be_bytes([setting[0], setting[1]]) {
+                        SETTINGS_INITIAL_WINDOW_SIZE => {
+                            let value = i64::from(value);
+                            if value > MAX_WINDOW {
+                                return Err(Error::new(FLOW_CONTROL_ERROR, "window too large"));
+                            }
+                            if let Some(sending) = &mut self.sending {
+                                sending.window += value - self.initial_window;
+                            }
+                            self.initial_window = value;
+                        }
+                        SETTINGS_MAX_FRAME_SIZE => {
+                            if !(16_384..=16_777_215).contains(&value) {
+                                return Err(Error::new(
+                                    PROTOCOL_ERROR,
+                                    "bad SETTINGS_MAX_FRAME_SIZE",
+                                ));
+                            }
+                            self.max_frame_size = value as usize;
+                        }
+                        _ => {}
+                    }
+                }
+                self.write_frame(SETTINGS, ACK, 0, &[])?;
+            }
+            PING => {
+                if frame.stream != 0 || frame.payload.len() != 8 {
+                    return Err(Error::new(FRAME_SIZE_ERROR, "malformed PING"));
+                }
+                if frame.flags & ACK == 0 {
+                    self.write_frame(PING, ACK, 0, &frame.payload)?;
+                }
+            }
+            WINDOW_UPDATE => {
+                let [a, b, c, d] = frame.payload[..] else {
+                    return Err(Error::new(FRAME_SIZE_ERROR, "malformed WINDOW_UPDATE"));
+                };
+                let increment = i64::from(u32::from_be_bytes([a, b, c, d]) & 0x7fff_ffff);
+                if increment == 0 {
+                    return Err(Error::new(PROTOCOL_ERROR, "WINDOW_UPDATE of 0"));
+                }
+                let window = if frame.stream == 0 {
+                    Some(&mut self.window)
+                } else {
+                    self.sending
+                        .as_mut()
+                        .filter(|sending| sending.id == frame.stream)
+                        .map(|sending| &mut sending.window)
+                };
+                if let Some(window) = window {
+                    *window += increment;
+                    if *window > MAX_WINDOW {
+                        return Err(Error::new(FLOW_CONTROL_ERROR, "window too large"));
+                    }
+                }
+            }
+            PUSH_PROMISE => return Err(Error::new(PROTOCOL_ERROR, "PUSH_PROMISE from a client")),
+            CONTINUATION => return Err(Error::new(PROTOCOL_ERROR, "CONTINUATION out of place")),
+            // PRIORITY is advice, and unknown frame types must be ignored.
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Reads the rest of the header block that `frame` starts.
+    fn header_block(&mut self, frame: Frame) -> Result<Vec<u8>, Error> {
+        let mut fragment = unpad(&frame)?;
+        if frame.flags & PRIORITY_FLAG != 0 {
+            fragment = fragment
+                .get(5..)
+                .ok_or_else(|| Error::new(FRAME_SIZE_ERROR, "HEADERS too short"))?;
+        }
+        let mut block = fragment.to_vec();
+        let mut end = frame.flags & END_HEADERS != 0;
+        while !end {
+            let next = self
+                .read_frame()?
+                .ok_or_else(|| Error::new(PROTOCOL_ERROR, "closed inside a header block"))?;
+            if next.kind != CONTINUATION || next.stream != frame.stream {
+                return Err(Error::new(PROTOCOL_ERROR, "expected CONTINUATION"));
+            }
+            block.extend_from_slice(&next.payload);
+            if block.len() > MAX_HEADER_BLOCK {
+                return Err(Error::new(ENHANCE_YOUR_CALM, "header block too large"));
+            }
+            end = next.flags & END_HEADERS != 0;
+        }
+        Ok(block)
+    }
+
+    /// Answers a complete request and records it in the access log.
+    fn finish(
+        &mut self,
+        stream: Stream,
+        peer: Option<SocketAddr>,
+        context: &Arc<Context>,
+    ) -> Result<(), Error> {
+        let mut request = match stream.request {
+            Ok(request) => request,
+            Err(response) => {
+                return self.send(stream.id, &context.identified(response), stream.head);
+            }
+        };
+        let length = stream.body.len() as u64;
+        request.set_body(stream.body);
+        set_peer(&mut request, peer, context);
+
+        let buffered = request.body().to_vec();
+        let mut source = &buffered[..];
+        let response = answer(&mut request, &mut Body::new(&mut source, length), context);
+        // HTTP/2 has no `101`; each stream shares the one connection.
+        let response = if response.upgrades() {
+            warn!(
+                "Refusing to switch protocols on {} {} over HTTP/2",
+                request.method(),
+                request.path()
+            );
+            server_error(context)
+        } else {
+            response
+        };
+        context
+            .traffic
+            .record(request.wire_len(), response.wire_len());
+        if let Some(access_log) = &context.access_log {
+            access_log.record(peer, &request, &response);
+        }
+        let pending = Pending::new(&request, &response);
+        let writing = Instant::now();
+        self.send(stream.id, &response, stream.head)?;
+        if let Some(pending) = pending {
+            pending.written(writing.elapsed());
+        }
+        Ok(())
+    }
+
+    /// Sends `response` on stream `id`, without the body if `head`.
+    fn send(&mut self, id: u32, response: &Response, head: bool) -> Result<(), Error> {
+        let status = response.status().to_string();
+        let length = response.body_len().to_string();
+        let has_body = !matches!(response.status(), 100..=199 | 204 | 304);
+        let names: Vec<String> = response
+            .headers()
+            .iter()
+            .map(|(name, _)| name.to_ascii_lowercase())
+            .collect();
+        let mut fields = vec![(":status", status.as_str())];
+        fields.extend(
+            names
+                .iter()
+                .zip(response.headers())
+                .filter(|(name, _)| !is_connection_specific(name) && *name != "content-length")
+                .map(|(name, (_, value))| (name.as_str(), value.as_str())),
+        );
+        let date = date::http_date_now();
+        if !names.iter().any(|name| name == "date") {
+            fields.push(("date", date.as_str()));
+        }
+        if has_body {
+            fields.push(("content-length", length.as_str()));
+        }
+        let block = hpack::encode(fields);
+
+        let end_stream = head || !has_body || response.body_len() == 0;
+        let mut chunks = block.chunks(self.max_frame_size).peekable();
+        let mut kind = HEADERS;
+        let mut flags = if end_stream { END_STREAM } else { 0 };
+        while let Some(chunk) = chunks.next() {
+            if chunks.peek().is_none() {
+                flags |= END_HEADERS;
+            }
+            self.write_frame(kind, flags, id, chunk)?;
+            kind = CONTINUATION;
+            flags = 0;
+        }
+        if block.is_empty() {
+            self.write_frame(HEADERS, flags | END_HEADERS, id, &[])?;
+        }
+        if end_stream {
+            return Ok(());
+        }
+
+        self.sending = Some(Sending {
+            id,
+            window: self.initial_window,
+            reset: false,
+        });
+        let mut writer = DataWriter {
+            connection: self,
+            buffer: Vec::new(),
+            error: None,
+        };
+        let written = response
+            .write_body_to(&mut writer)
+            .and_then(|()| writer.flush_frames(true));
+        let error = writer.error.take();
+        let reset = self.sending.take().is_some_and(|sending| sending.reset);
+        match (written, error) {
+            (Ok(()), _) => Ok(()),
+            (Err(_), Some(error)) => Err(error),
+            // The client cancelled the stream; the connection goes on.
+            (Err(_), None) if reset => Ok(()),
+            (Err(err), None) => {
+                warn!("Failed to send the response body: {}", err);
+                self.reset(id, INTERNAL_ERROR)
+            }
+        }
+    }
+
+    /// Sends `data` on the stream being answered once the client's windows
+    /// allow it, handling the frames that arrive meanwhile. Returns `false`
+    /// if the client reset the stream.
+    fn send_data(&mut self, data: &[u8], end_stream: bool) -> Result<bool, Error> {
+        let mut data = data;
+        loop {
+            let Some(sending) = self.sending.as_ref().filter(|sending| !sending.reset) else {
+                return Ok(false);
+            };
+            let id = sending.id;
+            let allowed = self
+                .window
+                .min(sending.window)
+                .min(self.max_frame_size as i64)
+                .max(0) as usize;
+            if allowed > 0 || data.is_empty() {
+                let (chunk, rest) = data.split_at(allowed.min(data.len()));
+                let last = rest.is_empty();
+                let flags = if last && end_stream { END_STREAM } else { 0 };
+                self.write_frame(DATA, flags, id, chunk)?;
+                self.window -= chunk.len() as i64;
+                if let Some(sending) = &mut self.sending {
+                    sending.window -= chunk.len() as i64;
+                }
+                if last {
+                    return Ok(true);
+                }
+                data = rest;
+                continue;
+            }
+
+            let frame = self
+                .read_frame()?
+                .ok_or_else(|| Error::new(PROTOCOL_ERROR, "closed while a response was sent"))?;
+            match frame.kind {
+                HEADERS => {
+                    // Another stream while this one is in progress.
+                    let stream = frame.stream;
+                    let block = self.header_block(frame)?;
+                    self.decoder
+                        .decode(&block)
+                        .map_err(|err| Error::new(COMPRESSION_ERROR, err))?;
+                    if stream % 2 == 0 || stream <= self.last_stream {
+                        return Err(Error::new(
+                            PROTOCOL_ERROR,
+                            format!("bad stream id {}", stream),
+                        ));
+                    }
+                    self.last_stream = stream;
+                    self.reset(stream, REFUSED_STREAM)?;
+                }
+                DATA => {
+                    if frame.stream > self.last_stream {
+                        return Err(Error::new(PROTOCOL_ERROR, "DATA on an idle stream"));
+                    }
+                    self.release(0, frame.payload.len())?;
+                }
+                RST_STREAM if frame.stream == id => {
+                    if let Some(sending) = &mut self.sending {
+                        sending.reset = true;
+                    }
+                }
+                RST_STREAM => {}
+                GOAWAY => return Err(Error::new(NO_ERROR, "client went away during a response")),
+                _ => self.control(frame)?,
+            }
+        }
+    }
+
+    /// Gives the client back the window used by `length` bytes of DATA.
+    fn release(&mut self, stream: u32, length: usize) -> Result<(), Error> {
+        if length == 0 {
+            return Ok(());
+        }
+        let increment = (length as u32).to_be_bytes();
+        self.write_frame(WINDOW_UPDATE, 0, stream, &increment)
+    }
+
+    fn reset(&mut self, stream: u32, code: u32) -> Result<(), Error> {
+        self.write_frame(RST_STREAM, 0, stream, &code.to_be_bytes())
+    }
+
+    /// Reads the next frame, or `None` once the client closes the
+    /// connection between frames.
+    fn read_frame(&mut self) -> Result<Option<Frame>, Error> {
+        read_frame(&mut self.reader)
+    }
+
+    fn write_frame(
+        &mut self,
+        kind: u8,
+        flags: u8,
+        stream: u32,
+        payload: &[u8],
+    ) -> Result<(), Error> {
+        let mut frame = Vec::with_capacity(9 + payload.len());
+        write_frame(&mut frame, kind, flags, stream, payload);
+        self.reader.get_mut().write_all(&frame)?;
+        Ok(())
+    }
+}
+
+/// Cuts a response body into DATA frames.
+struct DataWriter<'c, S> {
+    connection: &'c mut Connection<S>,
+    buffer: Vec<u8>,
+    /// The connection error that stopped the body, if any.
+    error: Option<Error>,
+}
+
+impl<S: Read + Write + ReadTimeout> DataWriter<'_, S> {
+    /// Sends the full frames buffered, or everything with `END_STREAM` if
+    /// `end`.
+    fn flush_frames(&mut self, end: bool) -> io::Result<()> {
+        let size = self.connection.max_frame_size;
+        while self.buffer.len() >= size || end {
+            let take = self.buffer.len().min(size);
+            let last = end && take == self.buffer.len();
+            let chunk: Vec<u8> = self.buffer.drain(..take).collect();
+            match self.connection.send_data(&chunk, last) {
+                Ok(true) if last => return Ok(()),
+                Ok(true) => {}
+                Ok(false) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::ConnectionAborted,
+                        "stream reset by the client",
+                    ));
+                }
+                Err(err) => {
+                    let io_error = io::Error::other(err.message.clone());
+                    self.error = Some(err);
+                    return Err(io_error);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<S: Read + Write + ReadTimeout> Write for DataWriter<'_, S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        self.flush_frames(false)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Turns a request's header fields into the request handlers see. A
+/// malformed request becomes the `400` that answers it, sent without
+/// waiting for its body.
+fn new_stream(id: u32, fields: Vec<(String, String)>, context: &Context) -> Stream {
+    let request = parse_fields(&fields).and_then(|request| {
+        check_host(&request, &context.allowed_hosts)?;
+        check_method(&request, &context.allowed_methods)?;
+        Ok(request)
+    });
+    let (head, limit) = match &request {
+        Ok(request) => {
+            let limit = context.router.body_limit(request);
+            let limit = limit.or(context.max_body_bytes.map(|limit| limit as u64));
+            (request.method() == "HEAD", limit)
+        }
+        Err(_) => (false, None),
+    };
+    Stream {
+        id,
+        request,
+        head,
+        body: Vec::new(),
+        limit,
+    }
+}
+
+/// Builds a request from its pseudo-header and header fields, by way of
+/// the HTTP/1 head they stand for.
+fn parse_fields(fields: &[(String, String)]) -> Result<HttpRequest, Response> {
+    let bad_request = |err: String| {
+        warn!("Invalid HTTP/2 request: {}", err);
+        Response::error_page(400)
+    };
+
+    let (mut method, mut path, mut authority) = (None, None, None);
+    let mut headers = String::new();
+    for (index, (name, value)) in fields.iter().enumerate() {
+        let invalid = |text: &str| text.contains(['\r', '\n', '\0']);
+        if name.is_empty() || invalid(name) || name[1..].contains(':') || invalid(value) {
+            return Err(bad_request(format!("invalid field {:?}", name)));
+        }
+        if let Some(pseudo) = name.strip_prefix(':') {
+            if fields[..index]
+                .iter()
+                .any(|(name, _)| !name.starts_with(':'))
+            {
+                return Err(bad_request(format!("{} after the headers", name)));
+            }
+            let slot = match pseudo {
+                "method" => &mut method,
+                "path" => &mut path,
+                "authority" => &mut authority,
+                "scheme" => continue,
+                _ => return Err(bad_request(format!("unknown pseudo-header {}", name))),
+            };
+            if slot.replace(value.as_str()).is_some() {
+                return Err(bad_request(format!("repeated {}", name)));
+            }
+            continue;
+        }
+        if name.bytes().any(|byte| byte.is_ascii_uppercase()) || is_connection_specific(name) {
+            return Err(bad_request(format!("invalid header {:?}", name)));
+        }
+        headers.push_str(&format!("{}: {}\r\n", name, value));
+    }
+
+    let (Some(method), Some(path)) = (method, path) else {
+        return Err(bad_request("missing :method or :path".to_string()));
+    };
+    let mut head = format!("{} {} HTTP/2.0\r\n", method, path);
+    if let Some(authority) = authority
+        && !fields.iter().any(|(name, _)| name == "host")
+    {
+        head.push_str(&format!("host: {}\r\n", authority));
+    }
+    head.push_str(&headers);
+    head.push_str("\r\n");
+    match HttpRequest::parse_head(&mut head.as_bytes()) {
+        Ok(Some(request)) => Ok(request),
+        Ok(None) => Err(bad_request("empty request".to_string())),
+        Err(err) => Err(bad_request(err.to_string())),
+    }
+}
+
+/// Headers that only mean something to a single HTTP/1 connection.
+fn is_connection_specific(name: &str) -> bool {
+    matches!(
+        name,
+        "connection" | "keep-alive" | "proxy-connection" | "transfer-encoding" | "upgrade"
+    )
+}
+
+/// The payload of a DATA or HEADERS frame without its padding.
+fn unpad(frame: &Frame) -> Result<&[u8], Error> {
+    if frame.flags & PADDED == 0 {
+        return Ok(&frame.payload);
+    }
+    let malformed = || Error::new(PROTOCOL_ERROR, "padding longer than the frame");
+    let (&padding, rest) = frame.payload.split_first().ok_or_else(malformed)?;
+    let end = rest
+        .len()
+        .checked_sub(usize::from(padding))
+        .ok_or_else(malformed)?;
+    Ok(&rest[..end])
+}
+
+fn setting(id: u16, value: u32) -> Vec<u8> {
+    let mut setting = id.to_be_bytes().to_vec();
+    setting.extend_from_slice(&value.to_be_bytes());
+    setting
+}
+
+fn read_frame<R: io::BufRead>(reader: &mut R) -> Result<Option<Frame>, Error> {
+    if reader.fill_buf()?.is_empty() {
+        return Ok(None);
+    }
+    let mut head = [0; 9];
+    reader.read_exact(&mut head)?;
+    let length = usize::from(head[0]) << 16 | usize::from(head[1]) << 8 | usize::from(head[2]);
+    if length > DEFAULT_FRAME_SIZE {
+        return Err(Error::new(
+            FRAME_SIZE_ERROR,
+            format!("{} byte frame", length),
+        ));
+    }
+    let mut payload = vec![0; length];
+    reader.read_exact(&mut payload)?;
+    Ok(Some(Frame {
+        kind: head[3],
+        flags: head[4],
+        stream: u32::from_be_bytes([head[5], head[6], head[7], head[8]]) & 0x7fff_ffff,
+        payload,
+    }))
+}
+
+fn write_frame(out: &mut Vec<u8>, kind: u8, flags: u8, stream: u32, payload: &[u8]) {
+    out.extend_from_slice(&(payload.len() as u32).to_be_bytes()[1..]);
+    out.push(kind);
+    out.push(flags);
+    out.extend_from_slice(&stream.to_be_bytes());
+    out.extend_from_slice(payload);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_should_round_trip() {
+        let mut bytes = Vec::new();
+        write_frame(&mut bytes, PING, ACK, 0, b"12345678");
+        write_frame(&mut bytes, DATA, PADDED | END_STREAM, 3, b"\x02hi\0\0");
+        assert_eq!(&bytes[..9], [0, 0, 8, PING, ACK, 0, 0, 0, 0]);
+
+        let mut reader = &bytes[..];
+        let ping = read_frame(&mut reader).unwrap().unwrap();
+        assert_eq!(ping.payload, b"12345678");
+        let data = read_frame(&mut reader).unwrap().unwrap();
+        assert_eq!((data.kind, data.stream), (DATA, 3));
+        assert_eq!(unpad(&data).unwrap(), b"hi");
+        assert!(read_frame(&mut reader).unwrap().is_none());
+
+        let mut oversized = Vec::new();
+        write_frame(&mut oversized, DATA, 0, 1, &vec![0; DEFAULT_FRAME_SIZE + 1]);
+        let error = read_frame(&mut &oversized[..]).unwrap_err();
+        assert_eq!(error.code, FRAME_SIZE_ERROR);
+    }
+
+    #[test]
+    fn parse_fields_should_build_the_request() {
+        let fields = |pairs: &[(&str, &str)]| -> Vec<(String, String)> {
+            pairs
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect()
+        };
+
+        let request = parse_fields(&fields(&[
+            (":method", "GET"),
+            (":scheme", "http"),
+            (":authority", "example.com"),
+            (":path", "/search?q=1"),
+            ("cookie", "a=1"),
+            ("cookie", "b=2"),
+        ]))
+        .unwrap();
+        assert_eq!(request.method(), "GET");
+        assert_eq!(request.path(), "/search");
+        assert_eq!(request.host(), Some("example.com"));
+        assert_eq!(request.version(), "HTTP/2.0");
+        assert_eq!(request.header_values("Cookie").count(), 2);
+
+        for bad in [
+            fields(&[(":method", "GET")]),
+            fields(&[(":method", "GET"), (":path", "/"), ("Accept", "*/*")]),
+            fields(&[(":method", "GET"), ("accept", "*/*"), (":path", "/")]),
+            fields(&[(":method", "GET"), (":path", "/"), ("connection", "close")]),
+            fields(&[(":method", "GET"), (":path", "/"), ("x", "a\r\nb: c")]),
+        ] {
+            assert_eq!(parse_fields(&bad).unwrap_err().status(), 400);
+        }
+    }
+}
@@ -0,0 +1,375 @@
+//! HTTP endpoints exposing the jobs tracked by the server's `ThreadPool`.
+
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use uuid::Uuid;
+
+use super::{
+    request::HttpRequest,
+    response::Response,
+    template::{self, escape_html},
+};
+use crate::{
+    JobMetadata, JobStatus, JobSubmitter, JobTracker,
+    json::{self, Value},
+    registry::{JobDescriptor, JobRegistry},
+};
+
+/// Most jobs listed on the dashboard, newest first.
+const DASHBOARD_JOBS: usize = 100;
+
+/// How often the dashboard's browser reconnects to the event stream.
+const DASHBOARD_REFRESH: Duration = Duration::from_secs(2);
+
+/// Longest job result shown on the dashboard, in characters.
+const DASHBOARD_RESULT_CHARS: usize = 80;
+
+/// The `/admin/jobs` page. The event stream sends the same summary and
+/// rows again, as JSON.
+const DASHBOARD: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>Jobs</title>
+<style>
+body { font-family: sans-serif; margin: 2em; }
+table { border-collapse: collapse; }
+th, td { padding: 0.3em 1em; border-bottom: 1px solid #ddd; text-align: left; }
+td { font-family: monospace; }
+.Failed { color: #b00; }
+</style>
+</head>
+<body>
+<h1>Jobs</h1>
+<p id="summary">{{summary}}</p>
+<table>
+<thead><tr><th>Job</th><th>Status</th><th>Submitted</th><th>Duration</th><th>Worker</th><th>Result</th></tr></thead>
+<tbody id="jobs">{{{rows}}}</tbody>
+</table>
+<script>
+const events = new EventSource("/admin/jobs/events");
+events.onmessage = (event) => {
+  const update = JSON.parse(event.data);
+  document.getElementById("summary").textContent = update.summary;
+  document.getElementById("jobs").innerHTML = update.rows;
+};
+</script>
+</body>
+</html>
+"#;
+
+/// The state shared by the jobs endpoints: the pool handles and the
+/// registry of job types clients may submit.
+#[derive(Clone, Debug)]
+pub struct JobsApi {
+    tracker: JobTracker,
+    submitter: JobSubmitter,
+    registry: Arc<JobRegistry>,
+}
+
+impl JobsApi {
+    pub fn new(
+        tracker: JobTracker,
+        submitter: JobSubmitter,
+        registry: Arc<JobRegistry>,
+    ) -> JobsApi {
+        JobsApi {
+            tracker,
+            submitter,
+            registry,
+        }
+    }
+
+    /// Handles `GET /jobs/{uuid}`, answering with the job state and result
+    /// as JSON, or `404` if the id is not a tracked job.
+    pub fn status(&self, job_id: &str) -> Response {
+        let metadata = Uuid::parse_str(job_id)
+            .ok()
+            .and_then(|id| self.tracker.get(id).map(|metadata| (id, metadata)));
+
+        match metadata {
+            Some((id, metadata)) => Response::json(200, metadata_json(id, &metadata)),
+            None => error(404, "job not found"),
+        }
+    }
+
+    /// Handles `POST /jobs`.
+    ///
+    /// The body must be a JSON object such as
+    /// `{"job": "send_email", "args": {...}}` naming a registered job type.
+    /// On success the job is queued and its id returned with `202 Accepted`.
+    pub fn submit(&self, request: &HttpRequest) -> Response {
+        let body = match std::str::from_utf8(request.body()) {
+            Ok(body) => body,
+            Err(_) => return error(400, "body is not valid UTF-8"),
+        };
+
+        let document = match Value::parse(body) {
+            Ok(document) => document,
+            Err(err) => return error(400, &format!("invalid JSON: {}", err)),
+        };
+
+        let descriptor = match JobDescriptor::from_json(&document) {
+            Ok(descriptor) => descriptor,
+            Err(err) => return error(400, &err),
+        };
+
+        if !self.registry.contains(&descriptor.job) {
+            return error(400, &format!("unknown job type: {}", descriptor.job));
+        }
+
+        let job_id = match self.registry.submit(&self.submitter, &descriptor) {
+            Ok(job_id) => job_id,
+            Err(err) => return error(400, &err),
+        };
+
+        Response::json(
+            202,
+            format!("{{\"id\":{}}}", json::quote(&job_id.to_string())),
+        )
+        .header("Location", &format!("/jobs/{}", job_id))
+    }
+
+    /// Handles `GET /admin/jobs`, a page listing the latest jobs with their
+    /// status, duration and worker, which keeps itself up to date from
+    /// `dashboard_events`.
+    pub fn dashboard(&self) -> Response {
+        let (summary, rows) = self.dashboard_contents();
+        let context = HashMap::from([("summary", summary), ("rows", rows)]);
+        match template::render(DASHBOARD, &context) {
+            Ok(page) => Response::html(200, page).header("Cache-Control", "no-store"),
+            Err(_) => Response::error_page(500),
+        }
+    }
+
+    /// Handles `GET /admin/jobs/events`, a server-sent event with the
+    /// dashboard's current contents. The stream ends after one event, and
+    /// the browser reconnects for the next after `DASHBOARD_REFRESH`, so
+    /// no connection is held open between updates.
+    pub fn dashboard_events(&self) -> Response {
+        let (summary, rows) = self.dashboard_contents();
+        Response::new(200)
+            .header("Content-Type", "text/event-stream")
+            .header("Cache-Control", "no-store")
+            .body(format!(
+                "retry: {}\ndata: {{\"summary\":{},\"rows\":{}}}\n\n",
+                DASHBOARD_REFRESH.as_millis(),
+                json::quote(&summary),
+                json::quote(&rows)
+            ))
+    }
+
+    /// A count of the jobs in each state, and the table rows of the latest.
+    fn dashboard_contents(&self) -> (String, String) {
+        let jobs = self.tracker.jobs();
+        let mut counts = [0; 4];
+        for (_, metadata) in &jobs {
+            counts[match metadata.state() {
+                JobStatus::Pending => 0,
+                JobStatus::Processing => 1,
+                JobStatus::Completed => 2,
+                JobStatus::Failed(_) => 3,
+            }] += 1;
+        }
+        let mut summary = format!(
+            "{} pending, {} processing, {} completed, {} failed",
+            counts[0], counts[1], counts[2], counts[3]
+        );
+        let wait = self.tracker.queue_wait();
+        if wait.samples > 0 {
+            summary.push_str(&format!(
+                "; queue wait p50 {:.1?}, p99 {:.1?}",
+                wait.p50, wait.p99
+            ));
+        }
+        if jobs.len() > DASHBOARD_JOBS {
+            summary.push_str(&format!(
+                "; showing the latest {} of {}",
+                DASHBOARD_JOBS,
+                jobs.len()
+            ));
+        }
+
+        let rows = jobs
+            .iter()
+            .rev()
+            .take(DASHBOARD_JOBS)
+            .map(|(id, metadata)| dashboard_row(*id, metadata))
+            .collect();
+        (summary, rows)
+    }
+}
+
+fn dashboard_row(id: Uuid, metadata: &JobMetadata) -> String {
+    let state = metadata.state().name();
+    let duration = metadata
+        .duration()
+        .map_or("-".to_string(), |duration| format!("{:.1?}", duration));
+    let worker = metadata
+        .worker()
+        .map_or("-".to_string(), |worker| worker.to_string());
+    let mut result: String = metadata
+        .result()
+        .unwrap_or("")
+        .chars()
+        .take(DASHBOARD_RESULT_CHARS)
+        .collect();
+    if result.len() < metadata.result().map_or(0, str::len) {
+        result.push('…');
+    }
+    format!(
+        "<tr class=\"{}\"><td><a href=\"/jobs/{}\">{}</a></td><td>{}</td><td>{}s ago</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+        state,
+        id,
+        id,
+        state,
+        metadata.age().as_secs(),
+        duration,
+        worker,
+        escape_html(&result)
+    )
+}
+
+fn metadata_json(id: Uuid, metadata: &JobMetadata) -> String {
+    let result = match metadata.result() {
+        Some(result) => json::quote(result),
+        None => "null".to_string(),
+    };
+
+    format!(
+        "{{\"id\":{},\"state\":{},\"result\":{}}}",
+        json::quote(&id.to_string()),
+        json::quote(metadata.state().name()),
+        result
+    )
+}
+
+fn error(status: u16, message: &str) -> Response {
+    Response::json(status, format!("{{\"error\":{}}}", json::quote(message)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ThreadPool;
+    use std::sync::mpsc::channel;
+
+    fn api(pool: &ThreadPool, registry: JobRegistry) -> JobsApi {
+        JobsApi::new(
+            pool.tracker(),
+            pool.submitter().unwrap(),
+            Arc::new(registry),
+        )
+    }
+
+    fn post(body: &str) -> HttpRequest {
+        let raw = format!(
+            "POST /jobs HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        HttpRequest::parse(&mut raw.as_bytes()).unwrap().unwrap()
+    }
+
+    #[test]
+    fn status_should_return_job_as_json() {
+        let pool = ThreadPool::build(1).unwrap();
+        let (sender, receiver) = channel();
+        let job_id = pool.execute(move || {
+            receiver.recv().unwrap();
+            Ok("done".to_string())
+        });
+
+        let response = api(&pool, JobRegistry::new()).status(&job_id.to_string());
+        sender.send(()).unwrap();
+
+        assert_eq!(response.status(), 200);
+        let body = String::from_utf8(response.get_body().to_vec()).unwrap();
+        assert!(body.contains(&format!("\"id\":\"{}\"", job_id)));
+        assert!(body.contains("\"result\":null"));
+    }
+
+    #[test]
+    fn status_should_return_404_for_unknown_or_invalid_ids() {
+        let pool = ThreadPool::build(1).unwrap();
+        let api = api(&pool, JobRegistry::new());
+
+        assert_eq!(api.status(&Uuid::new_v4().to_string()).status(), 404);
+        assert_eq!(api.status("not-a-uuid").status(), 404);
+    }
+
+    #[test]
+    fn submit_should_queue_registered_job() {
+        let pool = ThreadPool::build(1).unwrap();
+        let (sender, receiver) = channel();
+        let sender = std::sync::Mutex::new(sender);
+        let mut registry = JobRegistry::new();
+        registry.register("ping", move |_| {
+            let sender = sender.lock().unwrap().clone();
+            Ok(Box::new(move || {
+                sender.send(()).unwrap();
+                Ok("pong".to_string())
+            }))
+        });
+
+        let response = api(&pool, registry).submit(&post(r#"{"job": "ping"}"#));
+
+        assert_eq!(response.status(), 202);
+        assert!(
+            response
+                .get_header("Location")
+                .unwrap()
+                .starts_with("/jobs/")
+        );
+        receiver.recv().unwrap();
+    }
+
+    #[test]
+    fn dashboard_should_list_jobs_newest_first() {
+        let pool = ThreadPool::build(1).unwrap();
+        let failed = pool.execute(|| Err("<oops>".to_string()));
+        let completed = pool.execute(|| Ok("done".to_string()));
+        let api = api(&pool, JobRegistry::new());
+        let tracker = pool.tracker();
+        while tracker
+            .jobs()
+            .iter()
+            .any(|(_, metadata)| metadata.result().is_none())
+        {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        let page = api.dashboard();
+        let page = String::from_utf8(page.get_body().to_vec()).unwrap();
+        assert!(page.contains("0 pending, 0 processing, 1 completed, 1 failed"));
+        assert!(page.contains("; queue wait p50 "));
+        assert!(page.find(&completed.to_string()) < page.find(&failed.to_string()));
+        assert!(page.contains("<td>0</td><td>&lt;oops&gt;</td>"));
+
+        let events = api.dashboard_events();
+        assert_eq!(events.get_header("Content-Type"), Some("text/event-stream"));
+        let events = String::from_utf8(events.get_body().to_vec()).unwrap();
+        assert!(events.starts_with("retry: 2000\ndata: {\"summary\":\"0 pending"));
+        assert!(events.ends_with("}\n\n"));
+    }
+
+    #[test]
+    fn submit_should_reject_invalid_requests() {
+        let pool = ThreadPool::build(1).unwrap();
+        let mut registry = JobRegistry::new();
+        registry.register("strict", |args| match args.get("n") {
+            Some(_) => Ok(Box::new(|| Ok(String::new()))),
+            None => Err("missing n".to_string()),
+        });
+        let api = api(&pool, registry);
+
+        for body in [
+            "not json",
+            "{}",
+            r#"{"job": "nope"}"#,
+            r#"{"job": "strict"}"#,
+        ] {
+            assert_eq!(api.submit(&post(body)).status(), 400, "accepted {}", body);
+        }
+    }
+}
@@ -0,0 +1,667 @@
+//! Listening sockets: TCP addresses or Unix domain socket paths.
+
+use std::{
+    fmt, io,
+    io::{Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    path::PathBuf,
+    time::Duration,
+};
+
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+
+/// Where a server listens.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BindTarget {
+    Tcp(SocketAddr),
+    /// A Unix domain socket at this path.
+    Unix(PathBuf),
+}
+
+impl BindTarget {
+    /// Parses `unix:/path/to.sock` as a Unix socket and anything else as a
+    /// TCP `host:port`, resolving host names.
+    pub fn parse(address: &str) -> Result<BindTarget, String> {
+        if let Some(path) = address.strip_prefix("unix:") {
+            if path.is_empty() {
+                return Err("Unix socket path is empty".to_string());
+            }
+            return Ok(BindTarget::Unix(PathBuf::from(path)));
+        }
+
+        if !address.starts_with('[') && address.matches(':').count() > 1 {
+            return Err(format!(
+                "Invalid address {}: IPv6 addresses need brackets, as in [::1]:7878",
+                address
+            ));
+        }
+        address
+            .to_socket_addrs()
+            .map_err(|err| format!("Invalid address {}: {}", address, err))?
+            .next()
+            .map(BindTarget::Tcp)
+            .ok_or_else(|| format!("Address {} did not resolve", address))
+    }
+}
+
+impl fmt::Display for BindTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BindTarget::Tcp(addr) => write!(f, "{}", addr),
+            BindTarget::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// A bound listening socket.
+#[derive(Debug)]
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, PathBuf),
+}
+
+impl Listener {
+    /// Binds `target`.
+    ///
+    /// For Unix sockets, a leftover socket file nobody is listening on is
+    /// removed first; a live one makes the bind fail with `AddrInUse`.
+    pub fn bind(target: &BindTarget) -> io::Result<Listener> {
+        match target {
+            BindTarget::Tcp(addr) => TcpListener::bind(addr).map(Listener::Tcp),
+            #[cfg(unix)]
+            BindTarget::Unix(path) => {
+                remove_stale_socket(path)?;
+                UnixListener::bind(path).map(|listener| Listener::Unix(listener, path.clone()))
+            }
+            #[cfg(not(unix))]
+            BindTarget::Unix(_) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Unix sockets are not supported on this platform",
+            )),
+        }
+    }
+
+    /// Binds `target` so that further sockets can bind the same address with
+    /// `bind_reuse_port` and the kernel balances connections between them.
+    ///
+    /// Only TCP on Linux with the `reuseport` feature sets `SO_REUSEPORT`;
+    /// everywhere else this is the same as `bind`.
+    pub fn bind_reuse_port(target: &BindTarget) -> io::Result<Listener> {
+        match target {
+            #[cfg(all(target_os = "linux", feature = "reuseport"))]
+            BindTarget::Tcp(addr) => socket::bind(*addr, true, false).map(Listener::Tcp),
+            _ => Listener::bind(target),
+        }
+    }
+
+    /// Binds `target` like `bind`, or `bind_reuse_port` with `reuse_port`,
+    /// but an IPv6 socket only accepts IPv6 clients (`IPV6_V6ONLY`).
+    ///
+    /// Linux usually lets `[::]` accept IPv4 clients as well, which keeps
+    /// an IPv4 address on the same port from being bound. Only sockets
+    /// bound this way leave the port free for it. Needs the `sockopt`
+    /// feature on Linux; elsewhere the system default applies.
+    pub fn bind_v6_only(target: &BindTarget, reuse_port: bool) -> io::Result<Listener> {
+        match target {
+            #[cfg(all(target_os = "linux", feature = "sockopt"))]
+            BindTarget::Tcp(addr) => socket::bind(
+                *addr,
+                reuse_port && Listener::supports_reuse_port(target),
+                true,
+            )
+            .map(Listener::Tcp),
+            _ if reuse_port => Listener::bind_reuse_port(target),
+            _ => Listener::bind(target),
+        }
+    }
+
+    /// Whether `bind_reuse_port` gives every socket its own accept queue for
+    /// `target`, rather than failing on the second bind.
+    pub fn supports_reuse_port(target: &BindTarget) -> bool {
+        matches!(target, BindTarget::Tcp(_))
+            && cfg!(all(target_os = "linux", feature = "reuseport"))
+    }
+
+    /// The address actually bound, e.g. with the port chosen for port 0.
+    pub fn local_target(&self) -> io::Result<BindTarget> {
+        match self {
+            Listener::Tcp(listener) => listener.local_addr().map(BindTarget::Tcp),
+            #[cfg(unix)]
+            Listener::Unix(_, path) => Ok(BindTarget::Unix(path.clone())),
+        }
+    }
+
+    /// Makes `accept` return `WouldBlock` instead of waiting.
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Listener::Tcp(listener) => listener.set_nonblocking(nonblocking),
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => listener.set_nonblocking(nonblocking),
+        }
+    }
+
+    /// Sets how many connections may wait in the kernel's accept queue
+    /// before it refuses more.
+    #[cfg(all(unix, feature = "sockopt"))]
+    pub fn set_backlog(&self, backlog: u32) -> io::Result<()> {
+        use std::os::fd::AsRawFd;
+
+        let fd = match self {
+            Listener::Tcp(listener) => listener.as_raw_fd(),
+            Listener::Unix(listener, _) => listener.as_raw_fd(),
+        };
+        let backlog = backlog.min(libc::c_int::MAX as u32) as libc::c_int;
+        // SAFETY: the descriptor is a listening socket, for which listening
+        // again only changes the backlog.
+        if unsafe { libc::listen(fd, backlog) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Leaves a Unix socket's file in place when the listener is dropped,
+    /// because another process has taken the socket over.
+    #[cfg(all(target_os = "linux", feature = "upgrade"))]
+    pub(crate) fn keep_socket_file(&mut self) {
+        if let Listener::Unix(_, path) = self {
+            *path = PathBuf::new();
+        }
+    }
+
+    /// Waits for the next connection.
+    ///
+    /// IPv4 clients of a dual-stack IPv6 socket are reported with their
+    /// IPv4 address rather than as `::ffff:a.b.c.d`, so they match IPv4
+    /// ranges such as `trusted_proxies`.
+    pub fn accept(&self) -> io::Result<(Stream, Option<SocketAddr>)> {
+        match self {
+            Listener::Tcp(listener) => listener.accept().map(|(stream, peer)| {
+                let peer = SocketAddr::new(peer.ip().to_canonical(), peer.port());
+                (Stream::Tcp(stream), Some(peer))
+            }),
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => listener
+                .accept()
+                .map(|(stream, _)| (Stream::Unix(stream), None)),
+        }
+    }
+}
+
+/// Why accepting a connection failed, which decides how soon to retry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum AcceptError {
+    /// The client went away before its connection was accepted; the next
+    /// one is unaffected.
+    Connection,
+    /// The process or system ran out of file descriptors or memory, so
+    /// accepting again right away fails the same way.
+    Exhausted,
+    /// Anything else, which may or may not last.
+    Other,
+}
+
+/// `EMFILE` and `ENFILE`, which have these values on every Unix.
+#[cfg(unix)]
+const DESCRIPTOR_LIMITS: [i32; 2] = [24, 23];
+
+impl AcceptError {
+    pub(crate) fn classify(err: &io::Error) -> AcceptError {
+        #[cfg(unix)]
+        if err
+            .raw_os_error()
+            .is_some_and(|code| DESCRIPTOR_LIMITS.contains(&code))
+        {
+            return AcceptError::Exhausted;
+        }
+        match err.kind() {
+            io::ErrorKind::OutOfMemory => AcceptError::Exhausted,
+            io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::Interrupted
+            | io::ErrorKind::HostUnreachable
+            | io::ErrorKind::NetworkUnreachable
+            | io::ErrorKind::NetworkDown => AcceptError::Connection,
+            _ => AcceptError::Other,
+        }
+    }
+}
+
+/// A file descriptor held in reserve for when the process runs out.
+///
+/// Out of descriptors, a listener can't accept the connections waiting on
+/// it, so their clients hang until they time out. Closing the spare frees
+/// one slot to accept the oldest of them and close it right away, so its
+/// client learns at once to retry later.
+#[derive(Debug)]
+pub(crate) struct SpareDescriptor {
+    file: Option<std::fs::File>,
+}
+
+impl SpareDescriptor {
+    pub(crate) fn new() -> SpareDescriptor {
+        SpareDescriptor {
+            file: SpareDescriptor::open(),
+        }
+    }
+
+    fn open() -> Option<std::fs::File> {
+        #[cfg(unix)]
+        return std::fs::File::open("/dev/null").ok();
+        #[cfg(not(unix))]
+        None
+    }
+
+    /// Closes one connection waiting on `listener` using the spare, and
+    /// takes the spare back. Returns whether a connection was closed.
+    pub(crate) fn shed(&mut self, listener: &Listener) -> bool {
+        if self.file.take().is_none() {
+            self.file = SpareDescriptor::open();
+            return false;
+        }
+        let shed = listener.accept().is_ok();
+        self.file = SpareDescriptor::open();
+        shed
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Listener::Unix(_, path) = self
+            && !path.as_os_str().is_empty()
+        {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn remove_stale_socket(path: &std::path::Path) -> io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err),
+    };
+    if !metadata.file_type().is_socket() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} exists and is not a socket", path.display()),
+        ));
+    }
+
+    match UnixStream::connect(path) {
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("another server is listening on {}", path.display()),
+        )),
+        Err(_) => std::fs::remove_file(path),
+    }
+}
+
+#[cfg(all(target_os = "linux", any(feature = "reuseport", feature = "sockopt")))]
+mod socket {
+    use std::{
+        io, mem,
+        net::{SocketAddr, TcpListener},
+        os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    };
+
+    /// Binds a listening socket, sharing the port with other sockets that
+    /// set `reuse_port` and, for IPv6, only for IPv6 clients with
+    /// `v6_only`.
+    pub fn bind(addr: SocketAddr, reuse_port: bool, v6_only: bool) -> io::Result<TcpListener> {
+        let domain = match addr {
+            SocketAddr::V4(_) => libc::AF_INET,
+            SocketAddr::V6(_) => libc::AF_INET6,
+        };
+        // SAFETY: `socket` has no preconditions; the result is checked
+        // before it is given to `OwnedFd`, which closes it on every path.
+        let fd = unsafe { libc::socket(domain, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        enable(&socket, libc::SOL_SOCKET, libc::SO_REUSEADDR)?;
+        if reuse_port {
+            enable(&socket, libc::SOL_SOCKET, libc::SO_REUSEPORT)?;
+        }
+        if v6_only && addr.is_ipv6() {
+            enable(&socket, libc::IPPROTO_IPV6, libc::IPV6_V6ONLY)?;
+        }
+
+        // SAFETY: both address structs are plain data for which all zeroes
+        // is valid, and the length passed matches the struct used.
+        let result = unsafe {
+            match addr {
+                SocketAddr::V4(addr) => {
+                    let mut raw: libc::sockaddr_in = mem::zeroed();
+                    raw.sin_family = libc::AF_INET as libc::sa_family_t;
+                    raw.sin_port = addr.port().to_be();
+                    raw.sin_addr.s_addr = u32::from(*addr.ip()).to_be();
+                    libc::bind(
+                        socket.as_raw_fd(),
+                        &raw as *const libc::sockaddr_in as *const libc::sockaddr,
+                        mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+                    )
+                }
+                SocketAddr::V6(addr) => {
+                    let mut raw: libc::sockaddr_in6 = mem::zeroed();
+                    raw.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+                    raw.sin6_port = addr.port().to_be();
+                    raw.sin6_addr.s6_addr = addr.ip().octets();
+                    raw.sin6_flowinfo = addr.flowinfo();
+                    raw.sin6_scope_id = addr.scope_id();
+                    libc::bind(
+                        socket.as_raw_fd(),
+                        &raw as *const libc::sockaddr_in6 as *const libc::sockaddr,
+                        mem::size_of::<libc::sockaddr_in6>() as libc::socklen_t,
+                    )
+                }
+            }
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // SAFETY: the descriptor is a bound stream socket.
+        if unsafe { libc::listen(socket.as_raw_fd(), 128) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(TcpListener::from(socket))
+    }
+
+    /// Turns on the boolean socket option `option` at `level`.
+    fn enable(socket: &OwnedFd, level: libc::c_int, option: libc::c_int) -> io::Result<()> {
+        let enable: libc::c_int = 1;
+        // SAFETY: `enable` outlives the call and its size is passed.
+        let result = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                level,
+                option,
+                &enable as *const libc::c_int as *const libc::c_void,
+                mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+/// An accepted connection.
+#[derive(Debug)]
+pub enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Stream {
+    /// Opens a client connection to `target`.
+    pub fn connect(target: &BindTarget) -> io::Result<Stream> {
+        match target {
+            BindTarget::Tcp(addr) => TcpStream::connect(addr).map(Stream::Tcp),
+            #[cfg(unix)]
+            BindTarget::Unix(path) => UnixStream::connect(path).map(Stream::Unix),
+            #[cfg(not(unix))]
+            BindTarget::Unix(_) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Unix sockets are not supported on this platform",
+            )),
+        }
+    }
+
+    /// Accepted streams may inherit the listener's non-blocking mode on some
+    /// platforms, so servers reset it explicitly.
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_nonblocking(nonblocking),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.set_nonblocking(nonblocking),
+        }
+    }
+
+    /// Sends small writes at once instead of waiting to coalesce them
+    /// (Nagle's algorithm). Unix sockets have nothing to change.
+    pub fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_nodelay(nodelay),
+            #[cfg(unix)]
+            Stream::Unix(_) => Ok(()),
+        }
+    }
+
+    /// Makes closing a TCP connection wait up to `linger` for unsent data
+    /// to go out (`SO_LINGER`). With zero it is reset at once instead.
+    #[cfg(all(unix, feature = "sockopt"))]
+    pub fn set_linger(&self, linger: Duration) -> io::Result<()> {
+        use std::os::fd::AsRawFd;
+
+        let Stream::Tcp(stream) = self else {
+            return Ok(());
+        };
+        let linger = libc::linger {
+            l_onoff: 1,
+            l_linger: linger.as_secs().min(libc::c_int::MAX as u64) as libc::c_int,
+        };
+        // SAFETY: `linger` outlives the call and its size is passed.
+        let result = unsafe {
+            libc::setsockopt(
+                stream.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_LINGER,
+                &linger as *const libc::linger as *const libc::c_void,
+                std::mem::size_of::<libc::linger>() as libc::socklen_t,
+            )
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    pub fn set_timeouts(&self, read: Option<Duration>, write: Option<Duration>) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => {
+                stream.set_read_timeout(read)?;
+                stream.set_write_timeout(write)
+            }
+            #[cfg(unix)]
+            Stream::Unix(stream) => {
+                stream.set_read_timeout(read)?;
+                stream.set_write_timeout(write)
+            }
+        }
+    }
+}
+
+impl From<TcpStream> for Stream {
+    fn from(stream: TcpStream) -> Stream {
+        Stream::Tcp(stream)
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_should_distinguish_tcp_and_unix_targets() {
+        assert_eq!(
+            BindTarget::parse("127.0.0.1:7878").unwrap(),
+            BindTarget::Tcp("127.0.0.1:7878".parse().unwrap())
+        );
+        assert_eq!(
+            BindTarget::parse("unix:/tmp/harbor.sock").unwrap(),
+            BindTarget::Unix(PathBuf::from("/tmp/harbor.sock"))
+        );
+        assert_eq!(
+            BindTarget::parse("[::]:7878").unwrap(),
+            BindTarget::Tcp("[::]:7878".parse().unwrap())
+        );
+        assert!(BindTarget::parse("::1:7878").is_err());
+        assert!(BindTarget::parse("unix:").is_err());
+        assert!(BindTarget::parse("no-port").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn bind_should_replace_stale_unix_sockets_and_clean_up() {
+        let path = std::env::temp_dir().join(format!("harbor-stale-{}.sock", std::process::id()));
+        let target = BindTarget::Unix(path.clone());
+
+        // Leave a socket file behind without anybody listening on it.
+        drop(UnixListener::bind(&path).unwrap());
+        assert!(path.exists());
+
+        let listener = Listener::bind(&target).unwrap();
+        assert!(matches!(
+            Listener::bind(&target).unwrap_err().kind(),
+            io::ErrorKind::AddrInUse
+        ));
+
+        drop(listener);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn classify_should_tell_exhaustion_from_lost_connections() {
+        assert_eq!(
+            AcceptError::classify(&io::ErrorKind::ConnectionAborted.into()),
+            AcceptError::Connection
+        );
+        assert_eq!(
+            AcceptError::classify(&io::ErrorKind::OutOfMemory.into()),
+            AcceptError::Exhausted
+        );
+        #[cfg(unix)]
+        assert_eq!(
+            AcceptError::classify(&io::Error::from_raw_os_error(24)),
+            AcceptError::Exhausted
+        );
+        assert_eq!(
+            AcceptError::classify(&io::ErrorKind::PermissionDenied.into()),
+            AcceptError::Other
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn shed_should_close_the_oldest_waiting_connection() {
+        let listener = Listener::bind(&BindTarget::parse("127.0.0.1:0").unwrap()).unwrap();
+        let Listener::Tcp(tcp) = &listener else {
+            unreachable!("bound a TCP address");
+        };
+        let mut client = TcpStream::connect(tcp.local_addr().unwrap()).unwrap();
+        let mut spare = SpareDescriptor::new();
+
+        assert!(spare.shed(&listener));
+        assert_eq!(client.read(&mut [0; 1]).unwrap(), 0);
+        assert!(spare.file.is_some());
+    }
+
+    #[cfg(all(target_os = "linux", feature = "reuseport"))]
+    #[test]
+    fn bind_reuse_port_should_share_the_address() {
+        let first = Listener::bind_reuse_port(&BindTarget::parse("127.0.0.1:0").unwrap()).unwrap();
+        let target = first.local_target().unwrap();
+        let second = Listener::bind_reuse_port(&target).unwrap();
+        assert_eq!(second.local_target().unwrap(), target);
+        assert!(Listener::bind(&target).is_err());
+    }
+
+    #[cfg(all(target_os = "linux", feature = "sockopt"))]
+    #[test]
+    fn bind_v6_only_should_leave_the_port_to_ipv4() {
+        let v6 = Listener::bind_v6_only(&BindTarget::parse("[::]:0").unwrap(), false).unwrap();
+        let Ok(BindTarget::Tcp(bound)) = v6.local_target() else {
+            unreachable!("bound a TCP address");
+        };
+        let v4 = BindTarget::Tcp(SocketAddr::from(([127, 0, 0, 1], bound.port())));
+        assert!(Listener::bind(&v4).is_ok());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn accept_should_report_ipv4_clients_of_dual_stack_sockets_as_ipv4() {
+        let listener = Listener::bind(&BindTarget::parse("[::]:0").unwrap()).unwrap();
+        let Ok(BindTarget::Tcp(bound)) = listener.local_target() else {
+            unreachable!("bound a TCP address");
+        };
+
+        let _client = TcpStream::connect(("127.0.0.1", bound.port())).unwrap();
+        let (_, peer) = listener.accept().unwrap();
+        assert_eq!(peer.unwrap().ip(), std::net::Ipv4Addr::LOCALHOST);
+    }
+
+    #[cfg(all(unix, feature = "sockopt"))]
+    #[test]
+    fn set_linger_should_apply_to_accepted_connections() {
+        use std::os::fd::AsRawFd;
+
+        let listener = Listener::bind(&BindTarget::parse("127.0.0.1:0").unwrap()).unwrap();
+        listener.set_backlog(16).unwrap();
+        let Listener::Tcp(tcp) = &listener else {
+            unreachable!("bound a TCP address");
+        };
+        let _client = TcpStream::connect(tcp.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+
+        stream.set_nodelay(true).unwrap();
+        stream.set_linger(Duration::from_secs(3)).unwrap();
+
+        let Stream::Tcp(accepted) = &stream else {
+            unreachable!("accepted from a TCP listener");
+        };
+        assert!(accepted.nodelay().unwrap());
+        let mut linger: libc::linger = unsafe { std::mem::zeroed() };
+        let mut len = std::mem::size_of::<libc::linger>() as libc::socklen_t;
+        // SAFETY: `linger` and `len` outlive the call, and `len` is its size.
+        let result = unsafe {
+            libc::getsockopt(
+                accepted.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_LINGER,
+                &mut linger as *mut libc::linger as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        assert_eq!(result, 0);
+        assert_eq!((linger.l_onoff, linger.l_linger), (1, 3));
+    }
+}
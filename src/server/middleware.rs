@@ -0,0 +1,132 @@
+//! Code that runs around every request, before and after its handler.
+
+use std::fmt;
+
+use super::{request::HttpRequest, response::Response};
+
+/// Wraps request handling: inspect or change the request, call `next` (or
+/// answer without it), then inspect or change the response.
+///
+/// Closures taking `(&mut HttpRequest, Next)` are middleware too.
+///
+/// # Example
+///
+/// ```
+/// # use harbor::server::{Server, middleware::Next};
+/// let server = Server::new("127.0.0.1:7878").middleware(
+///     |request: &mut harbor::server::HttpRequest, next: Next| {
+///         next.run(request).header("X-Frame-Options", "DENY")
+///     },
+/// );
+/// ```
+pub trait Middleware: Send + Sync {
+    fn handle(&self, request: &mut HttpRequest, next: Next<'_>) -> Response;
+}
+
+impl<F> Middleware for F
+where
+    F: Fn(&mut HttpRequest, Next<'_>) -> Response + Send + Sync,
+{
+    fn handle(&self, request: &mut HttpRequest, next: Next<'_>) -> Response {
+        self(request, next)
+    }
+}
+
+/// The rest of the chain: the remaining middleware, then the handler.
+pub struct Next<'a> {
+    chain: &'a [Box<dyn Middleware>],
+    endpoint: &'a dyn Fn(&HttpRequest) -> Response,
+}
+
+impl Next<'_> {
+    /// Passes the request on and returns the response produced further in.
+    pub fn run(self, request: &mut HttpRequest) -> Response {
+        match self.chain.split_first() {
+            Some((first, rest)) => first.handle(
+                request,
+                Next {
+                    chain: rest,
+                    endpoint: self.endpoint,
+                },
+            ),
+            None => (self.endpoint)(request),
+        }
+    }
+}
+
+/// Middleware in the order it runs; the first added is the outermost.
+#[derive(Default)]
+pub struct MiddlewareStack {
+    chain: Vec<Box<dyn Middleware>>,
+}
+
+impl MiddlewareStack {
+    pub fn new() -> MiddlewareStack {
+        MiddlewareStack::default()
+    }
+
+    pub fn push(&mut self, middleware: impl Middleware + 'static) {
+        self.chain.push(Box::new(middleware));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chain.is_empty()
+    }
+
+    /// Sends `request` through every middleware and finally `endpoint`.
+    pub fn run(
+        &self,
+        request: &mut HttpRequest,
+        endpoint: &dyn Fn(&HttpRequest) -> Response,
+    ) -> Response {
+        Next {
+            chain: &self.chain,
+            endpoint,
+        }
+        .run(request)
+    }
+}
+
+impl fmt::Debug for MiddlewareStack {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MiddlewareStack")
+            .field("len", &self.chain.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn run_should_call_middleware_outermost_first() {
+        let mut stack = MiddlewareStack::new();
+        stack.push(|request: &mut HttpRequest, next: Next| {
+            next.run(request).header("X-Order", "outer")
+        });
+        stack.push(|request: &mut HttpRequest, next: Next| {
+            if request.path() == "/blocked" {
+                return Response::new(403);
+            }
+            next.run(request).header("X-Order", "inner")
+        });
+        let endpoint = |_: &HttpRequest| Response::new(200);
+
+        let mut allowed = HttpRequest::parse(&mut &b"GET / HTTP/1.1\r\n\r\n"[..])
+            .unwrap()
+            .unwrap();
+        let mut blocked = HttpRequest::parse(&mut &b"GET /blocked HTTP/1.1\r\n\r\n"[..])
+            .unwrap()
+            .unwrap();
+        let response = stack.run(&mut allowed, &endpoint);
+        let refused = stack.run(&mut blocked, &endpoint);
+
+        let mut buffer = Vec::new();
+        response.write_to(&mut buffer).unwrap();
+        let head = String::from_utf8(buffer).unwrap();
+        assert!(head.find("X-Order: inner").unwrap() < head.find("X-Order: outer").unwrap());
+        assert_eq!(refused.status(), 403);
+        assert_eq!(refused.get_header("X-Order"), Some("outer"));
+    }
+}
@@ -0,0 +1,128 @@
+//! Content types for file extensions.
+
+use std::{collections::HashMap, path::Path};
+
+/// Sent for files whose extension is unknown.
+pub const DEFAULT_TYPE: &str = "application/octet-stream";
+
+const BUILT_IN: &[(&str, &str)] = &[
+    ("html", "text/html; charset=utf-8"),
+    ("htm", "text/html; charset=utf-8"),
+    ("css", "text/css; charset=utf-8"),
+    ("js", "text/javascript; charset=utf-8"),
+    ("mjs", "text/javascript; charset=utf-8"),
+    ("json", "application/json"),
+    ("map", "application/json"),
+    ("txt", "text/plain; charset=utf-8"),
+    ("md", "text/markdown; charset=utf-8"),
+    ("csv", "text/csv; charset=utf-8"),
+    ("xml", "application/xml"),
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("avif", "image/avif"),
+    ("svg", "image/svg+xml"),
+    ("ico", "image/x-icon"),
+    ("wasm", "application/wasm"),
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("ttf", "font/ttf"),
+    ("otf", "font/otf"),
+    ("pdf", "application/pdf"),
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+    ("mp3", "audio/mpeg"),
+    ("ogg", "audio/ogg"),
+    ("wav", "audio/wav"),
+    ("mp4", "video/mp4"),
+    ("webm", "video/webm"),
+];
+
+/// Looks up the built-in content type for `extension`, ignoring case.
+pub fn from_extension(extension: &str) -> Option<&'static str> {
+    BUILT_IN
+        .iter()
+        .find(|(known, _)| known.eq_ignore_ascii_case(extension))
+        .map(|(_, content_type)| *content_type)
+}
+
+/// The built-in content type for `path`, or `DEFAULT_TYPE`.
+pub fn from_path(path: impl AsRef<Path>) -> &'static str {
+    extension(path.as_ref())
+        .and_then(|extension| from_extension(&extension))
+        .unwrap_or(DEFAULT_TYPE)
+}
+
+/// The built-in table plus user overrides, which take precedence.
+///
+/// # Example
+///
+/// ```
+/// # use harbor::server::mime::MimeTypes;
+/// let types = MimeTypes::new().insert("webmanifest", "application/manifest+json");
+/// assert_eq!(types.lookup("site.webmanifest"), "application/manifest+json");
+/// assert_eq!(types.lookup("logo.png"), "image/png");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MimeTypes {
+    overrides: HashMap<String, String>,
+}
+
+impl MimeTypes {
+    pub fn new() -> MimeTypes {
+        MimeTypes::default()
+    }
+
+    /// Serves files ending in `.extension` as `content_type`.
+    pub fn insert(mut self, extension: &str, content_type: &str) -> MimeTypes {
+        self.overrides.insert(
+            extension.trim_start_matches('.').to_ascii_lowercase(),
+            content_type.to_string(),
+        );
+        self
+    }
+
+    /// The content type for `path`, or `DEFAULT_TYPE`.
+    pub fn lookup(&self, path: impl AsRef<Path>) -> &str {
+        let Some(extension) = extension(path.as_ref()) else {
+            return DEFAULT_TYPE;
+        };
+        match self.overrides.get(&extension) {
+            Some(content_type) => content_type,
+            None => from_extension(&extension).unwrap_or(DEFAULT_TYPE),
+        }
+    }
+}
+
+fn extension(path: &Path) -> Option<String> {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_path_should_match_extensions_case_insensitively() {
+        assert_eq!(from_path("index.HTML"), "text/html; charset=utf-8");
+        assert_eq!(from_path("app.wasm"), "application/wasm");
+        assert_eq!(from_path("font.woff2"), "font/woff2");
+        assert_eq!(from_path("README"), DEFAULT_TYPE);
+        assert_eq!(from_path("archive.unknown"), DEFAULT_TYPE);
+    }
+
+    #[test]
+    fn lookup_should_prefer_overrides() {
+        let types = MimeTypes::new()
+            .insert(".js", "application/javascript")
+            .insert("LOG", "text/plain");
+
+        assert_eq!(types.lookup("app.js"), "application/javascript");
+        assert_eq!(types.lookup("server.log"), "text/plain");
+        assert_eq!(types.lookup("style.css"), "text/css; charset=utf-8");
+    }
+}
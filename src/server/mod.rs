@@ -1,96 +1,2350 @@
 use std::{
-    fs,
-    io::{BufReader, prelude::*},
-    net::{TcpListener, TcpStream},
+    cell::RefCell,
+    collections::BTreeMap,
+    fmt, fs,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpStream},
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex, OnceLock, RwLock,
+        atomic::{AtomicBool, AtomicU8, Ordering},
+        mpsc::{self, RecvTimeoutError},
+    },
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-use crate::ThreadPool;
+use self::{
+    connections::{ConnectionGuard, is_disconnect},
+    deadline::{Deadline, ReadTimeout},
+    handler::State,
+    listener::{AcceptError, SpareDescriptor},
+    timing::Pending,
+};
+use crate::{
+    HarborError, JobSubmitter, PanicPolicy, ThreadPool, debug, error, info,
+    registry::{JobDescriptor, JobRegistry},
+    warn,
+};
 
-/// Entry point of the web server.
+pub mod access_log;
+pub mod assets;
+pub mod body;
+#[cfg(feature = "brotli")]
+mod brotli;
+pub mod cache;
+#[cfg(feature = "brotli")]
+pub mod compression;
+pub mod config;
+pub mod connections;
+pub mod cookie;
+pub mod date;
+mod deadline;
+pub mod encoding;
+#[cfg(all(target_os = "linux", feature = "evented"))]
+mod evented;
+pub mod forwarded;
+pub mod handler;
+pub mod headers;
+#[cfg(feature = "http2")]
+mod hpack;
+#[cfg(feature = "http2")]
+mod http2;
+pub mod jobs;
+pub mod listener;
+pub mod middleware;
+pub mod mime;
+pub mod multipart;
+pub mod proxy_protocol;
+pub mod request;
+pub mod response;
+pub mod rewrite;
+pub mod router;
+pub mod security;
+pub mod session;
+mod sha256;
+#[cfg(feature = "signals")]
+pub mod signals;
+pub mod static_files;
+pub mod takeover;
+pub mod template;
+pub mod timing;
+pub mod traffic;
+#[cfg(all(target_os = "linux", feature = "upgrade"))]
+mod upgrade;
+pub mod url;
+
+pub use access_log::{AccessLog, AccessLogFormat, Rotation};
+pub use assets::EmbeddedAssets;
+pub use body::{Body, BodyHandler};
+pub use cache::{CacheStats, FileCache};
+#[cfg(feature = "brotli")]
+pub use compression::Compression;
+pub use config::{ConfigError, DEFAULT_ALLOWED_METHODS, LogLevel, Redirect, ServerConfig};
+pub use connections::{ConnectionStats, Connections};
+pub use cookie::{Cookie, SameSite};
+pub use forwarded::Cidr;
+pub use handler::Handler;
+pub use headers::Headers;
+pub use jobs::JobsApi;
+pub use listener::{BindTarget, Listener, Stream};
+pub use middleware::{Middleware, MiddlewareStack, Next};
+pub use mime::MimeTypes;
+pub use request::{HttpRequest, ParseError};
+pub use response::{FlushPolicy, Response};
+pub use rewrite::Rewrite;
+pub use router::Router;
+pub use security::SecurityHeaders;
+pub use session::{MemoryStore, Session, SessionStore, Sessions};
+pub use static_files::{CacheRule, StaticFiles, TrailingSlash};
+pub use takeover::Upgraded;
+pub use timing::{Phases, Timing};
+pub use traffic::{Traffic, TrafficStats};
+
+/// A configurable web server backed by a harbor `ThreadPool`.
+#[derive(Debug)]
+pub struct Server {
+    config: ServerConfig,
+    router: Router,
+    middleware: MiddlewareStack,
+    assets: Option<EmbeddedAssets>,
+    state: Option<State>,
+    registry: Arc<JobRegistry>,
+    cache: Arc<FileCache>,
+    connections: Arc<Connections>,
+    traffic: Arc<Traffic>,
+    stop: Arc<AtomicBool>,
+    reload: Arc<AtomicBool>,
+    bound: Arc<OnceLock<Vec<BindTarget>>>,
+    /// The file the configuration was loaded from, reread on reload.
+    config_path: Option<PathBuf>,
+    on_shutdown: ShutdownHooks,
+    panic_policy: PanicPolicy,
+    #[cfg(feature = "signals")]
+    handle_signals: bool,
+}
+
+/// The callbacks registered with `Server::on_shutdown`, behind a lock
+/// only so the server can be shared with the accept loops.
+#[derive(Default)]
+struct ShutdownHooks(Mutex<Vec<Box<dyn FnOnce() + Send>>>);
+
+impl ShutdownHooks {
+    /// Runs every hook in the order they were added. One that panics is
+    /// logged and doesn't keep the others from running.
+    fn run(self) {
+        for hook in self.0.into_inner().unwrap() {
+            if let Err(panic) = panic::catch_unwind(AssertUnwindSafe(hook)) {
+                error!("Shutdown hook panicked: {}", crate::panic_message(&*panic));
+            }
+        }
+    }
+}
+
+impl fmt::Debug for ShutdownHooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ShutdownHooks")
+            .field("len", &self.0.lock().unwrap().len())
+            .finish()
+    }
+}
+
+/// Stops a running `Server` from another thread.
+#[derive(Debug, Clone)]
+pub struct ServerHandle {
+    stop: Arc<AtomicBool>,
+    reload: Arc<AtomicBool>,
+    bound: Arc<OnceLock<Vec<BindTarget>>>,
+}
+
+impl ServerHandle {
+    /// Asks the server to stop accepting connections. `run` then waits up to
+    /// the grace period for in-flight connections and returns.
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+
+    /// Asks the server to reread its configuration file and apply the
+    /// settings that can change while it runs. Only servers created with
+    /// `Server::from_config` have a file to reload.
+    ///
+    /// Routes, static files, redirects, limits, timeouts and the log level
+    /// take effect for new connections; open ones finish with the old
+    /// settings. Values changed with `Server` methods are replaced by the
+    /// file's. See `ServerConfig::restart_required` for the rest.
+    pub fn reload(&self) {
+        self.reload.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether `stop` has been called.
+    pub fn is_stopped(&self) -> bool {
+        self.stop.load(Ordering::SeqCst)
+    }
+
+    /// The addresses the server is listening on, in configuration order,
+    /// once `run` has bound them. Ports given as 0 show the port the
+    /// operating system picked.
+    pub fn local_targets(&self) -> Option<&[BindTarget]> {
+        self.bound.get().map(Vec::as_slice)
+    }
+
+    /// The first TCP address the server is listening on, once bound.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addrs()?.into_iter().next()
+    }
+
+    /// Every TCP address the server is listening on, such as both the
+    /// IPv4 and the IPv6 one, once bound.
+    pub fn local_addrs(&self) -> Option<Vec<SocketAddr>> {
+        let addrs = self
+            .local_targets()?
+            .iter()
+            .filter_map(|target| match target {
+                BindTarget::Tcp(address) => Some(*address),
+                BindTarget::Unix(_) => None,
+            })
+            .collect();
+        Some(addrs)
+    }
+}
+
+/// How long an idle accept loop waits, at most, before checking for new
+/// connections and shutdown again. Busy loops poll much sooner.
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The first wait after a connection was accepted; it doubles while the
+/// listener stays idle, up to `ACCEPT_POLL_INTERVAL`.
+const ACCEPT_MIN_POLL_INTERVAL: Duration = Duration::from_micros(50);
+
+/// How long accepting pauses after an error that is likely to repeat, such
+/// as the process running out of file descriptors.
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(50);
+
+/// Seconds a client refused with `503` or `429` is asked to wait before
+/// retrying.
+const RETRY_AFTER_SECS: u64 = 1;
+
+/// Most handlers left running past their timeout at once. Further timed
+/// requests are answered `503` until some of them return, and one timing
+/// out in the meantime keeps its worker waiting for it, so handlers that
+/// never return cannot pile up threads without bound.
+const MAX_ABANDONED_HANDLERS: usize = 64;
+
+/// How often a running server checks for a requested configuration reload.
+const RELOAD_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Everything a connection needs to produce a response.
 ///
-/// Bind a TCP Listener to the address, creates a thread pool, and enters
-/// a loop to handler incoming connections.
-pub fn start(address: &str) {
-    let listener = match TcpListener::bind(address) {
-        Ok(listener) => listener,
-        Err(err) => {
-            eprintln!("Failed to bind to address: {}. Error: {}", address, err);
-            return;
+/// The parts set up in code are shared between reloads; the rest comes
+/// from the configuration and is rebuilt by `reconfigured`.
+#[derive(Debug, Default)]
+struct Context {
+    router: Arc<Router>,
+    middleware: Arc<MiddlewareStack>,
+    assets: Option<EmbeddedAssets>,
+    state: Option<State>,
+    /// The default worker pool, for connections moving back to it.
+    pool: Option<JobSubmitter>,
+    /// The pools routes can be pinned to with `Router::pool`.
+    pools: BTreeMap<String, JobSubmitter>,
+    jobs: Option<JobsApi>,
+    cache: Option<Arc<FileCache>>,
+    access_log: Option<Arc<AccessLog>>,
+    traffic: Arc<Traffic>,
+    connections: Arc<Connections>,
+    redirects: Router,
+    rewrites: Vec<Rewrite>,
+    static_files: Option<StaticFiles>,
+    server_header: Option<String>,
+    job_dashboard: bool,
+    tcp_nodelay: bool,
+    #[cfg(all(unix, feature = "sockopt"))]
+    linger: Option<Duration>,
+    keep_alive: bool,
+    flush_policy: FlushPolicy,
+    max_requests_per_connection: Option<usize>,
+    max_body_bytes: Option<usize>,
+    max_connections: Option<usize>,
+    max_connections_per_ip: Option<usize>,
+    max_queued_connections: Option<usize>,
+    /// The queue depths to stop accepting above and resume at.
+    accept_water_marks: Option<(usize, usize)>,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    header_timeout: Option<Duration>,
+    keep_alive_timeout: Option<Duration>,
+    handler_timeout: Option<Duration>,
+    allowed_hosts: Vec<String>,
+    allowed_methods: Vec<String>,
+    proxy_protocol: bool,
+    trusted_proxies: Vec<Cidr>,
+    https_redirect: Option<u16>,
+}
+
+impl Context {
+    /// A context sharing this one's router, middleware, assets, jobs,
+    /// cache and access log, with everything else taken from `config`.
+    fn reconfigured(&self, config: &ServerConfig) -> Context {
+        let redirects = config
+            .redirects
+            .iter()
+            .fold(Router::new(), |router, redirect| {
+                if redirect.permanent {
+                    router.redirect_permanent(&redirect.from, &redirect.to)
+                } else {
+                    router.redirect(&redirect.from, &redirect.to)
+                }
+            });
+        let static_files = config.document_root.as_ref().map(|root| {
+            let mime_types = config
+                .mime_types
+                .iter()
+                .fold(MimeTypes::new(), |types, (extension, content_type)| {
+                    types.insert(extension, content_type)
+                });
+            let files = StaticFiles::new(root)
+                .directory_listing(config.directory_listing)
+                .precompressed(config.precompressed)
+                .trailing_slash(config.trailing_slash)
+                .mime_types(mime_types)
+                .cache_control(config.cache_control.clone());
+            match &self.cache {
+                Some(cache) => files.cache(Arc::clone(cache)),
+                None => files,
+            }
+        });
+
+        Context {
+            router: Arc::clone(&self.router),
+            middleware: Arc::clone(&self.middleware),
+            assets: self.assets.clone(),
+            state: self.state.clone(),
+            pool: self.pool.clone(),
+            pools: self.pools.clone(),
+            jobs: self.jobs.clone(),
+            cache: self.cache.clone(),
+            access_log: self.access_log.clone(),
+            traffic: Arc::clone(&self.traffic),
+            connections: Arc::clone(&self.connections),
+            redirects,
+            rewrites: config.rewrites.clone(),
+            static_files,
+            server_header: config.server_header.clone(),
+            job_dashboard: config.job_dashboard,
+            tcp_nodelay: config.tcp_nodelay,
+            #[cfg(all(unix, feature = "sockopt"))]
+            linger: config.linger,
+            keep_alive: config.keep_alive,
+            flush_policy: config.flush_policy,
+            max_requests_per_connection: config.max_requests_per_connection,
+            max_body_bytes: config.max_body_bytes,
+            max_connections: config.max_connections,
+            max_connections_per_ip: config.max_connections_per_ip,
+            max_queued_connections: config.max_queued_connections,
+            accept_water_marks: config.accept_high_water.map(|high| {
+                let low = config.accept_low_water.unwrap_or(high / 2);
+                (high, low.min(high))
+            }),
+            read_timeout: config.read_timeout,
+            write_timeout: config.write_timeout,
+            header_timeout: config.header_timeout,
+            keep_alive_timeout: config.keep_alive_timeout,
+            handler_timeout: config.handler_timeout,
+            allowed_hosts: config.allowed_hosts.clone(),
+            allowed_methods: config.allowed_methods.clone(),
+            proxy_protocol: config.proxy_protocol,
+            trusted_proxies: config.trusted_proxies.clone(),
+            https_redirect: config.https_redirect,
         }
-    };
+    }
 
-    let pool = match ThreadPool::build(4) {
-        Ok(threads) => threads,
-        Err(error) => {
-            eprintln!("You cannot create a thread pool of size zero: {:?}", error);
-            return;
+    /// `response` with the configured `Server` header, unless it set its own.
+    fn identified(&self, response: Response) -> Response {
+        match &self.server_header {
+            Some(name) if response.get_header("Server").is_none() => {
+                response.header("Server", name)
+            }
+            _ => response,
         }
-    };
+    }
 
-    for stream in listener.incoming() {
-        let stream = match stream {
-            Ok(stream) => stream,
+    /// The pool answering `request`, if it is for a route pinned to one of
+    /// the server's named pools.
+    fn pool_for(&self, request: &HttpRequest) -> Option<(&str, &JobSubmitter)> {
+        let name = self.router.pool_for(request)?;
+        let (name, pool) = self.pools.get_key_value(name)?;
+        Some((name, pool))
+    }
+}
+
+impl Server {
+    /// Creates a server that will listen on `address` with the default
+    /// configuration.
+    pub fn new(address: &str) -> Server {
+        Server::with_config(ServerConfig {
+            address: address.to_string(),
+            ..ServerConfig::default()
+        })
+    }
+
+    /// Creates a server from a full `ServerConfig`.
+    pub fn with_config(config: ServerConfig) -> Server {
+        Server {
+            cache: Arc::new(FileCache::new(config.file_cache_bytes)),
+            connections: Arc::new(Connections::new()),
+            traffic: Arc::new(Traffic::new()),
+            config,
+            router: Router::new(),
+            middleware: MiddlewareStack::new(),
+            assets: None,
+            state: None,
+            registry: Arc::new(JobRegistry::new()),
+            stop: Arc::new(AtomicBool::new(false)),
+            reload: Arc::new(AtomicBool::new(false)),
+            bound: Arc::new(OnceLock::new()),
+            config_path: None,
+            on_shutdown: ShutdownHooks::default(),
+            panic_policy: PanicPolicy::default(),
+            #[cfg(feature = "signals")]
+            handle_signals: false,
+        }
+    }
+
+    /// Creates a server from a TOML configuration file.
+    ///
+    /// See `ServerConfig` for the supported settings. The file is read
+    /// again when the server is asked to reload; see `ServerHandle::reload`.
+    pub fn from_config(path: impl AsRef<Path>) -> Result<Server, ConfigError> {
+        let path = path.as_ref();
+        let mut server = Server::with_config(ServerConfig::from_file(path)?);
+        server.config_path = Some(path.to_path_buf());
+        Ok(server)
+    }
+
+    /// Also listens on `address`, in addition to the configured one. Every
+    /// listener gets its own accept loop but shares the router and pool.
+    pub fn listen(mut self, address: &str) -> Server {
+        self.config.additional_addresses.push(address.to_string());
+        self
+    }
+
+    /// Returns a handle that stops this server once it is running.
+    pub fn handle(&self) -> ServerHandle {
+        ServerHandle {
+            stop: Arc::clone(&self.stop),
+            reload: Arc::clone(&self.reload),
+            bound: Arc::clone(&self.bound),
+        }
+    }
+
+    /// Sets how many threads accept connections on each address. Defaults
+    /// to 1; more helps once a single accept loop can't keep up with the
+    /// connection rate.
+    pub fn acceptors(mut self, acceptors: usize) -> Server {
+        self.config.acceptors = acceptors.max(1);
+        self
+    }
+
+    /// Turns Nagle's algorithm back on for accepted connections with
+    /// `false`. Defaults to `true`, so small responses go out at once.
+    pub fn tcp_nodelay(mut self, nodelay: bool) -> Server {
+        self.config.tcp_nodelay = nodelay;
+        self
+    }
+
+    /// Sets the length of each listener's accept queue, instead of the
+    /// platform default.
+    #[cfg(feature = "sockopt")]
+    pub fn backlog(mut self, backlog: u32) -> Server {
+        self.config.backlog = Some(backlog.max(1));
+        self
+    }
+
+    /// Makes closing a connection wait up to `linger` for unsent data, or
+    /// reset the connection at once with zero.
+    #[cfg(feature = "sockopt")]
+    pub fn linger(mut self, linger: Duration) -> Server {
+        self.config.linger = Some(linger);
+        self
+    }
+
+    /// Adds a worker pool of `workers` threads called `name`, which answers
+    /// the routes pinned to it with `Router::pool`. Connections move to it
+    /// for those requests and back to the default pool afterwards.
+    pub fn pool(mut self, name: &str, workers: usize) -> Server {
+        self.config.pools.insert(name.to_string(), workers.max(1));
+        self
+    }
+
+    /// Logs a warning when a job waits longer than `threshold` for a worker
+    /// in any of the server's pools, a sign they are too small.
+    pub fn queue_wait_threshold(mut self, threshold: Duration) -> Server {
+        self.config.queue_wait_threshold = Some(threshold);
+        self
+    }
+
+    /// Truncates job results longer than `limit` bytes in every one of the
+    /// server's pools, like `ThreadPool::max_result_bytes`.
+    pub fn max_job_result_bytes(mut self, limit: usize) -> Server {
+        self.config.max_job_result_bytes = Some(limit);
+        self
+    }
+
+    /// Sets what the server's pools do when a job panics, such as
+    /// `PanicPolicy::Abort` to fail fast. Handler panics are answered with
+    /// `500 Internal Server Error` instead, whatever the policy.
+    pub fn panic_policy(mut self, policy: PanicPolicy) -> Server {
+        self.panic_policy = policy;
+        self
+    }
+
+    /// Serves connections from one event loop thread that reads and writes
+    /// them without blocking, handing only complete requests to the
+    /// workers. Idle keep-alive connections then cost no worker.
+    ///
+    /// Responses are built in memory before they are sent, and the PROXY
+    /// protocol and HTTP/2 are only served by the default backend.
+    #[cfg(feature = "evented")]
+    pub fn evented(mut self, evented: bool) -> Server {
+        self.config.evented = evented;
+        self
+    }
+
+    /// Keeps connections open for further requests, which may be pipelined,
+    /// when the client allows it. Off by default.
+    pub fn keep_alive(mut self, keep_alive: bool) -> Server {
+        self.config.keep_alive = keep_alive;
+        self
+    }
+
+    /// Sets when responses are written out to the client; each as soon as
+    /// it is ready by default. `FlushPolicy::Pipeline` writes the answers
+    /// to pipelined requests together.
+    pub fn flush_policy(mut self, policy: FlushPolicy) -> Server {
+        self.config.flush_policy = policy;
+        self
+    }
+
+    /// Closes kept-alive connections after `max_requests` requests, so
+    /// clients reconnect and their load spreads over the workers again.
+    pub fn max_requests_per_connection(mut self, max_requests: usize) -> Server {
+        self.config.max_requests_per_connection = Some(max_requests.max(1));
+        self
+    }
+
+    /// Refuses request bodies larger than `max_body_bytes` with `413`,
+    /// without reading them.
+    pub fn max_body_bytes(mut self, max_body_bytes: usize) -> Server {
+        self.config.max_body_bytes = Some(max_body_bytes);
+        self
+    }
+
+    /// Closes new connections straight away while `max_connections` are
+    /// already open, so a flood of clients can't exhaust file descriptors.
+    pub fn max_connections(mut self, max_connections: usize) -> Server {
+        self.config.max_connections = Some(max_connections);
+        self
+    }
+
+    /// Answers new connections from a client address with `429 Too Many
+    /// Requests`, and closes them, while `max_connections` from it are
+    /// already open. Clients are told apart by the socket's peer address,
+    /// which behind a load balancer is the balancer's, or with
+    /// `proxy_protocol` by the address its header carries. `Forwarded` and
+    /// `X-Forwarded-For` come with each request, too late to count.
+    pub fn max_connections_per_ip(mut self, max_connections: usize) -> Server {
+        self.config.max_connections_per_ip = Some(max_connections);
+        self
+    }
+
+    /// Answers new connections with `503 Service Unavailable` while more
+    /// than `max_queued_connections` are already waiting for a worker,
+    /// instead of queueing them behind the backlog.
+    pub fn max_queued_connections(mut self, max_queued_connections: usize) -> Server {
+        self.config.max_queued_connections = Some(max_queued_connections);
+        self
+    }
+
+    /// Stops accepting connections while more than `high` jobs wait for a
+    /// worker, until at most `low` do. New connections wait in the
+    /// kernel's backlog meanwhile, and once that is full clients retry
+    /// their connect, which is gentler than answering them `503`.
+    pub fn accept_backpressure(mut self, high: usize, low: usize) -> Server {
+        self.config.accept_high_water = Some(high.max(1));
+        self.config.accept_low_water = Some(low);
+        self
+    }
+
+    /// Only answers requests whose `Host` header names one of `hosts`,
+    /// which guards against DNS rebinding. A leading `*.` matches any
+    /// subdomain.
+    pub fn allowed_hosts(mut self, hosts: &[&str]) -> Server {
+        self.config.allowed_hosts = hosts.iter().map(|host| host.to_string()).collect();
+        self
+    }
+
+    /// Only answers requests using one of `methods`, in place of
+    /// `DEFAULT_ALLOWED_METHODS`. Routes for other methods, such as
+    /// WebDAV's `PROPFIND`, need them listed here. An empty list accepts
+    /// any method.
+    pub fn allowed_methods(mut self, methods: &[&str]) -> Server {
+        self.config.allowed_methods = methods.iter().map(|method| method.to_string()).collect();
+        self
+    }
+
+    /// Expects every connection to open with a PROXY protocol header, as
+    /// sent by HAProxy and other load balancers, and uses the client
+    /// address it carries instead of the balancer's. Connections without
+    /// one are closed.
+    pub fn proxy_protocol(mut self, enabled: bool) -> Server {
+        self.config.proxy_protocol = enabled;
+        self
+    }
+
+    /// Believes the `Forwarded` and `X-Forwarded-For` headers of requests
+    /// from `proxies`, given as addresses or CIDR blocks like `10.0.0.0/8`,
+    /// when finding the client's address for `HttpRequest::client_ip` and
+    /// the access log.
+    ///
+    /// # Panics
+    ///
+    /// If an entry is not an address or CIDR block.
+    pub fn trusted_proxies(mut self, proxies: &[&str]) -> Server {
+        self.config.trusted_proxies = proxies
+            .iter()
+            .map(|proxy| {
+                proxy
+                    .parse()
+                    .unwrap_or_else(|err| panic!("invalid trusted proxy {:?}: {}", proxy, err))
+            })
+            .collect();
+        self
+    }
+
+    /// Sets the router whose handlers are tried before the built-in pages.
+    pub fn router(mut self, router: Router) -> Server {
+        self.router = router;
+        self
+    }
+
+    /// Serves files compiled into the binary, after the router and the
+    /// document root had no answer.
+    pub fn assets(mut self, assets: EmbeddedAssets) -> Server {
+        self.assets = Some(assets);
+        self
+    }
+
+    /// Shares `state`, such as a database pool or a cache, with every
+    /// request: handlers registered with `Router::stateful` receive it, and
+    /// middleware and other handlers can get it with `request.state()`.
+    /// Calling it again replaces the state.
+    pub fn with_state<T: Send + Sync + 'static>(mut self, state: Arc<T>) -> Server {
+        self.state = Some(state);
+        self
+    }
+
+    /// Returns the cache used for static files and built-in pages, whose
+    /// `stats` report the hit and miss counts while the server runs.
+    pub fn file_cache(&self) -> Arc<FileCache> {
+        Arc::clone(&self.cache)
+    }
+
+    /// Returns the counters of open, accepted and rejected connections,
+    /// which can be read while the server runs.
+    pub fn connections(&self) -> Arc<Connections> {
+        Arc::clone(&self.connections)
+    }
+
+    /// Returns the counters of bytes read and written for requests, with
+    /// recent size percentiles, which can be read while the server runs.
+    pub fn traffic(&self) -> Arc<Traffic> {
+        Arc::clone(&self.traffic)
+    }
+
+    /// Runs `middleware` around every request, including static files and
+    /// the built-in pages. Middleware added first runs outermost.
+    pub fn middleware(mut self, middleware: impl Middleware + 'static) -> Server {
+        self.middleware.push(middleware);
+        self
+    }
+
+    /// Sets the job types clients may submit through `POST /jobs`, and
+    /// that `job` and `[[jobs]]` entries name.
+    pub fn registry(mut self, registry: JobRegistry) -> Server {
+        self.registry = Arc::new(registry);
+        self
+    }
+
+    /// Queues the registered job `descriptor` names on the default pool
+    /// when the server starts, like a `[[jobs]]` entry.
+    pub fn job(mut self, descriptor: JobDescriptor) -> Server {
+        self.config.jobs.push(descriptor);
+        self
+    }
+
+    /// Sets how long a client may take to send a whole request head before
+    /// it is answered `408 Request Timeout` and disconnected. Defaults to
+    /// 10 seconds.
+    pub fn header_timeout(mut self, header_timeout: Duration) -> Server {
+        self.config.header_timeout = Some(header_timeout);
+        self
+    }
+
+    /// Sets how long a kept-alive connection may wait for its next request
+    /// before it is closed, freeing its worker. Defaults to 5 seconds.
+    pub fn keep_alive_timeout(mut self, keep_alive_timeout: Duration) -> Server {
+        self.config.keep_alive_timeout = Some(keep_alive_timeout);
+        self
+    }
+
+    /// Answers `504 Gateway Timeout` when a handler runs longer than
+    /// `handler_timeout`, unless its route has a `Router::timeout` of its
+    /// own. Unlimited by default.
+    ///
+    /// Each timed request then runs on a thread of its own, so the worker
+    /// can answer the client on time. A handler that overruns is not
+    /// stopped, only abandoned: `HttpRequest::cancelled` turns true for it
+    /// to return early. While 64 abandoned handlers are still running,
+    /// further timed requests get `503 Service Unavailable` rather than
+    /// another thread, and one already running that times out keeps its
+    /// worker until it returns; `Server::connections().stats()` counts the
+    /// timeouts and the abandoned handlers.
+    ///
+    /// Handlers reading their body with `Router::stream` are never timed,
+    /// as the body has to be read on the connection's own worker; uploads
+    /// to those routes never get a `504`.
+    pub fn handler_timeout(mut self, handler_timeout: Duration) -> Server {
+        self.config.handler_timeout = Some(handler_timeout);
+        self
+    }
+
+    /// Sets how long in-flight connections may keep running once the server
+    /// stops accepting new ones. Defaults to 30 seconds.
+    pub fn grace_period(mut self, grace_period: Duration) -> Server {
+        self.config.grace_period = grace_period;
+        self
+    }
+
+    /// Answers every request with a `301 Moved Permanently` to its
+    /// `https://` equivalent on `port`, keeping the host, path and query,
+    /// instead of serving it.
+    ///
+    /// # Example
+    ///
+    /// A second server on port 80 that sends plain HTTP clients to HTTPS:
+    ///
+    /// ```no_run
+    /// # use harbor::server::Server;
+    /// std::thread::spawn(|| Server::new("0.0.0.0:80").redirect_to_https(443).run());
+    /// ```
+    pub fn redirect_to_https(mut self, port: u16) -> Server {
+        self.config.https_redirect = Some(port);
+        self
+    }
+
+    /// Serves requests for paths matching `from` as if they were for `to`,
+    /// before routing. See `Rewrite` for the pattern syntax.
+    ///
+    /// # Panics
+    ///
+    /// If `from` isn't a path or `to` refers to a capture it lacks.
+    pub fn rewrite(mut self, from: &str, to: &str) -> Server {
+        let rewrite = Rewrite::new(from, to)
+            .unwrap_or_else(|err| panic!("invalid rewrite {:?}: {}", from, err));
+        self.config.rewrites.push(rewrite);
+        self
+    }
+
+    /// Like `rewrite`, but only for requests nothing else answers, such as
+    /// the client-side routes of a single-page app.
+    pub fn rewrite_fallback(mut self, from: &str, to: &str) -> Server {
+        let rewrite = Rewrite::new(from, to)
+            .unwrap_or_else(|err| panic!("invalid rewrite {:?}: {}", from, err));
+        self.config.rewrites.push(rewrite.fallback());
+        self
+    }
+
+    /// Serves a page at `/admin/jobs` listing the jobs on the pool, with
+    /// their status, duration and worker, which refreshes itself while
+    /// open. Off by default, as anyone who can reach it sees the jobs'
+    /// results; put it behind authentication middleware when enabled.
+    pub fn job_dashboard(mut self, enabled: bool) -> Server {
+        self.config.job_dashboard = enabled;
+        self
+    }
+
+    /// Sends `name` as the `Server` header of responses that don't set
+    /// their own, in place of `harbor`. An empty name leaves it out.
+    pub fn server_header(mut self, name: &str) -> Server {
+        self.config.server_header = Some(name.to_string()).filter(|name| !name.is_empty());
+        self
+    }
+
+    /// Reloads the configuration file whenever it changes on disk.
+    pub fn watch_config(mut self, watch: bool) -> Server {
+        self.config.watch_config = watch;
+        self
+    }
+
+    /// Listens on the Unix socket at `path` for a new process to hand the
+    /// listening sockets to, and takes them over from a running server on
+    /// startup. See `ServerConfig::upgrade_socket`.
+    #[cfg(feature = "upgrade")]
+    pub fn upgrade_socket(mut self, path: impl AsRef<Path>) -> Server {
+        self.config.upgrade_socket = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Runs `hook` once the server has stopped accepting connections, before
+    /// the in-flight ones are drained and the worker pool shut down, such
+    /// as to deregister from service discovery or to save queued work.
+    /// Hooks run in the order they were added, on the thread that called
+    /// `run`; the grace period only starts once they return.
+    pub fn on_shutdown(mut self, hook: impl FnOnce() + Send + 'static) -> Server {
+        self.on_shutdown.0.get_mut().unwrap().push(Box::new(hook));
+        self
+    }
+
+    /// Stops the server gracefully when the process receives SIGINT or
+    /// SIGTERM: new connections are refused, in-flight ones get up to the
+    /// grace period to finish, and then `run` returns. SIGHUP reloads the
+    /// configuration file instead.
+    #[cfg(feature = "signals")]
+    pub fn shutdown_on_signals(mut self) -> Server {
+        self.handle_signals = true;
+        self
+    }
+
+    /// Bind a Listener to the address, creates a thread pool, and enters
+    /// a loop to handler incoming connections.
+    ///
+    /// Addresses of the form `unix:/path/to.sock` listen on a Unix domain
+    /// socket; anything else is a TCP `host:port`. Each additional address
+    /// is accepted on its own thread.
+    ///
+    /// Returns once the server has shut down, or right away with the
+    /// error, which is logged too, if it cannot start: an address that
+    /// fails to bind, a pool or access log that cannot be created, or a
+    /// setting this build does not support.
+    pub fn run(mut self) -> Result<(), HarborError> {
+        crate::log::set_max_level(self.config.log.level);
+        if self.config.log.access_log_format == AccessLogFormat::Json {
+            // A logger the application installed keeps its records.
+            let _ = crate::log::set_logger(Box::new(crate::log::JsonLogger));
+        }
+        if self.config.tls.is_some() {
+            error!("TLS is configured but this build cannot serve HTTPS; refusing to start.");
+            return Err(HarborError::Config(ConfigError::Invalid {
+                key: "tls".to_string(),
+                message: "this build cannot serve HTTPS".to_string(),
+            }));
+        }
+        if self.config.evented && !cfg!(all(target_os = "linux", feature = "evented")) {
+            error!("The evented backend needs the `evented` feature on Linux; refusing to start.");
+            return Err(HarborError::Config(ConfigError::Invalid {
+                key: "evented".to_string(),
+                message: "needs the `evented` feature on Linux".to_string(),
+            }));
+        }
+        if self.config.evented && self.config.proxy_protocol {
+            error!("The evented backend does not read PROXY protocol headers; refusing to start.");
+            return Err(HarborError::Config(ConfigError::Invalid {
+                key: "proxy_protocol".to_string(),
+                message: "the evented backend does not read PROXY protocol headers".to_string(),
+            }));
+        }
+        if (self.config.backlog.is_some() || self.config.linger.is_some())
+            && !cfg!(all(unix, feature = "sockopt"))
+        {
+            error!(
+                "A backlog or linger setting needs the `sockopt` feature on Unix; refusing to start."
+            );
+            return Err(HarborError::Config(ConfigError::Invalid {
+                key: "socket".to_string(),
+                message: "backlog and linger need the `sockopt` feature on Unix".to_string(),
+            }));
+        }
+        if self.config.upgrade_socket.is_some()
+            && !cfg!(all(target_os = "linux", feature = "upgrade"))
+        {
+            error!("An upgrade socket needs the `upgrade` feature on Linux; refusing to start.");
+            return Err(HarborError::Config(ConfigError::Invalid {
+                key: "upgrade_socket".to_string(),
+                message: "needs the `upgrade` feature on Linux".to_string(),
+            }));
+        }
+
+        let mut inherited = Vec::new();
+        #[cfg(all(target_os = "linux", feature = "upgrade"))]
+        let mut previous = match &self.config.upgrade_socket {
+            Some(path) => match upgrade::inherit(path) {
+                Ok(previous) => previous,
+                Err(err) => {
+                    error!(
+                        "Failed to take over the listeners at {}: {}",
+                        path.display(),
+                        err
+                    );
+                    return Err(err.into());
+                }
+            },
+            None => None,
+        };
+        #[cfg(all(target_os = "linux", feature = "upgrade"))]
+        if let Some(previous) = &mut previous {
+            inherited = std::mem::take(&mut previous.listeners);
+        }
+
+        let addresses: Vec<&String> = std::iter::once(&self.config.address)
+            .chain(&self.config.additional_addresses)
+            .collect();
+        // IPv6 sockets on these ports must leave IPv4 clients to the
+        // IPv4 address bound next to them.
+        let v4_ports: Vec<u16> = addresses
+            .iter()
+            .filter_map(|address| match BindTarget::parse(address) {
+                Ok(BindTarget::Tcp(SocketAddr::V4(address))) if address.port() != 0 => {
+                    Some(address.port())
+                }
+                _ => None,
+            })
+            .collect();
+        let mut acceptors = Vec::new();
+        let mut targets = Vec::new();
+        for address in addresses {
+            match self.bind(address, &v4_ports, &mut inherited) {
+                Ok(bound) => {
+                    targets.extend(bound[0].local_target().ok());
+                    acceptors.extend(bound);
+                }
+                Err(err) => {
+                    error!("Failed to bind to address: {}. Error: {}", address, err);
+                    return Err(err);
+                }
+            }
+        }
+
+        #[cfg(all(unix, feature = "sockopt"))]
+        if let Some(backlog) = self.config.backlog {
+            for listener in &acceptors {
+                if let Err(err) = listener.set_backlog(backlog) {
+                    error!("Failed to set the listen backlog: {}", err);
+                    return Err(err.into());
+                }
+            }
+        }
+        for listener in inherited {
+            warn!(
+                "Closing inherited listener {:?}, which is no longer configured",
+                listener.local_target().ok()
+            );
+        }
+        let _ = self.bound.set(targets);
+
+        let pool = match ThreadPool::build(self.config.workers) {
+            Ok(threads) => self.pool_settings(threads),
+            Err(error) => {
+                error!("You cannot create a thread pool of size zero: {:?}", error);
+                return Err(error.into());
+            }
+        };
+        let mut pools = BTreeMap::new();
+        for (name, &workers) in &self.config.pools {
+            match ThreadPool::build(workers) {
+                Ok(threads) => pools.insert(name.clone(), self.pool_settings(threads)),
+                Err(error) => {
+                    error!("Failed to create the {:?} pool: {:?}", name, error);
+                    return Err(error.into());
+                }
+            };
+        }
+        for name in self.router.pools() {
+            if !pools.contains_key(name) {
+                warn!(
+                    "Routes are pinned to the {:?} pool, which is not configured; the default pool answers them.",
+                    name
+                );
+            }
+        }
+
+        #[cfg(feature = "signals")]
+        if self.handle_signals
+            && let Err(err) = signals::install()
+        {
+            error!("Failed to install signal handlers: {}", err);
+        }
+
+        let access_log = match self.access_log() {
+            Ok(access_log) => access_log,
             Err(err) => {
-                eprintln!("{}", err);
-                continue;
+                error!("Failed to open the access log: {}", err);
+                return Err(err.into());
             }
         };
-        pool.execute(move || match handle_connection(stream) {
-            Ok(_) => Ok("Connection handled successfully".to_string()),
-            Err(e) => Err(format!("Error handling connection: {}", e)),
+
+        if let Some(submitter) = pool.submitter() {
+            for descriptor in &self.config.jobs {
+                match self.registry.submit(&submitter, descriptor) {
+                    Ok(id) => info!("Queued the startup job '{}' as {}", descriptor.job, id),
+                    Err(err) => error!(
+                        "Failed to queue the startup job '{}': {}",
+                        descriptor.job, err
+                    ),
+                }
+            }
+        }
+
+        let shared = Context {
+            router: Arc::new(std::mem::take(&mut self.router)),
+            middleware: Arc::new(std::mem::take(&mut self.middleware)),
+            assets: self.assets.take(),
+            state: self.state.take(),
+            pool: pool.submitter(),
+            pools: pools
+                .iter()
+                .filter_map(|(name, pool)| Some((name.clone(), pool.submitter()?)))
+                .collect(),
+            jobs: pool.submitter().map(|submitter| {
+                JobsApi::new(pool.tracker(), submitter, Arc::clone(&self.registry))
+            }),
+            cache: Some(Arc::clone(&self.cache)),
+            access_log: access_log.map(Arc::new),
+            traffic: Arc::clone(&self.traffic),
+            connections: Arc::clone(&self.connections),
+            ..Context::default()
+        };
+        let live = RwLock::new(Arc::new(shared.reconfigured(&self.config)));
+        drop(shared);
+
+        #[cfg(all(target_os = "linux", feature = "upgrade"))]
+        let handed_over = AtomicBool::new(false);
+        thread::scope(|scope| {
+            if let Some(path) = &self.config_path {
+                scope.spawn(|| self.reload_loop(path, &live));
+            }
+            #[cfg(all(target_os = "linux", feature = "upgrade"))]
+            if let Some(path) = &self.config.upgrade_socket {
+                let (server, acceptors, handed_over) = (&self, &acceptors, &handed_over);
+                scope.spawn(move || server.upgrade_loop(path, previous, acceptors, handed_over));
+            }
+            #[cfg(all(target_os = "linux", feature = "evented"))]
+            if self.config.evented {
+                return self.event_loop(&acceptors, &pool, &live);
+            }
+            let (primary, additional) = acceptors.split_first().expect("at least one acceptor");
+            for listener in additional {
+                scope.spawn(|| self.accept_loop(listener, &pool, &live));
+            }
+            self.accept_loop(primary, &pool, &live);
         });
+        info!("Shutdown requested; no longer accepting connections.");
+        #[cfg(all(target_os = "linux", feature = "upgrade"))]
+        if handed_over.load(Ordering::SeqCst) {
+            // The last reference to each listener decides about its file.
+            for listener in acceptors {
+                if let Ok(mut listener) = Arc::try_unwrap(listener) {
+                    listener.keep_socket_file();
+                }
+            }
+        }
+
+        std::mem::take(&mut self.on_shutdown).run();
+
+        // The context holds job submitters, which keep the workers alive.
+        drop(live);
+        // Named pools first, as their connections may move back to the
+        // default one.
+        let deadline = Instant::now() + self.config.grace_period;
+        let mut finished = true;
+        for pool in pools.into_values().chain(std::iter::once(pool)) {
+            finished &= pool.shutdown_timeout(deadline.saturating_duration_since(Instant::now()));
+        }
+        if !finished {
+            warn!(
+                "Grace period of {:?} expired with connections still in flight.",
+                self.config.grace_period
+            );
+        }
+        Ok(())
     }
-}
 
-/// Handles a single TCP connection.
-///
-/// Read the first line of the HTTP request to determine the endpoint.
-/// Respons with the content of `hello.html` for the root path `/` and
-/// `notFound.html` for any other path.
-///
-/// Simulates a delay for the `/sleep` path.
-pub fn handle_connection(mut stream: TcpStream) -> Result<(), String> {
-    let buf_reader = BufReader::new(&stream);
+    /// Binds `address` once per acceptor, returning the listener each accept
+    /// loop should use. Without `SO_REUSEPORT` the acceptors share one
+    /// socket.
+    ///
+    /// An IPv6 address on one of the `v4_ports` only accepts IPv6 clients.
+    /// Sockets for `address` among those `inherited` from a previous
+    /// process are taken out and used instead.
+    fn bind(
+        &self,
+        address: &str,
+        v4_ports: &[u16],
+        inherited: &mut Vec<Arc<Listener>>,
+    ) -> Result<Vec<Arc<Listener>>, HarborError> {
+        let target = BindTarget::parse(address).map_err(HarborError::Http)?;
+        let v6_only = matches!(&target, BindTarget::Tcp(SocketAddr::V6(address)) if v4_ports.contains(&address.port()));
+        let bind = |target: &BindTarget, reuse_port: bool| match (v6_only, reuse_port) {
+            (true, _) => Listener::bind_v6_only(target, reuse_port),
+            (false, true) => Listener::bind_reuse_port(target),
+            (false, false) => Listener::bind(target),
+        };
+        let acceptors = self.config.acceptors.max(1);
+        let taken: Vec<_> = inherited
+            .extract_if(.., |listener| {
+                listener.local_target().is_ok_and(|bound| bound == target)
+            })
+            .collect();
+        if !taken.is_empty() {
+            return Ok((0..acceptors.max(taken.len()))
+                .map(|index| Arc::clone(&taken[index % taken.len()]))
+                .collect());
+        }
+        if acceptors == 1 || !Listener::supports_reuse_port(&target) {
+            let listener = Arc::new(bind(&target, false)?);
+            return Ok(vec![listener; acceptors]);
+        }
 
-    if let Some(request_line) = buf_reader.lines().next() {
-        let request_line = match request_line {
-            Ok(request) => request,
-            Err(err) => {
-                eprintln!("No text UTF-8 valid: {}", err);
-                return Err(err.to_string());
+        // Rebind the address actually bound, so port 0 is shared as well.
+        let first = bind(&target, true)?;
+        let target = first.local_target()?;
+        let mut listeners = vec![Arc::new(first)];
+        for _ in 1..acceptors {
+            let listener = bind(&target, true)?;
+            listeners.push(Arc::new(listener));
+        }
+        Ok(listeners)
+    }
+
+    /// Accepts connections from `listener` and hands them to the pool until
+    /// shutdown is requested.
+    fn accept_loop(&self, listener: &Listener, pool: &ThreadPool, live: &RwLock<Arc<Context>>) {
+        // Polling keeps `stop` and signals from waiting on one more client.
+        if let Err(err) = listener.set_nonblocking(true) {
+            error!("Failed to make the listener non-blocking: {}", err);
+            return;
+        }
+
+        let mut spare = SpareDescriptor::new();
+        let mut poll_interval = ACCEPT_MIN_POLL_INTERVAL;
+        let mut backpressure = Backpressure::default();
+        while !self.shutdown_requested() {
+            let marks = live.read().unwrap().accept_water_marks;
+            if backpressure.holds(pool.queue_depth(), marks) {
+                thread::sleep(ACCEPT_POLL_INTERVAL);
+                continue;
             }
+            let (stream, peer) = match listener.accept() {
+                Ok(accepted) => {
+                    poll_interval = ACCEPT_MIN_POLL_INTERVAL;
+                    accepted
+                }
+                Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => {
+                    thread::sleep(poll_interval);
+                    poll_interval = (poll_interval * 2).min(ACCEPT_POLL_INTERVAL);
+                    continue;
+                }
+                Err(err) => {
+                    if let Some(backoff) = self.accept_failed(&err, listener, &mut spare) {
+                        thread::sleep(backoff);
+                    }
+                    continue;
+                }
+            };
+            let accepted = Instant::now();
+            let context = Arc::clone(&live.read().unwrap());
+            let Some((stream, guard)) = self.admit(stream, peer, &context) else {
+                continue;
+            };
+            if let Err(err) = stream
+                .set_nonblocking(false)
+                .and_then(|_| stream.set_timeouts(context.read_timeout, context.write_timeout))
+                .and_then(|_| set_socket_options(&stream, &context))
+            {
+                warn!("Failed to set socket options: {}", err);
+            }
+            if let Some(limit) = context.max_queued_connections
+                && pool.queue_depth() > limit
+            {
+                reject_overloaded(stream, peer, &context);
+                continue;
+            }
+            pool.execute(move || job_result(serve(stream, peer, accepted, Some(guard), context)));
+        }
+    }
+
+    /// Claims the connection slots of a connection from `peer`, or refuses
+    /// it: at `max_connections` it is closed at once, and at
+    /// `max_connections_per_ip` for its address answered `429`. With the
+    /// PROXY protocol the address is only known once `serve` has read the
+    /// header, so it claims that slot itself.
+    fn admit(
+        &self,
+        stream: Stream,
+        peer: Option<SocketAddr>,
+        context: &Context,
+    ) -> Option<(Stream, ConnectionGuard)> {
+        let Some(mut guard) = self.connections.try_acquire(context.max_connections) else {
+            debug!(
+                "Connection limit reached; closing connection from {:?}",
+                peer
+            );
+            return None;
         };
+        if let Some(peer) = peer
+            && !context.proxy_protocol
+            && !guard.claim_ip(peer.ip(), context.max_connections_per_ip)
+        {
+            debug!(
+                "Connection limit for {} reached; refusing connection",
+                peer.ip()
+            );
+            refuse(stream, Response::error_page(429), context);
+            return None;
+        }
+        Some((stream, guard))
+    }
 
-        let (status_line, filename) = match &request_line[..] {
-            "GET / HTTP/1.1" => ("HTTP/1.1 200 OK", "hello.html"),
-            "GET /sleep HTTP/1.1" => {
-                thread::sleep(Duration::from_secs(5));
-                ("HTTP/1.1 200 OK", "hello.html")
+    /// Counts and logs a failed accept on `listener`, and returns how long
+    /// to wait before accepting again, if at all. Out of file descriptors,
+    /// it first closes one waiting connection using `spare`.
+    fn accept_failed(
+        &self,
+        err: &std::io::Error,
+        listener: &Listener,
+        spare: &mut SpareDescriptor,
+    ) -> Option<Duration> {
+        self.connections.accept_failed();
+        match AcceptError::classify(err) {
+            AcceptError::Connection => {
+                debug!("Connection lost before it was accepted: {}", err);
+                None
+            }
+            AcceptError::Exhausted => {
+                if spare.shed(listener) {
+                    warn!(
+                        "Failed to accept a connection ({}); closed one waiting",
+                        err
+                    );
+                } else {
+                    warn!("Failed to accept a connection: {}", err);
+                }
+                Some(ACCEPT_ERROR_BACKOFF)
+            }
+            AcceptError::Other => {
+                warn!("Failed to accept a connection: {}", err);
+                Some(ACCEPT_ERROR_BACKOFF)
             }
-            _ => ("HTTP/1.1 404 NOT FOUND", "notFound.html"),
+        }
+    }
+
+    /// Reloads the configuration from `path` whenever `ServerHandle::reload`
+    /// is called, SIGHUP arrives (with `shutdown_on_signals`) or, with
+    /// `watch_config`, the file changes.
+    fn reload_loop(&self, path: &Path, live: &RwLock<Arc<Context>>) {
+        let modified = || {
+            fs::metadata(path)
+                .and_then(|metadata| metadata.modified())
+                .ok()
         };
+        let mut last_modified = modified();
+        while !self.shutdown_requested() {
+            thread::sleep(RELOAD_POLL_INTERVAL);
+            let mut requested = self.reload.swap(false, Ordering::SeqCst);
+            #[cfg(feature = "signals")]
+            {
+                requested |= self.handle_signals && signals::take_hangup();
+            }
+            if self.config.watch_config {
+                let current = modified();
+                requested |= current != last_modified;
+                last_modified = current;
+            }
+            if requested {
+                self.reload(path, live);
+            }
+        }
+    }
+
+    /// Finishes taking over from the `previous` process, if any, then waits
+    /// on the upgrade socket at `path` to hand `listeners` to the next one
+    /// and stops this server once it has.
+    #[cfg(all(target_os = "linux", feature = "upgrade"))]
+    fn upgrade_loop(
+        &self,
+        path: &Path,
+        previous: Option<upgrade::Inherited>,
+        listeners: &[Arc<Listener>],
+        handed_over: &AtomicBool,
+    ) {
+        if let Some(previous) = previous {
+            match previous.complete() {
+                Ok(()) => info!("Took over the listeners of the previous process."),
+                Err(err) => warn!(
+                    "The previous process did not release the upgrade socket: {}",
+                    err
+                ),
+            }
+        }
+        match upgrade::serve(path, listeners, || self.shutdown_requested()) {
+            Ok(true) => {
+                handed_over.store(true, Ordering::SeqCst);
+                self.stop.store(true, Ordering::SeqCst);
+            }
+            Ok(false) => {}
+            Err(err) => error!(
+                "Failed to listen for upgrades on {}: {}",
+                path.display(),
+                err
+            ),
+        }
+    }
 
-        let contents = match fs::read_to_string(filename) {
-            Ok(file) => file,
+    /// Applies the settings in `path` that can change while running.
+    /// Everything else keeps its value from startup, with a warning.
+    fn reload(&self, path: &Path, live: &RwLock<Arc<Context>>) {
+        let config = match ServerConfig::from_file(path) {
+            Ok(config) => config,
             Err(err) => {
-                eprintln!("This file isn't avalible {}", err);
-                return Err(err.to_string());
+                error!("Keeping the current configuration: {}", err);
+                return;
             }
         };
+        for setting in self.config.restart_required(&config) {
+            warn!(
+                "Changing `{}` takes a restart; keeping its current value",
+                setting
+            );
+        }
+
+        crate::log::set_max_level(config.log.level);
+        let context = live.read().unwrap().reconfigured(&config);
+        *live.write().unwrap() = Arc::new(context);
+        info!("Reloaded the configuration from {}", path.display());
+    }
+
+    /// Builds the access logger described by the `log` settings, if any.
+    fn access_log(&self) -> std::io::Result<Option<AccessLog>> {
+        let log = &self.config.log;
+        let stdout = log.access_log && log.level >= LogLevel::Info;
+        let access_log = AccessLog::new(log.access_log_format, stdout);
+
+        match &log.access_log_file {
+            Some(path) => access_log
+                .with_file(path, log.rotation, log.max_files)
+                .map(Some),
+            None if stdout => Ok(Some(access_log)),
+            None => Ok(None),
+        }
+    }
+
+    /// Applies the settings every one of the server's pools shares.
+    fn pool_settings(&self, pool: ThreadPool) -> ThreadPool {
+        let pool = pool.panic_policy(self.panic_policy);
+        let pool = match self.config.max_job_result_bytes {
+            Some(limit) => pool.max_result_bytes(limit),
+            None => pool,
+        };
+        match self.config.queue_wait_threshold {
+            Some(threshold) => pool.queue_wait_threshold(threshold),
+            None => pool,
+        }
+    }
+
+    fn shutdown_requested(&self) -> bool {
+        #[cfg(feature = "signals")]
+        if self.handle_signals && signals::received() {
+            return true;
+        }
+        self.stop.load(Ordering::SeqCst)
+    }
+}
+
+/// Entry point of the web server.
+///
+/// Runs a `Server` on `address` with the default configuration.
+pub fn start(address: &str) {
+    // `run` has logged why it could not start.
+    let _ = Server::new(address).run();
+}
+
+/// Serves a single TCP connection with the default settings, as a
+/// `Server` does the ones it accepts: requests are parsed, answered with the
+/// built-in pages and written back until the connection closes. Refused
+/// requests get their error status and close it, and still return `Ok`.
+///
+/// Runtimes that read and write connections themselves can do the same
+/// one step at a time with `parse_request`, `handle_request` and
+/// `write_response`.
+pub fn handle_connection(stream: TcpStream) -> Result<(), HarborError> {
+    let peer = stream.peer_addr().ok();
+    serve(
+        stream,
+        peer,
+        Instant::now(),
+        None,
+        Arc::new(Context::default()),
+    )
+}
+
+/// Reads one request, its head and `Content-Length` body, for runtimes
+/// that accept and schedule connections themselves. Returns `Ok(None)` if
+/// the client closed the connection without sending anything.
+///
+/// Keep one `BufReader` per connection for every call: it may already
+/// hold the start of the next request.
+pub fn parse_request<R: BufRead>(reader: &mut R) -> Result<Option<HttpRequest>, HarborError> {
+    let Some(mut request) = HttpRequest::parse_head(reader)? else {
+        return Ok(None);
+    };
+    request.read_body(reader)?;
+    Ok(Some(request))
+}
+
+/// Answers `request` with the built-in pages, like `handle_connection`.
+/// A panic while answering becomes a `500`.
+pub fn handle_request(request: &HttpRequest) -> Response {
+    let mut request = request.clone();
+    answer(
+        &mut request,
+        &mut Body::new(&mut std::io::empty(), 0),
+        &Arc::new(Context::default()),
+    )
+}
+
+/// Writes `response` to `stream` with `Connection: close`, unless the
+/// response sets `Connection` itself.
+pub fn write_response<W: Write>(stream: &mut W, response: Response) -> Result<(), HarborError> {
+    let response = if response.get_header("Connection").is_some() {
+        response
+    } else {
+        response.header("Connection", "close")
+    };
+    response.write_to(stream)?;
+    stream.flush()?;
+    Ok(())
+}
+
+/// Whether an accept loop holds off accepting for a deep queue. Once the
+/// queue passes the high-water mark it stays paused until the queue is
+/// down to the low-water mark, so it doesn't flap around a single depth.
+#[derive(Debug, Default)]
+struct Backpressure {
+    paused: bool,
+}
+
+impl Backpressure {
+    /// Whether to leave new connections in the backlog, with `depth` jobs
+    /// queued and the `(high, low)` water marks configured.
+    fn holds(&mut self, depth: usize, marks: Option<(usize, usize)>) -> bool {
+        let Some((high, low)) = marks else {
+            self.paused = false;
+            return false;
+        };
+        if !self.paused && depth > high {
+            warn!(
+                "{} jobs are queued, over the high-water mark of {}; pausing accept until at most {} are",
+                depth, high, low
+            );
+            self.paused = true;
+        } else if self.paused && depth <= low {
+            info!(
+                "The queue drained to {} jobs; accepting connections again",
+                depth
+            );
+            self.paused = false;
+        }
+        self.paused
+    }
+}
 
-        let length = contents.len();
+/// Answers a connection the pool has no room for with `503` and closes it,
+/// without reading the request.
+fn reject_overloaded(stream: Stream, peer: Option<SocketAddr>, context: &Context) {
+    debug!("Pool saturated; refusing connection from {:?}", peer);
+    refuse(stream, Response::error_page(503), context);
+}
+
+/// Answers a connection with `response`, asking the client to retry
+/// later, and closes it without reading the request.
+fn refuse(mut stream: Stream, response: Response, context: &Context) {
+    let response = context
+        .identified(response)
+        .header("Retry-After", &RETRY_AFTER_SECS.to_string())
+        .header("Connection", "close");
+    if let Err(err) = response.write_to(&mut stream) {
+        warn!("Failed to refuse connection: {}", err);
+    }
+    drain(&mut stream);
+}
+
+/// Most bytes `drain` reads before the connection is closed anyway.
+const MAX_DRAIN_BYTES: usize = 256 * 1024;
+
+/// Reads whatever the client already sent, up to `MAX_DRAIN_BYTES`, so
+/// closing a connection after refusing it doesn't reset it before the
+/// response is read.
+fn drain(stream: &mut Stream) {
+    if stream.set_nonblocking(true).is_err() {
+        return;
+    }
+    let mut buffer = [0; 4096];
+    let mut drained = 0;
+    while drained < MAX_DRAIN_BYTES {
+        match stream.read(&mut buffer) {
+            Ok(read) if read > 0 => drained += read,
+            _ => break,
+        }
+    }
+}
 
-        let response = format!("{status_line}\r\nContent-Length: {length}\r\n\r\n{contents}");
+/// Applies the configured `TCP_NODELAY` and `SO_LINGER` to an accepted
+/// connection.
+fn set_socket_options(stream: &Stream, context: &Context) -> std::io::Result<()> {
+    stream.set_nodelay(context.tcp_nodelay)?;
+    #[cfg(all(unix, feature = "sockopt"))]
+    if let Some(linger) = context.linger {
+        stream.set_linger(linger)?;
+    }
+    Ok(())
+}
 
-        match stream.write_all(response.as_bytes()) {
-            Ok(stream) => stream,
+/// Parses requests from `stream`, routes them and writes the responses.
+///
+/// Handlers registered on the router take precedence over static files,
+/// which take precedence over the built-in pages.
+///
+/// Requests that cannot be parsed are answered with `400 Bad Request`, and
+/// handlers that panic with `500 Internal Server Error`.
+///
+/// With keep-alive enabled the connection stays open while the client
+/// allows it. Pipelined requests are answered one at a time, in the order
+/// they were sent.
+fn serve<S: Read + Write + ReadTimeout + Into<Stream> + Send + 'static>(
+    stream: S,
+    peer: Option<SocketAddr>,
+    accepted: Instant,
+    mut guard: Option<ConnectionGuard>,
+    context: Arc<Context>,
+) -> Result<(), HarborError> {
+    let queued = accepted.elapsed();
+    let mut reader = BufReader::new(Deadline::new(stream, context.read_timeout));
+    let peer = if context.proxy_protocol {
+        reader.get_mut().start(context.header_timeout);
+        let header = proxy_protocol::read_header(&mut reader);
+        let deadline = reader.get_mut();
+        if deadline.received() == 0 {
+            // Closed or idle without sending anything, like a port check.
+            return Ok(());
+        }
+        deadline.clear()?;
+        match header {
+            Ok(client) => client.or(peer),
             Err(err) => {
-                eprintln!("{}", err);
-                return Err(err.to_string());
+                warn!("Closing connection from {:?}: {}", peer, err);
+                return Err(HarborError::Http(err));
             }
         }
+    } else {
+        peer
     };
-    Ok(())
+    if context.proxy_protocol
+        && let (Some(guard), Some(client)) = (guard.as_mut(), peer)
+        && !guard.claim_ip(client.ip(), context.max_connections_per_ip)
+    {
+        debug!(
+            "Connection limit for {} reached; refusing connection",
+            client.ip()
+        );
+        let stream = reader.into_inner().into_inner().into();
+        refuse(stream, Response::error_page(429), &context);
+        return Ok(());
+    }
+
+    let connection = Connection {
+        reader,
+        output: Vec::new(),
+        peer,
+        context,
+        served: 0,
+        queued,
+        pool: None,
+        _guard: guard,
+    };
+    connection.serve(None)
+}
+
+/// A connection on the default backend. For a request to a route pinned
+/// to another pool, the whole connection moves to a worker of that pool.
+struct Connection<S> {
+    reader: BufReader<Deadline<S>>,
+    /// Responses held back by `FlushPolicy::Pipeline`, not written yet.
+    output: Vec<u8>,
+    peer: Option<SocketAddr>,
+    context: Arc<Context>,
+    served: usize,
+    /// How long the connection waited for the worker now serving it.
+    queued: Duration,
+    /// The named pool serving the connection; `None` for the default one.
+    pool: Option<String>,
+    _guard: Option<ConnectionGuard>,
+}
+
+impl<S: Read + Write + ReadTimeout + Into<Stream> + Send + 'static> Connection<S> {
+    /// Answers requests until the connection closes or moves to another
+    /// pool, starting with `next` if it was read already.
+    fn serve(mut self, mut next: Option<(HttpRequest, u64)>) -> Result<(), HarborError> {
+        loop {
+            let (mut request, streamed) = match next.take() {
+                Some(next) => next,
+                None => match self.read() {
+                    Ok(Some(read)) => read,
+                    Ok(None) => return self.flush_output().or_else(|err| self.closed(err)),
+                    Err(refusal) => {
+                        self.refuse(refusal);
+                        return Ok(());
+                    }
+                },
+            };
+            #[cfg(feature = "http2")]
+            if http2::is_preface(&request) {
+                return http2::serve(self.reader, self.peer, &self.context);
+            }
+            if let Some((pool, submitter)) = self.moves_to(&request) {
+                self.pool = pool;
+                let handed = Instant::now();
+                submitter.execute(move || {
+                    self.queued = handed.elapsed();
+                    job_result(self.serve(Some((request, streamed))))
+                });
+                return Ok(());
+            }
+
+            request.set_phases(Phases {
+                // Only the first request, or one that just moved, waited.
+                queue: std::mem::take(&mut self.queued),
+                ..request.phases()
+            });
+            self.served += 1;
+            let context = &self.context;
+            let mut body = Body::new(&mut self.reader, streamed);
+            let (mut response, keep_alive) =
+                exchange(&mut request, &mut body, self.peer, self.served, context);
+            let upgrade = response.take_upgrade();
+
+            let pending = Pending::new(&request, &response);
+            let writing = Instant::now();
+            let hold = keep_alive
+                && upgrade.is_none()
+                && self.context.flush_policy == FlushPolicy::Pipeline
+                && self.output.len() < FlushPolicy::HELD_BYTES
+                && holds_bodyless_request(self.reader.buffer());
+            let written = if hold {
+                response.write_to(&mut self.output)
+            } else {
+                self.write_out(&response)
+            };
+            if let Err(err) = written {
+                return self.closed(err);
+            }
+            if let Some(pending) = pending {
+                pending.written(writing.elapsed());
+            }
+            if let Some(upgrade) = upgrade {
+                let buffered = self.reader.buffer().to_vec();
+                let stream = self.reader.into_inner().into_inner().into();
+                upgrade.run(stream, buffered, self.peer);
+                return Ok(());
+            }
+            if !keep_alive {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Reads the next request, once the client starts sending one. `None`
+    /// means the connection is done, and `Err` holds the response refusing
+    /// a request the client got wrong; `read_request` logged why.
+    fn read(&mut self) -> Result<Option<(HttpRequest, u64)>, Response> {
+        let context = &self.context;
+        if self.served > 0 && !wait_for_request(&mut self.reader, context.keep_alive_timeout) {
+            return Ok(None);
+        }
+        let reading = Instant::now();
+        let Some((mut request, streamed)) = read_request(&mut self.reader, context)? else {
+            return Ok(None);
+        };
+        request.set_phases(Phases {
+            parse: reading.elapsed(),
+            ..Phases::default()
+        });
+        Ok(Some((request, streamed)))
+    }
+
+    /// Answers a request `read` refused and closes the connection, reading
+    /// what else the client sent as `refuse` does. Refusing a request the
+    /// client got wrong is not a failure of the job.
+    fn refuse(mut self, response: Response) {
+        let refusal = self
+            .context
+            .identified(response)
+            .header("Connection", "close");
+        // The client may already be gone; the refusal is what matters.
+        let _ = self.flush_output();
+        let _ = refusal.write_to(self.reader.get_mut());
+        let mut stream: Stream = self.reader.into_inner().into_inner().into();
+        drain(&mut stream);
+    }
+
+    /// Writes `response` to the client, after the responses held back for
+    /// earlier requests.
+    fn write_out(&mut self, response: &Response) -> io::Result<()> {
+        if self.output.is_empty() {
+            return response.write_to(self.reader.get_mut());
+        }
+        response.write_to(&mut self.output)?;
+        self.flush_output()
+    }
+
+    /// Ends the connection after writing to it failed with `err`. A client
+    /// hanging up before reading its response is not the server's fault:
+    /// it is counted and logged at debug level, and the job succeeds.
+    fn closed(&self, err: io::Error) -> Result<(), HarborError> {
+        if !is_disconnect(&err) {
+            warn!("{}", err);
+            return Err(err.into());
+        }
+        debug!("Client {:?} went away mid-response: {}", self.peer, err);
+        self.context.connections.client_disconnected();
+        Ok(())
+    }
+
+    /// Writes the responses held back for earlier requests.
+    fn flush_output(&mut self) -> io::Result<()> {
+        if self.output.is_empty() {
+            return Ok(());
+        }
+        let output = std::mem::take(&mut self.output);
+        self.reader.get_mut().write_all(&output)
+    }
+
+    /// The pool to move to for `request`, if it is not the one serving
+    /// the connection, and what queues jobs on it.
+    fn moves_to(&self, request: &HttpRequest) -> Option<(Option<String>, JobSubmitter)> {
+        let context = &self.context;
+        if context.pools.is_empty() {
+            return None;
+        }
+        match context.pool_for(request) {
+            Some((name, _)) if Some(name) == self.pool.as_deref() => None,
+            Some((name, pool)) => Some((Some(name.to_string()), pool.clone())),
+            None if self.pool.is_none() => None,
+            None => Some((None, context.pool.clone()?)),
+        }
+    }
+}
+
+/// How a pool job serving a connection reports the outcome.
+fn job_result(result: Result<(), HarborError>) -> Result<String, String> {
+    match result {
+        Ok(_) => Ok("Connection handled successfully".to_string()),
+        Err(e) => Err(format!("Error handling connection: {}", e)),
+    }
+}
+
+/// Answers the `served`th request on a connection from `peer`, and logs
+/// it. Also returns whether the connection stays open for another
+/// request.
+fn exchange(
+    request: &mut HttpRequest,
+    body: &mut Body<'_>,
+    peer: Option<SocketAddr>,
+    served: usize,
+    context: &Arc<Context>,
+) -> (Response, bool) {
+    set_peer(request, peer, context);
+    let keep_alive = context.keep_alive
+        && request.keep_alive()
+        && context
+            .max_requests_per_connection
+            .is_none_or(|max| served < max);
+
+    let response = answer(request, body, context);
+    // What a streaming handler left unread can't be told from the next
+    // request, or from what the client sends in a new protocol.
+    let response = if response.upgrades() && body.remaining() > 0 {
+        warn!(
+            "Refusing to switch protocols on {} {} with its body unread",
+            request.method(),
+            request.path()
+        );
+        server_error(context)
+    } else {
+        response
+    };
+    let keep_alive = keep_alive && body.remaining() == 0;
+    // Handlers may close the connection themselves with `Connection: close`.
+    let (keep_alive, response) = match response.get_header("Connection") {
+        Some(value) => (keep_alive && !value.eq_ignore_ascii_case("close"), response),
+        None if keep_alive => (true, keep_alive_headers(response, served, context)),
+        None => (false, response.header("Connection", "close")),
+    };
+
+    context
+        .traffic
+        .record(request.wire_len(), response.wire_len());
+    if let Some(access_log) = &context.access_log {
+        access_log.record(peer, request, &response);
+    }
+    (response, keep_alive)
+}
+
+/// Waits up to `idle` for the first byte of the next request on a
+/// kept-alive connection. Returns `false` once the client closes it or
+/// the time is up.
+fn wait_for_request<S: Read + ReadTimeout>(
+    reader: &mut BufReader<Deadline<S>>,
+    idle: Option<Duration>,
+) -> bool {
+    if !reader.buffer().is_empty() {
+        // Pipelined: the request is already here.
+        return true;
+    }
+    reader.get_mut().start(idle);
+    let arrived = reader.fill_buf().is_ok_and(|buf| !buf.is_empty());
+    if !arrived {
+        debug!("Closing idle keep-alive connection");
+    }
+    arrived && reader.get_mut().clear().is_ok()
+}
+
+/// Whether `buffered` starts with the whole head of a request without a
+/// body, which can be read and answered without waiting for the client
+/// or writing to it first.
+fn holds_bodyless_request(buffered: &[u8]) -> bool {
+    let Some(end) = buffered.windows(4).position(|window| window == b"\r\n\r\n") else {
+        return false;
+    };
+    let head = String::from_utf8_lossy(&buffered[..end]);
+    let mut lines = head.split("\r\n");
+    // The HTTP/2 preface looks like a request, and hands the connection on.
+    if lines.next().is_none_or(|line| line.starts_with("PRI ")) {
+        return false;
+    }
+    lines.all(|line| {
+        let (name, value) = line.split_once(':').unwrap_or((line, ""));
+        let name = name.trim();
+        !(name.eq_ignore_ascii_case("Transfer-Encoding")
+            || name.eq_ignore_ascii_case("Expect")
+            || name.eq_ignore_ascii_case("Content-Length") && value.trim() != "0")
+    })
+}
+
+/// Tells the client the connection stays open, and for how long.
+fn keep_alive_headers(response: Response, served: usize, context: &Context) -> Response {
+    let mut limits = Vec::new();
+    if let Some(idle) = context.keep_alive_timeout {
+        limits.push(format!("timeout={}", idle.as_millis().div_ceil(1000)));
+    }
+    if let Some(max) = context.max_requests_per_connection {
+        limits.push(format!("max={}", max - served));
+    }
+    let response = response.header("Connection", "keep-alive");
+    if limits.is_empty() {
+        response
+    } else {
+        response.header("Keep-Alive", &limits.join(", "))
+    }
+}
+
+/// Records who sent `request`: the connection's `peer`, and the client
+/// behind it if the peer is a trusted proxy.
+fn set_peer(request: &mut HttpRequest, peer: Option<SocketAddr>, context: &Context) {
+    request.set_peer_addr(peer);
+    if let Some(peer) = peer {
+        let client = forwarded::client_ip(peer.ip(), request, &context.trusted_proxies);
+        request.set_client_ip(Some(client));
+    }
+}
+
+/// Runs the middleware and handlers on `request`. A panicking handler must
+/// not cost the client its response, so a panic becomes a `500`. Error
+/// pages come back as JSON for clients preferring it.
+fn answer(request: &mut HttpRequest, body: &mut Body<'_>, context: &Arc<Context>) -> Response {
+    if let Some(state) = &context.state {
+        request.set_state(Arc::clone(state));
+    }
+    let timeout = context
+        .router
+        .timeout_for(request)
+        .or(context.handler_timeout);
+    let response = match timeout {
+        // A streamed body is read from the connection, which stays on this
+        // worker, so its handler runs untimed.
+        Some(timeout) if body.remaining() == 0 => answer_within(request, timeout, context),
+        _ => run_handlers(request, body, context),
+    };
+    let response = context.identified(acceptable(request, response).negotiated(request));
+    if request.method() == "HEAD" {
+        response.without_body()
+    } else {
+        response
+    }
+}
+
+/// `406` instead of `response` if it would go out as `identity` to a
+/// client whose `Accept-Encoding` rules that out. Only successful
+/// responses with a body are refused: redirects, `304`s, error pages and
+/// bodies in a coding the client accepts, such as a precompressed static
+/// file, are sent as they are.
+fn acceptable(request: &HttpRequest, response: Response) -> Response {
+    let header = request.header("Accept-Encoding");
+    if !(200..300).contains(&response.status())
+        || response.body_len() == 0
+        || response.get_header("Content-Encoding").is_some()
+        || encoding::negotiate(header, &[]).is_some()
+    {
+        return response;
+    }
+    debug!(
+        "No acceptable content coding for Accept-Encoding: {}",
+        header.unwrap_or_default()
+    );
+    Response::error_page(406)
+}
+
+/// Runs `request` through the middleware to its handler on a thread of its
+/// own, and answers `504` if that takes longer than `timeout`. The thread
+/// is left to finish on its own then, with `request.cancelled()` set, and
+/// counted in `ConnectionStats::abandoned_handlers` until it does. With
+/// `MAX_ABANDONED_HANDLERS` of them running, the request gets `503`; one
+/// that passed that check before others were abandoned gets no slot when
+/// it times out, so the worker waits for its handler instead.
+fn answer_within(request: &mut HttpRequest, timeout: Duration, context: &Arc<Context>) -> Response {
+    /// Who got to the handler's outcome first: the handler returning, or
+    /// the worker giving up on it.
+    const RUNNING: u8 = 0;
+    const RETURNED: u8 = 1;
+    const ABANDONED: u8 = 2;
+
+    let connections = &context.connections;
+    if connections.stats().abandoned_handlers >= MAX_ABANDONED_HANDLERS {
+        warn!(
+            "{} handlers are still running past their timeout; answering {} {} with 503",
+            MAX_ABANDONED_HANDLERS,
+            request.method(),
+            request.path()
+        );
+        return Response::error_page(503).header("Retry-After", &RETRY_AFTER_SECS.to_string());
+    }
+
+    let cancelled = Arc::new(AtomicBool::new(false));
+    request.set_cancellation(Arc::clone(&cancelled));
+    let outcome = Arc::new(AtomicU8::new(RUNNING));
+    let (sender, receiver) = mpsc::channel();
+    let mut timed = request.clone();
+    let shared = Arc::clone(context);
+    let finished = Arc::clone(&outcome);
+    let spawned = thread::Builder::new()
+        .name("harbor-handler".to_string())
+        .spawn(move || {
+            let response = run_handlers(
+                &mut timed,
+                &mut Body::new(&mut std::io::empty(), 0),
+                &shared,
+            );
+            let _ = sender.send(response);
+            if finished.swap(RETURNED, Ordering::SeqCst) == ABANDONED {
+                shared.connections.abandoned_handler_returned();
+            }
+        });
+    if let Err(err) = spawned {
+        warn!(
+            "Failed to start a thread for a timed handler; running it untimed: {}",
+            err
+        );
+        return run_handlers(request, &mut Body::new(&mut std::io::empty(), 0), context);
+    }
+
+    match receiver.recv_timeout(timeout) {
+        Ok(response) => response,
+        Err(RecvTimeoutError::Timeout) => {
+            // Counted first, so the handler returning right after the swap
+            // never takes the count below zero.
+            if !connections.try_abandon_handler(MAX_ABANDONED_HANDLERS) {
+                warn!(
+                    "Handler for {} {} ran longer than {:?} with {} others abandoned; waiting for it",
+                    request.method(),
+                    request.path(),
+                    timeout,
+                    MAX_ABANDONED_HANDLERS
+                );
+                cancelled.store(true, Ordering::SeqCst);
+                return receiver.recv().unwrap_or_else(|_| server_error(context));
+            }
+            if outcome
+                .compare_exchange(RUNNING, ABANDONED, Ordering::SeqCst, Ordering::SeqCst)
+                .is_err()
+            {
+                // It returned just now; its response is on the way.
+                connections.abandoned_handler_returned();
+                return receiver.recv().unwrap_or_else(|_| server_error(context));
+            }
+            cancelled.store(true, Ordering::SeqCst);
+            connections.handler_timed_out();
+            warn!(
+                "Handler for {} {} ran longer than {:?}; answering 504",
+                request.method(),
+                request.path(),
+                timeout
+            );
+            Response::error_page(504)
+        }
+        Err(RecvTimeoutError::Disconnected) => server_error(context),
+    }
+}
+
+/// Runs `request` through the middleware to its handler. A panic on the
+/// way becomes a `500`.
+fn run_handlers(request: &mut HttpRequest, body: &mut Body<'_>, context: &Context) -> Response {
+    let body = RefCell::new(body);
+    match panic::catch_unwind(AssertUnwindSafe(|| {
+        context.middleware.run(request, &|request| {
+            respond(request, &mut body.borrow_mut(), context)
+                .unwrap_or_else(|_| server_error(context))
+        })
+    })) {
+        Ok(response) => response,
+        Err(panic) => {
+            error!(
+                "Handler panicked on {} {}: {}",
+                request.method(),
+                request.path(),
+                crate::panic_message(&*panic)
+            );
+            server_error(context)
+        }
+    }
+}
+
+/// Reads the next request, sending `100 Continue` before the body when the
+/// client waits for it.
+///
+/// `Err` holds the response refusing the request: `400` if it is malformed,
+/// `431` if its head is over `MAX_HEAD_BYTES` or `MAX_HEADERS`, `408` if
+/// its head took longer than the header timeout, `421` if it is
+/// for a host that is not allowed, `417` for
+/// expectations other than `100-continue` and `413` if the body is over
+/// the limit.
+///
+/// The body of a request for a `Router::stream` handler is left unread;
+/// its length comes back alongside the request.
+fn read_request<S: Read + Write + ReadTimeout>(
+    reader: &mut BufReader<Deadline<S>>,
+    context: &Context,
+) -> Result<Option<(HttpRequest, u64)>, Response> {
+    let bad_request = |err: String| {
+        info!("Invalid HTTP request: {}", err);
+        Response::error_page(400)
+    };
+
+    // The budget covers the whole head, however slowly it trickles in.
+    reader.get_mut().start(context.header_timeout);
+    let head = HttpRequest::parse_head(reader);
+    let deadline = reader.get_mut();
+    if deadline.expired() {
+        if deadline.received() == 0 {
+            // Nothing was sent; just close the idle connection.
+            return Ok(None);
+        }
+        info!("Request head not received before the header timeout");
+        return Err(Response::error_page(408));
+    }
+    deadline
+        .clear()
+        .map_err(|err| bad_request(err.to_string()))?;
+
+    let mut request = match head {
+        Ok(Some(request)) => request,
+        Ok(None) => return Ok(None),
+        Err(err) => {
+            info!("Invalid HTTP request: {}", err);
+            return Err(Response::error_page(err.status()));
+        }
+    };
+    #[cfg(feature = "http2")]
+    if http2::is_preface(&request) {
+        return Ok(Some((request, 0)));
+    }
+    let expected = check_head(&request, context)?;
+    if expected.expects_continue {
+        reader
+            .get_mut()
+            .write_all(CONTINUE)
+            .map_err(|err| bad_request(err.to_string()))?;
+    }
+    if expected.streamed {
+        return Ok(Some((request, expected.length as u64)));
+    }
+    request
+        .read_body(reader)
+        .map_err(|err| bad_request(err.to_string()))?;
+    Ok(Some((request, 0)))
+}
+
+/// The interim response a client that sent `Expect: 100-continue` waits
+/// for before sending the body.
+const CONTINUE: &[u8] = b"HTTP/1.1 100 Continue\r\n\r\n";
+
+/// What `check_head` found out about the body following a request head.
+struct ExpectedBody {
+    /// Its `Content-Length`, 0 if there is none.
+    length: usize,
+    /// The client waits for `100 Continue` before sending it.
+    expects_continue: bool,
+    /// A `Router::stream` handler reads it itself.
+    streamed: bool,
+}
+
+/// Checks a request head before its body is read: the `Host`, the
+/// expectation and the body length against the route's limit. `Err` holds
+/// the response refusing it, as for `read_request`.
+fn check_head(request: &HttpRequest, context: &Context) -> Result<ExpectedBody, Response> {
+    expected_body(request, context).map_err(|response| response.negotiated(request))
+}
+
+fn expected_body(request: &HttpRequest, context: &Context) -> Result<ExpectedBody, Response> {
+    let length = request.content_length().map_err(|err| {
+        info!("Invalid HTTP request: {}", err);
+        Response::error_page(400)
+    })?;
+    check_host(request, &context.allowed_hosts)?;
+    check_method(request, &context.allowed_methods)?;
+
+    let expects_continue = match request.header("Expect") {
+        Some(expect) if expect.eq_ignore_ascii_case("100-continue") => true,
+        Some(expect) => {
+            warn!("Unsupported expectation: {}", expect);
+            return Err(Response::error_page(417));
+        }
+        None => false,
+    };
+    let stream_limit = context.router.body_limit(request);
+    let limit = stream_limit.or(context.max_body_bytes.map(|limit| limit as u64));
+    if let (Some(limit), Some(length)) = (limit, length)
+        && length as u64 > limit
+    {
+        info!(
+            "Request body of {} bytes exceeds the {} byte limit",
+            length, limit
+        );
+        return Err(Response::error_page(413));
+    }
+
+    let length = length.unwrap_or(0);
+    Ok(ExpectedBody {
+        length,
+        expects_continue: expects_continue && length > 0,
+        streamed: stream_limit.is_some(),
+    })
+}
+
+/// Refuses requests for hosts missing from `allowed`, unless it is empty.
+fn check_host(request: &HttpRequest, allowed: &[String]) -> Result<(), Response> {
+    if allowed.is_empty() {
+        return Ok(());
+    }
+    let Some(host) = request.host() else {
+        if request.version() == "HTTP/1.0" {
+            return Ok(());
+        }
+        warn!("Request without a Host header");
+        return Err(Response::error_page(400));
+    };
+
+    let host = host.to_ascii_lowercase();
+    let matches = |pattern: &String| {
+        let pattern = pattern.to_ascii_lowercase();
+        match pattern.strip_prefix("*.") {
+            Some(domain) => host
+                .strip_suffix(domain)
+                .is_some_and(|subdomain| subdomain.len() > 1 && subdomain.ends_with('.')),
+            None => host == pattern,
+        }
+    };
+    if allowed.iter().any(matches) {
+        Ok(())
+    } else {
+        warn!("Request for a host that is not allowed: {}", host);
+        Err(Response::error_page(421))
+    }
+}
+
+/// The methods RFC 9110 and RFC 5789 define. Others are unknown to the
+/// server, not merely disallowed.
+const STANDARD_METHODS: [&str; 9] = [
+    "GET", "HEAD", "POST", "PUT", "DELETE", "CONNECT", "OPTIONS", "TRACE", "PATCH",
+];
+
+/// Refuses requests whose method is missing from `allowed`, unless it is
+/// empty: standard methods with `405` and an `Allow` header, unknown ones
+/// with `501`.
+fn check_method(request: &HttpRequest, allowed: &[String]) -> Result<(), Response> {
+    let method = request.method();
+    if allowed.is_empty() || allowed.iter().any(|allowed| allowed == method) {
+        return Ok(());
+    }
+    warn!("Refused a request using the {} method", method);
+    if STANDARD_METHODS.contains(&method) {
+        Err(Response::error_page(405).header("Allow", &allowed.join(", ")))
+    } else {
+        Err(Response::error_page(501))
+    }
+}
+
+fn respond(
+    request: &HttpRequest,
+    body: &mut Body<'_>,
+    context: &Context,
+) -> Result<Response, String> {
+    if let Some(port) = context.https_redirect {
+        return Ok(router::https_redirect(request, port));
+    }
+    if request.target() == "*" {
+        return Ok(server_options(request, context));
+    }
+    let rewritten = rewrite::rewrite(&context.rewrites, request, false);
+    let request = rewritten.as_ref().unwrap_or(request);
+    if let Some(response) = resolve(request, body, context) {
+        return Ok(response);
+    }
+    if let Some(fallback) = rewrite::rewrite(&context.rewrites, request, true)
+        && let Some(response) = resolve(&fallback, body, context)
+    {
+        return Ok(response);
+    }
+    route(request, context)
+}
+
+/// Answers `request` from the router, redirects, static files or embedded
+/// assets, in that order, if any of them has it.
+fn resolve(request: &HttpRequest, body: &mut Body<'_>, context: &Context) -> Option<Response> {
+    context
+        .router
+        .dispatch_body(request, body)
+        .or_else(|| context.redirects.dispatch(request))
+        .or_else(|| {
+            context
+                .static_files
+                .as_ref()
+                .and_then(|files| files.serve(request))
+        })
+        .or_else(|| {
+            context
+                .assets
+                .as_ref()
+                .and_then(|assets| assets.serve(request))
+        })
+}
+
+/// Answers `OPTIONS *`, which asks about the server as a whole, with every
+/// method some resource handles. Other methods can't target `*`.
+fn server_options(request: &HttpRequest, context: &Context) -> Response {
+    if request.method() != "OPTIONS" {
+        return Response::error_page(400);
+    }
+    let mut methods = vec!["GET"];
+    if context.jobs.is_some() {
+        methods.push("POST");
+    }
+    for method in context
+        .router
+        .methods()
+        .into_iter()
+        .chain(context.redirects.methods())
+    {
+        if !methods.contains(&method) {
+            methods.push(method);
+        }
+    }
+    router::options(&methods)
+}
+
+/// The `500` page, `serverError.html`.
+fn server_error(context: &Context) -> Response {
+    match read_page(context, "serverError.html") {
+        Ok(contents) => Response::html(500, contents).marked_as_error_page(),
+        Err(err) => {
+            error!("This file isn't avalible {}", err);
+            Response::error_page(500)
+        }
+    }
+}
+
+fn read_page(context: &Context, filename: &str) -> std::io::Result<Vec<u8>> {
+    match &context.cache {
+        Some(cache) => cache.read(Path::new(filename)).map(|file| file.to_vec()),
+        None => fs::read(filename),
+    }
+}
+
+fn route(request: &HttpRequest, context: &Context) -> Result<Response, String> {
+    let jobs = context.jobs.as_ref();
+    let dashboard = jobs.filter(|_| context.job_dashboard);
+    let allowed: &[&str] = match request.path() {
+        "/" | "/sleep" => &["GET"],
+        "/admin/jobs" | "/admin/jobs/events" if dashboard.is_some() => &["GET"],
+        "/jobs" if jobs.is_some() => &["POST"],
+        path if path.starts_with("/jobs/") && jobs.is_some() => &["GET"],
+        _ => &[],
+    };
+    if !allowed.is_empty() && request.method() == "OPTIONS" {
+        return Ok(router::options(allowed));
+    }
+    // The built-in pages answer `HEAD` like `GET`; `answer` drops the body.
+    let method = match request.method() {
+        "HEAD" => "GET",
+        method => method,
+    };
+    if !allowed.is_empty() && !allowed.contains(&method) {
+        return Ok(router::method_not_allowed(allowed));
+    }
+
+    let (status, filename) = match (method, request.path()) {
+        ("GET", "/") => (200, "hello.html"),
+        ("GET", "/sleep") => {
+            thread::sleep(Duration::from_secs(5));
+            (200, "hello.html")
+        }
+        ("GET", "/admin/jobs") if let Some(dashboard) = dashboard => {
+            return Ok(dashboard.dashboard());
+        }
+        ("GET", "/admin/jobs/events") if let Some(dashboard) = dashboard => {
+            return Ok(dashboard.dashboard_events());
+        }
+        ("POST", "/jobs") => match jobs {
+            Some(jobs) => return Ok(jobs.submit(request)),
+            None => (404, "notFound.html"),
+        },
+        ("GET", path) => match (path.strip_prefix("/jobs/"), jobs) {
+            (Some(job_id), Some(jobs)) => return Ok(jobs.status(job_id)),
+            _ => (404, "notFound.html"),
+        },
+        _ => (404, "notFound.html"),
+    };
+
+    let contents = match read_page(context, filename) {
+        Ok(file) => file,
+        Err(err) => {
+            error!("This file isn't avalible {}", err);
+            return Err(err.to_string());
+        }
+    };
+
+    let page = Response::html(status, contents);
+    Ok(if status == 404 {
+        page.marked_as_error_page()
+    } else {
+        page
+    })
 }
@@ -0,0 +1,481 @@
+//! Parsing of `multipart/form-data` request bodies.
+//!
+//! Parts are read sequentially from any `Read`. Each part is kept in memory
+//! until it grows past a threshold, after which it is spilled to a temporary
+//! file that is removed when the `Part` is dropped.
+
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use uuid::Uuid;
+
+/// Parts larger than this are written to a temporary file by default.
+pub const DEFAULT_MEMORY_THRESHOLD: usize = 1024 * 1024;
+
+const MAX_PART_HEADER_BYTES: usize = 8 * 1024;
+const CHUNK_SIZE: usize = 8 * 1024;
+
+/// A single part of a multipart body.
+#[derive(Debug)]
+pub struct Part {
+    headers: Vec<(String, String)>,
+    name: Option<String>,
+    filename: Option<String>,
+    data: PartData,
+}
+
+#[derive(Debug)]
+enum PartData {
+    Memory(Vec<u8>),
+    File { path: PathBuf, len: u64 },
+}
+
+impl Part {
+    /// The `name` parameter of the part's `Content-Disposition` header.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// The `filename` parameter of the part's `Content-Disposition` header.
+    pub fn filename(&self) -> Option<&str> {
+        self.filename.as_deref()
+    }
+
+    pub fn content_type(&self) -> Option<&str> {
+        self.header("Content-Type")
+    }
+
+    /// Returns the value of the first part header matching `name`, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The size of the part's content in bytes.
+    pub fn len(&self) -> u64 {
+        match &self.data {
+            PartData::Memory(bytes) => bytes.len() as u64,
+            PartData::File { len, .. } => *len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The temporary file holding the content, if it was spilled to disk.
+    pub fn path(&self) -> Option<&Path> {
+        match &self.data {
+            PartData::Memory(_) => None,
+            PartData::File { path, .. } => Some(path),
+        }
+    }
+
+    /// Opens the part's content for reading, wherever it is stored.
+    pub fn reader(&self) -> io::Result<Box<dyn Read + '_>> {
+        match &self.data {
+            PartData::Memory(bytes) => Ok(Box::new(&bytes[..])),
+            PartData::File { path, .. } => Ok(Box::new(File::open(path)?)),
+        }
+    }
+
+    /// Reads the whole content of the part into memory.
+    pub fn bytes(&self) -> io::Result<Vec<u8>> {
+        match &self.data {
+            PartData::Memory(bytes) => Ok(bytes.clone()),
+            PartData::File { path, .. } => fs::read(path),
+        }
+    }
+}
+
+impl Drop for Part {
+    fn drop(&mut self) {
+        if let PartData::File { path, .. } = &self.data {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Extracts the boundary from a `multipart/form-data` content type.
+pub fn boundary(content_type: &str) -> Option<String> {
+    let mut params = content_type.split(';');
+    let media_type = params.next()?.trim();
+    if !media_type.eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+
+    params
+        .filter_map(|param| param.split_once('='))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case("boundary"))
+        .map(|(_, value)| value.trim().trim_matches('"').to_string())
+        .filter(|boundary| !boundary.is_empty() && boundary.len() <= 70)
+}
+
+/// Parses every part of a multipart body delimited by `boundary`.
+///
+/// Parts bigger than `threshold` bytes are streamed to temporary files.
+pub fn parse<R: Read>(reader: R, boundary: &str, threshold: usize) -> Result<Vec<Part>, String> {
+    // Starting the buffer with CRLF lets the first boundary be found with
+    // the same delimiter as the others, discarding any preamble.
+    let mut stream = Stream {
+        reader,
+        buf: b"\r\n".to_vec(),
+    };
+    let delimiter = format!("\r\n--{}", boundary).into_bytes();
+
+    stream.skip_until(&delimiter)?;
+
+    let mut parts = Vec::new();
+    loop {
+        stream.fill_to(2)?;
+        match &stream.buf[..2] {
+            b"--" => return Ok(parts),
+            b"\r\n" => stream.consume(2),
+            _ => return Err("Malformed multipart boundary line".to_string()),
+        }
+
+        let headers = stream.read_headers()?;
+        let (name, filename) = disposition(&headers);
+
+        let mut sink = Sink::new(threshold);
+        stream.copy_until(&delimiter, &mut sink)?;
+
+        parts.push(Part {
+            headers,
+            name,
+            filename,
+            data: sink.finish()?,
+        });
+    }
+}
+
+fn disposition(headers: &[(String, String)]) -> (Option<String>, Option<String>) {
+    let value = match headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case("Content-Disposition"))
+    {
+        Some((_, value)) => value,
+        None => return (None, None),
+    };
+
+    let mut name = None;
+    let mut filename = None;
+    for param in split_params(value).into_iter().skip(1) {
+        if let Some((key, value)) = param.split_once('=') {
+            let value = unquote(value);
+            match key.trim().to_ascii_lowercase().as_str() {
+                "name" => name = Some(value),
+                "filename" => filename = Some(value),
+                _ => {}
+            }
+        }
+    }
+    (name, filename)
+}
+
+/// Splits a header value at the `;` outside quoted strings.
+fn split_params(value: &str) -> Vec<&str> {
+    let mut params = Vec::new();
+    let (mut start, mut quoted, mut escaped) = (0, false, false);
+    for (i, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ';' if !quoted => {
+                params.push(&value[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    params.push(&value[start..]);
+    params
+}
+
+/// A parameter value, with the quotes and `\\` escapes of a quoted string
+/// removed.
+fn unquote(value: &str) -> String {
+    let value = value.trim();
+    let Some(quoted) = value.strip_prefix('"') else {
+        return value.to_string();
+    };
+    let mut unquoted = String::new();
+    let mut chars = quoted.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => break,
+            '\\' => unquoted.extend(chars.next()),
+            c => unquoted.push(c),
+        }
+    }
+    unquoted
+}
+
+struct Stream<R> {
+    reader: R,
+    buf: Vec<u8>,
+}
+
+impl<R: Read> Stream<R> {
+    /// Reads one more chunk; returns `false` at end of input.
+    fn fill(&mut self) -> Result<bool, String> {
+        let mut chunk = [0; CHUNK_SIZE];
+        loop {
+            match self.reader.read(&mut chunk) {
+                Ok(0) => return Ok(false),
+                Ok(read) => {
+                    self.buf.extend_from_slice(&chunk[..read]);
+                    return Ok(true);
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err.to_string()),
+            }
+        }
+    }
+
+    fn fill_to(&mut self, len: usize) -> Result<(), String> {
+        while self.buf.len() < len {
+            if !self.fill()? {
+                return Err("Unexpected end of multipart body".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    fn consume(&mut self, len: usize) {
+        self.buf.drain(..len);
+    }
+
+    fn skip_until(&mut self, needle: &[u8]) -> Result<(), String> {
+        self.copy_until(needle, &mut io::sink())
+    }
+
+    /// Writes everything up to `needle` into `out` and consumes the needle.
+    fn copy_until<W: Write>(&mut self, needle: &[u8], out: &mut W) -> Result<(), String> {
+        loop {
+            if let Some(index) = find(&self.buf, needle) {
+                out.write_all(&self.buf[..index])
+                    .map_err(|err| err.to_string())?;
+                self.consume(index + needle.len());
+                return Ok(());
+            }
+
+            // Keep enough bytes to detect a needle split across chunks.
+            let safe = self.buf.len().saturating_sub(needle.len() - 1);
+            out.write_all(&self.buf[..safe])
+                .map_err(|err| err.to_string())?;
+            self.consume(safe);
+
+            if !self.fill()? {
+                return Err("Multipart body ended before the closing boundary".to_string());
+            }
+        }
+    }
+
+    fn read_headers(&mut self) -> Result<Vec<(String, String)>, String> {
+        self.fill_to(2)?;
+        if self.buf.starts_with(b"\r\n") {
+            self.consume(2);
+            return Ok(Vec::new());
+        }
+
+        let mut raw = Vec::new();
+        {
+            let mut limited = LimitedWriter {
+                inner: &mut raw,
+                remaining: MAX_PART_HEADER_BYTES,
+            };
+            self.copy_until(b"\r\n\r\n", &mut limited)?;
+        }
+
+        let raw = String::from_utf8(raw).map_err(|_| "Part headers are not UTF-8".to_string())?;
+        raw.split("\r\n")
+            .map(|line| match line.split_once(':') {
+                Some((name, value)) => Ok((name.trim().to_string(), value.trim().to_string())),
+                None => Err(format!("Malformed part header: {}", line)),
+            })
+            .collect()
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+struct LimitedWriter<'a> {
+    inner: &'a mut Vec<u8>,
+    remaining: usize,
+}
+
+impl Write for LimitedWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.len() > self.remaining {
+            return Err(io::Error::other("Part headers are too large"));
+        }
+        self.remaining -= buf.len();
+        self.inner.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Collects a part's content, spilling to a temporary file past `threshold`.
+struct Sink {
+    threshold: usize,
+    memory: Vec<u8>,
+    file: Option<(PathBuf, BufWriter<File>)>,
+    len: u64,
+}
+
+impl Sink {
+    fn new(threshold: usize) -> Sink {
+        Sink {
+            threshold,
+            memory: Vec::new(),
+            file: None,
+            len: 0,
+        }
+    }
+
+    fn finish(mut self) -> Result<PartData, String> {
+        match self.file.take() {
+            Some((path, mut writer)) => {
+                if let Err(err) = writer.flush() {
+                    let _ = fs::remove_file(&path);
+                    return Err(err.to_string());
+                }
+                Ok(PartData::File {
+                    path,
+                    len: self.len,
+                })
+            }
+            None => Ok(PartData::Memory(std::mem::take(&mut self.memory))),
+        }
+    }
+}
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.file.is_none() && self.memory.len() + buf.len() > self.threshold {
+            let path = std::env::temp_dir().join(format!("harbor-upload-{}", Uuid::new_v4()));
+            let mut writer = BufWriter::new(File::create(&path)?);
+            writer.write_all(&self.memory)?;
+            self.memory = Vec::new();
+            self.file = Some((path, writer));
+        }
+
+        match &mut self.file {
+            Some((_, writer)) => writer.write_all(buf)?,
+            None => self.memory.extend_from_slice(buf),
+        }
+        self.len += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.file {
+            Some((_, writer)) => writer.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Sink {
+    fn drop(&mut self) {
+        // Only reached with a file when parsing failed before `finish`.
+        if let Some((path, _)) = self.file.take() {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BODY: &str = "preamble\r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"title\"\r\n\
+        \r\n\
+        Hello\r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\
+        Content-Type: text/plain\r\n\
+        \r\n\
+        line one\r\nline two\r\n\
+        --XyZ--\r\n";
+
+    #[test]
+    fn boundary_should_be_read_from_content_type() {
+        assert_eq!(
+            boundary("multipart/form-data; boundary=\"XyZ\""),
+            Some("XyZ".to_string())
+        );
+        assert_eq!(boundary("application/json; boundary=XyZ"), None);
+        assert_eq!(boundary("multipart/form-data"), None);
+    }
+
+    #[test]
+    fn parse_should_split_parts_with_headers() {
+        let parts = parse(BODY.as_bytes(), "XyZ", DEFAULT_MEMORY_THRESHOLD).unwrap();
+
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].name(), Some("title"));
+        assert_eq!(parts[0].bytes().unwrap(), b"Hello");
+        assert_eq!(parts[1].filename(), Some("a.txt"));
+        assert_eq!(parts[1].content_type(), Some("text/plain"));
+        assert_eq!(parts[1].bytes().unwrap(), b"line one\r\nline two");
+    }
+
+    #[test]
+    fn disposition_should_keep_separators_inside_quoted_strings() {
+        let headers = [(
+            "Content-Disposition".to_string(),
+            r#"form-data; filename="a;b=c \"d\".txt"; name=plain"#.to_string(),
+        )];
+
+        assert_eq!(
+            disposition(&headers),
+            (
+                Some("plain".to_string()),
+                Some(r#"a;b=c "d".txt"#.to_string())
+            )
+        );
+    }
+
+    #[test]
+    fn parse_should_spill_large_parts_to_temporary_files() {
+        let content = "x".repeat(3 * CHUNK_SIZE);
+        let body = format!(
+            "--b\r\nContent-Disposition: form-data; name=\"big\"\r\n\r\n{}\r\n--b--",
+            content
+        );
+
+        let parts = parse(body.as_bytes(), "b", 1024).unwrap();
+        let path = parts[0].path().unwrap().to_path_buf();
+
+        assert_eq!(parts[0].len(), content.len() as u64);
+        assert_eq!(fs::read(&path).unwrap(), content.as_bytes());
+        drop(parts);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn parse_should_reject_truncated_bodies() {
+        let truncated = &BODY[..BODY.len() - 12];
+
+        assert!(parse(truncated.as_bytes(), "XyZ", DEFAULT_MEMORY_THRESHOLD).is_err());
+        assert!(parse(&b"no boundary here"[..], "XyZ", DEFAULT_MEMORY_THRESHOLD).is_err());
+    }
+}
@@ -0,0 +1,153 @@
+//! The PROXY protocol (versions 1 and 2), with which load balancers such
+//! as HAProxy pass on the address of the client they accepted.
+
+use std::{
+    io::{BufRead, Read},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+/// The first bytes of every version 2 header.
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+/// The longest version 1 header, line break included.
+const V1_MAX_LEN: usize = 107;
+
+/// Reads the PROXY header that opens a connection and returns the client
+/// address it carries.
+///
+/// `Ok(None)` means the header does not name a client, as for health
+/// checks (`UNKNOWN` or `LOCAL`) or address families other than TCP over
+/// IPv4 or IPv6. A connection without a valid header is an error: once
+/// enabled, the header is required.
+pub fn read_header<R: BufRead>(reader: &mut R) -> Result<Option<SocketAddr>, String> {
+    let mut start = [0; 12];
+    reader
+        .read_exact(&mut start)
+        .map_err(|err| format!("PROXY header not received: {}", err))?;
+    if start == V2_SIGNATURE {
+        return read_v2(reader);
+    }
+    if !start.starts_with(b"PROXY ") {
+        return Err("Connection did not start with a PROXY header".to_string());
+    }
+
+    let mut line = start.to_vec();
+    reader
+        .take((V1_MAX_LEN - start.len()) as u64)
+        .read_until(b'\n', &mut line)
+        .map_err(|err| format!("PROXY header not received: {}", err))?;
+    let line = line
+        .strip_suffix(b"\r\n")
+        .ok_or("PROXY header is too long or not terminated")?;
+    parse_v1(std::str::from_utf8(line).map_err(|_| "PROXY header is not ASCII")?)
+}
+
+/// Parses a version 1 line without its line break, e.g.
+/// `PROXY TCP4 192.0.2.1 198.51.100.1 56324 443`.
+fn parse_v1(line: &str) -> Result<Option<SocketAddr>, String> {
+    let invalid = || format!("Malformed PROXY header: {:?}", line);
+    let fields: Vec<&str> = line.split(' ').collect();
+    match fields.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", family @ ("TCP4" | "TCP6"), source, _, port, _] => {
+            let ip: IpAddr = source.parse().map_err(|_| invalid())?;
+            if ip.is_ipv4() != (*family == "TCP4") {
+                return Err(invalid());
+            }
+            let port = port.parse().map_err(|_| invalid())?;
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        _ => Err(invalid()),
+    }
+}
+
+/// Reads the rest of a version 2 header, after its signature.
+fn read_v2<R: Read>(reader: &mut R) -> Result<Option<SocketAddr>, String> {
+    let mut head = [0; 4];
+    reader
+        .read_exact(&mut head)
+        .map_err(|err| format!("PROXY header not received: {}", err))?;
+    let [version_command, family, len_high, len_low] = head;
+    if version_command >> 4 != 2 {
+        return Err(format!(
+            "Unsupported PROXY protocol version {}",
+            version_command >> 4
+        ));
+    }
+    let mut addresses = vec![0; usize::from(u16::from_be_bytes([len_high, len_low]))];
+    reader
+        .read_exact(&mut addresses)
+        .map_err(|err| format!("PROXY header not received: {}", err))?;
+
+    match version_command & 0x0f {
+        // LOCAL: the proxy's own connection, e.g. a health check.
+        0 => return Ok(None),
+        1 => {}
+        command => return Err(format!("Unknown PROXY command {}", command)),
+    }
+    let truncated = || "PROXY header addresses are truncated".to_string();
+    match family {
+        // TCP over IPv4: source, destination, source port, destination port.
+        0x11 => {
+            let block = addresses.get(..12).ok_or_else(truncated)?;
+            let ip = Ipv4Addr::new(block[0], block[1], block[2], block[3]);
+            let port = u16::from_be_bytes([block[8], block[9]]);
+            Ok(Some(SocketAddr::new(ip.into(), port)))
+        }
+        // TCP over IPv6, laid out the same way.
+        0x21 => {
+            let block = addresses.get(..36).ok_or_else(truncated)?;
+            let mut ip = [0; 16];
+            ip.copy_from_slice(&block[..16]);
+            let port = u16::from_be_bytes([block[32], block[33]]);
+            Ok(Some(SocketAddr::new(Ipv6Addr::from(ip).into(), port)))
+        }
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_header_should_parse_version_1() {
+        let mut tcp4 = &b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\nGET / HTTP/1.1\r\n"[..];
+        let mut tcp6 = &b"PROXY TCP6 2001:db8::1 2001:db8::2 4000 443\r\n"[..];
+        let mut unknown = &b"PROXY UNKNOWN\r\n"[..];
+
+        assert_eq!(
+            read_header(&mut tcp4),
+            Ok(Some("192.0.2.1:56324".parse().unwrap()))
+        );
+        assert_eq!(tcp4, b"GET / HTTP/1.1\r\n");
+        assert_eq!(
+            read_header(&mut tcp6),
+            Ok(Some("[2001:db8::1]:4000".parse().unwrap()))
+        );
+        assert_eq!(read_header(&mut unknown), Ok(None));
+        assert!(read_header(&mut &b"PROXY TCP4 2001:db8::1 ::1 1 2\r\n"[..]).is_err());
+        assert!(read_header(&mut &b"GET / HTTP/1.1\r\nHost: x\r\n\r\n"[..]).is_err());
+    }
+
+    #[test]
+    fn read_header_should_parse_version_2() {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend_from_slice(&[0x21, 0x11, 0, 15]);
+        header.extend_from_slice(&[203, 0, 113, 7, 10, 0, 0, 1]);
+        header.extend_from_slice(&[0x1f, 0x90, 0x01, 0xbb]);
+        header.extend_from_slice(&[0x04, 0, 0]); // An empty NOOP TLV.
+        header.extend_from_slice(b"GET /");
+        let mut reader = &header[..];
+
+        assert_eq!(
+            read_header(&mut reader),
+            Ok(Some("203.0.113.7:8080".parse().unwrap()))
+        );
+        assert_eq!(reader, b"GET /");
+
+        let mut local = V2_SIGNATURE.to_vec();
+        local.extend_from_slice(&[0x20, 0x00, 0, 0]);
+        assert_eq!(read_header(&mut &local[..]), Ok(None));
+    }
+}
@@ -0,0 +1,776 @@
+use std::{
+    any::Any,
+    collections::HashMap,
+    fmt,
+    io::{self, BufRead, Read},
+    net::{IpAddr, SocketAddr},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use super::{
+    cookie,
+    handler::State,
+    headers::Headers,
+    multipart::{self, Part},
+    session::Session,
+    timing::Phases,
+    url,
+};
+
+/// Most bytes a request head may take, line endings included; longer
+/// heads are refused with `431`.
+pub const MAX_HEAD_BYTES: usize = 64 * 1024;
+
+/// Most header lines a request head may have.
+pub const MAX_HEADERS: usize = 100;
+
+/// Why bytes read as a request are not one.
+///
+/// The parser never panics on what it reads, and never holds more than
+/// `MAX_HEAD_BYTES` of a head or more of a body than the client sent, so
+/// any input ends in a request, `None` for an empty one, or one of these.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// The input ended partway through the head, or before the body its
+    /// `Content-Length` announced.
+    Truncated,
+    /// The head ran past `MAX_HEAD_BYTES` before its empty line.
+    HeadTooLarge,
+    /// The head has more than `MAX_HEADERS` header lines.
+    TooManyHeaders,
+    /// A line of the head is not UTF-8.
+    NotUtf8,
+    /// The request line is not a method, a target and a version.
+    MalformedRequestLine(String),
+    /// The method is not a token.
+    InvalidMethod(String),
+    /// The target is neither a path nor `*`.
+    InvalidTarget(String),
+    /// The version is not `HTTP/` something.
+    InvalidVersion(String),
+    /// The path has a broken `%` escape, or is not UTF-8 once decoded.
+    InvalidPercentEncoding(String),
+    /// The path decodes to one with a NUL byte.
+    NulInPath(String),
+    /// A header line is not a token name, a colon and a value.
+    MalformedHeader(String),
+    /// The `Content-Length` is not a number of bytes.
+    InvalidContentLength(String),
+    /// There is more than one `Content-Length`.
+    DuplicateContentLength,
+    /// There are both a `Content-Length` and a `Transfer-Encoding`.
+    ConflictingFraming,
+    /// The `Transfer-Encoding` does not end in a single `chunked`.
+    InvalidTransferEncoding(String),
+    /// The body is framed with a `Transfer-Encoding`, which the server
+    /// does not decode. Refused rather than read as if it had no body,
+    /// which would serve the chunks as the next request.
+    UnsupportedTransferEncoding(String),
+    /// Reading the input failed.
+    Io(io::ErrorKind),
+}
+
+impl ParseError {
+    /// The status a server refuses the request with: `431` for heads
+    /// over the limits, `501` for a `Transfer-Encoding`, `400` for the
+    /// rest.
+    pub fn status(&self) -> u16 {
+        match self {
+            ParseError::HeadTooLarge | ParseError::TooManyHeaders => 431,
+            ParseError::UnsupportedTransferEncoding(_) => 501,
+            _ => 400,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Truncated => write!(f, "Request ended early"),
+            ParseError::HeadTooLarge => write!(f, "Request head over {} bytes", MAX_HEAD_BYTES),
+            ParseError::TooManyHeaders => write!(f, "More than {} headers", MAX_HEADERS),
+            ParseError::NotUtf8 => write!(f, "Request head is not valid UTF-8"),
+            ParseError::MalformedRequestLine(line) => write!(f, "Malformed request line: {}", line),
+            ParseError::InvalidMethod(method) => write!(f, "Invalid method: {}", method),
+            ParseError::InvalidTarget(target) => write!(f, "Invalid request target: {}", target),
+            ParseError::InvalidVersion(version) => write!(f, "Invalid HTTP version: {}", version),
+            ParseError::InvalidPercentEncoding(target) => {
+                write!(f, "Invalid percent-encoding in: {}", target)
+            }
+            ParseError::NulInPath(target) => write!(f, "Path contains a NUL byte: {}", target),
+            ParseError::MalformedHeader(line) => write!(f, "Malformed header line: {}", line),
+            ParseError::InvalidContentLength(length) => {
+                write!(f, "Invalid Content-Length: {}", length)
+            }
+            ParseError::DuplicateContentLength => write!(f, "More than one Content-Length"),
+            ParseError::ConflictingFraming => {
+                write!(f, "Both Content-Length and Transfer-Encoding")
+            }
+            ParseError::InvalidTransferEncoding(codings) => {
+                write!(f, "Conflicting Transfer-Encoding: {}", codings)
+            }
+            ParseError::UnsupportedTransferEncoding(codings) => {
+                write!(f, "Unsupported Transfer-Encoding: {}", codings)
+            }
+            ParseError::Io(kind) => write!(f, "Reading the request failed: {}", kind),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// A parsed HTTP request head.
+#[derive(Debug, Clone)]
+pub struct HttpRequest {
+    method: String,
+    target: String,
+    path: String,
+    query: Option<String>,
+    version: String,
+    headers: Headers,
+    /// Bytes the request line and headers took, with line endings.
+    head_len: usize,
+    body: Vec<u8>,
+    params: Vec<(String, String)>,
+    peer_addr: Option<SocketAddr>,
+    client_ip: Option<IpAddr>,
+    session: Option<Arc<Session>>,
+    state: Option<State>,
+    phases: Phases,
+    /// Set once the server answered `504` in place of the handler.
+    cancelled: Option<Arc<AtomicBool>>,
+}
+
+impl HttpRequest {
+    /// Reads the request line, headers and `Content-Length` body from `reader`.
+    ///
+    /// Returns `Ok(None)` if the client closed the connection before
+    /// sending anything.
+    pub fn parse<R: BufRead>(reader: &mut R) -> Result<Option<HttpRequest>, ParseError> {
+        match HttpRequest::parse_head(reader)? {
+            Some(mut request) => {
+                request.read_body(reader)?;
+                Ok(Some(request))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Reads only the request line and headers, leaving the body in
+    /// `reader` for `read_body`. Servers use this to answer
+    /// `Expect: 100-continue` before the client sends the body.
+    ///
+    /// The head must end with its empty line within `MAX_HEAD_BYTES` and
+    /// `MAX_HEADERS` headers; input ending before that is `Truncated`.
+    pub fn parse_head<R: BufRead>(reader: &mut R) -> Result<Option<HttpRequest>, ParseError> {
+        let mut budget = MAX_HEAD_BYTES;
+        let request_line = match read_head_line(reader, &mut budget)? {
+            Some(line) => line,
+            None => return Ok(None),
+        };
+
+        let mut parts = request_line.split_whitespace();
+        let (Some(method), Some(target), Some(version), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(ParseError::MalformedRequestLine(request_line));
+        };
+        if !is_token(method) {
+            return Err(ParseError::InvalidMethod(method.to_string()));
+        }
+        if !target.starts_with('/') && target != "*" {
+            return Err(ParseError::InvalidTarget(target.to_string()));
+        }
+        if !version.starts_with("HTTP/") {
+            return Err(ParseError::InvalidVersion(version.to_string()));
+        }
+
+        let (raw_path, query) = match target.split_once('?') {
+            Some((path, query)) => (path, Some(query.to_string())),
+            None => (target, None),
+        };
+        let decoded = url::percent_decode(raw_path)
+            .map_err(|_| ParseError::InvalidPercentEncoding(target.to_string()))?;
+        let path = url::normalize_path(&decoded);
+        if path.contains('\0') {
+            return Err(ParseError::NulInPath(target.to_string()));
+        }
+
+        let mut headers = Headers::new();
+        loop {
+            let line = read_head_line(reader, &mut budget)?.ok_or(ParseError::Truncated)?;
+            if line.is_empty() {
+                break;
+            }
+            if headers.len() == MAX_HEADERS {
+                return Err(ParseError::TooManyHeaders);
+            }
+            match line.split_once(':') {
+                Some((name, value)) if is_token(name) => {
+                    headers.push(name.to_string(), value.trim().to_string())
+                }
+                _ => return Err(ParseError::MalformedHeader(line)),
+            }
+        }
+        let head_len = MAX_HEAD_BYTES - budget;
+
+        let request = HttpRequest {
+            method: method.to_string(),
+            target: target.to_string(),
+            path,
+            query,
+            version: version.to_string(),
+            headers,
+            head_len,
+            body: Vec::new(),
+            params: Vec::new(),
+            peer_addr: None,
+            client_ip: None,
+            session: None,
+            state: None,
+            phases: Phases::default(),
+            cancelled: None,
+        };
+        request.check_framing()?;
+
+        Ok(Some(request))
+    }
+
+    /// Reads the `Content-Length` body that follows the head from `reader`.
+    ///
+    /// The body grows as its bytes arrive, so a client announcing more
+    /// than it sends costs no more memory than what it sent.
+    pub fn read_body<R: BufRead>(&mut self, reader: &mut R) -> Result<(), ParseError> {
+        if let Some(length) = self.content_length()? {
+            let mut body = Vec::new();
+            reader
+                .take(length as u64)
+                .read_to_end(&mut body)
+                .map_err(|err| ParseError::Io(err.kind()))?;
+            if body.len() < length {
+                return Err(ParseError::Truncated);
+            }
+            self.body = body;
+        }
+        Ok(())
+    }
+
+    /// The announced body length, if the request has a body.
+    pub fn content_length(&self) -> Result<Option<usize>, ParseError> {
+        let mut lengths = self.header_values("Content-Length");
+        let Some(length) = lengths.next() else {
+            return Ok(None);
+        };
+        if length.contains(',') || lengths.next().is_some() {
+            return Err(ParseError::DuplicateContentLength);
+        }
+        // `parse` would take a leading `+`, which the grammar does not.
+        match length.parse() {
+            Ok(parsed) if length.bytes().all(|byte| byte.is_ascii_digit()) => Ok(Some(parsed)),
+            _ => Err(ParseError::InvalidContentLength(length.to_string())),
+        }
+    }
+
+    /// Refuses heads whose body could be framed more than one way, which
+    /// lets a request smuggle another past a proxy that frames it the
+    /// other way: a `Transfer-Encoding` next to a `Content-Length`, a
+    /// `Transfer-Encoding` whose last coding is not a single `chunked`,
+    /// or several `Content-Length`s. Well-formed `Transfer-Encoding`s are
+    /// refused too, as `UnsupportedTransferEncoding`, since only
+    /// `Content-Length` bodies are read.
+    fn check_framing(&self) -> Result<(), ParseError> {
+        self.content_length()?;
+        let codings: Vec<&str> = self
+            .header_values("Transfer-Encoding")
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .collect();
+        if codings.is_empty() {
+            return Ok(());
+        }
+        if self.headers.contains("Content-Length") {
+            return Err(ParseError::ConflictingFraming);
+        }
+        let chunked = |coding: &str| coding.eq_ignore_ascii_case("chunked");
+        let chunks = codings.iter().filter(|coding| chunked(coding)).count();
+        if chunks != 1 || !codings.last().is_some_and(|coding| chunked(coding)) {
+            return Err(ParseError::InvalidTransferEncoding(codings.join(", ")));
+        }
+        Err(ParseError::UnsupportedTransferEncoding(codings.join(", ")))
+    }
+
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+
+    /// The request target exactly as sent by the client.
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    /// The target's path, percent-decoded and normalized with
+    /// `url::normalize_path`. Routing and file lookups use this form.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn query(&self) -> Option<&str> {
+        self.query.as_deref()
+    }
+
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    /// The size in bytes of the request as the client sent it: its head
+    /// and the body its `Content-Length` announced.
+    pub fn wire_len(&self) -> u64 {
+        let body = match self.content_length() {
+            Ok(Some(length)) => length,
+            _ => self.body.len(),
+        };
+        (self.head_len + body) as u64
+    }
+
+    /// Every header the client sent.
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    /// Returns the value of the first header matching `name`, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    /// The values of every header called `name`, in the order received.
+    pub fn header_values<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> {
+        self.headers.get_all(name)
+    }
+
+    /// The host name from the `Host` header, without the port. IPv6
+    /// literals keep their brackets, e.g. `[::1]`.
+    pub fn host(&self) -> Option<&str> {
+        let host = self.header("Host")?;
+        let name = match host.find(']') {
+            Some(end) if host.starts_with('[') => &host[..=end],
+            _ => host.split(':').next().unwrap_or(host),
+        };
+        Some(name).filter(|name| !name.is_empty())
+    }
+
+    /// Whether the client asked to reuse the connection: the default for
+    /// HTTP/1.1 unless it sent `Connection: close`, and opt-in with
+    /// `Connection: keep-alive` for HTTP/1.0.
+    pub fn keep_alive(&self) -> bool {
+        let has_token = |token: &str| {
+            self.header("Connection").is_some_and(|value| {
+                value
+                    .split(',')
+                    .any(|option| option.trim().eq_ignore_ascii_case(token))
+            })
+        };
+        match self.version.as_str() {
+            "HTTP/1.0" => has_token("keep-alive"),
+            _ => !has_token("close"),
+        }
+    }
+
+    /// The cookies sent in `Cookie` headers, by name.
+    pub fn cookies(&self) -> HashMap<String, String> {
+        let mut cookies = HashMap::new();
+        for value in self.header_values("Cookie") {
+            for (name, value) in cookie::parse(value) {
+                cookies.entry(name).or_insert(value);
+            }
+        }
+        cookies
+    }
+
+    /// The value of the cookie called `name`, if the client sent it.
+    pub fn cookie(&self, name: &str) -> Option<String> {
+        self.cookies().remove(name)
+    }
+
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// A copy of the request sent to `target` instead, keeping the query
+    /// when `target` has none.
+    pub(crate) fn with_target(&self, target: &str) -> Result<HttpRequest, String> {
+        let (raw_path, query) = match target.split_once('?') {
+            Some((path, query)) => (path, Some(query.to_string())),
+            None => (target, self.query.clone()),
+        };
+        let path = url::normalize_path(&url::percent_decode(raw_path)?);
+        if path.contains('\0') {
+            return Err(format!("Path contains a NUL byte: {}", target));
+        }
+        let target = match &query {
+            Some(query) => format!("{}?{}", raw_path, query),
+            None => raw_path.to_string(),
+        };
+        Ok(HttpRequest {
+            target,
+            path,
+            query,
+            params: Vec::new(),
+            ..self.clone()
+        })
+    }
+
+    #[cfg(any(feature = "http2", all(target_os = "linux", feature = "evented")))]
+    pub(crate) fn set_body(&mut self, body: Vec<u8>) {
+        self.body = body;
+    }
+
+    /// The address of the client, as given by the PROXY protocol header
+    /// when that is enabled. `None` for Unix sockets and requests parsed
+    /// outside a server.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+
+    pub(crate) fn set_peer_addr(&mut self, peer_addr: Option<SocketAddr>) {
+        self.peer_addr = peer_addr;
+    }
+
+    /// The address of the client as far as trusted proxies vouch for it:
+    /// taken from `Forwarded` or `X-Forwarded-For` when the peer is one of
+    /// the `trusted_proxies`, and the peer's own address otherwise. This
+    /// is the address to log, rate limit and allow or deny.
+    pub fn client_ip(&self) -> Option<IpAddr> {
+        self.client_ip
+            .or(self.peer_addr.map(|peer_addr| peer_addr.ip()))
+    }
+
+    pub(crate) fn set_client_ip(&mut self, client_ip: Option<IpAddr>) {
+        self.client_ip = client_ip;
+    }
+
+    /// The part of the path captured by the `{name}` segment or `*name`
+    /// wildcard of the route that matched the request.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(param, _)| param == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub(crate) fn set_param(&mut self, name: &str, value: &str) {
+        self.params.push((name.to_string(), value.to_string()));
+    }
+
+    /// The session attached by the `Sessions` middleware, if installed.
+    pub fn session(&self) -> Option<&Session> {
+        self.session.as_deref()
+    }
+
+    pub(crate) fn set_session(&mut self, session: Arc<Session>) {
+        self.session = Some(session);
+    }
+
+    /// The state passed to `Server::with_state`, if it is a `T`.
+    pub fn state<T: Any>(&self) -> Option<&T> {
+        self.state.as_deref()?.downcast_ref()
+    }
+
+    pub(crate) fn set_state(&mut self, state: State) {
+        self.state = Some(state);
+    }
+
+    /// How long the server took to read the request and how long it waited
+    /// for a worker. Zero for requests parsed outside a server.
+    pub fn phases(&self) -> Phases {
+        self.phases
+    }
+
+    pub(crate) fn set_phases(&mut self, phases: Phases) {
+        self.phases = phases;
+    }
+
+    /// Whether the handler ran past its timeout and the client was already
+    /// answered `504 Gateway Timeout`. Long handlers can check it between
+    /// steps to stop work nobody waits for anymore.
+    pub fn cancelled(&self) -> bool {
+        self.cancelled
+            .as_ref()
+            .is_some_and(|cancelled| cancelled.load(Ordering::SeqCst))
+    }
+
+    pub(crate) fn set_cancellation(&mut self, cancelled: Arc<AtomicBool>) {
+        self.cancelled = Some(cancelled);
+    }
+
+    /// Parses an `application/x-www-form-urlencoded` body, as sent by HTML
+    /// forms, into a map. When a name repeats, the first value is kept; use
+    /// `url::parse_form` to see every value.
+    pub fn form(&self) -> Result<HashMap<String, String>, String> {
+        let is_form = self.header("Content-Type").is_some_and(|content_type| {
+            content_type.split(';').next().is_some_and(|mime| {
+                mime.trim()
+                    .eq_ignore_ascii_case("application/x-www-form-urlencoded")
+            })
+        });
+        if !is_form {
+            return Err("Request is not application/x-www-form-urlencoded".to_string());
+        }
+        let body = std::str::from_utf8(&self.body)
+            .map_err(|_| "Form body is not valid UTF-8".to_string())?;
+
+        let mut form = HashMap::new();
+        for (name, value) in url::parse_form(body)? {
+            form.entry(name).or_insert(value);
+        }
+        Ok(form)
+    }
+
+    /// Parses a `multipart/form-data` body into its parts.
+    ///
+    /// Parts over `multipart::DEFAULT_MEMORY_THRESHOLD` bytes are stored in
+    /// temporary files; use `multipart::parse` to choose another threshold.
+    pub fn multipart(&self) -> Result<Vec<Part>, String> {
+        let boundary = self
+            .header("Content-Type")
+            .and_then(multipart::boundary)
+            .ok_or_else(|| "Request is not multipart/form-data".to_string())?;
+
+        multipart::parse(
+            &self.body[..],
+            &boundary,
+            multipart::DEFAULT_MEMORY_THRESHOLD,
+        )
+    }
+}
+
+/// Whether `text` is an RFC 9110 token, as methods and header names must be.
+pub(crate) fn is_token(text: &str) -> bool {
+    !text.is_empty()
+        && text
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte))
+}
+
+pub(crate) fn read_line<R: BufRead>(reader: &mut R) -> Result<Option<String>, String> {
+    let mut line = String::new();
+    match reader.read_line(&mut line) {
+        Ok(0) => Ok(None),
+        Ok(_) => Ok(Some(line.trim_end_matches(['\r', '\n']).to_string())),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+/// Reads one line of a request head, without its line ending, taking the
+/// bytes it read off the `budget` the head has left. `None` means the
+/// input ended before the line started.
+fn read_head_line<R: BufRead>(
+    reader: &mut R,
+    budget: &mut usize,
+) -> Result<Option<String>, ParseError> {
+    let mut line = Vec::new();
+    let read = reader
+        .take(*budget as u64)
+        .read_until(b'\n', &mut line)
+        .map_err(|err| ParseError::Io(err.kind()))?;
+    if line.last() != Some(&b'\n') {
+        return Err(match read {
+            0 => return Ok(None),
+            read if read == *budget => ParseError::HeadTooLarge,
+            _ => ParseError::Truncated,
+        });
+    }
+    *budget -= read;
+    line.pop();
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    String::from_utf8(line)
+        .map(Some)
+        .map_err(|_| ParseError::NotUtf8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_should_read_request_line_and_headers() {
+        let raw = b"GET /jobs/42?verbose=1 HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let request = HttpRequest::parse(&mut &raw[..]).unwrap().unwrap();
+
+        assert_eq!(request.method(), "GET");
+        assert_eq!(request.path(), "/jobs/42");
+        assert_eq!(request.query(), Some("verbose=1"));
+        assert_eq!(request.version(), "HTTP/1.1");
+        assert_eq!(request.header("host"), Some("localhost"));
+    }
+
+    #[test]
+    fn parse_should_decode_and_normalize_the_path() {
+        let raw = b"GET //hello%20world/./x/..?q=%20 HTTP/1.1\r\n\r\n";
+        let request = HttpRequest::parse(&mut &raw[..]).unwrap().unwrap();
+
+        assert_eq!(request.path(), "/hello world/");
+        assert_eq!(request.target(), "//hello%20world/./x/..?q=%20");
+        assert_eq!(request.query(), Some("q=%20"));
+        assert!(HttpRequest::parse(&mut &b"GET /%00 HTTP/1.1\r\n\r\n"[..]).is_err());
+    }
+
+    #[test]
+    fn parse_should_read_body_using_content_length() {
+        let raw = b"POST /jobs HTTP/1.1\r\nContent-Length: 4\r\n\r\n{}{}extra";
+        let request = HttpRequest::parse(&mut &raw[..]).unwrap().unwrap();
+
+        assert_eq!(request.body(), b"{}{}");
+    }
+
+    #[test]
+    fn parse_head_should_leave_the_body_unread() {
+        let raw = b"POST /up HTTP/1.1\r\nContent-Length: 3\r\nExpect: 100-continue\r\n\r\nabc";
+        let mut reader = &raw[..];
+
+        let mut request = HttpRequest::parse_head(&mut reader).unwrap().unwrap();
+        assert_eq!(request.content_length(), Ok(Some(3)));
+        assert!(request.body().is_empty());
+        assert_eq!(reader, b"abc");
+
+        request.read_body(&mut reader).unwrap();
+        assert_eq!(request.body(), b"abc");
+        assert!(
+            HttpRequest::parse(&mut &b"GET / HTTP/1.1\r\nContent-Length: x\r\n\r\n"[..]).is_err()
+        );
+    }
+
+    #[test]
+    fn parse_head_should_reject_ambiguous_body_framing() {
+        let parse = |headers: &str| {
+            let raw = format!("POST / HTTP/1.1\r\n{}\r\n", headers);
+            HttpRequest::parse_head(&mut raw.as_bytes())
+        };
+
+        assert!(parse("Content-Length: 3\r\nContent-Length: 3\r\n").is_err());
+        assert!(parse("Content-Length: 3, 4\r\n").is_err());
+        assert!(parse("Content-Length: +3\r\n").is_err());
+        assert!(parse("Transfer-Encoding: chunked\r\nContent-Length: 3\r\n").is_err());
+        assert!(parse("Transfer-Encoding: chunked, gzip\r\n").is_err());
+        assert!(parse("Transfer-Encoding: chunked\r\nTransfer-Encoding: chunked\r\n").is_err());
+        assert_eq!(
+            parse("Transfer-Encoding: gzip, Chunked\r\n").unwrap_err(),
+            ParseError::UnsupportedTransferEncoding("gzip, Chunked".to_string())
+        );
+        assert_eq!(
+            ParseError::UnsupportedTransferEncoding(String::new()).status(),
+            501
+        );
+        let request = parse("Content-Length: 3\r\n").unwrap().unwrap();
+        assert_eq!(request.headers().get("content-length"), Some("3"));
+    }
+
+    #[test]
+    fn keep_alive_should_follow_version_and_connection_header() {
+        let keep_alive = |raw: &str| {
+            HttpRequest::parse(&mut raw.as_bytes())
+                .unwrap()
+                .unwrap()
+                .keep_alive()
+        };
+
+        assert!(keep_alive("GET / HTTP/1.1\r\n\r\n"));
+        assert!(!keep_alive("GET / HTTP/1.1\r\nConnection: Close\r\n\r\n"));
+        assert!(!keep_alive("GET / HTTP/1.0\r\n\r\n"));
+        assert!(keep_alive(
+            "GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n"
+        ));
+    }
+
+    #[test]
+    fn host_should_strip_the_port() {
+        let host = |value: &str| {
+            let raw = format!("GET / HTTP/1.1\r\nHost: {}\r\n\r\n", value);
+            HttpRequest::parse(&mut raw.as_bytes())
+                .unwrap()
+                .unwrap()
+                .host()
+                .map(str::to_string)
+        };
+
+        assert_eq!(host("example.com:8080").as_deref(), Some("example.com"));
+        assert_eq!(host("Example.COM").as_deref(), Some("Example.COM"));
+        assert_eq!(host("[::1]:7878").as_deref(), Some("[::1]"));
+        assert_eq!(host(":80"), None);
+    }
+
+    #[test]
+    fn cookies_should_merge_every_cookie_header() {
+        let raw = b"GET / HTTP/1.1\r\nCookie: a=1; b=2\r\ncookie: c=3; a=ignored\r\n\r\n";
+        let request = HttpRequest::parse(&mut &raw[..]).unwrap().unwrap();
+
+        let cookies = request.cookies();
+        assert_eq!(cookies.len(), 3);
+        assert_eq!(cookies["a"], "1");
+        assert_eq!(request.cookie("c").as_deref(), Some("3"));
+        assert_eq!(request.cookie("missing"), None);
+    }
+
+    #[test]
+    fn form_should_parse_urlencoded_bodies() {
+        let raw = b"POST /f HTTP/1.1\r\nContent-Type: application/x-www-form-urlencoded; charset=UTF-8\r\nContent-Length: 24\r\n\r\nuser=ada&city=S%C3%A3o+P";
+        let request = HttpRequest::parse(&mut &raw[..]).unwrap().unwrap();
+        let json = HttpRequest::parse(
+            &mut &b"POST /f HTTP/1.1\r\nContent-Type: application/json\r\n\r\n"[..],
+        )
+        .unwrap()
+        .unwrap();
+
+        let form = request.form().unwrap();
+        assert_eq!(form["user"], "ada");
+        assert_eq!(form["city"], "São P");
+        assert!(json.form().is_err());
+    }
+
+    #[test]
+    fn parse_should_read_pipelined_requests_in_order() {
+        let raw = b"POST /a HTTP/1.1\r\nContent-Length: 3\r\n\r\nabcGET /b HTTP/1.1\r\n\r\n";
+        let mut reader = &raw[..];
+
+        let first = HttpRequest::parse(&mut reader).unwrap().unwrap();
+        let second = HttpRequest::parse(&mut reader).unwrap().unwrap();
+
+        assert_eq!((first.path(), first.body()), ("/a", &b"abc"[..]));
+        assert_eq!(second.path(), "/b");
+        assert!(HttpRequest::parse(&mut reader).unwrap().is_none());
+    }
+
+    #[test]
+    fn parse_should_return_none_on_empty_input() {
+        let request = HttpRequest::parse(&mut &b""[..]).unwrap();
+
+        assert!(request.is_none());
+    }
+
+    #[test]
+    fn parse_should_reject_malformed_request_line() {
+        let request = HttpRequest::parse(&mut &b"GARBAGE\r\n\r\n"[..]);
+
+        assert!(request.is_err());
+    }
+
+    #[test]
+    fn parse_should_reject_garbage_and_non_utf8_input() {
+        let cases: [&[u8]; 6] = [
+            b"\r\n\r\n",
+            b"hello there friend\r\n\r\n",
+            b"GET / HTTP/1.1 extra\r\n\r\n",
+            b"G(T / HTTP/1.1\r\n\r\n",
+            b"GET / HTTP/1.1\r\nBad Header: x\r\n\r\n",
+            b"GET /\xff\xfe HTTP/1.1\r\n\r\n",
+        ];
+
+        for raw in cases {
+            assert!(HttpRequest::parse(&mut &raw[..]).is_err(), "{:?}", raw);
+        }
+    }
+}
@@ -0,0 +1,695 @@
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, IoSlice, Read, Write},
+    path::Path,
+};
+
+use crate::error;
+
+use super::{
+    cookie::Cookie,
+    date, encoding, mime,
+    request::HttpRequest,
+    takeover::{OnUpgrade, Upgraded},
+    template,
+    timing::Phases,
+    url,
+};
+
+/// Size of the chunks streamed file bodies are copied in.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// When the threaded backend writes responses out to the client.
+///
+/// Either way a response goes out in as few writes as possible: its head
+/// and an in-memory body in one vectored write, and a file body in
+/// `CHUNK_SIZE` chunks, the first sharing a write with the head. The
+/// evented backend always writes whatever the socket takes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FlushPolicy {
+    /// Every response is written as soon as it is ready.
+    #[default]
+    Response,
+    /// Responses to pipelined requests without a body are held while the
+    /// next request is already buffered, and written together with the
+    /// last one, or once they add up to `CHUNK_SIZE` bytes.
+    Pipeline,
+}
+
+impl FlushPolicy {
+    pub fn parse(name: &str) -> Option<FlushPolicy> {
+        match name.to_ascii_lowercase().as_str() {
+            "response" => Some(FlushPolicy::Response),
+            "pipeline" => Some(FlushPolicy::Pipeline),
+            _ => None,
+        }
+    }
+
+    /// How many bytes of held responses `Pipeline` writes out at.
+    pub(crate) const HELD_BYTES: usize = CHUNK_SIZE;
+}
+
+/// An HTTP response ready to be written to a client.
+#[derive(Debug)]
+pub struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Body,
+    /// Set by the `Timing` middleware, for the server to log.
+    phases: Option<Phases>,
+    /// One of the server's error pages, which `negotiated` may turn into
+    /// JSON.
+    error_page: bool,
+}
+
+#[derive(Debug)]
+enum Body {
+    Bytes(Vec<u8>),
+    /// The first `len` bytes of an open file, copied to the client in
+    /// `CHUNK_SIZE` chunks when the response is written.
+    File {
+        file: File,
+        len: u64,
+    },
+    /// No body; the connection is taken over once the head is written.
+    Upgrade(OnUpgrade),
+    /// The answer to a `HEAD` request: no body, but framed like the `GET`
+    /// answer's body of this many bytes.
+    Omitted(u64),
+}
+
+impl Response {
+    /// Creates an empty response with the given status code.
+    pub fn new(status: u16) -> Response {
+        Response {
+            status,
+            headers: Vec::new(),
+            body: Body::Bytes(Vec::new()),
+            phases: None,
+            error_page: false,
+        }
+    }
+
+    /// Creates a response with an HTML body.
+    pub fn html(status: u16, body: impl Into<Vec<u8>>) -> Response {
+        Response::new(status)
+            .header("Content-Type", "text/html; charset=utf-8")
+            .body(body)
+    }
+
+    /// The server's own page for the error `status`. Clients whose
+    /// `Accept` header prefers JSON get it as an `application/problem+json`
+    /// document (RFC 9457) instead.
+    pub fn error_page(status: u16) -> Response {
+        Response::html(status, format!("<h1>{}</h1>", title(status))).marked_as_error_page()
+    }
+
+    /// Marks an HTML page, such as `notFound.html`, as the server's page
+    /// for its error status.
+    pub(crate) fn marked_as_error_page(mut self) -> Response {
+        self.error_page = true;
+        self
+    }
+
+    /// Turns an error page into a problem document if `request` prefers
+    /// JSON to HTML. Other responses are returned unchanged.
+    pub(crate) fn negotiated(mut self, request: &HttpRequest) -> Response {
+        if !self.error_page || !prefers_json(request.header("Accept")) {
+            return self;
+        }
+        let problem = format!(
+            "{{\"type\":\"about:blank\",\"title\":\"{}\",\"status\":{}}}",
+            title(self.status),
+            self.status
+        );
+        self.headers
+            .retain(|(name, _)| !name.eq_ignore_ascii_case("Content-Type"));
+        self.header("Content-Type", "application/problem+json")
+            .body(problem)
+    }
+
+    /// Creates a response with a JSON body.
+    pub fn json(status: u16, body: impl Into<Vec<u8>>) -> Response {
+        Response::new(status)
+            .header("Content-Type", "application/json")
+            .body(body)
+    }
+
+    /// A `302 Found` redirect to `location`.
+    ///
+    /// Characters not allowed in a URI, such as spaces or line breaks, are
+    /// percent-encoded, so untrusted input cannot inject headers.
+    pub fn redirect(location: &str) -> Response {
+        Response::redirect_with(302, location)
+    }
+
+    /// A `301 Moved Permanently` redirect to `location`, which clients and
+    /// caches may remember.
+    pub fn redirect_permanent(location: &str) -> Response {
+        Response::redirect_with(301, location)
+    }
+
+    /// A redirect with any 3xx `status`, e.g. `307` or `308` to keep the
+    /// request method.
+    pub fn redirect_with(status: u16, location: &str) -> Response {
+        let location = url::encode_location(location);
+        let body = format!(
+            "<h1>Redirecting</h1><p><a href=\"{0}\">{0}</a></p>",
+            template::escape_html(&location)
+        );
+        Response::html(status, body).header("Location", &location)
+    }
+
+    /// Renders the template at `template_path` with `context` into a
+    /// `200` HTML response.
+    ///
+    /// Answers `500` if the template cannot be read or rendered.
+    pub fn render(template_path: impl AsRef<Path>, context: &HashMap<&str, String>) -> Response {
+        let template_path = template_path.as_ref();
+        let rendered = fs::read_to_string(template_path)
+            .map_err(|err| err.to_string())
+            .and_then(|source| template::render(&source, context));
+
+        match rendered {
+            Ok(page) => Response::html(200, page),
+            Err(err) => {
+                error!(
+                    "Failed to render template {}: {}",
+                    template_path.display(),
+                    err
+                );
+                Response::error_page(500)
+            }
+        }
+    }
+
+    /// A `101 Switching Protocols` response agreeing to `protocol`, after
+    /// which `on_upgrade` owns the connection. It runs on the thread that
+    /// served the request, and the connection closes when it returns.
+    ///
+    /// Over HTTP/2, or for a request whose body the handler left unread,
+    /// the client gets `500` instead.
+    ///
+    /// ```
+    /// # use std::io::{Read, Write};
+    /// # use harbor::server::{Response, Router};
+    /// let router = Router::new().get("/echo", |_| {
+    ///     Response::switching_protocols("echo", |mut connection| {
+    ///         let mut buffer = [0; 1024];
+    ///         while let Ok(read @ 1..) = connection.read(&mut buffer) {
+    ///             if connection.write_all(&buffer[..read]).is_err() {
+    ///                 break;
+    ///             }
+    ///         }
+    ///     })
+    /// });
+    /// ```
+    pub fn switching_protocols(
+        protocol: &str,
+        on_upgrade: impl FnOnce(Upgraded) + Send + 'static,
+    ) -> Response {
+        let mut response = Response::new(101)
+            .header("Connection", "Upgrade")
+            .header("Upgrade", protocol);
+        response.body = Body::Upgrade(OnUpgrade::new(on_upgrade));
+        response
+    }
+
+    /// Whether the connection is handed over once this is written.
+    pub fn upgrades(&self) -> bool {
+        matches!(self.body, Body::Upgrade(_))
+    }
+
+    /// The answer to a `HEAD` request for the resource this answers `GET`
+    /// for: the same head, with the `Content-Length` of the body, but no
+    /// body.
+    pub(crate) fn without_body(mut self) -> Response {
+        if !self.upgrades() {
+            self.body = Body::Omitted(self.body_len());
+        }
+        self
+    }
+
+    pub(crate) fn take_upgrade(&mut self) -> Option<OnUpgrade> {
+        match std::mem::replace(&mut self.body, Body::Bytes(Vec::new())) {
+            Body::Upgrade(on_upgrade) => Some(on_upgrade),
+            body => {
+                self.body = body;
+                None
+            }
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Response {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Adds a `Set-Cookie` header for `cookie`. Call it once per cookie.
+    pub fn cookie(self, cookie: &Cookie) -> Response {
+        self.header("Set-Cookie", &cookie.to_string())
+    }
+
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Response {
+        self.body = Body::Bytes(body.into());
+        self
+    }
+
+    /// Sets the `Content-Type` header from the extension of `path`, using
+    /// the built-in `mime` table.
+    pub fn content_type_for(self, path: impl AsRef<Path>) -> Response {
+        self.header("Content-Type", mime::from_path(path))
+    }
+
+    /// Streams the contents of `file` as the body instead of holding them
+    /// in memory. The length is taken from the file's metadata now.
+    pub fn file(mut self, file: File) -> io::Result<Response> {
+        let len = file.metadata()?.len();
+        self.body = Body::File { file, len };
+        Ok(self)
+    }
+
+    pub(crate) fn with_phases(mut self, phases: Phases) -> Response {
+        self.phases = Some(phases);
+        self
+    }
+
+    pub(crate) fn phases(&self) -> Option<Phases> {
+        self.phases
+    }
+
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    /// Returns the value of the first header matching `name`, ignoring case.
+    pub fn get_header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The body held in memory; empty for streamed file bodies.
+    pub fn get_body(&self) -> &[u8] {
+        match &self.body {
+            Body::Bytes(bytes) => bytes,
+            Body::File { .. } | Body::Upgrade(_) | Body::Omitted(_) => &[],
+        }
+    }
+
+    /// The number of body bytes sent as `Content-Length`.
+    pub fn body_len(&self) -> u64 {
+        match &self.body {
+            Body::Bytes(bytes) => bytes.len() as u64,
+            Body::File { len, .. } | Body::Omitted(len) => *len,
+            Body::Upgrade(_) => 0,
+        }
+    }
+
+    /// Serializes the status line, headers and body into `writer`.
+    ///
+    /// The body is framed here: a `Content-Length` or `Transfer-Encoding`
+    /// set on the response is replaced by the length of its body. A `Date`
+    /// with the current time is added unless the response has one.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let head = self.head();
+        match &self.body {
+            Body::Bytes(bytes) => write_all_vectored(
+                writer,
+                &mut [IoSlice::new(head.as_bytes()), IoSlice::new(bytes)],
+            ),
+            Body::File { .. } => {
+                let mut buffered = BufWriter::with_capacity(CHUNK_SIZE, writer);
+                buffered.write_all(head.as_bytes())?;
+                self.write_body_to(&mut buffered)?;
+                buffered.flush()
+            }
+            Body::Upgrade(_) | Body::Omitted(_) => writer.write_all(head.as_bytes()),
+        }
+    }
+
+    /// The number of bytes `write_to` writes: the head and the body.
+    pub fn wire_len(&self) -> u64 {
+        let body = match self.body {
+            Body::Omitted(_) => 0,
+            _ => self.body_len(),
+        };
+        self.head().len() as u64 + body
+    }
+
+    /// The status line and headers, framed, ending with the empty line.
+    fn head(&self) -> String {
+        // Writing to a `String` cannot fail.
+        let mut head = String::with_capacity(256);
+        let _ = write!(head, "HTTP/1.1 {} {}\r\n", self.status, reason(self.status));
+        for (name, value) in &self.headers {
+            if is_framing(name) {
+                continue;
+            }
+            let _ = write!(head, "{}: {}\r\n", name, value);
+        }
+        if self.get_header("Date").is_none() {
+            let _ = write!(head, "Date: {}\r\n", date::http_date_now());
+        }
+        // 1xx, 204 and 304 responses never have a body to measure.
+        if !matches!(self.status, 100..=199 | 204 | 304) {
+            let _ = write!(head, "Content-Length: {}\r\n", self.body_len());
+        }
+        head.push_str("\r\n");
+        head
+    }
+
+    /// The headers in the order they were added.
+    #[cfg(feature = "http2")]
+    pub(crate) fn headers(&self) -> &[(String, String)] {
+        &self.headers
+    }
+
+    /// Writes only the body into `writer`, streaming file bodies.
+    pub(crate) fn write_body_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        match &self.body {
+            Body::Bytes(bytes) => writer.write_all(bytes),
+            Body::File { file, len } => {
+                let mut reader = BufReader::with_capacity(CHUNK_SIZE, file.take(*len));
+                let copied = io::copy(&mut reader, writer)?;
+                if copied < *len {
+                    // The file shrank; the promised length can't be met.
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        format!("file ended after {} of {} bytes", copied, len),
+                    ));
+                }
+                Ok(())
+            }
+            Body::Upgrade(_) | Body::Omitted(_) => Ok(()),
+        }
+    }
+}
+
+/// Writes all of `buffers` with as few `write_vectored` calls as the
+/// writer allows, like `write_all` for one buffer.
+fn write_all_vectored<W: Write>(writer: &mut W, mut buffers: &mut [IoSlice<'_>]) -> io::Result<()> {
+    IoSlice::advance_slices(&mut buffers, 0);
+    while !buffers.is_empty() {
+        match writer.write_vectored(buffers) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(written) => IoSlice::advance_slices(&mut buffers, written),
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+/// Whether the header `name` frames the body, which only the writer knows
+/// how to do.
+pub(crate) fn is_framing(name: &str) -> bool {
+    name.eq_ignore_ascii_case("Content-Length") || name.eq_ignore_ascii_case("Transfer-Encoding")
+}
+
+/// Whether an `Accept` header ranks JSON above HTML. Without one, or
+/// with `*/*` alone, HTML wins.
+fn prefers_json(accept: Option<&str>) -> bool {
+    let Some(accept) = accept else {
+        return false;
+    };
+    // Media ranges are weighted like content codings; the most specific
+    // range naming a type decides its quality.
+    let ranges = encoding::parse(accept);
+    let quality = |types: &[&str]| {
+        types
+            .iter()
+            .find_map(|name| ranges.iter().find(|(range, _)| range == name))
+            .map_or(0, |(_, quality)| *quality)
+    };
+    let json = quality(&[
+        "application/problem+json",
+        "application/json",
+        "application/*",
+        "*/*",
+    ]);
+    let html = quality(&["text/html", "text/*", "*/*"]);
+    json > html
+}
+
+/// The title of an error page. The status line keeps the historical
+/// `NOT FOUND`.
+fn title(status: u16) -> &'static str {
+    match status {
+        404 => "Not Found",
+        status => reason(status),
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        100 => "Continue",
+        101 => "Switching Protocols",
+        200 => "OK",
+        202 => "Accepted",
+        204 => "No Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        304 => "Not Modified",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "NOT FOUND",
+        405 => "Method Not Allowed",
+        406 => "Not Acceptable",
+        408 => "Request Timeout",
+        413 => "Content Too Large",
+        417 => "Expectation Failed",
+        421 => "Misdirected Request",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        505 => "HTTP Version Not Supported",
+        _ => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The serialized response, without the `Date` header added to it.
+    fn without_date(buffer: Vec<u8>) -> String {
+        let written = String::from_utf8(buffer).unwrap();
+        let start = written.find("Date: ").expect("no Date header");
+        let end = start + written[start..].find("\r\n").unwrap() + 2;
+        format!("{}{}", &written[..start], &written[end..])
+    }
+
+    #[test]
+    fn negotiated_should_turn_error_pages_into_json_for_json_clients() {
+        let request = |accept: &str| {
+            let raw = format!("GET / HTTP/1.1\r\nAccept: {}\r\n\r\n", accept);
+            HttpRequest::parse(&mut raw.as_bytes()).unwrap().unwrap()
+        };
+        let page = || Response::error_page(405).header("Allow", "GET");
+
+        let json = page().negotiated(&request("application/json"));
+        assert_eq!(
+            json.get_header("Content-Type"),
+            Some("application/problem+json")
+        );
+        assert_eq!(json.get_header("Allow"), Some("GET"));
+        assert_eq!(
+            json.get_body(),
+            br#"{"type":"about:blank","title":"Method Not Allowed","status":405}"#
+        );
+
+        for accept in [
+            "text/html,application/xhtml+xml,*/*;q=0.8",
+            "*/*",
+            "application/json;q=0.1, text/*",
+        ] {
+            let html = page().negotiated(&request(accept));
+            assert_eq!(
+                html.get_body(),
+                b"<h1>Method Not Allowed</h1>",
+                "{}",
+                accept
+            );
+        }
+        let handler_page = Response::html(404, "<h1>Gone</h1>");
+        assert_eq!(
+            handler_page
+                .negotiated(&request("application/json"))
+                .get_body(),
+            b"<h1>Gone</h1>"
+        );
+    }
+
+    #[test]
+    fn render_should_fill_template_file() {
+        let path =
+            std::env::temp_dir().join(format!("harbor-template-{}.html", std::process::id()));
+        fs::write(&path, "<h1>{{title}}</h1>").unwrap();
+
+        let response = Response::render(
+            &path,
+            &HashMap::from([("title", "Jobs & more".to_string())]),
+        );
+        let missing = Response::render(path.with_extension("missing"), &HashMap::new());
+
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.get_body(), b"<h1>Jobs &amp; more</h1>");
+        assert_eq!(missing.status(), 500);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn write_to_should_serialize_status_headers_and_body() {
+        let response = Response::json(200, "{}");
+        let mut buffer = Vec::new();
+
+        response.write_to(&mut buffer).unwrap();
+
+        assert_eq!(
+            without_date(buffer),
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 2\r\n\r\n{}"
+        );
+    }
+
+    #[test]
+    fn write_to_should_frame_the_body_and_add_a_date() {
+        let response = Response::new(200)
+            .header("Content-Length", "99")
+            .header("transfer-encoding", "chunked")
+            .body("hello");
+        let dated = Response::new(204).header("Date", "Sun, 06 Nov 1994 08:49:37 GMT");
+        let (mut buffer, mut dated_buffer) = (Vec::new(), Vec::new());
+
+        response.write_to(&mut buffer).unwrap();
+        dated.write_to(&mut dated_buffer).unwrap();
+        assert_eq!(response.wire_len(), buffer.len() as u64);
+        assert_eq!(dated.wire_len(), dated_buffer.len() as u64);
+
+        let head = String::from_utf8(buffer).unwrap();
+        let date = head.lines().find_map(|line| line.strip_prefix("Date: "));
+        assert!(date.and_then(date::parse_http_date).is_some(), "{}", head);
+        assert_eq!(
+            without_date(head.into_bytes()),
+            "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello"
+        );
+        assert_eq!(
+            String::from_utf8(dated_buffer).unwrap(),
+            "HTTP/1.1 204 No Content\r\nDate: Sun, 06 Nov 1994 08:49:37 GMT\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn cookie_should_add_one_set_cookie_header_each() {
+        let response = Response::new(200)
+            .cookie(&Cookie::new("a", "1").unwrap())
+            .cookie(&Cookie::removal("b").unwrap());
+        let mut buffer = Vec::new();
+
+        response.write_to(&mut buffer).unwrap();
+
+        assert_eq!(
+            without_date(buffer),
+            "HTTP/1.1 200 OK\r\nSet-Cookie: a=1\r\nSet-Cookie: b=; Max-Age=0\r\nContent-Length: 0\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn redirect_should_set_an_encoded_location() {
+        let found = Response::redirect("/new?x=<1>");
+        let moved = Response::redirect_permanent("/a\r\nSet-Cookie: evil=1");
+
+        assert_eq!(found.status(), 302);
+        assert_eq!(found.get_header("Location"), Some("/new?x=%3C1%3E"));
+        assert!(String::from_utf8_lossy(found.get_body()).contains("href=\"/new?x=%3C1%3E\""));
+        assert_eq!(moved.status(), 301);
+        assert_eq!(
+            moved.get_header("Location"),
+            Some("/a%0D%0ASet-Cookie:%20evil=1")
+        );
+    }
+
+    #[test]
+    fn write_to_should_send_the_head_with_the_body() {
+        /// Counts the calls a response is written with.
+        #[derive(Default)]
+        struct Writes {
+            calls: usize,
+            written: Vec<u8>,
+        }
+
+        impl Write for Writes {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.write_vectored(&[IoSlice::new(buf)])
+            }
+
+            fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+                self.calls += 1;
+                let before = self.written.len();
+                for buf in bufs {
+                    self.written.extend_from_slice(buf);
+                }
+                Ok(self.written.len() - before)
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let path = std::env::temp_dir().join(format!("harbor-vectored-{}.txt", std::process::id()));
+        fs::write(&path, "from a file").unwrap();
+        let (mut bytes, mut file) = (Writes::default(), Writes::default());
+
+        Response::new(200)
+            .body("hello")
+            .write_to(&mut bytes)
+            .unwrap();
+        Response::new(200)
+            .file(File::open(&path).unwrap())
+            .unwrap()
+            .write_to(&mut file)
+            .unwrap();
+
+        assert_eq!(bytes.calls, 1);
+        assert!(bytes.written.ends_with(b"\r\n\r\nhello"));
+        assert_eq!(file.calls, 1);
+        assert!(file.written.ends_with(b"\r\n\r\nfrom a file"));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn write_to_should_stream_file_bodies() {
+        let path = std::env::temp_dir().join(format!("harbor-stream-{}.bin", std::process::id()));
+        let contents: Vec<u8> = (0..CHUNK_SIZE * 2 + 7).map(|i| (i % 251) as u8).collect();
+        fs::write(&path, &contents).unwrap();
+        let response = Response::new(200).file(File::open(&path).unwrap()).unwrap();
+        let mut buffer = Vec::new();
+
+        response.write_to(&mut buffer).unwrap();
+
+        let head = format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n",
+            contents.len()
+        );
+        let head_len = buffer
+            .windows(4)
+            .position(|end| end == b"\r\n\r\n")
+            .unwrap()
+            + 4;
+        assert_eq!(response.body_len(), contents.len() as u64);
+        assert!(response.get_body().is_empty());
+        assert_eq!(without_date(buffer[..head_len].to_vec()), head);
+        assert_eq!(&buffer[head_len..], &contents[..]);
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,176 @@
+//! Rewriting request paths before they are routed.
+//!
+//! Unlike a redirect, a rewrite is invisible to the client: the request is
+//! answered as if it had been sent for the new path.
+
+use super::{request::HttpRequest, url};
+use crate::warn;
+
+/// The marker in `from` that captures any run of characters.
+const CAPTURE: &str = "(.*)";
+
+/// Serves requests whose path matches `from` as if they were for `to`.
+///
+/// `from` is a path in which each `(.*)` captures any run of characters,
+/// `/old/(.*)`, or an exact path. `to` refers to the captures as `$1` to
+/// `$9` and may carry its own query string; otherwise the request's query
+/// is kept.
+///
+/// A fallback rule only applies to requests that no route, redirect or
+/// static file answers, so `/app/(.*)` to `/app/index.html` serves a
+/// single-page app's client-side routes without hiding its assets.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rewrite {
+    from: String,
+    to: String,
+    fallback: bool,
+}
+
+impl Rewrite {
+    pub fn new(from: &str, to: &str) -> Result<Rewrite, String> {
+        if !from.starts_with('/') {
+            return Err(format!("{:?} is not a path starting with '/'", from));
+        }
+        let groups = from.matches(CAPTURE).count();
+        for reference in to.split('$').skip(1) {
+            match reference
+                .chars()
+                .next()
+                .and_then(|digit| digit.to_digit(10))
+            {
+                Some(group) if (1..=groups as u32).contains(&group) => {}
+                _ => {
+                    return Err(format!(
+                        "{:?} refers to a capture that {:?} doesn't have",
+                        to, from
+                    ));
+                }
+            }
+        }
+        Ok(Rewrite {
+            from: from.to_string(),
+            to: to.to_string(),
+            fallback: false,
+        })
+    }
+
+    /// Applies the rule only to requests nothing else answers.
+    pub fn fallback(mut self) -> Rewrite {
+        self.fallback = true;
+        self
+    }
+
+    pub fn is_fallback(&self) -> bool {
+        self.fallback
+    }
+
+    /// The target `path` is rewritten to, or `None` if the rule doesn't
+    /// match it. Captured text is percent-encoded again.
+    pub fn apply(&self, path: &str) -> Option<String> {
+        let parts: Vec<&str> = self.from.split(CAPTURE).collect();
+        let mut captures = Vec::new();
+        if !capture(&parts, path, &mut captures) {
+            return None;
+        }
+
+        let mut pieces = self.to.split('$');
+        let mut target = pieces.next().unwrap_or_default().to_string();
+        for piece in pieces {
+            // `new` checked that every `$` is followed by a capture's number.
+            let group = piece.as_bytes()[0] - b'1';
+            target.push_str(&url::encode_path(captures[usize::from(group)]));
+            target.push_str(&piece[1..]);
+        }
+        Some(target)
+    }
+}
+
+/// Matches `text` against the literal `parts` with a capture between each
+/// two, collecting the captures. Earlier captures take as much as they can.
+fn capture<'a>(parts: &[&str], text: &'a str, captures: &mut Vec<&'a str>) -> bool {
+    let Some(rest) = text.strip_prefix(parts[0]) else {
+        return false;
+    };
+    if parts.len() == 1 {
+        return rest.is_empty();
+    }
+    for end in (0..=rest.len())
+        .rev()
+        .filter(|&end| rest.is_char_boundary(end))
+    {
+        captures.push(&rest[..end]);
+        if capture(&parts[1..], &rest[end..], captures) {
+            return true;
+        }
+        captures.pop();
+    }
+    false
+}
+
+/// `request` as rewritten by the first of `rules` that matches it, among
+/// the fallback rules or the others.
+pub(crate) fn rewrite(
+    rules: &[Rewrite],
+    request: &HttpRequest,
+    fallback: bool,
+) -> Option<HttpRequest> {
+    let target = rules
+        .iter()
+        .filter(|rule| rule.fallback == fallback)
+        .find_map(|rule| rule.apply(request.path()))?;
+    match request.with_target(&target) {
+        Ok(rewritten) => Some(rewritten),
+        Err(err) => {
+            warn!("Rewrite of {} failed: {}", request.path(), err);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn apply_should_substitute_captures() {
+        let moved = Rewrite::new("/old/(.*)", "/new/$1").unwrap();
+        let swapped = Rewrite::new("/(.*)/(.*).html", "/pages/$2?section=$1").unwrap();
+        let exact = Rewrite::new("/about", "/about.html").unwrap();
+
+        assert_eq!(moved.apply("/old/a/b").as_deref(), Some("/new/a/b"));
+        assert_eq!(moved.apply("/old/a b").as_deref(), Some("/new/a%20b"));
+        assert_eq!(moved.apply("/older"), None);
+        assert_eq!(
+            swapped.apply("/docs/intro.html").as_deref(),
+            Some("/pages/intro?section=docs")
+        );
+        assert_eq!(exact.apply("/about").as_deref(), Some("/about.html"));
+        assert_eq!(exact.apply("/about/"), None);
+    }
+
+    #[test]
+    fn new_should_reject_invalid_rules() {
+        assert!(Rewrite::new("old/(.*)", "/new/$1").is_err());
+        assert!(Rewrite::new("/old/(.*)", "/new/$2").is_err());
+        assert!(Rewrite::new("/old", "/new/$").is_err());
+    }
+
+    #[test]
+    fn rewrite_should_keep_the_query_unless_replaced() {
+        let request = HttpRequest::parse(&mut "GET /old/x?page=2 HTTP/1.1\r\n\r\n".as_bytes())
+            .unwrap()
+            .unwrap();
+        let rules = [
+            Rewrite::new("/old/(.*)", "/new/$1").unwrap(),
+            Rewrite::new("/(.*)", "/index.html").unwrap().fallback(),
+        ];
+
+        let rewritten = rewrite(&rules, &request, false).unwrap();
+        let fallback = rewrite(&rules, &request, true).unwrap();
+
+        assert_eq!(rewritten.path(), "/new/x");
+        assert_eq!(rewritten.query(), Some("page=2"));
+        assert_eq!(rewritten.target(), "/new/x?page=2");
+        assert_eq!(fallback.path(), "/index.html");
+    }
+}
@@ -0,0 +1,1042 @@
+//! Registration of user-defined request handlers.
+
+use std::{cell::RefCell, collections::HashMap, mem, sync::Arc, time::Duration};
+
+use super::{
+    body::{Body, BodyHandler},
+    handler::{Handler, Stateful},
+    middleware::{Middleware, MiddlewareStack},
+    request::HttpRequest,
+    response::Response,
+};
+
+/// What answers one method of a route.
+enum Endpoint {
+    Handler(Box<dyn Handler>),
+    /// A handler reading the body itself, of at most `limit` bytes.
+    Body {
+        limit: u64,
+        handler: Box<dyn BodyHandler>,
+    },
+    /// An endpoint from a mounted router, run inside that router's
+    /// middleware.
+    Scoped {
+        middleware: Arc<MiddlewareStack>,
+        endpoint: Box<Endpoint>,
+    },
+    /// An endpoint answered on the named worker pool.
+    Pooled {
+        pool: String,
+        endpoint: Box<Endpoint>,
+    },
+    /// An endpoint answered `504` once it runs longer than `timeout`.
+    Timed {
+        timeout: Duration,
+        endpoint: Box<Endpoint>,
+    },
+}
+
+impl Endpoint {
+    fn call(&self, request: &HttpRequest, body: &mut Body<'_>) -> Response {
+        match self {
+            Endpoint::Handler(handler) => handler.handle(request),
+            Endpoint::Body { handler, .. } => handler.handle(request, body),
+            Endpoint::Scoped {
+                middleware,
+                endpoint,
+            } => {
+                let mut request = request.clone();
+                let body = RefCell::new(body);
+                middleware.run(&mut request, &|request| {
+                    endpoint.call(request, &mut body.borrow_mut())
+                })
+            }
+            Endpoint::Pooled { endpoint, .. } | Endpoint::Timed { endpoint, .. } => {
+                endpoint.call(request, body)
+            }
+        }
+    }
+
+    fn body_limit(&self) -> Option<u64> {
+        match self {
+            Endpoint::Handler(_) => None,
+            Endpoint::Body { limit, .. } => Some(*limit),
+            Endpoint::Scoped { endpoint, .. }
+            | Endpoint::Pooled { endpoint, .. }
+            | Endpoint::Timed { endpoint, .. } => endpoint.body_limit(),
+        }
+    }
+
+    /// The worker pool the endpoint is answered on; the innermost wins.
+    fn pool(&self) -> Option<&str> {
+        match self {
+            Endpoint::Handler(_) | Endpoint::Body { .. } => None,
+            Endpoint::Scoped { endpoint, .. } | Endpoint::Timed { endpoint, .. } => endpoint.pool(),
+            Endpoint::Pooled { pool, endpoint } => endpoint.pool().or(Some(pool)),
+        }
+    }
+
+    /// How long the endpoint may run; the innermost wins.
+    fn timeout(&self) -> Option<Duration> {
+        match self {
+            Endpoint::Handler(_) | Endpoint::Body { .. } => None,
+            Endpoint::Scoped { endpoint, .. } | Endpoint::Pooled { endpoint, .. } => {
+                endpoint.timeout()
+            }
+            Endpoint::Timed { timeout, endpoint } => endpoint.timeout().or(Some(*timeout)),
+        }
+    }
+}
+
+/// The handlers registered for one path, by method.
+struct Route {
+    path: String,
+    /// The names of the path's `{name}` segments, in order.
+    params: Vec<String>,
+    /// For paths ending in a `*name` segment, the name the rest of the
+    /// request path is captured as.
+    wildcard: Option<String>,
+    handlers: Vec<(String, Endpoint)>,
+}
+
+impl Route {
+    fn new(path: &str) -> Route {
+        let mut params = Vec::new();
+        let mut wildcard = None;
+        if let Some(rest) = path.strip_prefix('/') {
+            let mut segments = rest.split('/').peekable();
+            while let Some(segment) = segments.next() {
+                let last = segments.peek().is_none();
+                match segment_kind(segment, last) {
+                    Segment::Param(name) => params.push(name.to_string()),
+                    Segment::Wildcard(name) => wildcard = Some(name.to_string()),
+                    Segment::Static => {}
+                }
+            }
+        }
+        Route {
+            path: path.to_string(),
+            params,
+            wildcard,
+            handlers: Vec::new(),
+        }
+    }
+
+    /// The endpoint answering `method`. `HEAD` falls back to `GET`, whose
+    /// response the server sends without its body.
+    fn endpoint(&self, method: &str) -> Option<&Endpoint> {
+        let find = |wanted: &str| {
+            self.handlers
+                .iter()
+                .find(|(method, _)| method == wanted)
+                .map(|(_, endpoint)| endpoint)
+        };
+        find(method).or_else(|| if method == "HEAD" { find("GET") } else { None })
+    }
+}
+
+/// What one segment of a registered path matches.
+enum Segment<'a> {
+    /// Itself.
+    Static,
+    /// `{name}`: any one non-empty segment.
+    Param(&'a str),
+    /// `*name` as the last segment: the rest of the path.
+    Wildcard(&'a str),
+}
+
+fn segment_kind(segment: &str, last: bool) -> Segment<'_> {
+    if let Some(name) = segment.strip_prefix('*')
+        && last
+    {
+        return Segment::Wildcard(name);
+    }
+    match segment
+        .strip_prefix('{')
+        .and_then(|name| name.strip_suffix('}'))
+    {
+        Some(name) if !name.is_empty() => Segment::Param(name),
+        _ => Segment::Static,
+    }
+}
+
+/// The routes below one segment of a path, as indices into
+/// `Router::routes`. Looking a path up walks one node per segment, however
+/// many routes there are.
+#[derive(Default)]
+struct Node {
+    /// The route whose path ends here.
+    route: Option<usize>,
+    children: HashMap<String, Node>,
+    /// The paths with a `{name}` segment here.
+    param: Option<Box<Node>>,
+    /// The route whose path ends in a `*name` segment here.
+    wildcard: Option<usize>,
+}
+
+impl Node {
+    /// The slot for the route with the path `rest`, below this node.
+    fn slot(&mut self, rest: &str) -> &mut Option<usize> {
+        let (segment, next) = match rest.split_once('/') {
+            Some((segment, next)) => (segment, Some(next)),
+            None => (rest, None),
+        };
+        let child = match segment_kind(segment, next.is_none()) {
+            Segment::Wildcard(_) => return &mut self.wildcard,
+            Segment::Param(_) => self.param.get_or_insert_with(Box::default),
+            Segment::Static => self.children.entry(segment.to_string()).or_default(),
+        };
+        match next {
+            Some(next) => child.slot(next),
+            None => &mut child.route,
+        }
+    }
+
+    /// The route matching the path `rest` below this node, and the part of
+    /// it a wildcard matched. Static segments are tried before `{name}`
+    /// ones, and those before a wildcard here, so exact paths win and
+    /// longer wildcard prefixes beat shorter ones. `values` collects what
+    /// the `{name}` segments matched.
+    fn find<'p>(
+        &self,
+        rest: &'p str,
+        values: &mut Vec<&'p str>,
+    ) -> Option<(usize, Option<&'p str>)> {
+        let (segment, next) = match rest.split_once('/') {
+            Some((segment, next)) => (segment, Some(next)),
+            None => (rest, None),
+        };
+        if let Some(child) = self.children.get(segment)
+            && let Some(found) = child.descend(next, values)
+        {
+            return Some(found);
+        }
+        if let Some(param) = &self.param
+            && !segment.is_empty()
+        {
+            values.push(segment);
+            if let Some(found) = param.descend(next, values) {
+                return Some(found);
+            }
+            values.pop();
+        }
+        self.wildcard.map(|route| (route, Some(rest)))
+    }
+
+    fn descend<'p>(
+        &self,
+        next: Option<&'p str>,
+        values: &mut Vec<&'p str>,
+    ) -> Option<(usize, Option<&'p str>)> {
+        match next {
+            Some(next) => self.find(next, values),
+            None => self.route.map(|route| (route, None)),
+        }
+    }
+}
+
+/// Maps a method and a path to the handler that answers it.
+///
+/// Paths match exactly, except for two kinds of segments. A `{name}`
+/// segment matches any one non-empty segment: `/users/{id}` answers
+/// `/users/42` with `request.param("id")` set to `42`. A last segment of
+/// `*name` matches everything below the rest of the path: `/static/*file`
+/// answers `/static/css/site.css` with `request.param("file")` set to
+/// `css/site.css`. A bare `*` matches without capturing. Static segments
+/// win over `{name}` ones, exact paths over wildcards, and longer wildcard
+/// prefixes over shorter ones.
+///
+/// Routes are kept in a tree of path segments, so finding the one for a
+/// request takes as long as its path is, not as many routes as there are.
+///
+/// Only the methods a route means need registering. `HEAD` requests for a
+/// path without a `HEAD` handler run its `GET` handler, and get its
+/// headers and `Content-Length` without the body. Requests for a
+/// registered path with a method it has no handler for are answered with
+/// `405 Method Not Allowed` and an `Allow` header. `OPTIONS` requests get
+/// the same list in a `204 No Content`, unless the path has an `OPTIONS`
+/// handler of its own; either way they pass through the router's
+/// middleware, which can add CORS headers to preflights.
+///
+/// # Example
+///
+/// ```
+/// # use harbor::server::{Response, Router};
+/// let router = Router::new()
+///     .get("/health", |_| Response::json(200, "{\"ok\":true}"))
+///     .post("/echo", |request| Response::new(200).body(request.body().to_vec()));
+/// ```
+///
+/// Routers compose: `mount` adds another router's routes under a prefix,
+/// and `middleware` wraps all of a router's routes, so a group of routes
+/// can share it.
+///
+/// ```
+/// # use harbor::server::{HttpRequest, Response, Router, middleware::Next};
+/// let admin = Router::new()
+///     .get("/", |_| Response::html(200, "<h1>Admin</h1>"))
+///     .get("/users", |_| Response::json(200, "[]"))
+///     .middleware(|request: &mut HttpRequest, next: Next| {
+///         match request.header("Authorization") {
+///             Some(_) => next.run(request),
+///             None => Response::new(401),
+///         }
+///     });
+/// let router = Router::new()
+///     .get("/health", |_| Response::json(200, "{\"ok\":true}"))
+///     .mount("/admin", admin);
+/// ```
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+    /// The routes with paths starting with `/`, by segment.
+    tree: Node,
+    /// The others, which only match exactly.
+    unrooted: HashMap<String, usize>,
+    middleware: MiddlewareStack,
+}
+
+impl Router {
+    pub fn new() -> Router {
+        Router::default()
+    }
+
+    /// Registers `handler` for requests with the given method and path.
+    pub fn route<F>(self, method: &str, path: &str, handler: F) -> Router
+    where
+        F: Fn(&HttpRequest) -> Response + Send + Sync + 'static,
+    {
+        self.handler(method, path, handler)
+    }
+
+    /// Registers a `Handler`, such as a struct holding shared state, for
+    /// requests with the given method and path.
+    pub fn handler(mut self, method: &str, path: &str, handler: impl Handler + 'static) -> Router {
+        self.insert(method, path, Endpoint::Handler(Box::new(handler)));
+        self
+    }
+
+    /// Registers `handler` for requests with the given method and path,
+    /// passing it the state of type `T` that the server was given with
+    /// `Server::with_state`. Without one the request is answered with
+    /// `500`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use std::sync::{Arc, atomic::{AtomicUsize, Ordering}};
+    /// # use harbor::server::{HttpRequest, Response, Router, Server};
+    /// struct App {
+    ///     visits: AtomicUsize,
+    /// }
+    ///
+    /// let router = Router::new().stateful("GET", "/visits", |_: &HttpRequest, app: &App| {
+    ///     let visits = app.visits.fetch_add(1, Ordering::Relaxed) + 1;
+    ///     Response::new(200).body(visits.to_string())
+    /// });
+    /// let server = Server::new("127.0.0.1:7878")
+    ///     .router(router)
+    ///     .with_state(Arc::new(App { visits: AtomicUsize::new(0) }));
+    /// ```
+    pub fn stateful<T, F>(self, method: &str, path: &str, handler: F) -> Router
+    where
+        T: Send + Sync + 'static,
+        F: Fn(&HttpRequest, &T) -> Response + Send + Sync + 'static,
+    {
+        self.handler(method, path, Stateful::new(handler))
+    }
+
+    /// Registers a handler that reads the request body itself, as it
+    /// arrives, instead of after the server has buffered it. Bodies over
+    /// `max_bytes` are refused with `413` before the handler runs; the
+    /// server's `max_body_bytes` does not apply.
+    pub fn stream<F>(mut self, method: &str, path: &str, max_bytes: u64, handler: F) -> Router
+    where
+        F: Fn(&HttpRequest, &mut Body<'_>) -> Response + Send + Sync + 'static,
+    {
+        let endpoint = Endpoint::Body {
+            limit: max_bytes,
+            handler: Box::new(handler),
+        };
+        self.insert(method, path, endpoint);
+        self
+    }
+
+    fn insert(&mut self, method: &str, path: &str, endpoint: Endpoint) {
+        let next = self.routes.len();
+        let index = match path.strip_prefix('/') {
+            Some(rest) => *self.tree.slot(rest).get_or_insert(next),
+            None => *self.unrooted.entry(path.to_string()).or_insert(next),
+        };
+        if index == next {
+            self.routes.push(Route::new(path));
+        }
+        self.routes[index]
+            .handlers
+            .push((method.to_string(), endpoint));
+    }
+
+    pub fn get<F>(self, path: &str, handler: F) -> Router
+    where
+        F: Fn(&HttpRequest) -> Response + Send + Sync + 'static,
+    {
+        self.route("GET", path, handler)
+    }
+
+    pub fn post<F>(self, path: &str, handler: F) -> Router
+    where
+        F: Fn(&HttpRequest) -> Response + Send + Sync + 'static,
+    {
+        self.route("POST", path, handler)
+    }
+
+    /// Runs `middleware` around every route of this router, including
+    /// those registered later. Added middleware runs outermost first.
+    pub fn middleware(mut self, middleware: impl Middleware + 'static) -> Router {
+        self.middleware.push(middleware);
+        self
+    }
+
+    /// Adds the routes of `router` under `prefix`, so `/jobs` mounted at
+    /// `/api` answers `/api/jobs` and its `/` answers `/api`. The mounted
+    /// routes keep running inside `router`'s middleware.
+    pub fn mount(mut self, prefix: &str, router: Router) -> Router {
+        let prefix = prefix.trim_end_matches('/');
+        let middleware = (!router.middleware.is_empty()).then(|| Arc::new(router.middleware));
+        for route in router.routes {
+            let path = match route.path.as_str() {
+                "/" if !prefix.is_empty() => prefix.to_string(),
+                path => format!("{}{}", prefix, path),
+            };
+            for (method, endpoint) in route.handlers {
+                let endpoint = match &middleware {
+                    Some(middleware) => Endpoint::Scoped {
+                        middleware: Arc::clone(middleware),
+                        endpoint: Box::new(endpoint),
+                    },
+                    None => endpoint,
+                };
+                self.insert(&method, &path, endpoint);
+            }
+        }
+        self
+    }
+
+    /// Mounts the routes built by `group` on a fresh router under `prefix`.
+    pub fn group(self, prefix: &str, group: impl FnOnce(Router) -> Router) -> Router {
+        self.mount(prefix, group(Router::new()))
+    }
+
+    /// Answers the routes built by `routes` on the worker pool `name`,
+    /// added with `Server::pool`, instead of the default one. Slow
+    /// endpoints on a pool of their own can't take every worker away from
+    /// the fast ones. Routes pinned to a pool the server doesn't have are
+    /// answered on the default pool.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use harbor::server::{Response, Router, Server};
+    /// let router = Router::new()
+    ///     .get("/health", |_| Response::new(200))
+    ///     .pool("slow", |slow| slow.get("/report", |_| Response::new(200).body("done")));
+    /// let server = Server::new("127.0.0.1:7878").router(router).pool("slow", 2);
+    /// ```
+    pub fn pool(self, name: &str, routes: impl FnOnce(Router) -> Router) -> Router {
+        let mut router = routes(Router::new());
+        for route in &mut router.routes {
+            route.handlers = mem::take(&mut route.handlers)
+                .into_iter()
+                .map(|(method, endpoint)| {
+                    let endpoint = Endpoint::Pooled {
+                        pool: name.to_string(),
+                        endpoint: Box::new(endpoint),
+                    };
+                    (method, endpoint)
+                })
+                .collect();
+        }
+        self.mount("", router)
+    }
+
+    /// Answers the routes `routes` adds with `504 Gateway Timeout` when
+    /// their handler runs longer than `timeout`, in place of the server's
+    /// `handler_timeout`. Streaming routes among them are not timed, as
+    /// their body is read on the connection's worker.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use std::time::Duration;
+    /// # use harbor::server::{Response, Router};
+    /// let router = Router::new().timeout(Duration::from_secs(2), |quick| {
+    ///     quick.get("/search", |_| Response::new(200).body("results"))
+    /// });
+    /// ```
+    pub fn timeout(self, timeout: Duration, routes: impl FnOnce(Router) -> Router) -> Router {
+        let mut router = routes(Router::new());
+        for route in &mut router.routes {
+            route.handlers = mem::take(&mut route.handlers)
+                .into_iter()
+                .map(|(method, endpoint)| {
+                    let endpoint = Endpoint::Timed {
+                        timeout,
+                        endpoint: Box::new(endpoint),
+                    };
+                    (method, endpoint)
+                })
+                .collect();
+        }
+        self.mount("", router)
+    }
+
+    /// Redirects `GET` and `HEAD` requests for `from` to `to` with
+    /// `302 Found`.
+    pub fn redirect(self, from: &str, to: &str) -> Router {
+        self.redirect_with(302, from, to)
+    }
+
+    /// Redirects `GET` and `HEAD` requests for `from` to `to` with
+    /// `301 Moved Permanently`.
+    pub fn redirect_permanent(self, from: &str, to: &str) -> Router {
+        self.redirect_with(301, from, to)
+    }
+
+    fn redirect_with(self, status: u16, from: &str, to: &str) -> Router {
+        let get = to.to_string();
+        let head = to.to_string();
+        self.route("GET", from, move |_| Response::redirect_with(status, &get))
+            .route("HEAD", from, move |_| {
+                Response::redirect_with(status, &head)
+            })
+    }
+
+    /// Runs the handler registered for the request, answers `OPTIONS`, or
+    /// answers `405` if the path is registered for other methods only.
+    /// `None` means the path is not routed at all.
+    pub fn dispatch(&self, request: &HttpRequest) -> Option<Response> {
+        let mut bytes = request.body();
+        let length = bytes.len() as u64;
+        self.dispatch_body(request, &mut Body::new(&mut bytes, length))
+    }
+
+    /// Like `dispatch`, with `body` for handlers registered with `stream`.
+    pub(crate) fn dispatch_body(
+        &self,
+        request: &HttpRequest,
+        body: &mut Body<'_>,
+    ) -> Option<Response> {
+        let (route, params) = self.find(request.path())?;
+        let allowed = self.allowed_methods(request.path());
+        let endpoint = route.endpoint(request.method());
+        if endpoint.is_none() && request.method() != "OPTIONS" {
+            return Some(method_not_allowed(&allowed));
+        }
+        let answer = |request: &HttpRequest, body: &mut Body<'_>| match endpoint {
+            Some(endpoint) => endpoint.call(request, body),
+            None => options(&allowed),
+        };
+
+        if self.middleware.is_empty() && params.is_empty() {
+            return Some(answer(request, body));
+        }
+        let mut request = request.clone();
+        for (name, value) in params {
+            request.set_param(name, value);
+        }
+        let body = RefCell::new(body);
+        Some(self.middleware.run(&mut request, &|request| {
+            answer(request, &mut body.borrow_mut())
+        }))
+    }
+
+    /// The body limit of the `stream` handler that would answer `request`;
+    /// `None` when its body should be buffered as usual.
+    pub(crate) fn body_limit(&self, request: &HttpRequest) -> Option<u64> {
+        let (route, _) = self.find(request.path())?;
+        route
+            .endpoint(request.method())
+            .and_then(|endpoint| endpoint.body_limit())
+    }
+
+    /// The worker pool the handler that would answer `request` is pinned
+    /// to with `pool`; `None` for the default pool.
+    pub(crate) fn pool_for(&self, request: &HttpRequest) -> Option<&str> {
+        let (route, _) = self.find(request.path())?;
+        route
+            .endpoint(request.method())
+            .and_then(|endpoint| endpoint.pool())
+    }
+
+    /// The timeout set with `timeout` for the handler that would answer
+    /// `request`; `None` for the server's own.
+    pub(crate) fn timeout_for(&self, request: &HttpRequest) -> Option<Duration> {
+        let (route, _) = self.find(request.path())?;
+        route
+            .endpoint(request.method())
+            .and_then(|endpoint| endpoint.timeout())
+    }
+
+    /// Every pool some route is pinned to.
+    pub(crate) fn pools(&self) -> Vec<&str> {
+        let mut pools: Vec<&str> = Vec::new();
+        for (_, endpoint) in self.routes.iter().flat_map(|route| &route.handlers) {
+            if let Some(pool) = endpoint.pool()
+                && !pools.contains(&pool)
+            {
+                pools.push(pool);
+            }
+        }
+        pools
+    }
+
+    /// The methods with a handler registered for `path`, in registration
+    /// order.
+    pub fn allowed_methods(&self, path: &str) -> Vec<&str> {
+        self.find(path)
+            .map(|(route, _)| {
+                route
+                    .handlers
+                    .iter()
+                    .map(|(method, _)| method.as_str())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Every method some route has a handler for, in registration order.
+    pub fn methods(&self) -> Vec<&str> {
+        let mut methods: Vec<&str> = Vec::new();
+        for (method, _) in self.routes.iter().flat_map(|route| &route.handlers) {
+            if !methods.contains(&method.as_str()) {
+                methods.push(method);
+            }
+        }
+        methods
+    }
+
+    /// The route matching `path` and the parts of the path its `{name}`
+    /// segments and named wildcard matched, by name.
+    fn find<'p>(&self, path: &'p str) -> Option<(&Route, Vec<(&str, &'p str)>)> {
+        let Some(rest) = path.strip_prefix('/') else {
+            let route = &self.routes[*self.unrooted.get(path)?];
+            return Some((route, Vec::new()));
+        };
+        let mut values = Vec::new();
+        let (index, captured) = self.tree.find(rest, &mut values)?;
+        let route = &self.routes[index];
+        let mut params: Vec<(&str, &str)> = route
+            .params
+            .iter()
+            .map(String::as_str)
+            .zip(values)
+            .collect();
+        if let Some(name) = route.wildcard.as_deref().filter(|name| !name.is_empty())
+            && let Some(captured) = captured
+        {
+            params.push((name, captured));
+        }
+        Some((route, params))
+    }
+}
+
+/// A `405` response listing `allowed` in its `Allow` header.
+pub fn method_not_allowed(allowed: &[&str]) -> Response {
+    Response::error_page(405).header("Allow", &allow(allowed))
+}
+
+/// A `301` to the `https://` URL for `request` on `port`, keeping its host,
+/// path and query. Requests without a `Host` header get `400`.
+pub fn https_redirect(request: &HttpRequest, port: u16) -> Response {
+    let Some(host) = request.host() else {
+        return Response::error_page(400);
+    };
+    let target = if request.target() == "*" {
+        "/"
+    } else {
+        request.target()
+    };
+    let location = if port == 443 {
+        format!("https://{}{}", host, target)
+    } else {
+        format!("https://{}:{}{}", host, port, target)
+    };
+    Response::redirect_with(301, &location)
+}
+
+/// The `204` answer to an `OPTIONS` request for a resource that handles
+/// `allowed`.
+pub fn options(allowed: &[&str]) -> Response {
+    Response::new(204).header("Allow", &allow(allowed))
+}
+
+/// An `Allow` header value: `allowed`, plus `HEAD` where `GET` is, and
+/// `OPTIONS`, which every routed path answers.
+fn allow(allowed: &[&str]) -> String {
+    let mut methods = allowed.to_vec();
+    if let Some(get) = methods.iter().position(|method| *method == "GET")
+        && !methods.contains(&"HEAD")
+    {
+        methods.insert(get + 1, "HEAD");
+    }
+    if !methods.contains(&"OPTIONS") {
+        methods.push("OPTIONS");
+    }
+    methods.join(", ")
+}
+
+impl std::fmt::Debug for Router {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let routes: Vec<String> = self
+            .routes
+            .iter()
+            .flat_map(|route| {
+                route
+                    .handlers
+                    .iter()
+                    .map(move |(method, _)| format!("{} {}", method, route.path))
+            })
+            .collect();
+        f.debug_struct("Router")
+            .field("routes", &routes)
+            .field("middleware", &self.middleware)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::middleware::Next;
+
+    fn request(raw: &str) -> HttpRequest {
+        HttpRequest::parse(&mut raw.as_bytes()).unwrap().unwrap()
+    }
+
+    #[test]
+    fn dispatch_should_match_method_and_path() {
+        let router = Router::new()
+            .get("/a", |_| Response::new(200))
+            .post("/a", |_| Response::new(201));
+
+        let get = router.dispatch(&request("GET /a HTTP/1.1\r\n\r\n"));
+        let post = router.dispatch(&request("POST /a HTTP/1.1\r\n\r\n"));
+        let head = router.dispatch(&request("HEAD /a HTTP/1.1\r\n\r\n"));
+
+        assert_eq!(get.map(|response| response.status()), Some(200));
+        assert_eq!(post.map(|response| response.status()), Some(201));
+        assert_eq!(head.map(|response| response.status()), Some(200));
+    }
+
+    #[test]
+    fn handler_should_register_stateful_handlers() {
+        struct Greeting {
+            name: String,
+        }
+
+        impl Handler for Greeting {
+            fn handle(&self, _: &HttpRequest) -> Response {
+                Response::new(200).body(format!("hello {}", self.name))
+            }
+        }
+
+        let router = Router::new().handler(
+            "GET",
+            "/hello",
+            Greeting {
+                name: "ada".to_string(),
+            },
+        );
+
+        let response = router
+            .dispatch(&request("GET /hello HTTP/1.1\r\n\r\n"))
+            .unwrap();
+
+        assert_eq!(response.get_body(), b"hello ada");
+    }
+
+    #[test]
+    fn stateful_should_pass_the_request_state_to_the_handler() {
+        let router = Router::new().stateful("GET", "/name", |_: &HttpRequest, name: &String| {
+            Response::new(200).body(name.clone())
+        });
+        let mut with_state = request("GET /name HTTP/1.1\r\n\r\n");
+        with_state.set_state(Arc::new("ada".to_string()));
+
+        let response = router.dispatch(&with_state).unwrap();
+
+        assert_eq!(response.get_body(), b"ada");
+    }
+
+    #[test]
+    fn pool_should_pin_routes_through_mounts() {
+        let api = Router::new()
+            .get("/jobs", |_| Response::new(200))
+            .pool("slow", |slow| slow.get("/report", |_| Response::new(200)));
+        let router = Router::new()
+            .pool("batch", |batch| batch.mount("/api", api))
+            .get("/", |_| Response::new(200));
+
+        let pool = |raw| router.pool_for(&request(raw));
+
+        assert_eq!(pool("GET /api/report HTTP/1.1\r\n\r\n"), Some("slow"));
+        assert_eq!(pool("GET /api/jobs HTTP/1.1\r\n\r\n"), Some("batch"));
+        assert_eq!(pool("GET / HTTP/1.1\r\n\r\n"), None);
+        assert_eq!(router.pools(), ["batch", "slow"]);
+        assert!(
+            router
+                .dispatch(&request("GET /api/report HTTP/1.1\r\n\r\n"))
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn timeout_should_apply_to_its_routes_with_the_innermost_winning() {
+        let router = Router::new()
+            .timeout(Duration::from_secs(5), |timed| {
+                timed
+                    .get("/report", |_| Response::new(200))
+                    .timeout(Duration::from_secs(1), |quick| {
+                        quick.get("/search", |_| Response::new(200))
+                    })
+            })
+            .get("/", |_| Response::new(200));
+
+        let timeout = |raw| router.timeout_for(&request(raw));
+
+        assert_eq!(
+            timeout("GET /report HTTP/1.1\r\n\r\n"),
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(
+            timeout("GET /search HTTP/1.1\r\n\r\n"),
+            Some(Duration::from_secs(1))
+        );
+        assert_eq!(timeout("GET / HTTP/1.1\r\n\r\n"), None);
+    }
+
+    #[test]
+    fn mount_should_prefix_routes_and_keep_their_middleware() {
+        let api = Router::new()
+            .get("/", |_| Response::new(200).body("index"))
+            .get("/jobs", |_| Response::new(200).body("jobs"))
+            .middleware(|request: &mut HttpRequest, next: Next| {
+                next.run(request).header("X-Scope", "api")
+            });
+        let router = Router::new()
+            .get("/jobs", |_| Response::new(200).body("top"))
+            .mount("/api/", api)
+            .group("/admin", |admin| {
+                admin.post("/users", |_| Response::new(201))
+            });
+
+        let index = router
+            .dispatch(&request("GET /api HTTP/1.1\r\n\r\n"))
+            .unwrap();
+        let jobs = router
+            .dispatch(&request("GET /api/jobs HTTP/1.1\r\n\r\n"))
+            .unwrap();
+        let top = router
+            .dispatch(&request("GET /jobs HTTP/1.1\r\n\r\n"))
+            .unwrap();
+
+        assert_eq!(index.get_body(), b"index");
+        assert_eq!(jobs.get_header("X-Scope"), Some("api"));
+        assert_eq!(top.get_header("X-Scope"), None);
+        assert_eq!(router.allowed_methods("/admin/users"), vec!["POST"]);
+    }
+
+    #[test]
+    fn dispatch_should_capture_the_rest_of_wildcard_paths() {
+        let echo = |request: &HttpRequest| {
+            Response::new(200).body(request.param("file").unwrap_or("-").to_string())
+        };
+        let router = Router::new()
+            .get("/static/*file", echo)
+            .get("/static/app/*file", move |request| {
+                echo(request).header("X-Route", "app")
+            })
+            .get("/static/robots.txt", |_| Response::new(200).body("exact"))
+            .get("/assets/*", echo);
+
+        let file = router
+            .dispatch(&request("GET /static/css/site.css HTTP/1.1\r\n\r\n"))
+            .unwrap();
+        let longer = router
+            .dispatch(&request("GET /static/app/main.js HTTP/1.1\r\n\r\n"))
+            .unwrap();
+        let exact = router
+            .dispatch(&request("GET /static/robots.txt HTTP/1.1\r\n\r\n"))
+            .unwrap();
+        let unnamed = router
+            .dispatch(&request("GET /assets/logo.png HTTP/1.1\r\n\r\n"))
+            .unwrap();
+
+        assert_eq!(file.get_body(), b"css/site.css");
+        assert_eq!(
+            (longer.get_body(), longer.get_header("X-Route")),
+            (&b"main.js"[..], Some("app"))
+        );
+        assert_eq!(exact.get_body(), b"exact");
+        assert_eq!(unnamed.get_body(), b"-");
+        assert!(
+            router
+                .dispatch(&request("GET /static HTTP/1.1\r\n\r\n"))
+                .is_none()
+        );
+        assert_eq!(router.allowed_methods("/assets/a/b"), vec!["GET"]);
+    }
+
+    #[test]
+    fn dispatch_should_capture_params_and_prefer_static_segments() {
+        let echo = |request: &HttpRequest| {
+            let params: Vec<&str> = ["user", "post", "rest"]
+                .iter()
+                .filter_map(|name| request.param(name))
+                .collect();
+            Response::new(200).body(params.join(","))
+        };
+        let router = Router::new()
+            .get("/users/{user}", echo)
+            .get("/users/{user}/posts/{post}", echo)
+            .get("/users/me", |_| Response::new(200).body("me"))
+            .get("/users/{user}/files/*rest", echo)
+            .get("/users/*rest", echo);
+        let body = |path: &str| {
+            router
+                .dispatch(&request(&format!("GET {} HTTP/1.1\r\n\r\n", path)))
+                .map(|response| String::from_utf8(response.get_body().to_vec()).unwrap())
+        };
+
+        assert_eq!(body("/users/42").as_deref(), Some("42"));
+        assert_eq!(body("/users/42/posts/7").as_deref(), Some("42,7"));
+        assert_eq!(body("/users/me").as_deref(), Some("me"));
+        assert_eq!(
+            body("/users/42/files/a/b.txt").as_deref(),
+            Some("42,a/b.txt")
+        );
+        assert_eq!(body("/users/42/posts").as_deref(), Some("42/posts"));
+        assert_eq!(body("/users/").as_deref(), Some(""));
+        assert_eq!(body("/users"), None);
+        assert_eq!(router.allowed_methods("/users/me/posts/1"), vec!["GET"]);
+    }
+
+    #[test]
+    fn redirect_should_answer_get_and_head() {
+        let router = Router::new()
+            .redirect("/old", "/new")
+            .redirect_permanent("/gone", "https://example.com/");
+
+        let old = router
+            .dispatch(&request("HEAD /old HTTP/1.1\r\n\r\n"))
+            .unwrap();
+        let gone = router
+            .dispatch(&request("GET /gone HTTP/1.1\r\n\r\n"))
+            .unwrap();
+
+        assert_eq!(
+            (old.status(), old.get_header("Location")),
+            (302, Some("/new"))
+        );
+        assert_eq!(gone.status(), 301);
+        assert_eq!(router.allowed_methods("/old"), vec!["GET", "HEAD"]);
+    }
+
+    #[test]
+    fn dispatch_should_return_none_without_a_matching_route() {
+        let router = Router::new().get("/a", |_| Response::new(200));
+
+        assert!(
+            router
+                .dispatch(&request("GET /b HTTP/1.1\r\n\r\n"))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn dispatch_should_answer_405_for_other_methods_on_a_known_path() {
+        let router = Router::new()
+            .get("/a", |_| Response::new(200))
+            .post("/a", |_| Response::new(201));
+
+        let response = router
+            .dispatch(&request("PUT /a HTTP/1.1\r\n\r\n"))
+            .unwrap();
+
+        assert_eq!(response.status(), 405);
+        assert_eq!(
+            response.get_header("Allow"),
+            Some("GET, HEAD, POST, OPTIONS")
+        );
+        assert_eq!(router.allowed_methods("/b"), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn dispatch_should_stream_a_buffered_body_to_body_handlers() {
+        let router = Router::new().stream("POST", "/upload", 1024, |_, body| {
+            let mut text = String::new();
+            match std::io::Read::read_to_string(body, &mut text) {
+                Ok(_) => Response::new(200).body(text.to_uppercase()),
+                Err(_) => Response::new(400),
+            }
+        });
+        let upload = request("POST /upload HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello");
+
+        assert_eq!(router.dispatch(&upload).unwrap().get_body(), b"HELLO");
+        assert_eq!(router.body_limit(&upload), Some(1024));
+        assert_eq!(
+            router.body_limit(&request("GET /upload HTTP/1.1\r\n\r\n")),
+            None
+        );
+    }
+
+    #[test]
+    fn https_redirect_should_keep_host_path_and_query() {
+        let get = request("GET /a%20b?page=2 HTTP/1.1\r\nHost: example.com:8080\r\n\r\n");
+        let ipv6 = request("POST /form HTTP/1.1\r\nHost: [::1]\r\n\r\n");
+
+        let response = https_redirect(&get, 443);
+
+        assert_eq!(response.status(), 301);
+        assert_eq!(
+            response.get_header("Location"),
+            Some("https://example.com/a%20b?page=2")
+        );
+        assert_eq!(
+            https_redirect(&ipv6, 8443).get_header("Location"),
+            Some("https://[::1]:8443/form")
+        );
+        assert_eq!(
+            https_redirect(&request("GET / HTTP/1.0\r\n\r\n"), 443).status(),
+            400
+        );
+    }
+
+    #[test]
+    fn dispatch_should_answer_options_with_the_allowed_methods() {
+        let router = Router::new()
+            .get("/a", |_| Response::new(200))
+            .post("/a", |_| Response::new(201))
+            .route("OPTIONS", "/custom", |_| Response::new(200).body("mine"));
+
+        let options = router
+            .dispatch(&request("OPTIONS /a HTTP/1.1\r\n\r\n"))
+            .unwrap();
+        let custom = router
+            .dispatch(&request("OPTIONS /custom HTTP/1.1\r\n\r\n"))
+            .unwrap();
+
+        assert_eq!(options.status(), 204);
+        assert_eq!(
+            options.get_header("Allow"),
+            Some("GET, HEAD, POST, OPTIONS")
+        );
+        assert_eq!(custom.get_body(), b"mine");
+        assert!(
+            router
+                .dispatch(&request("OPTIONS /b HTTP/1.1\r\n\r\n"))
+                .is_none()
+        );
+        assert_eq!(router.methods(), vec!["GET", "POST", "OPTIONS"]);
+    }
+}
@@ -0,0 +1,144 @@
+//! Standard security headers on every response.
+
+use std::time::Duration;
+
+use super::{
+    middleware::{Middleware, Next},
+    request::HttpRequest,
+    response::Response,
+};
+
+/// Middleware adding security headers to every response that doesn't set
+/// them itself.
+///
+/// By default it sends `X-Content-Type-Options: nosniff`,
+/// `X-Frame-Options: DENY` and
+/// `Referrer-Policy: strict-origin-when-cross-origin`. A
+/// `Content-Security-Policy` and `Strict-Transport-Security` are only sent
+/// once configured, as the right values depend on the site.
+///
+/// # Example
+///
+/// ```
+/// # use std::time::Duration;
+/// # use harbor::server::{SecurityHeaders, Server};
+/// let server = Server::new("127.0.0.1:7878").middleware(
+///     SecurityHeaders::new()
+///         .content_security_policy("default-src 'self'")
+///         .strict_transport_security(Duration::from_secs(31_536_000), true),
+/// );
+/// ```
+#[derive(Debug, Clone)]
+pub struct SecurityHeaders {
+    headers: Vec<(String, String)>,
+}
+
+impl SecurityHeaders {
+    pub fn new() -> SecurityHeaders {
+        SecurityHeaders::default()
+    }
+
+    /// Sets `X-Frame-Options`, such as `SAMEORIGIN` for sites that frame
+    /// their own pages.
+    pub fn frame_options(self, value: &str) -> SecurityHeaders {
+        self.header("X-Frame-Options", value)
+    }
+
+    /// Sets `Referrer-Policy`, such as `no-referrer`.
+    pub fn referrer_policy(self, value: &str) -> SecurityHeaders {
+        self.header("Referrer-Policy", value)
+    }
+
+    /// Sends `policy` as the `Content-Security-Policy`.
+    pub fn content_security_policy(self, policy: &str) -> SecurityHeaders {
+        self.header("Content-Security-Policy", policy)
+    }
+
+    /// Tells browsers to only use HTTPS for the site for `max_age`, and
+    /// for its subdomains too with `include_subdomains`. Browsers ignore
+    /// it over plain HTTP, so enable it where TLS is on, whether here or
+    /// at a proxy in front.
+    pub fn strict_transport_security(
+        self,
+        max_age: Duration,
+        include_subdomains: bool,
+    ) -> SecurityHeaders {
+        let mut value = format!("max-age={}", max_age.as_secs());
+        if include_subdomains {
+            value.push_str("; includeSubDomains");
+        }
+        self.header("Strict-Transport-Security", &value)
+    }
+
+    /// Sends another header, or replaces the value of one already set.
+    pub fn header(mut self, name: &str, value: &str) -> SecurityHeaders {
+        self = self.without(name);
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Stops sending `name`, including one of the defaults.
+    pub fn without(mut self, name: &str) -> SecurityHeaders {
+        self.headers
+            .retain(|(existing, _)| !existing.eq_ignore_ascii_case(name));
+        self
+    }
+}
+
+impl Default for SecurityHeaders {
+    fn default() -> SecurityHeaders {
+        SecurityHeaders {
+            headers: Vec::new(),
+        }
+        .header("X-Content-Type-Options", "nosniff")
+        .header("X-Frame-Options", "DENY")
+        .header("Referrer-Policy", "strict-origin-when-cross-origin")
+    }
+}
+
+impl Middleware for SecurityHeaders {
+    fn handle(&self, request: &mut HttpRequest, next: Next<'_>) -> Response {
+        let mut response = next.run(request);
+        for (name, value) in &self.headers {
+            if response.get_header(name).is_none() {
+                response = response.header(name, value);
+            }
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::middleware::MiddlewareStack;
+
+    #[test]
+    fn security_headers_should_add_defaults_the_handler_did_not_set() {
+        let mut stack = MiddlewareStack::new();
+        stack.push(
+            SecurityHeaders::new()
+                .without("referrer-policy")
+                .content_security_policy("default-src 'self'"),
+        );
+        let mut request = HttpRequest::parse(&mut &b"GET / HTTP/1.1\r\n\r\n"[..])
+            .unwrap()
+            .unwrap();
+
+        let response = stack.run(&mut request, &|_| {
+            Response::new(200).header("X-Frame-Options", "SAMEORIGIN")
+        });
+
+        assert_eq!(
+            response.get_header("X-Content-Type-Options"),
+            Some("nosniff")
+        );
+        assert_eq!(response.get_header("X-Frame-Options"), Some("SAMEORIGIN"));
+        assert_eq!(response.get_header("Referrer-Policy"), None);
+        assert_eq!(
+            response.get_header("Content-Security-Policy"),
+            Some("default-src 'self'")
+        );
+        assert_eq!(response.get_header("Strict-Transport-Security"), None);
+    }
+}
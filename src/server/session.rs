@@ -0,0 +1,426 @@
+//! Cookie-based sessions.
+//!
+//! The `Sessions` middleware gives every request a `Session`, reachable
+//! with `HttpRequest::session`. Clients only hold a random session id in a
+//! cookie signed with HMAC-SHA256, so they cannot forge or guess others';
+//! the data itself lives in a `SessionStore`.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use uuid::Uuid;
+
+use super::{
+    cookie::{Cookie, SameSite},
+    middleware::{Middleware, Next},
+    request::HttpRequest,
+    response::Response,
+    sha256,
+};
+
+/// The values stored in one session.
+pub type SessionData = HashMap<String, String>;
+
+/// Where session data is kept between requests.
+pub trait SessionStore: Send + Sync {
+    /// The data saved for `id`, or `None` if the session is unknown or
+    /// has expired.
+    fn load(&self, id: &str) -> Option<SessionData>;
+    fn save(&self, id: &str, data: &SessionData);
+    fn remove(&self, id: &str);
+}
+
+/// How long `MemoryStore` keeps a session nobody uses, by default.
+pub const DEFAULT_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Keeps sessions in memory. They are lost when the process exits.
+///
+/// A session expires once it has gone unused for the store's TTL, however
+/// long its cookie lives. Saving a session drops the expired ones.
+#[derive(Debug)]
+pub struct MemoryStore {
+    sessions: Mutex<HashMap<String, Entry>>,
+    ttl: Duration,
+}
+
+#[derive(Debug)]
+struct Entry {
+    data: SessionData,
+    expires: Instant,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore {
+            sessions: Mutex::new(HashMap::new()),
+            ttl: DEFAULT_TTL,
+        }
+    }
+
+    /// Expires sessions unused for `ttl` instead of `DEFAULT_TTL`.
+    pub fn ttl(mut self, ttl: Duration) -> MemoryStore {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Number of sessions stored.
+    pub fn len(&self) -> usize {
+        self.sessions.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> MemoryStore {
+        MemoryStore::new()
+    }
+}
+
+impl SessionStore for MemoryStore {
+    fn load(&self, id: &str) -> Option<SessionData> {
+        let now = Instant::now();
+        let mut sessions = self.sessions.lock().unwrap();
+        let entry = sessions.get_mut(id).filter(|entry| entry.expires > now)?;
+        entry.expires = now + self.ttl;
+        Some(entry.data.clone())
+    }
+
+    fn save(&self, id: &str, data: &SessionData) {
+        let now = Instant::now();
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|_, entry| entry.expires > now);
+        sessions.insert(
+            id.to_string(),
+            Entry {
+                data: data.clone(),
+                expires: now + self.ttl,
+            },
+        );
+    }
+
+    fn remove(&self, id: &str) {
+        self.sessions.lock().unwrap().remove(id);
+    }
+}
+
+impl<S: SessionStore> SessionStore for Arc<S> {
+    fn load(&self, id: &str) -> Option<SessionData> {
+        S::load(self, id)
+    }
+
+    fn save(&self, id: &str, data: &SessionData) {
+        S::save(self, id, data)
+    }
+
+    fn remove(&self, id: &str) {
+        S::remove(self, id)
+    }
+}
+
+/// The session of the current request.
+///
+/// Changes are saved to the store once the handler has returned. A new
+/// session only gets a cookie, and a store entry, once something is set.
+#[derive(Debug)]
+pub struct Session {
+    id: String,
+    is_new: bool,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    data: SessionData,
+    changed: bool,
+    destroyed: bool,
+}
+
+impl Session {
+    fn new(id: String, data: Option<SessionData>) -> Session {
+        Session {
+            id,
+            is_new: data.is_none(),
+            state: Mutex::new(State {
+                data: data.unwrap_or_default(),
+                ..State::default()
+            }),
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.state.lock().unwrap().data.get(key).cloned()
+    }
+
+    pub fn set(&self, key: &str, value: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.data.insert(key.to_string(), value.to_string());
+        state.changed = true;
+    }
+
+    pub fn remove(&self, key: &str) -> Option<String> {
+        let mut state = self.state.lock().unwrap();
+        let removed = state.data.remove(key);
+        state.changed |= removed.is_some();
+        removed
+    }
+
+    /// Deletes the session from the store and the client, e.g. on logout.
+    pub fn destroy(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.data.clear();
+        state.destroyed = true;
+    }
+}
+
+/// Middleware attaching a `Session` to every request.
+///
+/// # Example
+///
+/// ```
+/// # use harbor::server::{Response, Router, Server};
+/// # use harbor::server::session::{MemoryStore, Sessions};
+/// let router = Router::new().post("/login", |request| {
+///     if let Some(session) = request.session() {
+///         session.set("user", "ada");
+///     }
+///     Response::new(200)
+/// });
+/// let server = Server::new("127.0.0.1:7878")
+///     .router(router)
+///     .middleware(Sessions::new(MemoryStore::new(), b"a long random secret"));
+/// ```
+pub struct Sessions {
+    store: Box<dyn SessionStore>,
+    secret: Vec<u8>,
+    cookie_name: String,
+    max_age: Option<Duration>,
+    secure: bool,
+}
+
+impl Sessions {
+    /// Signs session cookies with `secret`, which should be long, random
+    /// and stable across restarts so existing cookies stay valid.
+    pub fn new(store: impl SessionStore + 'static, secret: &[u8]) -> Sessions {
+        Sessions {
+            store: Box::new(store),
+            secret: secret.to_vec(),
+            cookie_name: "harbor_session".to_string(),
+            max_age: None,
+            secure: false,
+        }
+    }
+
+    /// Sets the cookie name. Defaults to `harbor_session`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` is not a valid cookie name.
+    pub fn cookie_name(mut self, name: &str) -> Sessions {
+        if let Err(err) = Cookie::new(name, "") {
+            panic!("{}", err);
+        }
+        self.cookie_name = name.to_string();
+        self
+    }
+
+    /// Keeps the cookie for `max_age` instead of until the browser closes.
+    /// The session itself lasts as long as the store keeps it, so a
+    /// `MemoryStore` should get a `ttl` no longer than this.
+    pub fn max_age(mut self, max_age: Duration) -> Sessions {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Only sends the session cookie over HTTPS.
+    pub fn secure(mut self, secure: bool) -> Sessions {
+        self.secure = secure;
+        self
+    }
+
+    fn sign(&self, id: &str) -> String {
+        let mac = sha256::hmac(&self.secret, id.as_bytes());
+        let mac: String = mac.iter().map(|byte| format!("{:02x}", byte)).collect();
+        format!("{}.{}", id, mac)
+    }
+
+    /// The session id in `value` if its signature is valid.
+    fn verify<'a>(&self, value: &'a str) -> Option<&'a str> {
+        let (id, _) = value.split_once('.')?;
+        sha256::constant_time_eq(self.sign(id).as_bytes(), value.as_bytes()).then_some(id)
+    }
+
+    fn open(&self, request: &HttpRequest) -> Session {
+        let known = request
+            .cookie(&self.cookie_name)
+            .and_then(|value| self.verify(&value).map(str::to_string))
+            .and_then(|id| self.store.load(&id).map(|data| (id, data)));
+        match known {
+            Some((id, data)) => Session::new(id, Some(data)),
+            None => Session::new(Uuid::new_v4().simple().to_string(), None),
+        }
+    }
+
+    fn cookie(&self, value: &str) -> Cookie {
+        let cookie = Cookie::new(&self.cookie_name, value)
+            .expect("session cookies hold only hex digits and dots")
+            .path("/")
+            .http_only(true)
+            .secure(self.secure)
+            .same_site(SameSite::Lax);
+        match self.max_age {
+            Some(max_age) => cookie.max_age(max_age),
+            None => cookie,
+        }
+    }
+}
+
+impl Middleware for Sessions {
+    fn handle(&self, request: &mut HttpRequest, next: Next<'_>) -> Response {
+        let session = Arc::new(self.open(request));
+        request.set_session(Arc::clone(&session));
+        let response = next.run(request);
+
+        let state = session.state.lock().unwrap();
+        if state.destroyed {
+            self.store.remove(&session.id);
+            if session.is_new {
+                return response;
+            }
+            return response.cookie(&self.cookie("").max_age(Duration::ZERO));
+        }
+        if !state.changed {
+            return response;
+        }
+        self.store.save(&session.id, &state.data);
+        if session.is_new {
+            response.cookie(&self.cookie(&self.sign(&session.id)))
+        } else {
+            response
+        }
+    }
+}
+
+impl fmt::Debug for Sessions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sessions")
+            .field("cookie_name", &self.cookie_name)
+            .field("max_age", &self.max_age)
+            .field("secure", &self.secure)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::middleware::MiddlewareStack;
+
+    fn request(cookie: Option<&str>) -> HttpRequest {
+        let raw = match cookie {
+            Some(cookie) => format!("GET / HTTP/1.1\r\nCookie: {}\r\n\r\n", cookie),
+            None => "GET / HTTP/1.1\r\n\r\n".to_string(),
+        };
+        HttpRequest::parse(&mut raw.as_bytes()).unwrap().unwrap()
+    }
+
+    fn session_cookie(response: &Response) -> Option<String> {
+        response
+            .get_header("Set-Cookie")
+            .map(|value| value.split(';').next().unwrap().to_string())
+    }
+
+    #[test]
+    fn sessions_should_persist_values_across_requests() {
+        let store = Arc::new(MemoryStore::new());
+        let mut stack = MiddlewareStack::new();
+        stack.push(Sessions::new(Arc::clone(&store), b"secret"));
+        let count = |request: &HttpRequest| {
+            let session = request.session().unwrap();
+            let visits = session.get("visits").map_or(0, |v| v.parse().unwrap()) + 1;
+            session.set("visits", &visits.to_string());
+            Response::new(200).body(visits.to_string())
+        };
+
+        let first = stack.run(&mut request(None), &count);
+        let cookie = session_cookie(&first).expect("a new session sets a cookie");
+        let second = stack.run(&mut request(Some(&cookie)), &count);
+
+        assert_eq!(second.get_body(), b"2");
+        assert_eq!(session_cookie(&second), None);
+        assert_eq!(store.len(), 1);
+    }
+
+    #[test]
+    fn sessions_should_ignore_forged_cookies() {
+        let store = Arc::new(MemoryStore::new());
+        let sessions = Sessions::new(Arc::clone(&store), b"secret");
+        let id = "0123456789abcdef";
+        store.save(
+            id,
+            &SessionData::from([("user".to_string(), "ada".to_string())]),
+        );
+
+        let forged = format!("harbor_session={}.{}", id, "00".repeat(32));
+        let signed = format!("harbor_session={}", sessions.sign(id));
+
+        assert_eq!(sessions.open(&request(Some(&forged))).get("user"), None);
+        assert_eq!(
+            sessions
+                .open(&request(Some(&signed)))
+                .get("user")
+                .as_deref(),
+            Some("ada")
+        );
+    }
+
+    #[test]
+    fn memory_store_should_expire_unused_sessions() {
+        let store = MemoryStore::new().ttl(Duration::from_millis(200));
+        store.save("old", &SessionData::new());
+        store.save("used", &SessionData::new());
+        std::thread::sleep(Duration::from_millis(120));
+        assert!(store.load("used").is_some());
+        std::thread::sleep(Duration::from_millis(120));
+
+        assert_eq!(store.load("old"), None);
+        assert!(store.load("used").is_some());
+        store.save("new", &SessionData::new());
+        assert_eq!(store.len(), 2);
+    }
+
+    #[test]
+    fn destroy_should_remove_the_session_and_expire_the_cookie() {
+        let store = Arc::new(MemoryStore::new());
+        let sessions = Sessions::new(Arc::clone(&store), b"secret");
+        store.save("abc", &SessionData::new());
+        let mut stack = MiddlewareStack::new();
+        stack.push(sessions);
+        let cookie = format!(
+            "harbor_session={}",
+            Sessions::new(MemoryStore::new(), b"secret").sign("abc")
+        );
+
+        let response = stack.run(&mut request(Some(&cookie)), &|request: &HttpRequest| {
+            request.session().unwrap().destroy();
+            Response::new(200)
+        });
+
+        assert!(store.is_empty());
+        assert!(
+            response
+                .get_header("Set-Cookie")
+                .is_some_and(|value| value.contains("Max-Age=0"))
+        );
+    }
+}
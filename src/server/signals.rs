@@ -0,0 +1,65 @@
+//! SIGINT/SIGTERM handling used to drain the server gracefully, and SIGHUP
+//! handling used to reload its configuration.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::HarborError;
+
+static RECEIVED: AtomicBool = AtomicBool::new(false);
+static HANGUP: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_signal(signal: libc::c_int) {
+    if signal == libc::SIGHUP {
+        HANGUP.store(true, Ordering::SeqCst);
+    } else {
+        RECEIVED.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Installs handlers for SIGINT, SIGTERM and SIGHUP that record the signal.
+///
+/// The handler only sets a flag; the server polls it through `received`
+/// and `take_hangup`.
+pub fn install() -> Result<(), HarborError> {
+    for signal in [libc::SIGINT, libc::SIGTERM, libc::SIGHUP] {
+        // SAFETY: `on_signal` is async-signal-safe (it only stores to an
+        // atomic) and the `sigaction` struct is fully initialized.
+        let result = unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = on_signal as *const () as libc::sighandler_t;
+            libc::sigemptyset(&mut action.sa_mask);
+            libc::sigaction(signal, &action, std::ptr::null_mut())
+        };
+        if result != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+    }
+    Ok(())
+}
+
+/// Returns `true` once SIGINT or SIGTERM has been delivered.
+pub fn received() -> bool {
+    RECEIVED.load(Ordering::SeqCst)
+}
+
+/// Returns `true` if SIGHUP was delivered since the last call.
+pub fn take_hangup() -> bool {
+    HANGUP.swap(false, Ordering::SeqCst)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn install_should_record_delivered_signals() {
+        install().unwrap();
+
+        // SAFETY: raising a signal whose handler was just installed.
+        unsafe {
+            libc::raise(libc::SIGTERM);
+        }
+
+        assert!(received());
+    }
+}
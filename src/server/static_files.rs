@@ -0,0 +1,778 @@
+//! Serving files from a document root.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{error, warn};
+
+use super::{
+    cache::FileCache,
+    date::{format_http_date, format_timestamp, parse_http_date},
+    encoding,
+    mime::MimeTypes,
+    request::HttpRequest,
+    response::Response,
+    template::escape_html,
+    url::encode_path,
+};
+
+/// Files larger than this are streamed from disk instead of being read
+/// into memory (and the cache).
+pub const STREAM_THRESHOLD: u64 = 1024 * 1024;
+
+/// The content codings of pre-compressed copies and the suffix of each
+/// copy, in order of preference.
+const PRECOMPRESSED: [(&str, &str); 2] = [("br", ".br"), ("gzip", ".gz")];
+
+/// A `Cache-Control` value sent with the static files whose request path
+/// matches `path`.
+///
+/// `path` is either a prefix ending in `/*` (`/assets/*`), an extension
+/// (`*.html`) or an exact path (`/favicon.ico`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct CacheRule {
+    pub path: String,
+    pub value: String,
+}
+
+impl CacheRule {
+    pub fn new(path: &str, value: &str) -> CacheRule {
+        CacheRule {
+            path: path.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    /// Whether the rule applies to the file served for `request_path`.
+    pub fn matches(&self, request_path: &str) -> bool {
+        if let Some(prefix) = self.path.strip_suffix('*') {
+            request_path.starts_with(prefix)
+        } else if let Some(extension) = self.path.strip_prefix("*.") {
+            request_path
+                .rsplit_once('.')
+                .is_some_and(|(_, found)| found.eq_ignore_ascii_case(extension))
+        } else {
+            request_path == self.path
+        }
+    }
+
+    /// The `max-age` directive of the value, if any.
+    fn max_age(&self) -> Option<Duration> {
+        self.value.split(',').find_map(|directive| {
+            let (name, seconds) = directive.split_once('=')?;
+            if !name.trim().eq_ignore_ascii_case("max-age") {
+                return None;
+            }
+            seconds.trim().parse().ok().map(Duration::from_secs)
+        })
+    }
+}
+
+/// Where `StaticFiles` wants a trailing slash on the request path.
+///
+/// Requests for the other form are answered with `301 Moved Permanently`
+/// to the preferred one, keeping the query, so relative links in index
+/// pages resolve against the directory. The root `/` is never redirected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TrailingSlash {
+    /// Directories are served at `/docs/` and files at `/file.txt`.
+    #[default]
+    Add,
+    /// Directories and files are both served without a trailing slash.
+    Remove,
+    /// Both forms are served as they are.
+    Ignore,
+}
+
+impl TrailingSlash {
+    pub fn parse(name: &str) -> Option<TrailingSlash> {
+        match name.to_ascii_lowercase().as_str() {
+            "add" => Some(TrailingSlash::Add),
+            "remove" => Some(TrailingSlash::Remove),
+            "ignore" => Some(TrailingSlash::Ignore),
+            _ => None,
+        }
+    }
+}
+
+/// Serves files below `root` for `GET` and `HEAD` requests.
+///
+/// Every resolved path is canonicalized. Anything that lands outside the
+/// root, for example through a symlink, is refused with `403 Forbidden`.
+#[derive(Debug, Clone)]
+pub struct StaticFiles {
+    root: PathBuf,
+    directory_listing: bool,
+    precompressed: bool,
+    trailing_slash: TrailingSlash,
+    cache: Option<Arc<FileCache>>,
+    mime_types: MimeTypes,
+    cache_control: Vec<CacheRule>,
+}
+
+impl StaticFiles {
+    /// Serves files from `root`, which should exist by now so it can be
+    /// canonicalized once.
+    pub fn new(root: impl Into<PathBuf>) -> StaticFiles {
+        let root = root.into();
+        StaticFiles {
+            root: fs::canonicalize(&root).unwrap_or(root),
+            directory_listing: false,
+            precompressed: false,
+            trailing_slash: TrailingSlash::default(),
+            cache: None,
+            mime_types: MimeTypes::new(),
+            cache_control: Vec::new(),
+        }
+    }
+
+    /// Serves file contents through `cache` instead of reading the disk
+    /// on every request.
+    pub fn cache(mut self, cache: Arc<FileCache>) -> StaticFiles {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Sets the table used to pick each file's `Content-Type`.
+    pub fn mime_types(mut self, mime_types: MimeTypes) -> StaticFiles {
+        self.mime_types = mime_types;
+        self
+    }
+
+    /// Sends `Cache-Control` with the files matching one of `rules`; the
+    /// first match wins. Rules with a `max-age` add a matching `Expires`
+    /// for HTTP/1.0 caches.
+    pub fn cache_control(mut self, rules: Vec<CacheRule>) -> StaticFiles {
+        self.cache_control = rules;
+        self
+    }
+
+    /// Enables HTML listings for directories that have no `index.html`.
+    pub fn directory_listing(mut self, enabled: bool) -> StaticFiles {
+        self.directory_listing = enabled;
+        self
+    }
+
+    /// Serves `file.br` or `file.gz` in place of `file`, when it exists,
+    /// to clients accepting Brotli or gzip, with the `Content-Type` of
+    /// `file`. Brotli wins when the client ranks both alike. The copies
+    /// should be compressed ahead of time from the current `file`.
+    pub fn precompressed(mut self, enabled: bool) -> StaticFiles {
+        self.precompressed = enabled;
+        self
+    }
+
+    /// Sets where a trailing slash belongs, `TrailingSlash::Add` by
+    /// default.
+    pub fn trailing_slash(mut self, trailing_slash: TrailingSlash) -> StaticFiles {
+        self.trailing_slash = trailing_slash;
+        self
+    }
+
+    /// Answers the request from the document root.
+    ///
+    /// Returns `None` when the request is not a `GET` or nothing under the
+    /// root matches its path, so other handlers can try it.
+    pub fn serve(&self, request: &HttpRequest) -> Option<Response> {
+        self.serve_path(request, request.path())
+    }
+
+    /// Like `serve`, but looks up `path` below the root instead of the
+    /// request's own path. A wildcard route uses this to hand what it
+    /// captured to the static files:
+    ///
+    /// ```
+    /// # use harbor::server::{Response, Router, StaticFiles};
+    /// let files = StaticFiles::new("public/assets");
+    /// let router = Router::new().get("/assets/*file", move |request| {
+    ///     let file = request.param("file").unwrap_or_default();
+    ///     files
+    ///         .serve_path(request, file)
+    ///         .unwrap_or_else(|| Response::error_page(404))
+    /// });
+    /// ```
+    pub fn serve_path(&self, request: &HttpRequest, path: &str) -> Option<Response> {
+        if !matches!(request.method(), "GET" | "HEAD") {
+            return None;
+        }
+
+        let path = match self.confine(path) {
+            Ok(Some(path)) => path,
+            Ok(None) => return None,
+            Err(response) => return Some(response),
+        };
+        let metadata = fs::metadata(&path).ok()?;
+
+        if metadata.is_dir() {
+            let index = match self.canonicalize(&path.join("index.html"), request.path()) {
+                Ok(index) => index,
+                Err(response) => return Some(response),
+            };
+            let index = index.filter(|index| index.is_file());
+            if index.is_none() && !self.directory_listing {
+                return None;
+            }
+            if let Some(redirect) = self.redirect_slash(request, true) {
+                return Some(redirect);
+            }
+            if let Some(index) = index {
+                let index_path = format!("{}/index.html", request.path().trim_end_matches('/'));
+                return self.read_file(&index, request, &index_path);
+            }
+            return Some(listing(&path, request.path()));
+        }
+
+        if let Some(redirect) = self.redirect_slash(request, false) {
+            return Some(redirect);
+        }
+        self.read_file(&path, request, request.path())
+    }
+
+    /// The redirect to the preferred form of the request path, when the
+    /// request used the other one for a directory or a file.
+    fn redirect_slash(&self, request: &HttpRequest, directory: bool) -> Option<Response> {
+        let path = request.path();
+        if path == "/" {
+            return None;
+        }
+        let slashed = path.ends_with('/');
+        let location = match (self.trailing_slash, directory, slashed) {
+            (TrailingSlash::Add, true, false) => format!("{}/", path),
+            (TrailingSlash::Add, false, true) | (TrailingSlash::Remove, _, true) => {
+                path.trim_end_matches('/').to_string()
+            }
+            _ => return None,
+        };
+        let mut location = encode_path(&location);
+        if let Some(query) = request.query() {
+            location.push('?');
+            location.push_str(query);
+        }
+        Some(Response::redirect_permanent(&location))
+    }
+
+    /// Maps a request path to an existing, canonical location under the
+    /// root. `Ok(None)` means nothing is there; `Err` holds the `403` for
+    /// paths that would escape the root.
+    fn confine(&self, request_path: &str) -> Result<Option<PathBuf>, Response> {
+        match self.resolve(request_path) {
+            Some(path) => self.canonicalize(&path, request_path),
+            None => Err(forbidden(request_path)),
+        }
+    }
+
+    fn canonicalize(&self, path: &Path, request_path: &str) -> Result<Option<PathBuf>, Response> {
+        match fs::canonicalize(path) {
+            Ok(canonical) if canonical.starts_with(&self.root) => Ok(Some(canonical)),
+            Ok(_) => Err(forbidden(request_path)),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Maps a request path to a location under the root.
+    ///
+    /// Paths with `..` segments never map to a file.
+    fn resolve(&self, request_path: &str) -> Option<PathBuf> {
+        let mut path = self.root.clone();
+        for segment in request_path.split('/') {
+            match segment {
+                "" | "." => continue,
+                ".." => return None,
+                segment => path.push(segment),
+            }
+        }
+        Some(path)
+    }
+
+    /// Answers with the file at `path`, or `304 Not Modified` when the
+    /// request's `If-Modified-Since` is no older than the file.
+    fn read_file(
+        &self,
+        path: &Path,
+        request: &HttpRequest,
+        request_path: &str,
+    ) -> Option<Response> {
+        let metadata = fs::metadata(path).ok();
+        let modified = metadata
+            .as_ref()
+            .and_then(|metadata| metadata.modified().ok());
+
+        let mut headers = Vec::new();
+        if let Some(rule) = self
+            .cache_control
+            .iter()
+            .find(|rule| rule.matches(request_path))
+        {
+            headers.push(("Cache-Control", rule.value.clone()));
+            if let Some(expires) = rule
+                .max_age()
+                .and_then(|max_age| SystemTime::now().checked_add(max_age))
+            {
+                headers.push(("Expires", format_http_date(expires)));
+            }
+        }
+        if let Some(modified) = modified {
+            headers.push(("Last-Modified", format_http_date(modified)));
+        }
+        let copies = self.compressed_copies(path, request_path);
+        if !copies.is_empty() {
+            headers.push(("Vary", "Accept-Encoding".to_string()));
+        }
+        let codings: Vec<&'static str> = copies.iter().map(|(coding, _)| *coding).collect();
+        let compressed = encoding::negotiate(request.header("Accept-Encoding"), &codings)
+            .and_then(|chosen| copies.into_iter().find(|(coding, _)| *coding == chosen));
+        let with_headers = |response: Response| {
+            headers.iter().fold(response, |response, (name, value)| {
+                response.header(name, value)
+            })
+        };
+
+        if modified.is_some_and(|modified| not_modified_since(request, modified)) {
+            return Some(with_headers(Response::new(304)));
+        }
+
+        let response =
+            with_headers(Response::new(200).header("Content-Type", self.mime_types.lookup(path)));
+        let (path, metadata, response) = match &compressed {
+            Some((coding, compressed)) => (
+                compressed.as_path(),
+                fs::metadata(compressed).ok(),
+                response.header("Content-Encoding", coding),
+            ),
+            None => (path, metadata, response),
+        };
+        let large = metadata.is_some_and(|metadata| metadata.len() > STREAM_THRESHOLD);
+        if large {
+            return match fs::File::open(path).and_then(|file| response.file(file)) {
+                Ok(response) => Some(response),
+                Err(err) => {
+                    warn!("This file isn't avalible {}", err);
+                    None
+                }
+            };
+        }
+
+        let contents = match &self.cache {
+            Some(cache) => cache.read(path).map(|contents| contents.to_vec()),
+            None => fs::read(path),
+        };
+        match contents {
+            Ok(contents) => Some(response.body(contents)),
+            Err(err) => {
+                warn!("This file isn't avalible {}", err);
+                None
+            }
+        }
+    }
+
+    /// The pre-compressed copies of the file at `path` under the root
+    /// with their coding, when serving those is enabled.
+    fn compressed_copies(&self, path: &Path, request_path: &str) -> Vec<(&'static str, PathBuf)> {
+        if !self.precompressed {
+            return Vec::new();
+        }
+        PRECOMPRESSED
+            .iter()
+            .filter_map(|&(coding, suffix)| {
+                let mut copy = path.as_os_str().to_owned();
+                copy.push(suffix);
+                let copy = self.canonicalize(Path::new(&copy), request_path).ok()??;
+                copy.is_file().then_some((coding, copy))
+            })
+            .collect()
+    }
+}
+
+/// Whether the request's `If-Modified-Since` date is at or after
+/// `modified`. Dates in the future are ignored, as RFC 9110 asks.
+fn not_modified_since(request: &HttpRequest, modified: SystemTime) -> bool {
+    let Some(since) = request
+        .header("If-Modified-Since")
+        .and_then(parse_http_date)
+    else {
+        return false;
+    };
+    // HTTP dates have whole seconds only.
+    let secs = |time: SystemTime| {
+        time.duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs())
+    };
+    secs(since) <= secs(SystemTime::now()) && secs(modified) <= secs(since)
+}
+
+fn forbidden(request_path: &str) -> Response {
+    warn!(
+        "Refusing to serve {} from outside the document root",
+        request_path
+    );
+    Response::error_page(403)
+}
+
+/// Renders an HTML table of the entries of `dir`, linked relative to
+/// `request_path`.
+fn listing(dir: &Path, request_path: &str) -> Response {
+    let base = request_path.trim_end_matches('/');
+
+    let mut entries: Vec<(String, bool, u64, Option<SystemTime>)> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(Result::ok)
+            .filter_map(|entry| {
+                let metadata = entry.metadata().ok()?;
+                let name = entry.file_name().to_string_lossy().into_owned();
+                Some((
+                    name,
+                    metadata.is_dir(),
+                    metadata.len(),
+                    metadata.modified().ok(),
+                ))
+            })
+            .collect(),
+        Err(err) => {
+            error!("Failed to list directory {}: {}", dir.display(), err);
+            return Response::html(500, "<h1>Failed to list directory</h1>");
+        }
+    };
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let title = escape_html(&format!("Index of {}/", base));
+    let mut rows = String::new();
+    if !base.is_empty() {
+        let parent = &base[..base.rfind('/').unwrap_or(0)];
+        rows.push_str(&format!(
+            "<tr><td><a href=\"{}/\">../</a></td><td></td><td></td></tr>\n",
+            encode_path(parent)
+        ));
+    }
+    for (name, is_dir, size, modified) in entries {
+        let suffix = if is_dir { "/" } else { "" };
+        let href = format!("{}{}", encode_path(&format!("{}/{}", base, name)), suffix);
+        let size = if is_dir {
+            "-".to_string()
+        } else {
+            size.to_string()
+        };
+        let modified = modified.map(format_timestamp).unwrap_or_default();
+        rows.push_str(&format!(
+            "<tr><td><a href=\"{}\">{}{}</a></td><td>{}</td><td>{}</td></tr>\n",
+            escape_html(&href),
+            escape_html(&name),
+            suffix,
+            size,
+            modified
+        ));
+    }
+
+    Response::html(
+        200,
+        format!(
+            "<!DOCTYPE html>\n<html lang=\"en\">\n  <head>\n    <meta charset=\"utf-8\">\n    <title>{title}</title>\n  </head>\n  <body>\n    <h1>{title}</h1>\n    <table>\n<tr><th>Name</th><th>Size</th><th>Modified</th></tr>\n{rows}    </table>\n  </body>\n</html>"
+        ),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get(path: &str) -> HttpRequest {
+        let raw = format!("GET {} HTTP/1.1\r\n\r\n", path);
+        HttpRequest::parse(&mut raw.as_bytes()).unwrap().unwrap()
+    }
+
+    fn fixture(name: &str) -> PathBuf {
+        let root =
+            std::env::temp_dir().join(format!("harbor-static-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::create_dir_all(root.join("site")).unwrap();
+        fs::write(root.join("site/index.html"), "<h1>Site</h1>").unwrap();
+        fs::write(root.join("docs/a&b <x>.txt"), "hello").unwrap();
+        fs::write(root.join("site/plain.txt"), "plain").unwrap();
+        root
+    }
+
+    #[test]
+    fn serve_should_return_files_and_index_pages() {
+        let root = fixture("files");
+        let files = StaticFiles::new(&root);
+
+        let file = files.serve(&get("/site/plain.txt")).unwrap();
+        let index = files.serve(&get("/site/")).unwrap();
+
+        assert_eq!(file.get_body(), b"plain");
+        assert_eq!(index.get_body(), b"<h1>Site</h1>");
+        assert_eq!(
+            file.get_header("Content-Type"),
+            Some("text/plain; charset=utf-8")
+        );
+        assert_eq!(
+            index.get_header("Content-Type"),
+            Some("text/html; charset=utf-8")
+        );
+        assert!(files.serve(&get("/missing")).is_none());
+        assert!(files.serve(&get("/../etc/passwd")).is_none());
+        assert!(files.serve(&get("/%2e%2e/%2E%2E/etc/passwd")).is_none());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn serve_path_should_look_up_the_given_path() {
+        let root = fixture("serve-path");
+        let files = StaticFiles::new(root.join("site"));
+
+        let file = files
+            .serve_path(&get("/assets/plain.txt"), "plain.txt")
+            .unwrap();
+
+        assert_eq!(file.get_body(), b"plain");
+        assert!(files.serve_path(&get("/assets/x"), "missing").is_none());
+        assert_eq!(
+            files
+                .serve_path(&get("/assets/x"), "../docs")
+                .map(|response| response.status()),
+            Some(403)
+        );
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn confine_should_forbid_parent_segments() {
+        let root = fixture("parent");
+        let files = StaticFiles::new(&root);
+
+        let response = files.confine("/site/../../etc/passwd").unwrap_err();
+
+        assert_eq!(response.status(), 403);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn serve_should_forbid_symlinks_out_of_the_root() {
+        let root = fixture("symlink");
+        let outside = root.with_extension("outside");
+        fs::create_dir_all(&outside).unwrap();
+        fs::write(outside.join("secret.txt"), "secret").unwrap();
+        std::os::unix::fs::symlink(outside.join("secret.txt"), root.join("site/secret.txt"))
+            .unwrap();
+        std::os::unix::fs::symlink(&outside, root.join("escape")).unwrap();
+        std::os::unix::fs::symlink(root.join("site/plain.txt"), root.join("inside.txt")).unwrap();
+        let files = StaticFiles::new(&root);
+
+        let file = files.serve(&get("/site/secret.txt")).unwrap();
+        let dir = files.serve(&get("/escape/secret.txt")).unwrap();
+        let inside = files.serve(&get("/inside.txt")).unwrap();
+
+        assert_eq!(file.status(), 403);
+        assert_eq!(dir.status(), 403);
+        assert_eq!(inside.get_body(), b"plain");
+        fs::remove_dir_all(root).unwrap();
+        fs::remove_dir_all(outside).unwrap();
+    }
+
+    #[test]
+    fn serve_should_send_binary_files_byte_for_byte() {
+        let root = fixture("binary");
+        let contents = [
+            0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n', 0x00, 0xff, 0xfe,
+        ];
+        fs::write(root.join("pixel.png"), contents).unwrap();
+        let files = StaticFiles::new(&root);
+
+        let response = files.serve(&get("/pixel.png")).unwrap();
+        let mut buffer = Vec::new();
+        response.write_to(&mut buffer).unwrap();
+
+        assert_eq!(response.get_body(), contents);
+        assert_eq!(response.get_header("Content-Type"), Some("image/png"));
+        assert!(String::from_utf8_lossy(&buffer).contains("Content-Length: 11\r\n"));
+        assert!(buffer.ends_with(&contents));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn serve_should_stream_large_files_without_caching_them() {
+        let root = fixture("large");
+        let contents = vec![0xff_u8; STREAM_THRESHOLD as usize + 1];
+        fs::write(root.join("large.bin"), &contents).unwrap();
+        let cache = Arc::new(FileCache::new(usize::MAX));
+        let files = StaticFiles::new(&root).cache(Arc::clone(&cache));
+
+        let response = files.serve(&get("/large.bin")).unwrap();
+        let mut buffer = Vec::new();
+        response.write_to(&mut buffer).unwrap();
+
+        assert_eq!(response.body_len(), contents.len() as u64);
+        assert!(buffer.ends_with(&contents));
+        assert_eq!(cache.stats().entries, 0);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn serve_should_read_through_the_cache() {
+        let root = fixture("cached");
+        let cache = Arc::new(FileCache::new(1024));
+        let files = StaticFiles::new(&root).cache(Arc::clone(&cache));
+
+        files.serve(&get("/site/plain.txt")).unwrap();
+        let response = files.serve(&get("/site/plain.txt")).unwrap();
+
+        assert_eq!(response.get_body(), b"plain");
+        assert_eq!(cache.stats().hits, 1);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn serve_should_add_cache_headers_from_the_first_matching_rule() {
+        let root = fixture("cache-control");
+        let files = StaticFiles::new(&root).cache_control(vec![
+            CacheRule::new("/site/plain.txt", "no-store"),
+            CacheRule::new("/site/*", "public, max-age=31536000, immutable"),
+            CacheRule::new("*.html", "no-cache"),
+        ]);
+
+        let exact = files.serve(&get("/site/plain.txt")).unwrap();
+        let index = files.serve(&get("/site/")).unwrap();
+        let other = files.serve(&get("/docs/a%26b%20%3Cx%3E.txt")).unwrap();
+
+        assert_eq!(exact.get_header("Cache-Control"), Some("no-store"));
+        assert_eq!(exact.get_header("Expires"), None);
+        assert_eq!(
+            index.get_header("Cache-Control"),
+            Some("public, max-age=31536000, immutable")
+        );
+        assert!(
+            index
+                .get_header("Expires")
+                .is_some_and(|v| v.ends_with(" GMT"))
+        );
+        assert_eq!(other.get_header("Cache-Control"), None);
+        assert!(CacheRule::new("*.html", "no-cache").matches("/about/INDEX.HTML"));
+        assert!(!CacheRule::new("/assets/*", "no-cache").matches("/assets"));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn serve_should_answer_304_unless_modified_since() {
+        let root = fixture("conditional");
+        let files = StaticFiles::new(&root);
+        let conditional = |since: &str| {
+            let raw = format!(
+                "GET /site/plain.txt HTTP/1.1\r\nIf-Modified-Since: {}\r\n\r\n",
+                since
+            );
+            HttpRequest::parse(&mut raw.as_bytes()).unwrap().unwrap()
+        };
+
+        let fresh = files.serve(&get("/site/plain.txt")).unwrap();
+        let last_modified = fresh.get_header("Last-Modified").unwrap().to_string();
+        let unchanged = files.serve(&conditional(&last_modified)).unwrap();
+        let older = files
+            .serve(&conditional("Thu, 01 Jan 1970 00:00:00 GMT"))
+            .unwrap();
+        let future = files
+            .serve(&conditional(&format_http_date(
+                SystemTime::now() + Duration::from_secs(86_400),
+            )))
+            .unwrap();
+
+        assert_eq!(fresh.status(), 200);
+        assert_eq!(unchanged.status(), 304);
+        assert!(unchanged.get_body().is_empty());
+        assert_eq!(
+            unchanged.get_header("Last-Modified"),
+            Some(last_modified.as_str())
+        );
+        assert_eq!(older.status(), 200);
+        assert_eq!(future.status(), 200);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn serve_should_list_directories_only_when_enabled() {
+        let root = fixture("listing");
+
+        assert!(StaticFiles::new(&root).serve(&get("/docs/")).is_none());
+
+        let listing = StaticFiles::new(&root)
+            .directory_listing(true)
+            .serve(&get("/docs/"))
+            .unwrap();
+        let body = String::from_utf8(listing.get_body().to_vec()).unwrap();
+
+        assert!(body.contains("<a href=\"/docs/a%26b%20%3Cx%3E.txt\">a&amp;b &lt;x&gt;.txt</a>"));
+        assert!(body.contains("<td>5</td>"));
+        assert!(body.contains("<a href=\"/\">../</a>"));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn serve_should_send_precompressed_files_the_client_accepts() {
+        let root = fixture("precompressed");
+        fs::write(root.join("site/plain.txt.br"), [0x0b, 0x02, 0x80]).unwrap();
+        fs::write(root.join("site/plain.txt.gz"), [0x1f, 0x8b]).unwrap();
+        let files = StaticFiles::new(&root).precompressed(true);
+        let accepting = |encodings: &str| {
+            let raw = format!(
+                "GET /site/plain.txt HTTP/1.1\r\nAccept-Encoding: {}\r\n\r\n",
+                encodings
+            );
+            HttpRequest::parse(&mut raw.as_bytes()).unwrap().unwrap()
+        };
+
+        let compressed = files.serve(&accepting("gzip, br")).unwrap();
+        let gzipped = files.serve(&accepting("br;q=0.5, gzip")).unwrap();
+        let plain = files.serve(&accepting("deflate")).unwrap();
+        let unconfigured = StaticFiles::new(&root).serve(&accepting("br")).unwrap();
+
+        assert_eq!(compressed.get_body(), [0x0b, 0x02, 0x80]);
+        assert_eq!(compressed.get_header("Content-Encoding"), Some("br"));
+        assert_eq!(
+            compressed.get_header("Content-Type"),
+            Some("text/plain; charset=utf-8")
+        );
+        assert_eq!(compressed.get_header("Vary"), Some("Accept-Encoding"));
+        assert_eq!(gzipped.get_body(), [0x1f, 0x8b]);
+        assert_eq!(gzipped.get_header("Content-Encoding"), Some("gzip"));
+        assert_eq!(plain.get_body(), b"plain");
+        assert_eq!(plain.get_header("Content-Encoding"), None);
+        assert_eq!(plain.get_header("Vary"), Some("Accept-Encoding"));
+        assert_eq!(unconfigured.get_body(), b"plain");
+        assert_eq!(unconfigured.get_header("Vary"), None);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn serve_should_redirect_to_the_preferred_trailing_slash() {
+        let root = fixture("trailing-slash");
+        let location = |files: &StaticFiles, path: &str| {
+            let response = files.serve(&get(path)).unwrap();
+            assert_eq!(response.status(), 301);
+            response.get_header("Location").unwrap().to_string()
+        };
+
+        let add = StaticFiles::new(&root);
+        assert_eq!(location(&add, "/site?lang=en"), "/site/?lang=en");
+        assert_eq!(location(&add, "/site/plain.txt/"), "/site/plain.txt");
+        assert_eq!(add.serve(&get("/site/")).unwrap().status(), 200);
+        assert!(add.serve(&get("/docs")).is_none());
+
+        let remove = StaticFiles::new(&root).trailing_slash(TrailingSlash::Remove);
+        assert_eq!(location(&remove, "/site/"), "/site");
+        let index = remove.serve(&get("/site")).unwrap();
+        assert_eq!(index.get_body(), b"<h1>Site</h1>");
+        assert_eq!(remove.serve(&get("/")).map(|r| r.status()), None);
+
+        let ignore = StaticFiles::new(&root).trailing_slash(TrailingSlash::Ignore);
+        assert_eq!(ignore.serve(&get("/site")).unwrap().status(), 200);
+        assert_eq!(
+            ignore.serve(&get("/site/plain.txt/")).unwrap().status(),
+            200
+        );
+        fs::remove_dir_all(root).unwrap();
+    }
+}
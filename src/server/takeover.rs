@@ -0,0 +1,157 @@
+//! Handing a connection to a handler after `101 Switching Protocols`.
+//!
+//! A handler answering with `Response::switching_protocols` gets the
+//! connection once the `101` has been written, and speaks whatever
+//! protocol it agreed on with the client from then on. The server no
+//! longer reads or writes it, and closes it when the handler is done.
+
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    net::SocketAddr,
+    panic::{self, AssertUnwindSafe},
+};
+
+use super::listener::Stream;
+use crate::{error, warn};
+
+/// A connection taken over after `101 Switching Protocols`.
+///
+/// Reads return the bytes the client sent after its request first, which
+/// the server had already read, and then go to the socket. The stream is
+/// blocking with no timeouts; `stream` sets some. `into_parts` gives up
+/// the wrapper for the `Stream` itself, which for TCP clients holds the
+/// `TcpStream`.
+#[derive(Debug)]
+pub struct Upgraded {
+    stream: Stream,
+    buffered: Vec<u8>,
+    /// How much of `buffered` reads have returned.
+    position: usize,
+    peer: Option<SocketAddr>,
+}
+
+impl Upgraded {
+    pub(crate) fn new(
+        stream: Stream,
+        buffered: Vec<u8>,
+        peer: Option<SocketAddr>,
+    ) -> io::Result<Upgraded> {
+        stream.set_nonblocking(false)?;
+        stream.set_timeouts(None, None)?;
+        Ok(Upgraded {
+            stream,
+            buffered,
+            position: 0,
+            peer,
+        })
+    }
+
+    /// The client's address, as the request saw it.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer
+    }
+
+    /// The underlying connection, e.g. to set timeouts.
+    pub fn stream(&self) -> &Stream {
+        &self.stream
+    }
+
+    /// The underlying connection, and what the client sent after its
+    /// request that reads have not returned yet.
+    pub fn into_parts(self) -> (Stream, Vec<u8>) {
+        let Upgraded {
+            stream,
+            mut buffered,
+            position,
+            ..
+        } = self;
+        buffered.drain(..position);
+        (stream, buffered)
+    }
+}
+
+impl Read for Upgraded {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position < self.buffered.len() {
+            let read = (&self.buffered[self.position..]).read(buf)?;
+            self.position += read;
+            return Ok(read);
+        }
+        self.stream.read(buf)
+    }
+}
+
+impl Write for Upgraded {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+/// What takes the connection over once a `101` response is written.
+pub(crate) struct OnUpgrade(Box<dyn FnOnce(Upgraded) + Send>);
+
+impl OnUpgrade {
+    pub(crate) fn new(on_upgrade: impl FnOnce(Upgraded) + Send + 'static) -> OnUpgrade {
+        OnUpgrade(Box::new(on_upgrade))
+    }
+
+    /// Hands `stream` over, with the bytes read past the request, and
+    /// returns once the new protocol is done with it. A panic ends it
+    /// like a return does.
+    pub(crate) fn run(self, stream: Stream, buffered: Vec<u8>, peer: Option<SocketAddr>) {
+        let upgraded = match Upgraded::new(stream, buffered, peer) {
+            Ok(upgraded) => upgraded,
+            Err(err) => {
+                warn!(
+                    "Failed to hand over the connection from {:?}: {}",
+                    peer, err
+                );
+                return;
+            }
+        };
+        if let Err(panic) = panic::catch_unwind(AssertUnwindSafe(|| (self.0)(upgraded))) {
+            error!(
+                "Upgraded connection from {:?} panicked: {}",
+                peer,
+                crate::panic_message(&*panic)
+            );
+        }
+    }
+}
+
+impl fmt::Debug for OnUpgrade {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("OnUpgrade")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{TcpListener, TcpStream};
+
+    #[test]
+    fn upgraded_should_return_buffered_bytes_before_reading_the_socket() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, peer) = listener.accept().unwrap();
+        client.write_all(b" world").unwrap();
+
+        let mut upgraded =
+            Upgraded::new(Stream::Tcp(server), b"hello".to_vec(), Some(peer)).unwrap();
+        let mut greeting = [0; 11];
+        upgraded.read_exact(&mut greeting).unwrap();
+        upgraded.write_all(b"bye").unwrap();
+        let mut reply = [0; 3];
+        client.read_exact(&mut reply).unwrap();
+
+        assert_eq!(&greeting, b"hello world");
+        assert_eq!(&reply, b"bye");
+        assert_eq!(upgraded.peer_addr(), Some(peer));
+    }
+}
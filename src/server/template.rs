@@ -0,0 +1,94 @@
+//! A tiny template engine for HTML responses.
+//!
+//! `{{name}}` is replaced with the HTML-escaped value of `name` and
+//! `{{{name}}}` with the raw value. Whitespace inside the braces is ignored.
+
+use std::collections::HashMap;
+
+/// Renders `source`, substituting placeholders with values from `context`.
+///
+/// Fails if a placeholder is not closed or names a missing value.
+pub fn render(source: &str, context: &HashMap<&str, String>) -> Result<String, String> {
+    let mut out = String::with_capacity(source.len());
+    let mut rest = source;
+
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let (open, close, raw) = if rest.starts_with("{{{") {
+            ("{{{", "}}}", true)
+        } else {
+            ("{{", "}}", false)
+        };
+        let end = rest.find(close).ok_or_else(|| {
+            format!(
+                "Unclosed placeholder: {}",
+                rest.lines().next().unwrap_or("")
+            )
+        })?;
+        let name = rest[open.len()..end].trim();
+
+        let value = context
+            .get(name)
+            .ok_or_else(|| format!("Missing template value: {}", name))?;
+        if raw {
+            out.push_str(value);
+        } else {
+            out.push_str(&escape_html(value));
+        }
+
+        rest = &rest[end + close.len()..];
+    }
+
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Escapes the characters that are significant in HTML text and attributes.
+pub fn escape_html(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_should_substitute_escaped_and_raw_values() {
+        let context = HashMap::from([
+            ("name", "<b>Ann & Bob</b>".to_string()),
+            ("rows", "<tr></tr>".to_string()),
+        ]);
+
+        let page = render("<p>{{ name }}</p><table>{{{rows}}}</table>", &context).unwrap();
+
+        assert_eq!(
+            page,
+            "<p>&lt;b&gt;Ann &amp; Bob&lt;/b&gt;</p><table><tr></tr></table>"
+        );
+    }
+
+    #[test]
+    fn render_should_fail_on_missing_or_unclosed_placeholders() {
+        let context = HashMap::new();
+
+        assert!(render("{{missing}}", &context).is_err());
+        assert!(render("{{open", &context).is_err());
+        assert_eq!(
+            render("no placeholders", &context).unwrap(),
+            "no placeholders"
+        );
+    }
+}
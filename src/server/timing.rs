@@ -0,0 +1,156 @@
+//! Measuring where a request's time goes.
+//!
+//! The server notes how long each request took to read and how long it
+//! waited for a worker. The `Timing` middleware adds the handler's time,
+//! and once the response has been written the server logs all four.
+
+use std::time::{Duration, Instant};
+
+use super::{
+    middleware::{Middleware, Next},
+    request::HttpRequest,
+    response::Response,
+};
+use crate::info;
+
+/// How long each phase of serving a request took.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Phases {
+    /// Reading the request head and body from the connection.
+    pub parse: Duration,
+    /// Waiting in the pool's queue for a worker. Only the first request on
+    /// a connection waits with the default backend.
+    pub queue: Duration,
+    /// Running the middleware inside `Timing` and the handler.
+    pub handler: Duration,
+    /// Writing the response. Only known once it has been sent, so it is
+    /// logged but never part of the `Server-Timing` header.
+    pub write: Duration,
+}
+
+impl Phases {
+    /// The phases known before the response is written, as the value of a
+    /// `Server-Timing` header, in milliseconds.
+    pub fn server_timing(&self) -> String {
+        [
+            ("parse", self.parse),
+            ("queue", self.queue),
+            ("handler", self.handler),
+        ]
+        .iter()
+        .map(|(name, duration)| format!("{};dur={:.3}", name, duration.as_secs_f64() * 1000.0))
+        .collect::<Vec<_>>()
+        .join(", ")
+    }
+}
+
+/// Middleware measuring the handler of every request, so the server logs
+/// each request's parse, queue, handler and write times at `info` level.
+///
+/// Added first, it also times the middleware added after it.
+///
+/// # Example
+///
+/// ```
+/// # use harbor::server::{Server, Timing};
+/// let server = Server::new("127.0.0.1:7878").middleware(Timing::new().server_timing_header(true));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Timing {
+    header: bool,
+}
+
+impl Timing {
+    pub fn new() -> Timing {
+        Timing::default()
+    }
+
+    /// Also tells the client in a `Server-Timing` header, which browsers'
+    /// developer tools display next to the request. Off by default, as it
+    /// reveals how busy the server is.
+    pub fn server_timing_header(mut self, enabled: bool) -> Timing {
+        self.header = enabled;
+        self
+    }
+}
+
+impl Middleware for Timing {
+    fn handle(&self, request: &mut HttpRequest, next: Next<'_>) -> Response {
+        let started = Instant::now();
+        let response = next.run(request);
+        let phases = Phases {
+            handler: started.elapsed(),
+            ..request.phases()
+        };
+        let response = if self.header {
+            response.header("Server-Timing", &phases.server_timing())
+        } else {
+            response
+        };
+        response.with_phases(phases)
+    }
+}
+
+/// A timed request whose response is being written.
+#[derive(Debug)]
+pub(crate) struct Pending {
+    request: String,
+    phases: Phases,
+}
+
+impl Pending {
+    /// The request to log once `response` is written, if `Timing` timed it.
+    pub(crate) fn new(request: &HttpRequest, response: &Response) -> Option<Pending> {
+        Some(Pending {
+            request: format!("{} {}", request.method(), request.target()),
+            phases: response.phases()?,
+        })
+    }
+
+    /// Logs the request, whose response took `write` to send.
+    pub(crate) fn written(self, write: Duration) {
+        let phases = Phases {
+            write,
+            ..self.phases
+        };
+        info!(
+            "{} parse={:.2?} queue={:.2?} handler={:.2?} write={:.2?}",
+            self.request, phases.parse, phases.queue, phases.handler, phases.write
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::middleware::MiddlewareStack;
+
+    #[test]
+    fn timing_should_add_the_handler_time_to_the_server_phases() {
+        let mut stack = MiddlewareStack::new();
+        stack.push(Timing::new().server_timing_header(true));
+        let mut request = HttpRequest::parse(&mut &b"GET /slow HTTP/1.1\r\n\r\n"[..])
+            .unwrap()
+            .unwrap();
+        request.set_phases(Phases {
+            parse: Duration::from_micros(1500),
+            queue: Duration::from_millis(2),
+            ..Phases::default()
+        });
+
+        let response = stack.run(&mut request, &|_| {
+            std::thread::sleep(Duration::from_millis(5));
+            Response::new(200)
+        });
+        let phases = response.phases().unwrap();
+
+        assert_eq!(phases.parse, Duration::from_micros(1500));
+        assert!(phases.handler >= Duration::from_millis(5));
+        let header = response.get_header("Server-Timing").unwrap();
+        assert!(
+            header.starts_with("parse;dur=1.500, queue;dur=2.000, handler;dur="),
+            "{}",
+            header
+        );
+    }
+}
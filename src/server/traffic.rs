@@ -0,0 +1,128 @@
+//! Counting the bytes requests and responses take on the wire.
+
+use std::{
+    collections::VecDeque,
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+/// How many recent requests `TrafficStats` percentiles are taken over.
+pub const TRAFFIC_SAMPLES: usize = 1024;
+
+/// Totals and recent sizes of the requests a server answered.
+///
+/// Sizes are whole HTTP/1.1 messages: head and body. A request counts the
+/// body its `Content-Length` announced, and a response the head and body
+/// it is written with. HTTP/2 streams count as the same messages would in
+/// HTTP/1.1, before header compression and framing.
+#[derive(Debug, Default)]
+pub struct Traffic {
+    requests: AtomicU64,
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+    /// The request and response sizes of the latest requests, oldest
+    /// first.
+    recent: Mutex<VecDeque<(u64, u64)>>,
+}
+
+/// A snapshot of a `Traffic`'s counters.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TrafficStats {
+    /// Requests answered since the server started.
+    pub requests: u64,
+    /// Bytes of all their requests.
+    pub bytes_read: u64,
+    /// Bytes of all their responses.
+    pub bytes_written: u64,
+    /// Sizes of the latest `TRAFFIC_SAMPLES` requests.
+    pub request_sizes: Sizes,
+    /// Sizes of the latest `TRAFFIC_SAMPLES` responses.
+    pub response_sizes: Sizes,
+}
+
+/// Nearest-rank percentiles of message sizes, in bytes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Sizes {
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+    pub max: u64,
+}
+
+impl Traffic {
+    pub fn new() -> Traffic {
+        Traffic::default()
+    }
+
+    /// Counts a request of `read` bytes answered with `written`.
+    pub fn record(&self, read: u64, written: u64) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.bytes_read.fetch_add(read, Ordering::Relaxed);
+        self.bytes_written.fetch_add(written, Ordering::Relaxed);
+        let mut recent = self.recent.lock().unwrap();
+        if recent.len() == TRAFFIC_SAMPLES {
+            recent.pop_front();
+        }
+        recent.push_back((read, written));
+    }
+
+    pub fn stats(&self) -> TrafficStats {
+        let (requests, responses): (Vec<u64>, Vec<u64>) =
+            self.recent.lock().unwrap().iter().copied().unzip();
+        TrafficStats {
+            requests: self.requests.load(Ordering::Relaxed),
+            bytes_read: self.bytes_read.load(Ordering::Relaxed),
+            bytes_written: self.bytes_written.load(Ordering::Relaxed),
+            request_sizes: Sizes::of(requests),
+            response_sizes: Sizes::of(responses),
+        }
+    }
+}
+
+impl Sizes {
+    fn of(mut sizes: Vec<u64>) -> Sizes {
+        if sizes.is_empty() {
+            return Sizes::default();
+        }
+        sizes.sort_unstable();
+        let percentile = |percent: usize| sizes[(percent * sizes.len()).div_ceil(100).max(1) - 1];
+        Sizes {
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+            max: sizes[sizes.len() - 1],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stats_should_total_and_rank_recent_sizes() {
+        let traffic = Traffic::new();
+        for size in 1..=100 {
+            traffic.record(size, size * 10);
+        }
+
+        let stats = traffic.stats();
+
+        assert_eq!(stats.requests, 100);
+        assert_eq!(stats.bytes_read, 5050);
+        assert_eq!(stats.bytes_written, 50_500);
+        assert_eq!(
+            stats.request_sizes,
+            Sizes {
+                p50: 50,
+                p90: 90,
+                p99: 99,
+                max: 100,
+            }
+        );
+        assert_eq!(stats.response_sizes.p50, 500);
+        assert_eq!(Traffic::new().stats(), TrafficStats::default());
+    }
+}
@@ -0,0 +1,316 @@
+//! Zero-downtime restarts: a new process takes over the listening sockets
+//! of the running one through a Unix control socket.
+//!
+//! The running server listens on the control socket. A new server started
+//! with the same `upgrade_socket` connects to it and receives every
+//! listening socket with `SCM_RIGHTS`, each named by its address. It
+//! answers `ready` once it has them; the old server then removes the
+//! control socket, closes the connection and drains its in-flight requests
+//! while the new one accepts from the very same sockets. Connections
+//! waiting in the kernel's accept queue are never reset, because the
+//! sockets themselves are never closed.
+
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    mem,
+    net::TcpListener,
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        unix::net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    ptr,
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+use super::listener::{BindTarget, Listener};
+use crate::{info, warn};
+
+/// Most sockets handed over at once.
+const MAX_SOCKETS: usize = 64;
+
+/// How long either side waits for the other during a handover.
+const HANDOVER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often the control socket is checked for a new process.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The acknowledgement the new process sends once it holds the sockets.
+const READY: &str = "ready";
+
+/// Listening sockets taken over from a running server, which drains once
+/// `complete` tells it the new one is ready.
+#[derive(Debug)]
+pub(crate) struct Inherited {
+    control: UnixStream,
+    pub(crate) listeners: Vec<Arc<Listener>>,
+}
+
+/// Asks the server listening on the control socket at `path` for its
+/// listeners. `Ok(None)` means no server is running there.
+pub(crate) fn inherit(path: &Path) -> io::Result<Option<Inherited>> {
+    let control = match UnixStream::connect(path) {
+        Ok(control) => control,
+        Err(err)
+            if matches!(
+                err.kind(),
+                io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused
+            ) =>
+        {
+            return Ok(None);
+        }
+        Err(err) => return Err(err),
+    };
+    control.set_read_timeout(Some(HANDOVER_TIMEOUT))?;
+
+    let (names, fds) = receive(&control)?;
+    let names = String::from_utf8(names)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "socket names are not UTF-8"))?;
+    let names: Vec<&str> = names.lines().collect();
+    if names.len() != fds.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("received {} sockets for {} names", fds.len(), names.len()),
+        ));
+    }
+
+    let listeners = names
+        .into_iter()
+        .zip(fds)
+        .map(|(name, fd)| {
+            let listener = match BindTarget::parse(name)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?
+            {
+                BindTarget::Tcp(_) => Listener::Tcp(TcpListener::from(fd)),
+                BindTarget::Unix(path) => Listener::Unix(UnixListener::from(fd), path),
+            };
+            Ok(Arc::new(listener))
+        })
+        .collect::<io::Result<_>>()?;
+    Ok(Some(Inherited { control, listeners }))
+}
+
+impl Inherited {
+    /// Tells the old server to stop accepting, and waits until it has let
+    /// go of the control socket so this one can listen on it.
+    pub(crate) fn complete(self) -> io::Result<()> {
+        let Inherited { mut control, .. } = self;
+        writeln!(control, "{}", READY)?;
+        let mut rest = String::new();
+        BufReader::new(control).read_line(&mut rest)?;
+        Ok(())
+    }
+}
+
+/// Listens on the control socket at `path` until `stopped` returns `true`
+/// or a new process has taken over `listeners`. Returns whether one has.
+pub(crate) fn serve(
+    path: &Path,
+    listeners: &[Arc<Listener>],
+    stopped: impl Fn() -> bool,
+) -> io::Result<bool> {
+    let control = ControlSocket::bind(path)?;
+    control.listener.set_nonblocking(true)?;
+
+    // Acceptors sharing a socket have one `Arc` each, side by side.
+    let mut listeners = listeners.to_vec();
+    listeners.dedup_by(|a, b| Arc::ptr_eq(a, b));
+
+    while !stopped() {
+        let stream = match control.listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep(POLL_INTERVAL);
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+        match hand_over(&stream, &listeners) {
+            Ok(()) => {
+                info!("A new process took over the listeners; draining.");
+                // Removing the file before hanging up lets the new process
+                // bind its own control socket right away.
+                drop(control);
+                drop(stream);
+                return Ok(true);
+            }
+            Err(err) => warn!("Handover to a new process failed: {}", err),
+        }
+    }
+    Ok(false)
+}
+
+/// Sends `listeners` to the new process on `stream` and waits for it to
+/// be ready.
+fn hand_over(stream: &UnixStream, listeners: &[Arc<Listener>]) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(HANDOVER_TIMEOUT))?;
+
+    let mut names = String::new();
+    let mut fds = Vec::new();
+    for listener in listeners {
+        names.push_str(&format!("{}\n", listener.local_target()?));
+        fds.push(match &**listener {
+            Listener::Tcp(listener) => listener.as_raw_fd(),
+            Listener::Unix(listener, _) => listener.as_raw_fd(),
+        });
+    }
+    send(stream, names.as_bytes(), &fds)?;
+
+    let mut answer = String::new();
+    BufReader::new(stream).read_line(&mut answer)?;
+    if answer.trim_end() != READY {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the new process did not confirm the handover",
+        ));
+    }
+    Ok(())
+}
+
+/// The control socket, whose file is removed when it is dropped.
+struct ControlSocket {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+impl ControlSocket {
+    fn bind(path: &Path) -> io::Result<ControlSocket> {
+        // A file left by a crashed server refuses connections.
+        if UnixStream::connect(path).is_err() {
+            match std::fs::remove_file(path) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+                _ => {}
+            }
+        }
+        Ok(ControlSocket {
+            listener: UnixListener::bind(path)?,
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Sends `data` with `fds` attached as one message.
+fn send(stream: &UnixStream, data: &[u8], fds: &[RawFd]) -> io::Result<()> {
+    if fds.len() > MAX_SOCKETS {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("cannot hand over more than {} sockets", MAX_SOCKETS),
+        ));
+    }
+    let fds_len = mem::size_of_val(fds) as u32;
+    let mut control = control_buffer(fds.len());
+    let mut iov = libc::iovec {
+        iov_base: data.as_ptr() as *mut libc::c_void,
+        iov_len: data.len(),
+    };
+    // SAFETY: the message points at `iov` and `control`, which outlive the
+    // call, and `control` is aligned and sized for one header holding
+    // `fds`.
+    let sent = unsafe {
+        let mut message: libc::msghdr = mem::zeroed();
+        message.msg_iov = &mut iov;
+        message.msg_iovlen = 1;
+        message.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        message.msg_controllen = libc::CMSG_SPACE(fds_len) as _;
+
+        let header = libc::CMSG_FIRSTHDR(&message);
+        (*header).cmsg_level = libc::SOL_SOCKET;
+        (*header).cmsg_type = libc::SCM_RIGHTS;
+        (*header).cmsg_len = libc::CMSG_LEN(fds_len) as _;
+        ptr::copy_nonoverlapping(
+            fds.as_ptr(),
+            libc::CMSG_DATA(header) as *mut RawFd,
+            fds.len(),
+        );
+
+        libc::sendmsg(stream.as_raw_fd(), &message, 0)
+    };
+    if sent < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Receives a message sent by `send`, with the descriptors attached to it.
+fn receive(stream: &UnixStream) -> io::Result<(Vec<u8>, Vec<OwnedFd>)> {
+    let mut data = vec![0; 64 * 1024];
+    let mut control = control_buffer(MAX_SOCKETS);
+    let mut iov = libc::iovec {
+        iov_base: data.as_mut_ptr() as *mut libc::c_void,
+        iov_len: data.len(),
+    };
+    // SAFETY: as in `send`; the kernel fills in at most
+    // `msg_controllen` bytes of `control`.
+    let mut message: libc::msghdr = unsafe { mem::zeroed() };
+    message.msg_iov = &mut iov;
+    message.msg_iovlen = 1;
+    message.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    message.msg_controllen = mem::size_of_val(control.as_slice()) as _;
+    let read = unsafe { libc::recvmsg(stream.as_raw_fd(), &mut message, libc::MSG_CMSG_CLOEXEC) };
+    if read < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut fds = Vec::new();
+    // SAFETY: the headers are walked with the kernel's macros inside the
+    // buffer it filled, and each descriptor it passed is owned only here.
+    unsafe {
+        let mut header = libc::CMSG_FIRSTHDR(&message);
+        while !header.is_null() {
+            if (*header).cmsg_level == libc::SOL_SOCKET && (*header).cmsg_type == libc::SCM_RIGHTS {
+                let count = ((*header).cmsg_len as usize - libc::CMSG_LEN(0) as usize)
+                    / mem::size_of::<RawFd>();
+                let first = libc::CMSG_DATA(header) as *const RawFd;
+                for index in 0..count {
+                    fds.push(OwnedFd::from_raw_fd(ptr::read_unaligned(first.add(index))));
+                }
+            }
+            header = libc::CMSG_NXTHDR(&message, header);
+        }
+    }
+    if message.msg_flags & libc::MSG_CTRUNC != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("more than {} sockets were handed over", MAX_SOCKETS),
+        ));
+    }
+    data.truncate(read as usize);
+    Ok((data, fds))
+}
+
+/// A buffer aligned for control messages, with room for `fds` descriptors.
+fn control_buffer(fds: usize) -> Vec<u64> {
+    // SAFETY: `CMSG_SPACE` only computes a size.
+    let space = unsafe { libc::CMSG_SPACE((fds * mem::size_of::<RawFd>()) as u32) } as usize;
+    vec![0; space.div_ceil(mem::size_of::<u64>())]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn send_should_pass_sockets_to_the_receiver() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let (sender, receiver) = UnixStream::pair().unwrap();
+
+        send(&sender, b"first\n", &[listener.as_raw_fd()]).unwrap();
+        let (data, fds) = receive(&receiver).unwrap();
+        let inherited = TcpListener::from(fds.into_iter().next().unwrap());
+
+        assert_eq!(data, b"first\n");
+        assert_eq!(
+            inherited.local_addr().unwrap(),
+            listener.local_addr().unwrap()
+        );
+    }
+}
@@ -0,0 +1,164 @@
+//! Percent-encoding and path normalization for request targets, and
+//! `application/x-www-form-urlencoded` parsing.
+
+/// Decodes `%XX` escapes in `input`.
+///
+/// Fails on truncated or non-hex escapes and when the decoded bytes are not
+/// valid UTF-8.
+pub fn percent_decode(input: &str) -> Result<String, String> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        if bytes[index] == b'%' {
+            let value = bytes
+                .get(index + 1..index + 3)
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                .ok_or_else(|| format!("Invalid percent-encoding in: {}", input))?;
+            decoded.push(value);
+            index += 3;
+        } else {
+            decoded.push(bytes[index]);
+            index += 1;
+        }
+    }
+    String::from_utf8(decoded).map_err(|_| format!("Not valid UTF-8 once decoded: {}", input))
+}
+
+/// Splits `name=value&...` pairs as sent by HTML forms and query strings,
+/// decoding `+` as a space and `%XX` escapes. Empty pairs are skipped and
+/// a pair without `=` has an empty value.
+pub fn parse_form(input: &str) -> Result<Vec<(String, String)>, String> {
+    input
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let decode = |text: &str| percent_decode(&text.replace('+', " "));
+            Ok((decode(name)?, decode(value)?))
+        })
+        .collect()
+}
+
+/// Percent-encodes everything in `path` except unreserved characters and `/`.
+pub fn encode_path(path: &str) -> String {
+    let mut out = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                out.push(byte as char)
+            }
+            byte => out.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    out
+}
+
+/// Percent-encodes the bytes of `location` that may not appear in a URI:
+/// spaces, control characters (including CR and LF) and non-ASCII. Other
+/// characters, and existing `%XX` escapes, are kept, so the result is safe
+/// to put in a `Location` header.
+pub fn encode_location(location: &str) -> String {
+    let mut out = String::with_capacity(location.len());
+    for byte in location.bytes() {
+        match byte {
+            b'!'..=b'~' if !b"\"<>\\^`{|}".contains(&byte) => out.push(byte as char),
+            byte => out.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    out
+}
+
+/// Collapses repeated slashes and resolves `.` and `..` segments.
+///
+/// The result always starts with `/`; `..` never climbs above the root.
+/// A trailing slash, or a trailing `.`/`..` segment, yields a trailing slash.
+pub fn normalize_path(path: &str) -> String {
+    let mut segments: Vec<&str> = Vec::new();
+    let mut trailing_slash = false;
+    for segment in path.split('/') {
+        trailing_slash = false;
+        match segment {
+            "" => trailing_slash = true,
+            "." => trailing_slash = true,
+            ".." => {
+                segments.pop();
+                trailing_slash = true;
+            }
+            segment => segments.push(segment),
+        }
+    }
+
+    let mut normalized = String::with_capacity(path.len() + 1);
+    for segment in &segments {
+        normalized.push('/');
+        normalized.push_str(segment);
+    }
+    if trailing_slash || segments.is_empty() {
+        normalized.push('/');
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_location_should_escape_line_breaks_and_spaces() {
+        assert_eq!(
+            encode_location("/new page?q=1\r\nSet-Cookie: x"),
+            "/new%20page?q=1%0D%0ASet-Cookie:%20x"
+        );
+        assert_eq!(
+            encode_location("https://example.com/a%20b#top"),
+            "https://example.com/a%20b#top"
+        );
+        assert_eq!(encode_location("/café"), "/caf%C3%A9");
+    }
+
+    #[test]
+    fn parse_form_should_decode_plus_and_percent_escapes() {
+        assert_eq!(
+            parse_form("name=Ada+Lovelace&note=1%2B1%3D2&&flag&empty=").unwrap(),
+            vec![
+                ("name".to_string(), "Ada Lovelace".to_string()),
+                ("note".to_string(), "1+1=2".to_string()),
+                ("flag".to_string(), String::new()),
+                ("empty".to_string(), String::new()),
+            ]
+        );
+        assert!(parse_form("bad=%zz").is_err());
+    }
+
+    #[test]
+    fn percent_decode_should_decode_escapes() {
+        assert_eq!(percent_decode("/hello%20world").unwrap(), "/hello world");
+        assert_eq!(percent_decode("/caf%C3%A9").unwrap(), "/café");
+        assert_eq!(percent_decode("/plain").unwrap(), "/plain");
+    }
+
+    #[test]
+    fn percent_decode_should_reject_invalid_escapes() {
+        assert!(percent_decode("/bad%2").is_err());
+        assert!(percent_decode("/bad%zz").is_err());
+        assert!(percent_decode("/bad%FF").is_err());
+    }
+
+    #[test]
+    fn encode_path_should_escape_reserved_characters() {
+        assert_eq!(encode_path("/a b/c&d.txt"), "/a%20b/c%26d.txt");
+    }
+
+    #[test]
+    fn normalize_path_should_resolve_dot_segments_and_slashes() {
+        assert_eq!(normalize_path("//"), "/");
+        assert_eq!(normalize_path(""), "/");
+        assert_eq!(normalize_path("/a//b"), "/a/b");
+        assert_eq!(normalize_path("/a/./b/"), "/a/b/");
+        assert_eq!(normalize_path("/a/../b"), "/b");
+        assert_eq!(normalize_path("/a/b/.."), "/a/");
+        assert_eq!(normalize_path("/../../etc/passwd"), "/etc/passwd");
+    }
+}
@@ -0,0 +1,239 @@
+//! Helpers for testing handlers against a real, running server, and jobs
+//! on a real pool without sleeping.
+//!
+//! ```
+//! use harbor::client;
+//! use harbor::server::{Response, Router};
+//! use harbor::test::TestServer;
+//!
+//! let router = Router::new().get("/ping", |_| Response::new(200).body("pong"));
+//! let server = TestServer::spawn(router);
+//!
+//! let response = client::get(&server.url("/ping")).unwrap();
+//! assert_eq!(response.get_body(), b"pong");
+//! ```
+//!
+//! A `Gate` holds jobs at a known point, and `wait_for_state` waits for a
+//! job to get somewhere, polling instead of guessing a sleep:
+//!
+//! ```
+//! use std::time::Duration;
+//! use harbor::{JobStatus, ThreadPool};
+//! use harbor::test::{Gate, wait_for_state};
+//!
+//! let pool = ThreadPool::build(1).unwrap();
+//! let gate = Gate::new();
+//! let blocked = pool.execute(gate.job());
+//! let queued = pool.execute(|| Ok("done".to_string()));
+//!
+//! let timeout = Duration::from_secs(5);
+//! let processing = |state: &JobStatus| matches!(state, JobStatus::Processing);
+//! wait_for_state(&pool.tracker(), blocked, processing, timeout);
+//! assert_eq!(pool.get_job_metadata(queued).unwrap().state().name(), "Pending");
+//! gate.open();
+//! let completed = |state: &JobStatus| matches!(state, JobStatus::Completed);
+//! let job = wait_for_state(&pool.tracker(), queued, completed, timeout);
+//! assert_eq!(job.result(), Some("done"));
+//! ```
+
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Condvar, Mutex},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use uuid::Uuid;
+
+use crate::{
+    JobMetadata, JobStatus, JobTracker,
+    server::{Router, Server, ServerHandle},
+};
+
+/// How long `spawn` waits for the server to bind before giving up.
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often `wait_for_state` looks at the job again.
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// A `Server` running on its own thread on a free local port.
+///
+/// The server is stopped, and its thread joined, when the `TestServer` is
+/// dropped. In-flight connections get a one second grace period.
+#[derive(Debug)]
+pub struct TestServer {
+    addr: SocketAddr,
+    handle: ServerHandle,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl TestServer {
+    /// Serves `router` on `127.0.0.1` at a port picked by the operating
+    /// system.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the server fails to start.
+    pub fn spawn(router: Router) -> TestServer {
+        TestServer::start(Server::new("127.0.0.1:0").router(router))
+    }
+
+    /// Runs a fully configured `server`, which should listen on port 0 so
+    /// tests can run in parallel.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the server fails to start or does not listen on TCP.
+    pub fn start(server: Server) -> TestServer {
+        let server = server.grace_period(Duration::from_secs(1));
+        let handle = server.handle();
+        let thread = thread::spawn(move || {
+            // A failed start is logged, and noticed below as the thread ending.
+            let _ = server.run();
+        });
+
+        let started = Instant::now();
+        let addr = loop {
+            if let Some(targets) = handle.local_targets() {
+                match handle.local_addr() {
+                    Some(addr) => break addr,
+                    None => panic!("test server is not listening on TCP: {:?}", targets),
+                }
+            }
+            if thread.is_finished() || started.elapsed() > STARTUP_TIMEOUT {
+                panic!("test server failed to start; see the log for details");
+            }
+            thread::sleep(Duration::from_millis(1));
+        };
+
+        TestServer {
+            addr,
+            handle,
+            thread: Some(thread),
+        }
+    }
+
+    /// The address the server is listening on.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// An `http://` URL for `path` on this server.
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.addr, path)
+    }
+
+    /// A handle that can stop the server early.
+    pub fn handle(&self) -> ServerHandle {
+        self.handle.clone()
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.handle.stop();
+        if let Some(thread) = self.thread.take() {
+            // A panic on the server thread has already been reported.
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Holds the jobs made by `job` until `open` is called, so a test knows a
+/// worker is busy and what is still queued behind it.
+///
+/// Clones share the gate. Jobs started after `open` pass straight through.
+#[derive(Debug, Clone, Default)]
+pub struct Gate {
+    state: Arc<(Mutex<GateState>, Condvar)>,
+}
+
+#[derive(Debug, Default)]
+struct GateState {
+    open: bool,
+    /// Jobs blocked at the gate right now.
+    waiting: usize,
+}
+
+impl Gate {
+    pub fn new() -> Gate {
+        Gate::default()
+    }
+
+    /// A job that blocks until the gate opens, then completes with
+    /// `"released"`.
+    pub fn job(&self) -> impl FnOnce() -> Result<String, String> + Send + 'static {
+        let gate = self.clone();
+        move || {
+            let (state, changed) = &*gate.state;
+            let mut state = state.lock().unwrap();
+            state.waiting += 1;
+            changed.notify_all();
+            while !state.open {
+                state = changed.wait(state).unwrap();
+            }
+            state.waiting -= 1;
+            Ok("released".to_string())
+        }
+    }
+
+    /// Waits until `count` jobs are blocked at the gate.
+    ///
+    /// # Panics
+    ///
+    /// Panics if they are not within `timeout`.
+    pub fn wait_for_waiting(&self, count: usize, timeout: Duration) {
+        let (state, changed) = &*self.state;
+        let state = state.lock().unwrap();
+        let (state, result) = changed
+            .wait_timeout_while(state, timeout, |state| state.waiting < count)
+            .unwrap();
+        if result.timed_out() {
+            panic!(
+                "{} jobs were blocked at the gate after {:?}, expected {}",
+                state.waiting, timeout, count
+            );
+        }
+    }
+
+    /// Releases the blocked jobs, and lets later ones pass.
+    pub fn open(&self) {
+        let (state, changed) = &*self.state;
+        state.lock().unwrap().open = true;
+        changed.notify_all();
+    }
+}
+
+/// Waits until the state of the job `id` matches `state`, such as
+/// `|state| matches!(state, JobStatus::Completed)`, and returns its
+/// metadata at that point.
+///
+/// # Panics
+///
+/// Panics if the job is unknown to `tracker`, or its state doesn't match
+/// within `timeout`.
+pub fn wait_for_state(
+    tracker: &JobTracker,
+    id: Uuid,
+    state: fn(&JobStatus) -> bool,
+    timeout: Duration,
+) -> JobMetadata {
+    let started = Instant::now();
+    loop {
+        let Some(job) = tracker.get(id) else {
+            panic!("job {} is not tracked by the pool", id);
+        };
+        if state(job.state()) {
+            return job;
+        }
+        if started.elapsed() > timeout {
+            panic!(
+                "job {} was still {} after {:?}",
+                id,
+                job.state().name(),
+                timeout
+            );
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
@@ -0,0 +1,496 @@
+//! A parser for the subset of TOML used by harbor's configuration files.
+//!
+//! Supported: `[table]` and `[[array.of.tables]]` headers, bare, quoted and
+//! dotted keys, basic and literal strings, integers, floats, booleans,
+//! arrays (which may span lines) and inline tables. Dates and multi-line
+//! strings are not.
+
+use std::{collections::BTreeMap, fmt};
+
+pub type Table = BTreeMap<String, Value>;
+
+/// A parsed TOML value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    Array(Vec<Value>),
+    Table(Table),
+}
+
+impl Value {
+    /// A human readable name of the value's type, for error messages.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::Integer(_) => "integer",
+            Value::Float(_) => "float",
+            Value::Boolean(_) => "boolean",
+            Value::Array(_) => "array",
+            Value::Table(_) => "table",
+        }
+    }
+}
+
+/// A syntax error with the line it was found on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Parses a TOML document into its root table.
+pub fn parse(input: &str) -> Result<Table, ParseError> {
+    let mut parser = Parser {
+        chars: input.chars().collect(),
+        pos: 0,
+        line: 1,
+    };
+    parser.document()
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+}
+
+impl Parser {
+    fn document(&mut self) -> Result<Table, ParseError> {
+        let mut root = Table::new();
+        // Path of the table that `key = value` lines currently write into.
+        let mut current: Vec<String> = Vec::new();
+
+        loop {
+            self.skip_blank_lines();
+            match self.peek() {
+                None => return Ok(root),
+                Some('[') => {
+                    let array = self.peek_at(1) == Some('[');
+                    self.pos += if array { 2 } else { 1 };
+                    self.skip_spaces();
+                    let path = self.key_path()?;
+                    self.skip_spaces();
+                    let closing = if array { "]]" } else { "]" };
+                    for expected in closing.chars() {
+                        if self.next() != Some(expected) {
+                            return Err(
+                                self.error(format!("expected '{}' after table name", closing))
+                            );
+                        }
+                    }
+                    self.end_of_line()?;
+
+                    if array {
+                        self.push_array_table(&mut root, &path)?;
+                    } else {
+                        self.table_at(&mut root, &path)?;
+                    }
+                    current = path;
+                }
+                Some(_) => {
+                    let path = self.key_path()?;
+                    self.skip_spaces();
+                    if self.next() != Some('=') {
+                        return Err(self.error("expected '=' after key".to_string()));
+                    }
+                    self.skip_spaces();
+                    let value = self.value()?;
+                    self.end_of_line()?;
+
+                    let table = self.table_at(&mut root, &current)?;
+                    self.insert(table, &path, value)?;
+                }
+            }
+        }
+    }
+
+    /// Walks `path` from `root`, creating tables as needed, and returns the
+    /// table at its end. Arrays of tables resolve to their last element.
+    fn table_at<'a>(
+        &self,
+        root: &'a mut Table,
+        path: &[String],
+    ) -> Result<&'a mut Table, ParseError> {
+        let mut table = root;
+        for key in path {
+            let entry = table
+                .entry(key.clone())
+                .or_insert_with(|| Value::Table(Table::new()));
+            table = match entry {
+                Value::Table(inner) => inner,
+                Value::Array(items) => match items.last_mut() {
+                    Some(Value::Table(inner)) => inner,
+                    _ => return Err(self.error(format!("'{}' is not a table", key))),
+                },
+                _ => return Err(self.error(format!("'{}' is already defined as a value", key))),
+            };
+        }
+        Ok(table)
+    }
+
+    fn push_array_table(&self, root: &mut Table, path: &[String]) -> Result<(), ParseError> {
+        let (last, parents) = match path.split_last() {
+            Some(split) => split,
+            None => return Err(self.error("empty table name".to_string())),
+        };
+        let parent = self.table_at(root, parents)?;
+        match parent
+            .entry(last.clone())
+            .or_insert_with(|| Value::Array(Vec::new()))
+        {
+            Value::Array(items) => {
+                items.push(Value::Table(Table::new()));
+                Ok(())
+            }
+            _ => Err(self.error(format!("'{}' is not an array of tables", last))),
+        }
+    }
+
+    fn insert(&self, table: &mut Table, path: &[String], value: Value) -> Result<(), ParseError> {
+        let (last, parents) = match path.split_last() {
+            Some(split) => split,
+            None => return Err(self.error("empty key".to_string())),
+        };
+        let table = self.table_at(table, parents)?;
+        if table.contains_key(last) {
+            return Err(self.error(format!("duplicate key '{}'", last)));
+        }
+        table.insert(last.clone(), value);
+        Ok(())
+    }
+
+    fn key_path(&mut self) -> Result<Vec<String>, ParseError> {
+        let mut path = vec![self.key()?];
+        loop {
+            self.skip_spaces();
+            if self.peek() != Some('.') {
+                return Ok(path);
+            }
+            self.pos += 1;
+            self.skip_spaces();
+            path.push(self.key()?);
+        }
+    }
+
+    fn key(&mut self) -> Result<String, ParseError> {
+        match self.peek() {
+            Some('"') => self.basic_string(),
+            Some('\'') => self.literal_string(),
+            _ => {
+                let start = self.pos;
+                while let Some(c) = self.peek() {
+                    if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                        self.pos += 1;
+                    } else {
+                        break;
+                    }
+                }
+                if start == self.pos {
+                    return Err(self.error("expected a key".to_string()));
+                }
+                Ok(self.chars[start..self.pos].iter().collect())
+            }
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, ParseError> {
+        match self.peek() {
+            Some('"') => self.basic_string().map(Value::String),
+            Some('\'') => self.literal_string().map(Value::String),
+            Some('[') => self.array(),
+            Some('{') => self.inline_table(),
+            Some('t') if self.starts_with("true") => {
+                self.pos += 4;
+                Ok(Value::Boolean(true))
+            }
+            Some('f') if self.starts_with("false") => {
+                self.pos += 5;
+                Ok(Value::Boolean(false))
+            }
+            Some(c) if c == '+' || c == '-' || c.is_ascii_digit() => self.number(),
+            Some(c) => Err(self.error(format!("unexpected character '{}' in value", c))),
+            None => Err(self.error("expected a value".to_string())),
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, ParseError> {
+        self.pos += 1;
+        let mut items = Vec::new();
+        loop {
+            self.skip_whitespace_and_comments();
+            if self.peek() == Some(']') {
+                self.pos += 1;
+                return Ok(Value::Array(items));
+            }
+            items.push(self.value()?);
+            self.skip_whitespace_and_comments();
+            match self.next() {
+                Some(',') => continue,
+                Some(']') => return Ok(Value::Array(items)),
+                _ => return Err(self.error("expected ',' or ']' in array".to_string())),
+            }
+        }
+    }
+
+    fn inline_table(&mut self) -> Result<Value, ParseError> {
+        self.pos += 1;
+        let mut table = Table::new();
+        self.skip_spaces();
+        if self.peek() == Some('}') {
+            self.pos += 1;
+            return Ok(Value::Table(table));
+        }
+        loop {
+            self.skip_spaces();
+            let path = self.key_path()?;
+            self.skip_spaces();
+            if self.next() != Some('=') {
+                return Err(self.error("expected '=' in inline table".to_string()));
+            }
+            self.skip_spaces();
+            let value = self.value()?;
+            self.insert(&mut table, &path, value)?;
+            self.skip_spaces();
+            match self.next() {
+                Some(',') => continue,
+                Some('}') => return Ok(Value::Table(table)),
+                _ => return Err(self.error("expected ',' or '}' in inline table".to_string())),
+            }
+        }
+    }
+
+    fn basic_string(&mut self) -> Result<String, ParseError> {
+        self.pos += 1;
+        let mut out = String::new();
+        loop {
+            let c = match self.peek() {
+                Some('\n') | None => return Err(self.error("unterminated string".to_string())),
+                Some(c) => c,
+            };
+            self.pos += 1;
+            match c {
+                '"' => return Ok(out),
+                '\\' => {
+                    let escaped = match self.next() {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('u') => self.unicode(4)?,
+                        Some('U') => self.unicode(8)?,
+                        _ => return Err(self.error("invalid escape sequence".to_string())),
+                    };
+                    out.push(escaped);
+                }
+                c => out.push(c),
+            }
+        }
+    }
+
+    fn unicode(&mut self, digits: usize) -> Result<char, ParseError> {
+        let hex: String = self.chars.iter().skip(self.pos).take(digits).collect();
+        self.pos += hex.chars().count();
+        u32::from_str_radix(&hex, 16)
+            .ok()
+            .filter(|_| hex.len() == digits)
+            .and_then(char::from_u32)
+            .ok_or_else(|| self.error("invalid unicode escape".to_string()))
+    }
+
+    fn literal_string(&mut self) -> Result<String, ParseError> {
+        self.pos += 1;
+        let mut out = String::new();
+        loop {
+            match self.peek() {
+                Some('\'') => {
+                    self.pos += 1;
+                    return Ok(out);
+                }
+                Some('\n') | None => return Err(self.error("unterminated string".to_string())),
+                Some(c) => {
+                    self.pos += 1;
+                    out.push(c);
+                }
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Value, ParseError> {
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.' | '_') {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+        let text: String = self.chars[start..self.pos].iter().collect();
+        let clean = text.replace('_', "");
+
+        if let Ok(integer) = clean.parse::<i64>() {
+            return Ok(Value::Integer(integer));
+        }
+        if clean.contains(['.', 'e', 'E'])
+            && let Ok(float) = clean.parse::<f64>()
+            && float.is_finite()
+        {
+            return Ok(Value::Float(float));
+        }
+        Err(self.error(format!("invalid number '{}'", text)))
+    }
+
+    /// Consumes trailing spaces and an optional comment up to the newline.
+    fn end_of_line(&mut self) -> Result<(), ParseError> {
+        self.skip_spaces();
+        if self.peek() == Some('#') {
+            self.skip_comment();
+        }
+        match self.peek() {
+            None | Some('\n') => Ok(()),
+            Some('\r') if self.peek_at(1) == Some('\n') => Ok(()),
+            Some(c) => Err(self.error(format!("unexpected '{}' after value", c))),
+        }
+    }
+
+    fn skip_blank_lines(&mut self) {
+        self.skip_whitespace_and_comments();
+    }
+
+    fn skip_whitespace_and_comments(&mut self) {
+        while let Some(c) = self.peek() {
+            match c {
+                ' ' | '\t' | '\r' => self.pos += 1,
+                '\n' => {
+                    self.pos += 1;
+                    self.line += 1;
+                }
+                '#' => self.skip_comment(),
+                _ => break,
+            }
+        }
+    }
+
+    fn skip_comment(&mut self) {
+        while let Some(c) = self.peek() {
+            if c == '\n' {
+                break;
+            }
+            self.pos += 1;
+        }
+    }
+
+    fn skip_spaces(&mut self) {
+        while let Some(' ' | '\t') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn starts_with(&self, word: &str) -> bool {
+        word.chars()
+            .enumerate()
+            .all(|(offset, c)| self.peek_at(offset) == Some(c))
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.peek_at(0)
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn error(&self, message: String) -> ParseError {
+        ParseError {
+            line: self.line,
+            message,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_should_read_tables_and_values() {
+        let document = r#"
+            # harbor settings
+            address = "127.0.0.1:8080"   # trailing comment
+            workers = 8
+            ratio = 0.5
+            enabled = true
+            hosts = [
+                "a.example",
+                'b.example',   # literal string
+            ]
+
+            [timeouts]
+            read_secs = 1_000
+            point = { x = 1, y = -2 }
+
+            [[rule]]
+            from = "/old"
+            [[rule]]
+            from = "/older"
+        "#;
+
+        let table = parse(document).unwrap();
+
+        assert_eq!(
+            table["address"],
+            Value::String("127.0.0.1:8080".to_string())
+        );
+        assert_eq!(table["workers"], Value::Integer(8));
+        assert_eq!(table["ratio"], Value::Float(0.5));
+        assert_eq!(table["enabled"], Value::Boolean(true));
+        assert_eq!(
+            table["hosts"],
+            Value::Array(vec![
+                Value::String("a.example".to_string()),
+                Value::String("b.example".to_string())
+            ])
+        );
+        let Value::Table(timeouts) = &table["timeouts"] else {
+            panic!("timeouts is not a table");
+        };
+        assert_eq!(timeouts["read_secs"], Value::Integer(1000));
+        let Value::Array(rules) = &table["rule"] else {
+            panic!("rule is not an array");
+        };
+        assert_eq!(rules.len(), 2);
+    }
+
+    #[test]
+    fn parse_should_report_the_line_of_errors() {
+        let error = parse("a = 1\nb = \"open\nc = 2").unwrap_err();
+
+        assert_eq!(error.line, 2);
+        assert!(parse("a = 1\na = 2").is_err());
+        assert!(parse("a = 1 b").is_err());
+        assert!(parse("[table\nx = 1").is_err());
+        assert!(parse("x = nope").is_err());
+    }
+}
@@ -0,0 +1,160 @@
+use harbor::{client, json::Value};
+use std::{
+    fs,
+    io::{BufRead, BufReader},
+    path::Path,
+    process::{Child, Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// A running `harbor-jobs`, killed when dropped.
+struct Service {
+    child: Child,
+    url: String,
+}
+
+impl Service {
+    fn start(journal: &Path) -> Service {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_harbor-jobs"))
+            .args(["--addr", "127.0.0.1:0", "--workers", "2", "--journal"])
+            .arg(journal)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("Failed to start harbor-jobs.");
+        let mut line = String::new();
+        BufReader::new(child.stdout.take().unwrap())
+            .read_line(&mut line)
+            .expect("Failed to read the listening address.");
+        let url = line
+            .trim()
+            .strip_prefix("Listening on ")
+            .unwrap_or_else(|| panic!("Unexpected first line: {:?}", line))
+            .to_string();
+        Service { child, url }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.url, path)
+    }
+
+    /// Polls `/metrics` until it has the line `metric`.
+    fn wait_for_metric(&self, metric: &str) -> String {
+        let started = Instant::now();
+        loop {
+            let response = client::get(&self.url("/metrics")).expect("Request failed.");
+            let metrics = String::from_utf8_lossy(response.get_body()).into_owned();
+            if metrics.lines().any(|line| line == metric) {
+                return metrics;
+            }
+            assert!(
+                started.elapsed() < Duration::from_secs(5),
+                "{} never showed up in:\n{}",
+                metric,
+                metrics
+            );
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+}
+
+impl Drop for Service {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn journal(name: &str) -> std::path::PathBuf {
+    let path =
+        std::env::temp_dir().join(format!("harbor-jobs-{}-{}.log", name, std::process::id()));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+#[test]
+fn test_jobs_are_queued_run_and_counted() {
+    let journal = journal("run");
+    let service = Service::start(&journal);
+
+    let response = client::post(
+        &service.url("/jobs"),
+        r#"{"job": "sum", "args": {"numbers": [1, 2, 3.5]}}"#,
+    )
+    .expect("Request failed.");
+    assert_eq!(response.status(), 202);
+    let body = Value::parse(std::str::from_utf8(response.get_body()).unwrap()).unwrap();
+    let id = body.get("id").and_then(Value::as_str).unwrap().to_string();
+
+    service.wait_for_metric(r#"harbor_jobs_succeeded_total{job="sum"} 1"#);
+    let status = client::get(&service.url(&format!("/jobs/{}", id))).expect("Request failed.");
+    let status = Value::parse(std::str::from_utf8(status.get_body()).unwrap()).unwrap();
+    assert_eq!(
+        status.get("state").and_then(Value::as_str),
+        Some("Completed")
+    );
+    assert_eq!(status.get("result").and_then(Value::as_str), Some("6.5"));
+
+    let refused = client::post(&service.url("/jobs"), r#"{"job": "sum", "args": {}}"#)
+        .expect("Request failed.");
+    assert_eq!(refused.status(), 400);
+    let metrics = service.wait_for_metric(r#"harbor_jobs_submitted_total{job="sum"} 1"#);
+    assert!(
+        metrics.contains("harbor_http_requests_total "),
+        "{}",
+        metrics
+    );
+    assert_eq!(fs::read_to_string(&journal).unwrap().lines().count(), 2);
+    let _ = fs::remove_file(journal);
+}
+
+#[test]
+fn test_unfinished_jobs_in_the_journal_run_again_on_start() {
+    let journal = journal("replay");
+    fs::write(
+        &journal,
+        "queued 0 {\"job\":\"echo\",\"args\":\"finished\"}\n\
+         done 0\n\
+         queued 1 {\"job\":\"echo\",\"args\":\"interrupted\"}\n",
+    )
+    .unwrap();
+
+    let service = Service::start(&journal);
+
+    // Only the interrupted job ran again, and the journal started over.
+    service.wait_for_metric(r#"harbor_jobs_succeeded_total{job="echo"} 1"#);
+    assert_eq!(
+        fs::read_to_string(&journal).unwrap(),
+        "queued 0 {\"job\":\"echo\",\"args\":\"interrupted\"}\ndone 0\n"
+    );
+    let _ = fs::remove_file(journal);
+}
+
+#[test]
+fn test_a_failed_start_keeps_the_journal_and_exits_non_zero() {
+    let journal = journal("occupied");
+    let contents = "queued 0 {\"job\":\"echo\",\"args\":\"kept\"}\n\
+                    queued 1 {\"job\":\"echo\",\"args\":\"finished\"}\n\
+                    done 1\n";
+    fs::write(&journal, contents).unwrap();
+    let occupied = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_harbor-jobs"))
+        .arg("--addr")
+        .arg(occupied.local_addr().unwrap().to_string())
+        .arg("--journal")
+        .arg(&journal)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .expect("Failed to run harbor-jobs.");
+
+    assert!(!status.success(), "{}", status);
+    // Only the finished job is gone, and the next start queues the rest.
+    assert_eq!(
+        fs::read_to_string(&journal).unwrap(),
+        "queued 0 {\"job\":\"echo\",\"args\":\"kept\"}\n"
+    );
+    let _ = fs::remove_file(journal);
+}
@@ -0,0 +1,254 @@
+use harbor::server::{
+    HttpRequest, ParseError,
+    request::{MAX_HEAD_BYTES, MAX_HEADERS},
+};
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, BufReader, Read},
+    panic,
+};
+
+/// The seed inputs `cargo fuzz run parse_request` starts from.
+const CORPUS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fuzz/corpus/parse_request");
+
+fn corpus() -> BTreeMap<String, Vec<u8>> {
+    fs::read_dir(CORPUS)
+        .expect("Failed to read the corpus.")
+        .map(|entry| {
+            let path = entry.unwrap().path();
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            (name, fs::read(&path).unwrap())
+        })
+        .collect()
+}
+
+fn parse(input: &[u8]) -> Result<Option<HttpRequest>, ParseError> {
+    HttpRequest::parse(&mut &input[..])
+}
+
+/// Parses `input` as pipelined requests, returning them once it runs out
+/// or the first error, and checking that nothing is left unread.
+fn parse_all(input: &[u8]) -> Result<Vec<HttpRequest>, ParseError> {
+    let mut rest = input;
+    let mut requests = Vec::new();
+    while let Some(request) = HttpRequest::parse(&mut rest)? {
+        requests.push(request);
+    }
+    assert!(rest.is_empty(), "Left unread: {:?}", rest);
+    Ok(requests)
+}
+
+#[test]
+fn test_corpus_requests_parse_as_expected() {
+    use ParseError::*;
+    let text = |text: &str| text.to_string();
+    // The number of requests the input holds, or why its first is refused.
+    let expected: BTreeMap<&str, Result<usize, ParseError>> = BTreeMap::from([
+        ("valid-get", Ok(1)),
+        ("valid-post", Ok(1)),
+        ("valid-bare-lf", Ok(1)),
+        ("valid-pipelined", Ok(2)),
+        ("empty", Ok(0)),
+        ("truncated-request-line", Err(Truncated)),
+        ("truncated-header", Err(Truncated)),
+        ("missing-empty-line", Err(Truncated)),
+        ("truncated-body", Err(Truncated)),
+        ("huge-announced-body", Err(Truncated)),
+        ("blank-request-line", Err(MalformedRequestLine(text("")))),
+        (
+            "short-request-line",
+            Err(MalformedRequestLine(text("GET /"))),
+        ),
+        (
+            "long-request-line",
+            Err(MalformedRequestLine(text("GET / HTTP/1.1 extra"))),
+        ),
+        ("invalid-method", Err(InvalidMethod(text("G(T")))),
+        (
+            "absolute-target",
+            Err(InvalidTarget(text("http://example.com/"))),
+        ),
+        ("invalid-version", Err(InvalidVersion(text("HTPT/1.1")))),
+        (
+            "broken-percent-escape",
+            Err(InvalidPercentEncoding(text("/%zz"))),
+        ),
+        (
+            "cut-percent-escape",
+            Err(InvalidPercentEncoding(text("/%4"))),
+        ),
+        (
+            "decoded-not-utf8",
+            Err(InvalidPercentEncoding(text("/%ff"))),
+        ),
+        ("nul-in-path", Err(NulInPath(text("/a%00b")))),
+        ("raw-not-utf8", Err(NotUtf8)),
+        ("header-not-utf8", Err(NotUtf8)),
+        (
+            "header-without-colon",
+            Err(MalformedHeader(text("Host localhost"))),
+        ),
+        (
+            "header-space-before-colon",
+            Err(MalformedHeader(text("Host : localhost"))),
+        ),
+        (
+            "header-empty-name",
+            Err(MalformedHeader(text(": localhost"))),
+        ),
+        (
+            "negative-content-length",
+            Err(InvalidContentLength(text("-1"))),
+        ),
+        (
+            "signed-content-length",
+            Err(InvalidContentLength(text("+3"))),
+        ),
+        (
+            "overflowing-content-length",
+            Err(InvalidContentLength(text("99999999999999999999999"))),
+        ),
+        ("duplicate-content-length", Err(DuplicateContentLength)),
+        ("listed-content-length", Err(DuplicateContentLength)),
+        ("content-length-and-chunked", Err(ConflictingFraming)),
+        (
+            "chunked-body",
+            Err(UnsupportedTransferEncoding(text("gzip, chunked"))),
+        ),
+        (
+            "chunked-not-last",
+            Err(InvalidTransferEncoding(text("chunked, gzip"))),
+        ),
+        (
+            "chunked-twice",
+            Err(InvalidTransferEncoding(text("chunked, chunked"))),
+        ),
+    ]);
+
+    let corpus = corpus();
+    assert_eq!(
+        corpus.keys().map(String::as_str).collect::<Vec<_>>(),
+        expected.keys().copied().collect::<Vec<_>>(),
+        "Every corpus file needs an expected outcome."
+    );
+    for (name, input) in &corpus {
+        let parsed = parse_all(input).map(|requests| requests.len());
+        assert_eq!(&parsed, &expected[name.as_str()], "{}", name);
+        if let Err(err) = parsed {
+            let status = match err {
+                HeadTooLarge | TooManyHeaders => 431,
+                UnsupportedTransferEncoding(_) => 501,
+                _ => 400,
+            };
+            assert_eq!(err.status(), status, "{}", name);
+        }
+    }
+
+    let post = parse_all(&corpus["valid-post"]).unwrap();
+    assert_eq!(post[0].body(), b"{\"job\":\"echo\"}\n");
+    let pipelined = parse_all(&corpus["valid-pipelined"]).unwrap();
+    let paths: Vec<&str> = pipelined.iter().map(HttpRequest::path).collect();
+    assert_eq!(paths, ["/a", "/b"]);
+}
+
+#[test]
+fn test_heads_over_the_limits_are_refused_without_reading_on() {
+    let endless = |prefix: &'static [u8]| BufReader::new(prefix.chain(io::repeat(b'a')));
+
+    assert_eq!(
+        HttpRequest::parse(&mut endless(b"")).unwrap_err(),
+        ParseError::HeadTooLarge
+    );
+    assert_eq!(
+        HttpRequest::parse(&mut endless(b"GET / HTTP/1.1\r\nX-Long: ")).unwrap_err(),
+        ParseError::HeadTooLarge
+    );
+    assert_eq!(ParseError::HeadTooLarge.status(), 431);
+
+    let line = "X: y\r\n";
+    let many = format!("GET / HTTP/1.1\r\n{}\r\n", line.repeat(MAX_HEADERS + 1));
+    assert!(many.len() < MAX_HEAD_BYTES);
+    assert_eq!(
+        parse(many.as_bytes()).unwrap_err(),
+        ParseError::TooManyHeaders
+    );
+    let most = format!("GET / HTTP/1.1\r\n{}\r\n", line.repeat(MAX_HEADERS));
+    assert_eq!(
+        parse(most.as_bytes()).unwrap().unwrap().headers().len(),
+        MAX_HEADERS
+    );
+
+    // The limit counts the whole head, not just one line.
+    let lines = "X-Filler: ".to_string() + &"a".repeat(1000) + "\r\n";
+    let long = format!(
+        "GET / HTTP/1.1\r\n{}\r\n",
+        lines.repeat(MAX_HEAD_BYTES / lines.len() + 1)
+    );
+    assert_eq!(
+        parse(long.as_bytes()).unwrap_err(),
+        ParseError::HeadTooLarge
+    );
+}
+
+/// A xorshift generator, so every run mutates the corpus the same way.
+struct Mutator(u64);
+
+impl Mutator {
+    fn next(&mut self, below: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % below.max(1) as u64) as usize
+    }
+
+    /// `input` with a few bytes flipped, inserted, removed or repeated,
+    /// favouring the bytes the parser splits on.
+    fn mutate(&mut self, input: &[u8]) -> Vec<u8> {
+        const BYTES: &[u8] = b"\r\n :%,;\t0\x00\xff\xc3";
+        let mut output = input.to_vec();
+        for _ in 0..=self.next(4) {
+            let at = self.next(output.len() + 1);
+            match self.next(5) {
+                0 if at < output.len() => output[at] ^= 1 << self.next(8),
+                1 => output.insert(at, BYTES[self.next(BYTES.len())]),
+                2 if at < output.len() => {
+                    output.remove(at);
+                }
+                3 => output.truncate(at),
+                _ => {
+                    let end = (at + self.next(16)).min(output.len());
+                    let repeated = output[at..end].to_vec();
+                    output.splice(at..at, repeated);
+                }
+            }
+        }
+        output
+    }
+}
+
+#[test]
+fn test_mutated_corpus_requests_never_panic() {
+    let mut mutator = Mutator(0x2545_f491_4f6c_dd1d);
+    for (name, input) in corpus() {
+        for _ in 0..2000 {
+            let mutated = mutator.mutate(&input);
+            let parsed = panic::catch_unwind(|| {
+                // Parse pipelined requests until the input runs out.
+                let mut rest = &mutated[..];
+                let mut requests = 0;
+                while let Ok(Some(request)) = HttpRequest::parse(&mut rest) {
+                    assert!(request.body().len() <= mutated.len());
+                    requests += 1;
+                }
+                requests
+            });
+            assert!(
+                parsed.is_ok_and(|requests| requests <= mutated.len()),
+                "{} mutated to {:?}",
+                name,
+                String::from_utf8_lossy(&mutated)
+            );
+        }
+    }
+}
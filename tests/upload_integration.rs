@@ -0,0 +1,62 @@
+use harbor::{
+    server::{Response, Router},
+    test::TestServer,
+};
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+};
+
+#[test]
+fn test_multipart_upload_is_exposed_to_handlers() {
+    let router = Router::new().post("/upload", |request| match request.multipart() {
+        Ok(parts) => {
+            let summary: Vec<String> = parts
+                .iter()
+                .map(|part| format!("{}={}", part.name().unwrap_or("?"), part.len()))
+                .collect();
+            Response::new(200).body(summary.join(","))
+        }
+        Err(err) => Response::new(400).body(err),
+    });
+
+    let server = TestServer::spawn(router);
+
+    let body = "--boundary\r\n\
+        Content-Disposition: form-data; name=\"note\"\r\n\r\n\
+        hi\r\n\
+        --boundary\r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"f.bin\"\r\n\r\n\
+        12345\r\n\
+        --boundary--\r\n";
+    let request = format!(
+        "POST /upload HTTP/1.1\r\n\
+        Content-Type: multipart/form-data; boundary=boundary\r\n\
+        Content-Length: {}\r\n\r\n{}",
+        body.len(),
+        body
+    );
+
+    let mut stream = TcpStream::connect(server.addr()).expect("Failed to connect to server.");
+    stream
+        .write_all(request.as_bytes())
+        .expect("Failed to write HTTP request.");
+
+    let mut buffer: Vec<u8> = Vec::new();
+    stream
+        .read_to_end(&mut buffer)
+        .expect("Failed to read server response.");
+
+    let response = String::from_utf8_lossy(&buffer);
+
+    assert!(
+        response.starts_with("HTTP/1.1 200 OK"),
+        "Response is not 200. Response: {}",
+        response
+    );
+    assert!(
+        response.ends_with("note=2,file=5"),
+        "Unexpected upload summary. Response: {}",
+        response
+    );
+}
@@ -116,3 +116,1984 @@ fn test_http_get_unknown_path_returns_404_not_found() {
         response
     );
 }
+
+#[test]
+fn test_http_get_unknown_job_returns_404_json() {
+    setup();
+
+    let mut stream = TcpStream::connect("127.0.0.1:7878")
+        .expect("Failed to connect to server. Make sure port 7878 is free.");
+
+    stream
+        .write_all(b"GET /jobs/00000000-0000-0000-0000-000000000000 HTTP/1.1\r\n\r\n")
+        .expect("Failed to write HTTP request.");
+
+    let mut buffer: Vec<u8> = Vec::new();
+
+    stream
+        .read_to_end(&mut buffer)
+        .expect("Failed to read server response.");
+
+    let response = String::from_utf8_lossy(&buffer);
+
+    assert!(
+        response.contains("HTTP/1.1 404 NOT FOUND"),
+        "Response does not contain 'HTTP/1.1 404 NOT FOUND'. Response: {}",
+        response
+    );
+    assert!(
+        response.contains("Content-Type: application/json"),
+        "Response is not JSON. Response: {}",
+        response
+    );
+}
+
+#[test]
+fn test_http_post_jobs_rejects_unknown_job_type() {
+    setup();
+
+    let mut stream = TcpStream::connect("127.0.0.1:7878")
+        .expect("Failed to connect to server. Make sure port 7878 is free.");
+
+    let body = r#"{"job": "unknown"}"#;
+    let request = format!(
+        "POST /jobs HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}",
+        body.len(),
+        body
+    );
+    stream
+        .write_all(request.as_bytes())
+        .expect("Failed to write HTTP request.");
+
+    let mut buffer: Vec<u8> = Vec::new();
+
+    stream
+        .read_to_end(&mut buffer)
+        .expect("Failed to read server response.");
+
+    let response = String::from_utf8_lossy(&buffer);
+
+    assert!(
+        response.contains("HTTP/1.1 400 Bad Request"),
+        "Response does not contain 'HTTP/1.1 400 Bad Request'. Response: {}",
+        response
+    );
+}
+
+#[test]
+fn test_http_get_non_normalized_root_returns_hello_html() {
+    setup();
+
+    let mut stream = TcpStream::connect("127.0.0.1:7878")
+        .expect("Failed to connect to server. Make sure port 7878 is free.");
+
+    stream
+        .write_all(b"GET //./%2E%2E/ HTTP/1.1\r\n\r\n")
+        .expect("Failed to write HTTP request.");
+
+    let mut buffer: Vec<u8> = Vec::new();
+
+    stream
+        .read_to_end(&mut buffer)
+        .expect("Failed to read server response.");
+
+    let response = String::from_utf8_lossy(&buffer);
+
+    assert!(
+        response.contains("<h1>Hello!</h1>"),
+        "Response does not contain '<h1>Hello!</h1>'. Response: {}",
+        response
+    );
+}
+
+#[test]
+fn test_malformed_requests_return_400() {
+    setup();
+
+    for raw in [
+        &b"\r\n\r\n"[..],
+        b"not an http request\r\n\r\n",
+        b"GET /\xff HTTP/1.1\r\n\r\n",
+        b"POST / HTTP/1.1\r\nContent-Length: 1\r\nContent-Length: 12\r\n\r\nx",
+        b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nContent-Length: 1\r\n\r\nx",
+    ] {
+        let mut stream = TcpStream::connect("127.0.0.1:7878")
+            .expect("Failed to connect to server. Make sure port 7878 is free.");
+
+        stream
+            .write_all(raw)
+            .expect("Failed to write HTTP request.");
+
+        let mut buffer: Vec<u8> = Vec::new();
+
+        stream
+            .read_to_end(&mut buffer)
+            .expect("Failed to read server response.");
+
+        let response = String::from_utf8_lossy(&buffer);
+
+        assert!(
+            response.starts_with("HTTP/1.1 400 Bad Request"),
+            "Response is not a 400. Response: {}",
+            response
+        );
+    }
+}
+
+#[test]
+fn test_chunked_bodies_are_refused_and_never_served_as_requests() {
+    use std::sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    };
+
+    let smuggled = Arc::new(AtomicUsize::new(0));
+    let counted = Arc::clone(&smuggled);
+    let router = harbor::server::Router::new()
+        .get("/", |_| harbor::server::Response::new(200).body("root"))
+        .get("/smuggled", move |_| {
+            counted.fetch_add(1, Ordering::SeqCst);
+            harbor::server::Response::new(200).body("smuggled")
+        });
+    let server = harbor::server::Server::new("127.0.0.1:0")
+        .router(router)
+        .keep_alive(true);
+    #[cfg(feature = "evented")]
+    let server = server.evented(true);
+    let server = harbor::test::TestServer::start(server);
+
+    // The chunk holds a whole request; read as a bodiless GET, the
+    // connection would then answer it.
+    let mut stream = TcpStream::connect(server.addr()).expect("Failed to connect to server.");
+    stream
+        .write_all(
+            b"GET / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+              1a\r\nGET /smuggled HTTP/1.1\r\n\r\n\r\n0\r\n\r\n",
+        )
+        .expect("Failed to write HTTP request.");
+    let mut buffer: Vec<u8> = Vec::new();
+    stream
+        .read_to_end(&mut buffer)
+        .expect("Failed to read server response.");
+
+    let response = String::from_utf8_lossy(&buffer);
+    assert!(
+        response.starts_with("HTTP/1.1 501 Not Implemented\r\n"),
+        "{}",
+        response
+    );
+    assert!(response.contains("Connection: close\r\n"), "{}", response);
+    assert_eq!(response.matches("HTTP/1.1 ").count(), 1, "{}", response);
+    assert_eq!(smuggled.load(Ordering::SeqCst), 0);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_http_get_root_over_unix_socket() {
+    use std::os::unix::net::UnixStream;
+
+    let path = std::env::temp_dir().join(format!("harbor-it-{}.sock", std::process::id()));
+    let address = format!("unix:{}", path.display());
+    thread::spawn(move || start(&address));
+    thread::sleep(Duration::from_millis(500));
+
+    let mut stream = UnixStream::connect(&path).expect("Failed to connect to the Unix socket.");
+    stream
+        .write_all(b"GET / HTTP/1.1\r\n\r\n")
+        .expect("Failed to write HTTP request.");
+
+    let mut buffer: Vec<u8> = Vec::new();
+    stream
+        .read_to_end(&mut buffer)
+        .expect("Failed to read server response.");
+
+    let response = String::from_utf8_lossy(&buffer);
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+    assert!(response.contains("<h1>Hello!</h1>"), "{}", response);
+}
+
+#[test]
+fn test_every_listener_serves_requests() {
+    thread::spawn(|| {
+        harbor::server::Server::new("127.0.0.1:7882")
+            .listen("127.0.0.1:7883")
+            .run()
+    });
+    thread::sleep(Duration::from_millis(500));
+
+    for address in ["127.0.0.1:7882", "127.0.0.1:7883"] {
+        let mut stream = TcpStream::connect(address).expect("Failed to connect to server.");
+        stream
+            .write_all(b"GET / HTTP/1.1\r\n\r\n")
+            .expect("Failed to write HTTP request.");
+
+        let mut buffer: Vec<u8> = Vec::new();
+        stream
+            .read_to_end(&mut buffer)
+            .expect("Failed to read server response.");
+
+        let response = String::from_utf8_lossy(&buffer);
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+    }
+}
+
+#[test]
+fn test_multiple_acceptors_serve_requests() {
+    thread::spawn(|| {
+        harbor::server::Server::new("127.0.0.1:7884")
+            .acceptors(3)
+            .run()
+    });
+    thread::sleep(Duration::from_millis(500));
+
+    for _ in 0..6 {
+        let mut stream =
+            TcpStream::connect("127.0.0.1:7884").expect("Failed to connect to server.");
+        stream
+            .write_all(b"GET / HTTP/1.1\r\n\r\n")
+            .expect("Failed to write HTTP request.");
+
+        let mut buffer: Vec<u8> = Vec::new();
+        stream
+            .read_to_end(&mut buffer)
+            .expect("Failed to read server response.");
+
+        let response = String::from_utf8_lossy(&buffer);
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+    }
+}
+
+#[test]
+fn test_server_handle_stop_returns_promptly() {
+    let server = harbor::server::Server::new("127.0.0.1:7885").grace_period(Duration::from_secs(1));
+    let handle = server.handle();
+    let running = thread::spawn(move || server.run());
+    thread::sleep(Duration::from_millis(300));
+
+    let stopped_at = std::time::Instant::now();
+    handle.stop();
+    running
+        .join()
+        .expect("Server thread panicked.")
+        .expect("Server failed to start.");
+
+    assert!(stopped_at.elapsed() < Duration::from_secs(1));
+    assert!(TcpStream::connect("127.0.0.1:7885").is_err());
+}
+
+#[test]
+fn test_http_post_root_returns_405_with_allow_header() {
+    setup();
+
+    let mut stream = TcpStream::connect("127.0.0.1:7878")
+        .expect("Failed to connect to server. Make sure port 7878 is free.");
+
+    stream
+        .write_all(b"POST / HTTP/1.1\r\nContent-Length: 0\r\n\r\n")
+        .expect("Failed to write HTTP request.");
+
+    let mut buffer: Vec<u8> = Vec::new();
+
+    stream
+        .read_to_end(&mut buffer)
+        .expect("Failed to read server response.");
+
+    let response = String::from_utf8_lossy(&buffer);
+
+    assert!(
+        response.starts_with("HTTP/1.1 405 Method Not Allowed"),
+        "Response is not a 405. Response: {}",
+        response
+    );
+    assert!(
+        response.contains("Allow: GET, HEAD, OPTIONS\r\n"),
+        "Response does not list the allowed methods. Response: {}",
+        response
+    );
+}
+
+#[test]
+fn test_panicking_handler_returns_500() {
+    thread::spawn(|| {
+        let router = harbor::server::Router::new()
+            .get("/panic", |_| panic!("handler failed"))
+            .get("/ok", |_| harbor::server::Response::new(200));
+        harbor::server::Server::new("127.0.0.1:7886")
+            .router(router)
+            .run()
+    });
+    thread::sleep(Duration::from_millis(500));
+
+    for (path, status) in [("/panic", "500 Internal Server Error"), ("/ok", "200 OK")] {
+        let mut stream =
+            TcpStream::connect("127.0.0.1:7886").expect("Failed to connect to server.");
+        stream
+            .write_all(format!("GET {} HTTP/1.1\r\n\r\n", path).as_bytes())
+            .expect("Failed to write HTTP request.");
+
+        let mut buffer: Vec<u8> = Vec::new();
+        stream
+            .read_to_end(&mut buffer)
+            .expect("Failed to read server response.");
+
+        let response = String::from_utf8_lossy(&buffer);
+        assert!(
+            response.starts_with(&format!("HTTP/1.1 {}", status)),
+            "{}",
+            response
+        );
+    }
+}
+
+#[test]
+fn test_pipelined_requests_are_answered_in_order() {
+    thread::spawn(|| {
+        let router = harbor::server::Router::new()
+            .get("/a", |_| harbor::server::Response::new(200).body("first"))
+            .post("/b", |request| {
+                harbor::server::Response::new(200).body(request.body().to_vec())
+            });
+        harbor::server::Server::new("127.0.0.1:7887")
+            .router(router)
+            .keep_alive(true)
+            .run()
+    });
+    thread::sleep(Duration::from_millis(500));
+
+    let mut stream = TcpStream::connect("127.0.0.1:7887").expect("Failed to connect to server.");
+    stream
+        .write_all(
+            b"GET /a HTTP/1.1\r\n\r\n\
+              POST /b HTTP/1.1\r\nContent-Length: 6\r\n\r\nsecond\
+              GET /a HTTP/1.1\r\nConnection: close\r\n\r\n",
+        )
+        .expect("Failed to write HTTP requests.");
+
+    let mut buffer: Vec<u8> = Vec::new();
+    stream
+        .read_to_end(&mut buffer)
+        .expect("Failed to read server responses.");
+
+    let response = String::from_utf8_lossy(&buffer);
+    let bodies: Vec<&str> = response
+        .split("HTTP/1.1 200 OK\r\n")
+        .skip(1)
+        .map(|part| part.split("\r\n\r\n").nth(1).unwrap_or_default())
+        .collect();
+    assert_eq!(bodies, ["first", "second", "first"], "{}", response);
+    let last = response.rsplit("HTTP/1.1 ").next().unwrap_or_default();
+    assert!(last.contains("Connection: close\r\n"), "{}", last);
+    assert!(last.ends_with("Content-Length: 5\r\n\r\nfirst"), "{}", last);
+}
+
+#[test]
+fn test_pipeline_flush_policy_answers_held_requests_in_order() {
+    let router = harbor::server::Router::new()
+        .get("/a", |_| harbor::server::Response::new(200).body("first"))
+        .post("/b", |request| {
+            harbor::server::Response::new(200).body(request.body().to_vec())
+        });
+    let server = harbor::test::TestServer::start(
+        harbor::server::Server::new("127.0.0.1:0")
+            .router(router)
+            .keep_alive(true)
+            .flush_policy(harbor::server::FlushPolicy::Pipeline),
+    );
+
+    let mut stream = TcpStream::connect(server.addr()).expect("Failed to connect to server.");
+    stream
+        .write_all(
+            b"GET /a HTTP/1.1\r\n\r\n\
+              GET /a HTTP/1.1\r\n\r\n\
+              POST /b HTTP/1.1\r\nContent-Length: 6\r\n\r\nsecond\
+              GET /a HTTP/1.1\r\n\r\n\
+              GET /a HTTP/1.1\r\nConnection: close\r\n\r\n",
+        )
+        .expect("Failed to write HTTP requests.");
+
+    let mut buffer: Vec<u8> = Vec::new();
+    stream
+        .read_to_end(&mut buffer)
+        .expect("Failed to read server responses.");
+
+    let response = String::from_utf8_lossy(&buffer);
+    let bodies: Vec<&str> = response
+        .split("HTTP/1.1 200 OK\r\n")
+        .skip(1)
+        .map(|part| part.split("\r\n\r\n").nth(1).unwrap_or_default())
+        .collect();
+    assert_eq!(
+        bodies,
+        ["first", "first", "second", "first", "first"],
+        "{}",
+        response
+    );
+}
+
+#[test]
+fn test_keep_alive_connections_close_at_their_limits() {
+    let router =
+        harbor::server::Router::new().get("/a", |_| harbor::server::Response::new(200).body("a"));
+    let server = harbor::test::TestServer::start(
+        harbor::server::Server::new("127.0.0.1:0")
+            .router(router)
+            .keep_alive(true)
+            .max_requests_per_connection(2)
+            .keep_alive_timeout(Duration::from_millis(200)),
+    );
+
+    let mut stream = TcpStream::connect(server.addr()).expect("Failed to connect to server.");
+    stream
+        .write_all(b"GET /a HTTP/1.1\r\n\r\nGET /a HTTP/1.1\r\n\r\nGET /a HTTP/1.1\r\n\r\n")
+        .expect("Failed to write HTTP requests.");
+    let mut buffer: Vec<u8> = Vec::new();
+    stream
+        .read_to_end(&mut buffer)
+        .expect("Failed to read server responses.");
+    let response = String::from_utf8_lossy(&buffer);
+    assert_eq!(
+        response.matches("HTTP/1.1 200 OK").count(),
+        2,
+        "{}",
+        response
+    );
+    assert!(
+        response.contains("Keep-Alive: timeout=1, max=1\r\n"),
+        "{}",
+        response
+    );
+    let last = response.rsplit("HTTP/1.1 ").next().unwrap_or_default();
+    assert!(last.contains("Connection: close\r\n"), "{}", last);
+    assert!(last.ends_with("Content-Length: 1\r\n\r\na"), "{}", last);
+
+    // An idle connection is closed once the timeout passes.
+    let mut stream = TcpStream::connect(server.addr()).expect("Failed to connect to server.");
+    stream
+        .write_all(b"GET /a HTTP/1.1\r\n\r\n")
+        .expect("Failed to write HTTP request.");
+    let started = std::time::Instant::now();
+    let mut buffer: Vec<u8> = Vec::new();
+    stream
+        .read_to_end(&mut buffer)
+        .expect("Failed to read server response.");
+    assert!(String::from_utf8_lossy(&buffer).contains("Connection: keep-alive\r\n"));
+    assert!(started.elapsed() < Duration::from_secs(2));
+}
+
+#[test]
+fn test_expect_100_continue_is_answered_before_the_body() {
+    thread::spawn(|| {
+        let router = harbor::server::Router::new().post("/echo", |request| {
+            harbor::server::Response::new(200).body(request.body().to_vec())
+        });
+        harbor::server::Server::new("127.0.0.1:7888")
+            .router(router)
+            .max_body_bytes(8)
+            .run()
+    });
+    thread::sleep(Duration::from_millis(500));
+
+    let mut stream = TcpStream::connect("127.0.0.1:7888").expect("Failed to connect to server.");
+    stream
+        .write_all(b"POST /echo HTTP/1.1\r\nContent-Length: 5\r\nExpect: 100-continue\r\n\r\n")
+        .expect("Failed to write HTTP request.");
+    let mut interim = [0; 25];
+    stream
+        .read_exact(&mut interim)
+        .expect("Failed to read the interim response.");
+    assert_eq!(&interim, b"HTTP/1.1 100 Continue\r\n\r\n");
+
+    stream.write_all(b"hello").expect("Failed to write body.");
+    let mut buffer: Vec<u8> = Vec::new();
+    stream
+        .read_to_end(&mut buffer)
+        .expect("Failed to read server response.");
+    let response = String::from_utf8_lossy(&buffer);
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+    assert!(response.ends_with("\r\n\r\nhello"), "{}", response);
+
+    for (head, status) in [
+        (
+            "Content-Length: 100\r\nExpect: 100-continue",
+            "413 Content Too Large",
+        ),
+        (
+            "Content-Length: 1\r\nExpect: magic",
+            "417 Expectation Failed",
+        ),
+    ] {
+        let mut stream =
+            TcpStream::connect("127.0.0.1:7888").expect("Failed to connect to server.");
+        stream
+            .write_all(format!("POST /echo HTTP/1.1\r\n{}\r\n\r\n", head).as_bytes())
+            .expect("Failed to write HTTP request.");
+        let mut buffer: Vec<u8> = Vec::new();
+        stream
+            .read_to_end(&mut buffer)
+            .expect("Failed to read server response.");
+        let response = String::from_utf8_lossy(&buffer);
+        assert!(
+            response.starts_with(&format!("HTTP/1.1 {}", status)),
+            "{}",
+            response
+        );
+    }
+}
+
+#[test]
+fn test_test_server_uses_a_free_port_and_stops_on_drop() {
+    let router = || {
+        harbor::server::Router::new()
+            .get("/ping", |_| harbor::server::Response::new(200).body("pong"))
+    };
+    let first = harbor::test::TestServer::spawn(router());
+    let second = harbor::test::TestServer::spawn(router());
+    assert_ne!(first.addr(), second.addr());
+
+    let response = harbor::client::get(&first.url("/ping")).expect("Request failed.");
+    assert_eq!(response.get_body(), b"pong");
+
+    let addr = first.addr();
+    drop(first);
+    assert!(TcpStream::connect(addr).is_err());
+}
+
+#[test]
+fn test_accept_backpressure_leaves_connections_in_the_backlog() {
+    let router = harbor::server::Router::new().get("/slow", |_| {
+        thread::sleep(Duration::from_millis(600));
+        harbor::server::Response::new(200).body("done")
+    });
+    let server = harbor::server::Server::with_config(harbor::server::ServerConfig {
+        address: "127.0.0.1:0".to_string(),
+        workers: 1,
+        ..Default::default()
+    })
+    .router(router)
+    .max_queued_connections(1)
+    .accept_backpressure(1, 0);
+    let server = harbor::test::TestServer::start(server);
+
+    // One request runs and two wait in the queue, which would refuse the
+    // fourth if accepting hadn't paused before it.
+    let mut streams = Vec::new();
+    for _ in 0..4 {
+        let mut stream = TcpStream::connect(server.addr()).expect("Failed to connect to server.");
+        stream
+            .write_all(b"GET /slow HTTP/1.1\r\n\r\n")
+            .expect("Failed to write HTTP request.");
+        streams.push(stream);
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    for mut stream in streams {
+        let mut buffer: Vec<u8> = Vec::new();
+        stream
+            .read_to_end(&mut buffer)
+            .expect("Failed to read server response.");
+        let response = String::from_utf8_lossy(&buffer);
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        assert!(response.ends_with("done"), "{}", response);
+    }
+}
+
+#[test]
+fn test_saturated_pool_returns_503_with_retry_after() {
+    let router = harbor::server::Router::new().get("/slow", |_| {
+        thread::sleep(Duration::from_millis(600));
+        harbor::server::Response::new(200).body("done")
+    });
+    let server = harbor::server::Server::with_config(harbor::server::ServerConfig {
+        address: "127.0.0.1:0".to_string(),
+        workers: 1,
+        ..Default::default()
+    })
+    .router(router)
+    .max_queued_connections(0);
+    let server = harbor::test::TestServer::start(server);
+
+    // The first request occupies the only worker and the second waits in
+    // the queue, so the third exceeds the limit.
+    let mut accepted = Vec::new();
+    for _ in 0..2 {
+        let mut stream = TcpStream::connect(server.addr()).expect("Failed to connect to server.");
+        stream
+            .write_all(b"GET /slow HTTP/1.1\r\n\r\n")
+            .expect("Failed to write HTTP request.");
+        accepted.push(stream);
+        thread::sleep(Duration::from_millis(150));
+    }
+
+    let mut refused = TcpStream::connect(server.addr()).expect("Failed to connect to server.");
+    let mut buffer: Vec<u8> = Vec::new();
+    refused
+        .read_to_end(&mut buffer)
+        .expect("Failed to read server response.");
+    let response = String::from_utf8_lossy(&buffer);
+    assert!(
+        response.starts_with("HTTP/1.1 503 Service Unavailable"),
+        "{}",
+        response
+    );
+    assert!(response.contains("Retry-After: 1\r\n"), "{}", response);
+
+    for mut stream in accepted {
+        let mut buffer: Vec<u8> = Vec::new();
+        stream
+            .read_to_end(&mut buffer)
+            .expect("Failed to read server response.");
+        assert!(String::from_utf8_lossy(&buffer).ends_with("done"));
+    }
+}
+
+#[test]
+fn test_connections_over_the_per_ip_limit_get_429() {
+    let server = harbor::server::Server::new("127.0.0.1:0").max_connections_per_ip(1);
+    let connections = server.connections();
+    let server = harbor::test::TestServer::start(server);
+
+    let mut idle = TcpStream::connect(server.addr()).expect("Failed to connect to server.");
+    idle.write_all(b"GET / HTTP/1.1\r\n")
+        .expect("Failed to write HTTP request.");
+    thread::sleep(Duration::from_millis(200));
+
+    let mut refused = TcpStream::connect(server.addr()).expect("Failed to connect to server.");
+    let mut buffer: Vec<u8> = Vec::new();
+    let _ = refused.read_to_end(&mut buffer);
+    let response = String::from_utf8_lossy(&buffer);
+    assert!(response.starts_with("HTTP/1.1 429 Too Many Requests\r\n"));
+    assert!(response.contains("Retry-After: 1\r\n"));
+    assert_eq!(connections.stats().rejected_per_ip, 1);
+
+    idle.write_all(b"Connection: close\r\n\r\n")
+        .expect("Failed to write HTTP request.");
+    let mut buffer: Vec<u8> = Vec::new();
+    idle.read_to_end(&mut buffer)
+        .expect("Failed to read server response.");
+    assert!(String::from_utf8_lossy(&buffer).starts_with("HTTP/1.1 200 OK\r\n"));
+    // The slot is released just after the connection closes.
+    thread::sleep(Duration::from_millis(100));
+    let response = harbor::client::get(&server.url("/")).expect("Request failed.");
+    assert_eq!(response.status(), 200);
+}
+
+#[test]
+fn test_the_per_ip_limit_counts_the_address_a_proxy_header_carries() {
+    let server = harbor::server::Server::new("127.0.0.1:0")
+        .max_connections_per_ip(1)
+        .proxy_protocol(true);
+    let connections = server.connections();
+    let server = harbor::test::TestServer::start(server);
+    let connect = |client: &str| {
+        let mut stream = TcpStream::connect(server.addr()).expect("Failed to connect to server.");
+        let header = format!(
+            "PROXY TCP4 {} 10.0.0.1 51000 80\r\nGET / HTTP/1.1\r\n",
+            client
+        );
+        stream
+            .write_all(header.as_bytes())
+            .expect("Failed to write HTTP request.");
+        stream
+    };
+
+    // All three come from the balancer's address, two from one client.
+    let mut idle = connect("203.0.113.7");
+    let mut other = connect("203.0.113.8");
+    thread::sleep(Duration::from_millis(200));
+    let mut refused = connect("203.0.113.7");
+    let mut buffer: Vec<u8> = Vec::new();
+    let _ = refused.read_to_end(&mut buffer);
+    assert!(String::from_utf8_lossy(&buffer).starts_with("HTTP/1.1 429 Too Many Requests\r\n"));
+    assert_eq!(connections.stats().rejected_per_ip, 1);
+
+    for stream in [&mut idle, &mut other] {
+        stream
+            .write_all(b"Connection: close\r\n\r\n")
+            .expect("Failed to write HTTP request.");
+        let mut buffer: Vec<u8> = Vec::new();
+        stream
+            .read_to_end(&mut buffer)
+            .expect("Failed to read server response.");
+        assert!(String::from_utf8_lossy(&buffer).starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+}
+
+#[test]
+fn test_connections_over_the_limit_are_closed() {
+    let server = harbor::server::Server::new("127.0.0.1:0").max_connections(1);
+    let connections = server.connections();
+    let server = harbor::test::TestServer::start(server);
+
+    // An unfinished request keeps the only slot busy.
+    let mut idle = TcpStream::connect(server.addr()).expect("Failed to connect to server.");
+    idle.write_all(b"GET / HTTP/1.1\r\n")
+        .expect("Failed to write HTTP request.");
+    thread::sleep(Duration::from_millis(200));
+
+    let mut refused = TcpStream::connect(server.addr()).expect("Failed to connect to server.");
+    let mut buffer: Vec<u8> = Vec::new();
+    let _ = refused.read_to_end(&mut buffer);
+    assert!(buffer.is_empty(), "{}", String::from_utf8_lossy(&buffer));
+    assert_eq!(connections.stats().open, 1);
+    assert_eq!(connections.stats().rejected, 1);
+
+    idle.write_all(b"\r\n")
+        .expect("Failed to write HTTP request.");
+    let mut buffer: Vec<u8> = Vec::new();
+    idle.read_to_end(&mut buffer)
+        .expect("Failed to read server response.");
+    assert!(String::from_utf8_lossy(&buffer).starts_with("HTTP/1.1 200 OK"));
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(connections.stats().open, 0);
+}
+
+#[test]
+fn test_clients_hanging_up_mid_response_are_counted() {
+    let router = harbor::server::Router::new().get("/large", |_| {
+        harbor::server::Response::new(200).body(vec![b'x'; 16 << 20])
+    });
+    let server = harbor::server::Server::new("127.0.0.1:0").router(router);
+    let connections = server.connections();
+    let server = harbor::test::TestServer::start(server);
+
+    let mut client = TcpStream::connect(server.addr()).expect("Failed to connect to server.");
+    client
+        .write_all(b"GET /large HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .expect("Failed to write HTTP request.");
+    thread::sleep(Duration::from_millis(200));
+    // Closing with the response unread resets the connection.
+    drop(client);
+    thread::sleep(Duration::from_millis(200));
+
+    assert_eq!(connections.stats().client_disconnects, 1);
+    let response = harbor::client::get(&server.url("/")).expect("Request failed.");
+    assert_eq!(response.status(), 200);
+}
+
+#[test]
+fn test_slow_request_heads_get_408() {
+    let server = harbor::test::TestServer::start(
+        harbor::server::Server::new("127.0.0.1:0").header_timeout(Duration::from_millis(300)),
+    );
+
+    let mut stream = TcpStream::connect(server.addr()).expect("Failed to connect to server.");
+    let started = std::time::Instant::now();
+    for byte in b"GET / HTTP/1.1\r\nHost: slow\r\n" {
+        if stream.write_all(&[*byte]).is_err() {
+            break;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    let mut buffer: Vec<u8> = Vec::new();
+    let _ = stream.read_to_end(&mut buffer);
+
+    let response = String::from_utf8_lossy(&buffer);
+    assert!(
+        response.starts_with("HTTP/1.1 408 Request Timeout"),
+        "{}",
+        response
+    );
+    assert!(started.elapsed() < Duration::from_secs(3));
+}
+
+#[test]
+fn test_sessions_remember_values_between_requests() {
+    use harbor::server::{MemoryStore, Response, Router, Server, Sessions};
+
+    fn get(addr: std::net::SocketAddr, path: &str, cookie: &str) -> String {
+        let mut stream = TcpStream::connect(addr).expect("Failed to connect to server.");
+        write!(
+            stream,
+            "GET {} HTTP/1.1\r\nCookie: {}\r\n\r\n",
+            path, cookie
+        )
+        .expect("Failed to write HTTP request.");
+        let mut response = String::new();
+        stream
+            .read_to_string(&mut response)
+            .expect("Failed to read server response.");
+        response
+    }
+
+    let router = Router::new()
+        .get("/login", |request| {
+            request.session().unwrap().set("user", "ada");
+            Response::new(200)
+        })
+        .get("/whoami", |request| {
+            let user = request.session().unwrap().get("user");
+            Response::new(200).body(user.unwrap_or_default())
+        });
+    let server = harbor::test::TestServer::start(
+        Server::new("127.0.0.1:0")
+            .router(router)
+            .middleware(Sessions::new(MemoryStore::new(), b"integration secret")),
+    );
+
+    let login = get(server.addr(), "/login", "");
+    let cookie = login
+        .lines()
+        .find_map(|line| line.strip_prefix("Set-Cookie: "))
+        .and_then(|value| value.split(';').next())
+        .expect("Login did not set a session cookie.")
+        .to_string();
+
+    assert!(get(server.addr(), "/whoami", &cookie).ends_with("\r\n\r\nada"));
+    assert!(get(server.addr(), "/whoami", "harbor_session=forged").ends_with("\r\n\r\n"));
+}
+
+#[test]
+fn test_requests_for_other_hosts_are_refused() {
+    let server = harbor::test::TestServer::start(
+        harbor::server::Server::new("127.0.0.1:0").allowed_hosts(&["localhost", "*.example.com"]),
+    );
+
+    for (head, status) in [
+        ("Host: localhost:7878", "200 OK"),
+        ("Host: api.EXAMPLE.com", "200 OK"),
+        ("Host: example.com", "421 Misdirected Request"),
+        ("Host: evil.test", "421 Misdirected Request"),
+        ("User-Agent: no-host", "400 Bad Request"),
+    ] {
+        let mut stream = TcpStream::connect(server.addr()).expect("Failed to connect to server.");
+        stream
+            .write_all(format!("GET / HTTP/1.1\r\n{}\r\n\r\n", head).as_bytes())
+            .expect("Failed to write HTTP request.");
+        let mut buffer: Vec<u8> = Vec::new();
+        stream
+            .read_to_end(&mut buffer)
+            .expect("Failed to read server response.");
+        let response = String::from_utf8_lossy(&buffer);
+        assert!(
+            response.starts_with(&format!("HTTP/1.1 {}", status)),
+            "{}: {}",
+            head,
+            response
+        );
+    }
+}
+
+#[test]
+fn test_reload_applies_the_new_configuration_without_restarting() {
+    let dir = std::env::temp_dir().join(format!("harbor-reload-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("harbor.toml");
+    std::fs::write(&path, "address = \"127.0.0.1:0\"\n").unwrap();
+    let server =
+        harbor::test::TestServer::start(harbor::server::Server::from_config(&path).unwrap());
+    let get_old = || {
+        let mut stream = TcpStream::connect(server.addr()).expect("Failed to connect to server.");
+        stream
+            .write_all(b"GET /old HTTP/1.1\r\n\r\n")
+            .expect("Failed to write HTTP request.");
+        let mut buffer: Vec<u8> = Vec::new();
+        stream
+            .read_to_end(&mut buffer)
+            .expect("Failed to read server response.");
+        String::from_utf8_lossy(&buffer).into_owned()
+    };
+    assert!(get_old().starts_with("HTTP/1.1 404"));
+
+    std::fs::write(
+        &path,
+        "address = \"127.0.0.1:0\"\n\n[[redirects]]\nfrom = \"/old\"\nto = \"/new\"\n",
+    )
+    .unwrap();
+    server.handle().reload();
+
+    let mut response = get_old();
+    for _ in 0..50 {
+        if response.starts_with("HTTP/1.1 302") {
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+        response = get_old();
+    }
+    assert!(response.starts_with("HTTP/1.1 302 Found"), "{}", response);
+    assert!(response.contains("Location: /new\r\n"));
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_rewrites_serve_other_paths_and_single_page_apps() {
+    let dir = std::env::temp_dir().join(format!("harbor-rewrite-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("public/app")).unwrap();
+    std::fs::write(dir.join("public/app/index.html"), "spa").unwrap();
+    std::fs::write(dir.join("public/app/app.js"), "script").unwrap();
+    let path = dir.join("harbor.toml");
+    std::fs::write(
+        &path,
+        format!(
+            "address = \"127.0.0.1:0\"\ndocument_root = {:?}\n\n[[rewrites]]\nfrom = \"/app/(.*)\"\nto = \"/app/index.html\"\nfallback = true\n",
+            dir.join("public")
+        ),
+    )
+    .unwrap();
+    let router = harbor::server::Router::new().get("/api/*name", |request| {
+        harbor::server::Response::new(200).body(format!(
+            "{} {}",
+            request.param("name").unwrap_or_default(),
+            request.query().unwrap_or_default()
+        ))
+    });
+    let server = harbor::test::TestServer::start(
+        harbor::server::Server::from_config(&path)
+            .unwrap()
+            .router(router)
+            .rewrite("/v1/(.*)", "/api/$1"),
+    );
+
+    let get = |path: &str| {
+        let response = harbor::client::get(&server.url(path)).expect("Request failed.");
+        (
+            response.status(),
+            String::from_utf8_lossy(response.get_body()).into_owned(),
+        )
+    };
+    assert_eq!(get("/v1/users?page=2"), (200, "users page=2".to_string()));
+    assert_eq!(get("/app/settings/profile"), (200, "spa".to_string()));
+    assert_eq!(get("/app/app.js"), (200, "script".to_string()));
+    assert_eq!(get("/elsewhere").0, 404);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_proxy_protocol_header_gives_the_client_address() {
+    let router = harbor::server::Router::new().get("/peer", |request| {
+        harbor::server::Response::new(200).body(format!("{:?}", request.peer_addr()))
+    });
+    let server = harbor::test::TestServer::start(
+        harbor::server::Server::new("127.0.0.1:0")
+            .router(router)
+            .proxy_protocol(true),
+    );
+
+    let mut stream = TcpStream::connect(server.addr()).expect("Failed to connect to server.");
+    stream
+        .write_all(b"PROXY TCP4 203.0.113.7 10.0.0.1 51000 80\r\nGET /peer HTTP/1.1\r\n\r\n")
+        .expect("Failed to write HTTP request.");
+    let mut buffer: Vec<u8> = Vec::new();
+    stream
+        .read_to_end(&mut buffer)
+        .expect("Failed to read server response.");
+    let response = String::from_utf8_lossy(&buffer);
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+    assert!(
+        response.ends_with("Some(203.0.113.7:51000)"),
+        "{}",
+        response
+    );
+
+    let mut stream = TcpStream::connect(server.addr()).expect("Failed to connect to server.");
+    stream
+        .write_all(b"GET /peer HTTP/1.1\r\n\r\n")
+        .expect("Failed to write HTTP request.");
+    let mut buffer: Vec<u8> = Vec::new();
+    let _ = stream.read_to_end(&mut buffer);
+    assert!(buffer.is_empty(), "{}", String::from_utf8_lossy(&buffer));
+}
+
+#[test]
+fn test_client_sends_headers_and_body() {
+    let router = harbor::server::Router::new().post("/echo", |request| {
+        harbor::server::Response::new(201)
+            .header("X-Token", request.header("X-Token").unwrap_or(""))
+            .body(request.body())
+    });
+    let server = harbor::test::TestServer::spawn(router);
+
+    let response = harbor::client::Request::new("POST", &server.url("/echo"))
+        .header("X-Token", "secret")
+        .body("hello")
+        .timeout(Duration::from_secs(5))
+        .send()
+        .expect("Request failed.");
+    let missing = harbor::client::get(&server.url("/missing")).expect("Request failed.");
+
+    assert_eq!(response.status(), 201);
+    assert_eq!(response.get_header("X-Token"), Some("secret"));
+    assert_eq!(response.get_body(), b"hello");
+    assert_eq!(missing.status(), 404);
+}
+
+#[test]
+fn test_options_requests_list_the_allowed_methods() {
+    let router = harbor::server::Router::new()
+        .get("/items", |_| harbor::server::Response::new(200))
+        .route("DELETE", "/items", |_| harbor::server::Response::new(204));
+    let server = harbor::test::TestServer::spawn(router);
+
+    let items = harbor::client::Request::new("OPTIONS", &server.url("/items"))
+        .send()
+        .expect("Request failed.");
+    assert_eq!(items.status(), 204);
+    assert_eq!(
+        items.get_header("Allow"),
+        Some("GET, HEAD, DELETE, OPTIONS")
+    );
+
+    let mut stream = TcpStream::connect(server.addr()).expect("Failed to connect to server.");
+    stream
+        .write_all(b"OPTIONS * HTTP/1.1\r\nConnection: close\r\n\r\n")
+        .expect("Failed to write HTTP request.");
+    let mut buffer: Vec<u8> = Vec::new();
+    stream
+        .read_to_end(&mut buffer)
+        .expect("Failed to read server response.");
+    let response = String::from_utf8_lossy(&buffer);
+    assert!(
+        response.starts_with("HTTP/1.1 204 No Content\r\n")
+            && response.contains("Allow: GET, HEAD, POST, DELETE, OPTIONS\r\n"),
+        "OPTIONS * did not describe the server. Response: {}",
+        response
+    );
+}
+
+#[test]
+fn test_head_requests_are_answered_like_get_without_the_body() {
+    let router = harbor::server::Router::new()
+        .get("/items", |_| {
+            harbor::server::Response::new(200)
+                .header("X-Items", "3")
+                .body("[1,2,3]")
+        })
+        .route("HEAD", "/custom", |_| {
+            harbor::server::Response::new(200).body("dropped")
+        });
+    let server = harbor::test::TestServer::spawn(router);
+
+    for (path, headers) in [
+        ("/items", &["Content-Length: 7\r\n", "X-Items: 3\r\n"][..]),
+        ("/custom", &["Content-Length: 7\r\n"][..]),
+        ("/", &["Content-Type: text/html"][..]),
+    ] {
+        let mut stream = TcpStream::connect(server.addr()).expect("Failed to connect to server.");
+        write!(
+            stream,
+            "HEAD {} HTTP/1.1\r\nConnection: close\r\n\r\n",
+            path
+        )
+        .expect("Failed to write HTTP request.");
+        let mut buffer: Vec<u8> = Vec::new();
+        stream
+            .read_to_end(&mut buffer)
+            .expect("Failed to read server response.");
+
+        let response = String::from_utf8_lossy(&buffer);
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        for header in headers {
+            assert!(response.contains(header), "{}", response);
+        }
+        assert!(response.ends_with("\r\n\r\n"), "{}", response);
+    }
+}
+
+#[test]
+fn test_https_redirect_listener_sends_clients_to_https() {
+    let server = harbor::test::TestServer::start(
+        harbor::server::Server::new("127.0.0.1:0").redirect_to_https(8443),
+    );
+
+    let response = harbor::client::Request::new("GET", &server.url("/docs/page?lang=en"))
+        .header("Host", "example.com")
+        .send()
+        .expect("Request failed.");
+
+    assert_eq!(response.status(), 301);
+    assert_eq!(
+        response.get_header("Location"),
+        Some("https://example.com:8443/docs/page?lang=en")
+    );
+}
+
+#[test]
+fn test_streaming_handlers_read_bodies_over_the_buffer_limit() {
+    let router = harbor::server::Router::new().stream("POST", "/upload", 1 << 20, |_, body| {
+        let mut checksum = 0u64;
+        let mut chunk = [0; 4096];
+        loop {
+            match body.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(read) => {
+                    checksum += chunk[..read]
+                        .iter()
+                        .map(|&byte| u64::from(byte))
+                        .sum::<u64>()
+                }
+                Err(_) => return harbor::server::Response::new(400),
+            }
+        }
+        harbor::server::Response::new(200).body(checksum.to_string())
+    });
+    let server = harbor::test::TestServer::start(
+        harbor::server::Server::new("127.0.0.1:0")
+            .max_body_bytes(16)
+            .router(router),
+    );
+
+    let upload = vec![1u8; 300_000];
+    let response = harbor::client::post(&server.url("/upload"), upload).expect("Request failed.");
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.get_body(), b"300000");
+
+    // Refused from the head alone, before any of the body is sent.
+    let mut stream = TcpStream::connect(server.addr()).expect("Failed to connect to server.");
+    stream
+        .write_all(b"POST /upload HTTP/1.1\r\nContent-Length: 1048577\r\n\r\n")
+        .expect("Failed to write HTTP request.");
+    let mut buffer: Vec<u8> = Vec::new();
+    stream
+        .read_to_end(&mut buffer)
+        .expect("Failed to read server response.");
+    assert!(String::from_utf8_lossy(&buffer).starts_with("HTTP/1.1 413 "));
+}
+
+#[test]
+fn test_refusing_a_bad_request_is_not_a_failed_connection() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("Failed to bind.");
+    let address = listener.local_addr().unwrap();
+    for request in [
+        &b"GET / HTTP/1.1\r\nHost localhost\r\n\r\n"[..],
+        &b"POST / HTTP/1.1\r\nContent-Length: 99999999999999999999999\r\n\r\n"[..],
+    ] {
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(address).expect("Failed to connect to server.");
+            stream
+                .write_all(request)
+                .expect("Failed to write HTTP request.");
+            let mut buffer: Vec<u8> = Vec::new();
+            stream
+                .read_to_end(&mut buffer)
+                .expect("Failed to read server response.");
+            buffer
+        });
+        let (stream, _) = listener.accept().expect("Failed to accept.");
+        assert!(harbor::server::handle_connection(stream).is_ok());
+        let response = client.join().unwrap();
+        assert!(String::from_utf8_lossy(&response).starts_with("HTTP/1.1 400 "));
+    }
+}
+
+#[cfg(feature = "http2")]
+#[test]
+fn test_http2_prior_knowledge_requests_are_answered() {
+    let router = harbor::server::Router::new().post("/echo", |request| {
+        harbor::server::Response::new(200).body(request.body().to_vec())
+    });
+    let server = harbor::test::TestServer::spawn(router);
+
+    // A literal header field without indexing, with a new name.
+    let field = |name: &str, value: &str| {
+        let mut field = vec![0, name.len() as u8];
+        field.extend_from_slice(name.as_bytes());
+        field.push(value.len() as u8);
+        field.extend_from_slice(value.as_bytes());
+        field
+    };
+    let frame = |kind: u8, flags: u8, stream: u32, payload: &[u8]| {
+        let mut frame = (payload.len() as u32).to_be_bytes()[1..].to_vec();
+        frame.extend_from_slice(&[kind, flags]);
+        frame.extend_from_slice(&stream.to_be_bytes());
+        frame.extend_from_slice(payload);
+        frame
+    };
+    let mut headers = vec![0x83, 0x86]; // :method POST, :scheme http
+    headers.extend(field(":path", "/echo"));
+    headers.extend(field(":authority", "localhost"));
+
+    let mut stream = TcpStream::connect(server.addr()).expect("Failed to connect to server.");
+    let mut request = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n".to_vec();
+    request.extend(frame(0x4, 0, 0, &[]));
+    request.extend(frame(0x1, 0x4, 1, &headers));
+    request.extend(frame(0x0, 0x1, 1, b"ping"));
+    stream
+        .write_all(&request)
+        .expect("Failed to write HTTP/2 request.");
+
+    let mut status = None;
+    loop {
+        let mut head = [0; 9];
+        stream
+            .read_exact(&mut head)
+            .expect("Failed to read a frame.");
+        let length = u32::from_be_bytes([0, head[0], head[1], head[2]]) as usize;
+        let mut payload = vec![0; length];
+        stream
+            .read_exact(&mut payload)
+            .expect("Failed to read a frame.");
+        match head[3] {
+            // `:status` is encoded with the static table's name.
+            0x1 => status = Some(payload[..5].to_vec()),
+            0x0 => {
+                assert_eq!(payload, b"ping");
+                assert_eq!(head[4] & 0x1, 0x1, "the body should end the stream");
+                break;
+            }
+            _ => {}
+        }
+    }
+    assert_eq!(status.as_deref(), Some(&b"\x08\x03200"[..]));
+}
+
+#[cfg(feature = "http2")]
+#[test]
+fn test_http2_requests_refused_from_their_headers_are_answered_before_the_body() {
+    let router = harbor::server::Router::new().post("/echo", |request| {
+        harbor::server::Response::new(200).body(request.body().to_vec())
+    });
+    let server = harbor::test::TestServer::spawn(router);
+
+    let field = |name: &str, value: &str| {
+        let mut field = vec![0, name.len() as u8];
+        field.extend_from_slice(name.as_bytes());
+        field.push(value.len() as u8);
+        field.extend_from_slice(value.as_bytes());
+        field
+    };
+    let frame = |kind: u8, flags: u8, stream: u32, payload: &[u8]| {
+        let mut frame = (payload.len() as u32).to_be_bytes()[1..].to_vec();
+        frame.extend_from_slice(&[kind, flags]);
+        frame.extend_from_slice(&stream.to_be_bytes());
+        frame.extend_from_slice(payload);
+        frame
+    };
+    let read_frame = |stream: &mut TcpStream| {
+        let mut head = [0; 9];
+        stream
+            .read_exact(&mut head)
+            .expect("Failed to read a frame.");
+        let length = u32::from_be_bytes([0, head[0], head[1], head[2]]) as usize;
+        let mut payload = vec![0; length];
+        stream
+            .read_exact(&mut payload)
+            .expect("Failed to read a frame.");
+        (head[3], head[4], payload)
+    };
+
+    // Without a `:path`, and with a body still to come.
+    let mut stream = TcpStream::connect(server.addr()).expect("Failed to connect to server.");
+    let mut request = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n".to_vec();
+    request.extend(frame(0x4, 0, 0, &[]));
+    request.extend(frame(0x1, 0x4, 1, &[0x83, 0x86]));
+    stream
+        .write_all(&request)
+        .expect("Failed to write HTTP/2 request.");
+    let mut status = None;
+    loop {
+        match read_frame(&mut stream) {
+            (0x1, _, payload) => status = Some(payload[..5].to_vec()),
+            (0x3, _, payload) => {
+                assert_eq!(payload, [0, 0, 0, 0], "reset with NO_ERROR");
+                break;
+            }
+            _ => {}
+        }
+    }
+    assert_eq!(status.as_deref(), Some(&b"\x08\x03400"[..]));
+
+    // Its body is dropped, and the connection serves the next stream.
+    let mut headers = vec![0x83, 0x86];
+    headers.extend(field(":path", "/echo"));
+    let mut request = frame(0x0, 0x1, 1, &[b'x'; 1000]);
+    request.extend(frame(0x1, 0x4, 3, &headers));
+    request.extend(frame(0x0, 0x1, 3, b"ping"));
+    stream
+        .write_all(&request)
+        .expect("Failed to write HTTP/2 request.");
+    loop {
+        if let (0x0, _, payload) = read_frame(&mut stream) {
+            assert_eq!(payload, b"ping");
+            break;
+        }
+    }
+}
+
+#[test]
+fn test_refusals_are_read_by_clients_that_sent_a_body_anyway() {
+    let server = harbor::test::TestServer::start(
+        harbor::server::Server::new("127.0.0.1:0").max_body_bytes(16),
+    );
+
+    for _ in 0..5 {
+        let mut stream = TcpStream::connect(server.addr()).expect("Failed to connect to server.");
+        let mut request = b"POST / HTTP/1.1\r\nContent-Length: 100000\r\n\r\n".to_vec();
+        request.resize(request.len() + 100_000, b'x');
+        stream
+            .write_all(&request)
+            .expect("Failed to write HTTP request.");
+        thread::sleep(Duration::from_millis(50));
+        let mut buffer: Vec<u8> = Vec::new();
+        stream
+            .read_to_end(&mut buffer)
+            .expect("The refusal should be read, not reset.");
+        assert!(String::from_utf8_lossy(&buffer).starts_with("HTTP/1.1 413 "));
+    }
+}
+
+#[test]
+fn test_406_only_refuses_bodies_that_would_go_out_as_identity() {
+    let root = std::env::temp_dir().join(format!("harbor-406-{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(root.join("plain.txt"), "plain").unwrap();
+    std::fs::write(root.join("packed.txt"), "packed").unwrap();
+    std::fs::write(root.join("packed.txt.gz"), [0x1f, 0x8b]).unwrap();
+    let router = harbor::server::Router::new()
+        .get("/empty", |_| harbor::server::Response::new(204))
+        .redirect("/moved", "/plain.txt");
+    let server = harbor::test::TestServer::start(
+        harbor::server::Server::with_config(harbor::server::ServerConfig {
+            address: "127.0.0.1:0".to_string(),
+            document_root: Some(root.clone()),
+            precompressed: true,
+            ..Default::default()
+        })
+        .router(router),
+    );
+    let status = |path: &str| {
+        harbor::client::Request::new("GET", &server.url(path))
+            .header("Accept-Encoding", "gzip, identity;q=0")
+            .send()
+            .expect("Request failed.")
+            .status()
+    };
+
+    assert_eq!(status("/packed.txt"), 200);
+    assert_eq!(status("/plain.txt"), 406);
+    assert_eq!(status("/empty"), 204);
+    assert_eq!(status("/moved"), 302);
+    assert_eq!(status("/missing"), 404);
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn test_http_layer_can_be_driven_without_the_server() {
+    let mut reader =
+        &b"POST /nowhere HTTP/1.1\r\nContent-Length: 2\r\n\r\nhiGET / HTTP/1.1\r\n\r\n"[..];
+
+    let first = harbor::server::parse_request(&mut reader)
+        .expect("Failed to parse request.")
+        .expect("Request missing.");
+    let second = harbor::server::parse_request(&mut reader)
+        .expect("Failed to parse request.")
+        .expect("Request missing.");
+    assert_eq!(first.body(), b"hi");
+    assert!(
+        harbor::server::parse_request(&mut reader)
+            .expect("Failed to parse request.")
+            .is_none()
+    );
+    assert!(matches!(
+        harbor::server::parse_request(&mut &b"NOT HTTP\r\n\r\n"[..]),
+        Err(harbor::HarborError::Http(_))
+    ));
+
+    let mut written = Vec::new();
+    harbor::server::write_response(&mut written, harbor::server::handle_request(&second))
+        .expect("Failed to write response.");
+    let response = String::from_utf8_lossy(&written);
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(response.contains("Connection: close\r\n"));
+    assert!(response.contains("<title>Hello!</title>"));
+}
+
+#[cfg(feature = "evented")]
+#[test]
+fn test_evented_backend_serves_others_while_connections_idle() {
+    let router = harbor::server::Router::new()
+        .get("/a", |_| harbor::server::Response::new(200).body("a"))
+        .post("/echo", |request| {
+            harbor::server::Response::new(200).body(request.body().to_vec())
+        });
+    let server = harbor::server::Server::with_config(harbor::server::ServerConfig {
+        address: "127.0.0.1:0".to_string(),
+        workers: 1,
+        ..Default::default()
+    })
+    .router(router)
+    .keep_alive(true)
+    .evented(true);
+    let server = harbor::test::TestServer::start(server);
+
+    // The kept-alive connection goes idle without holding the only worker.
+    let mut idle = TcpStream::connect(server.addr()).expect("Failed to connect to server.");
+    idle.write_all(b"GET /a HTTP/1.1\r\n\r\n")
+        .expect("Failed to write HTTP request.");
+    let mut buffer = Vec::new();
+    while !buffer.ends_with(b"\r\n\r\na") {
+        let mut chunk = [0; 1024];
+        let read = idle.read(&mut chunk).expect("Failed to read response.");
+        assert!(read > 0, "{}", String::from_utf8_lossy(&buffer));
+        buffer.extend_from_slice(&chunk[..read]);
+    }
+
+    // So another client is answered well before the idle one times out.
+    let mut stream = TcpStream::connect(server.addr()).expect("Failed to connect to server.");
+    stream
+        .set_read_timeout(Some(Duration::from_secs(2)))
+        .unwrap();
+    stream
+        .write_all(b"POST /echo HTTP/1.1\r\nContent-Length: 4\r\n\r\npingGET /a HTTP/1.1\r\nConnection: close\r\n\r\n")
+        .expect("Failed to write HTTP requests.");
+    let mut buffer = Vec::new();
+    stream
+        .read_to_end(&mut buffer)
+        .expect("Failed to read server responses.");
+    let response = String::from_utf8_lossy(&buffer);
+    assert_eq!(
+        response.matches("HTTP/1.1 200 OK").count(),
+        2,
+        "{}",
+        response
+    );
+    assert!(response.contains("\r\n\r\nping"), "{}", response);
+    let last = response.rsplit("HTTP/1.1 ").next().unwrap_or_default();
+    assert!(last.contains("Connection: close\r\n"), "{}", last);
+    assert!(last.ends_with("Content-Length: 1\r\n\r\na"), "{}", last);
+}
+
+#[cfg(feature = "upgrade")]
+#[test]
+fn test_upgrade_socket_hands_listeners_to_a_new_server() {
+    use std::sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    };
+
+    let path = std::env::temp_dir().join(format!("harbor-upgrade-{}.sock", std::process::id()));
+    let serve = |name: &'static str| {
+        harbor::server::Router::new()
+            .get("/", move |_| harbor::server::Response::new(200).body(name))
+    };
+    let old = harbor::test::TestServer::start(
+        harbor::server::Server::new("127.0.0.1:0")
+            .router(serve("old"))
+            .upgrade_socket(&path),
+    );
+    while !path.exists() {
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    // A client keeps asking throughout the handover and must never fail.
+    let url = old.url("/");
+    let done = Arc::new(AtomicBool::new(false));
+    let client = thread::spawn({
+        let done = Arc::clone(&done);
+        move || {
+            let mut answers = Vec::new();
+            while !done.load(Ordering::SeqCst) {
+                let response = harbor::client::get(&url).expect("Request failed during handover.");
+                answers.push(String::from_utf8_lossy(response.get_body()).into_owned());
+            }
+            answers
+        }
+    });
+    thread::sleep(Duration::from_millis(100));
+
+    let new = harbor::test::TestServer::start(
+        harbor::server::Server::new(&old.addr().to_string())
+            .router(serve("new"))
+            .upgrade_socket(&path),
+    );
+    assert_eq!(new.addr(), old.addr());
+    let stopped = std::time::Instant::now();
+    while !old.handle().is_stopped() {
+        assert!(
+            stopped.elapsed() < Duration::from_secs(5),
+            "old server kept running"
+        );
+        thread::sleep(Duration::from_millis(10));
+    }
+    drop(old);
+    thread::sleep(Duration::from_millis(100));
+
+    done.store(true, Ordering::SeqCst);
+    let answers = client.join().expect("The client failed.");
+    assert_eq!(answers.first().map(String::as_str), Some("old"));
+    assert_eq!(answers.last().map(String::as_str), Some("new"));
+    // The new server listens for the next upgrade in turn.
+    assert!(path.exists());
+}
+
+#[test]
+fn test_timing_middleware_sends_server_timing_header() {
+    let server = harbor::test::TestServer::start(
+        harbor::server::Server::new("127.0.0.1:0")
+            .middleware(harbor::server::Timing::new().server_timing_header(true)),
+    );
+
+    let response = harbor::client::get(&server.url("/")).expect("Request failed.");
+    let header = response
+        .get_header("Server-Timing")
+        .expect("No Server-Timing header.");
+
+    let names: Vec<&str> = header
+        .split(", ")
+        .map(|metric| metric.split(';').next().unwrap())
+        .collect();
+    assert_eq!(names, ["parse", "queue", "handler"]);
+}
+
+#[test]
+fn test_handlers_receive_the_server_state() {
+    use std::sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    };
+
+    let router = harbor::server::Router::new().stateful(
+        "GET",
+        "/count",
+        |_: &harbor::server::HttpRequest, counter: &AtomicUsize| {
+            let count = counter.fetch_add(1, Ordering::SeqCst) + 1;
+            harbor::server::Response::new(200).body(count.to_string())
+        },
+    );
+    let counter = Arc::new(AtomicUsize::new(0));
+    let server = harbor::test::TestServer::start(
+        harbor::server::Server::new("127.0.0.1:0")
+            .router(router)
+            .with_state(Arc::clone(&counter)),
+    );
+
+    for expected in ["1", "2"] {
+        let response = harbor::client::get(&server.url("/count")).expect("Request failed.");
+        assert_eq!(response.get_body(), expected.as_bytes());
+    }
+    assert_eq!(counter.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn test_routes_pinned_to_a_pool_leave_the_default_pool_free() {
+    let router = harbor::server::Router::new()
+        .get("/fast", |_| harbor::server::Response::new(200).body("fast"))
+        .pool("slow", |slow| {
+            slow.get("/slow", |_| {
+                thread::sleep(Duration::from_millis(600));
+                harbor::server::Response::new(200).body("slow")
+            })
+        });
+    let server = harbor::server::Server::with_config(harbor::server::ServerConfig {
+        address: "127.0.0.1:0".to_string(),
+        workers: 1,
+        ..Default::default()
+    })
+    .router(router)
+    .pool("slow", 1)
+    .keep_alive(true);
+    let server = harbor::test::TestServer::start(server);
+
+    // The slow request moves its connection off the only default worker,
+    // and the fast one after it on the same connection moves it back.
+    let mut slow = TcpStream::connect(server.addr()).expect("Failed to connect to server.");
+    slow.write_all(b"GET /slow HTTP/1.1\r\n\r\nGET /fast HTTP/1.1\r\nConnection: close\r\n\r\n")
+        .expect("Failed to write HTTP requests.");
+    thread::sleep(Duration::from_millis(100));
+
+    let started = std::time::Instant::now();
+    let fast = harbor::client::get(&server.url("/fast")).expect("Request failed.");
+    assert_eq!(fast.get_body(), b"fast");
+    assert!(started.elapsed() < Duration::from_millis(400));
+
+    let mut buffer: Vec<u8> = Vec::new();
+    slow.read_to_end(&mut buffer)
+        .expect("Failed to read server responses.");
+    let response = String::from_utf8_lossy(&buffer);
+    assert_eq!(
+        response.matches("HTTP/1.1 200 OK").count(),
+        2,
+        "{}",
+        response
+    );
+    assert!(response.ends_with("\r\n\r\nfast"), "{}", response);
+}
+
+#[test]
+fn test_error_pages_are_json_for_clients_preferring_it() {
+    let server = harbor::test::TestServer::spawn(harbor::server::Router::new());
+
+    let get = |accept: &str| {
+        harbor::client::Request::new("GET", &server.url("/missing"))
+            .header("Accept", accept)
+            .send()
+            .expect("Request failed.")
+    };
+
+    let json = get("application/json");
+    assert_eq!(json.status(), 404);
+    assert_eq!(
+        json.get_header("Content-Type"),
+        Some("application/problem+json")
+    );
+    assert_eq!(
+        json.get_body(),
+        br#"{"type":"about:blank","title":"Not Found","status":404}"#
+    );
+
+    let html = get("text/html,*/*;q=0.8");
+    assert_eq!(html.status(), 404);
+    assert!(
+        html.get_header("Content-Type")
+            .is_some_and(|value| value.starts_with("text/html"))
+    );
+}
+
+#[test]
+fn test_job_dashboard_lists_jobs_when_enabled() {
+    let hidden = harbor::test::TestServer::start(harbor::server::Server::new("127.0.0.1:0"));
+    let response = harbor::client::get(&hidden.url("/admin/jobs")).expect("Request failed.");
+    assert_eq!(response.status(), 404);
+
+    let server = harbor::test::TestServer::start(
+        harbor::server::Server::new("127.0.0.1:0").job_dashboard(true),
+    );
+
+    let page = harbor::client::get(&server.url("/admin/jobs")).expect("Request failed.");
+    assert_eq!(page.status(), 200);
+    let body = String::from_utf8_lossy(page.get_body());
+    assert!(body.contains("new EventSource(\"/admin/jobs/events\")"));
+
+    let events = harbor::client::get(&server.url("/admin/jobs/events")).expect("Request failed.");
+    assert_eq!(events.get_header("Content-Type"), Some("text/event-stream"));
+    let body = String::from_utf8_lossy(events.get_body());
+    assert!(body.starts_with("retry: "), "{}", body);
+    assert!(body.contains(" pending, "), "{}", body);
+}
+
+#[test]
+fn test_shutdown_hooks_run_in_order_when_the_server_stops() {
+    use std::sync::{Arc, Mutex};
+
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    let (first, last) = (Arc::clone(&calls), Arc::clone(&calls));
+    let server = harbor::test::TestServer::start(
+        harbor::server::Server::new("127.0.0.1:0")
+            .on_shutdown(move || first.lock().unwrap().push("first"))
+            .on_shutdown(|| panic!("hook failed"))
+            .on_shutdown(move || last.lock().unwrap().push("last")),
+    );
+    harbor::client::get(&server.url("/")).expect("Request failed.");
+    assert!(calls.lock().unwrap().is_empty());
+
+    drop(server);
+    assert_eq!(*calls.lock().unwrap(), ["first", "last"]);
+}
+
+#[test]
+fn test_server_listens_on_ipv4_and_ipv6_at_once() {
+    let server = harbor::test::TestServer::start(
+        harbor::server::Server::new("127.0.0.1:0").listen("[::1]:0"),
+    );
+
+    let addrs = server.handle().local_addrs().expect("Server is not bound.");
+    assert_eq!(addrs.len(), 2);
+    assert!(addrs[0].is_ipv4() && addrs[1].is_ipv6());
+    for addr in addrs {
+        let response = harbor::client::get(&format!("http://{}/", addr)).expect("Request failed.");
+        assert_eq!(response.status(), 200);
+    }
+}
+
+#[test]
+fn test_server_adds_date_and_server_headers() {
+    let server = harbor::test::TestServer::start(
+        harbor::server::Server::new("127.0.0.1:0")
+            .server_header("harbor-test")
+            .router(harbor::server::Router::new().get("/own", |_| {
+                harbor::server::Response::new(200).header("Server", "custom")
+            })),
+    );
+
+    let response = harbor::client::get(&server.url("/")).expect("Request failed.");
+    assert_eq!(response.get_header("Server"), Some("harbor-test"));
+    let date = response.get_header("Date").expect("No Date header.");
+    assert!(date.ends_with(" GMT"), "{}", date);
+
+    let response = harbor::client::get(&server.url("/own")).expect("Request failed.");
+    assert_eq!(response.get_header("Server"), Some("custom"));
+}
+
+#[test]
+fn test_server_answers_504_for_handlers_past_their_timeout() {
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let sender = std::sync::Mutex::new(sender);
+    let router = harbor::server::Router::new()
+        .get("/quick", |_| {
+            harbor::server::Response::new(200).body("quick")
+        })
+        .get("/slow", |_| {
+            thread::sleep(Duration::from_millis(500));
+            harbor::server::Response::new(200).body("slow")
+        })
+        .timeout(Duration::from_millis(100), |timed| {
+            timed.get("/stuck", move |request| {
+                while !request.cancelled() {
+                    thread::sleep(Duration::from_millis(5));
+                }
+                sender.lock().unwrap().send("cancelled").unwrap();
+                harbor::server::Response::new(200).body("too late")
+            })
+        });
+    let server = harbor::server::Server::new("127.0.0.1:0")
+        .router(router)
+        .handler_timeout(Duration::from_millis(200));
+    let connections = server.connections();
+    let server = harbor::test::TestServer::start(server);
+
+    let started = std::time::Instant::now();
+    let response = harbor::client::get(&server.url("/stuck")).expect("Request failed.");
+    assert_eq!(response.status(), 504);
+    assert!(started.elapsed() < Duration::from_secs(2));
+    assert_eq!(
+        receiver.recv_timeout(Duration::from_secs(5)),
+        Ok("cancelled")
+    );
+    assert_eq!(connections.stats().handler_timeouts, 1);
+
+    assert_eq!(
+        harbor::client::get(&server.url("/slow"))
+            .expect("Request failed.")
+            .status(),
+        504
+    );
+    let response = harbor::client::get(&server.url("/quick")).expect("Request failed.");
+    assert_eq!(response.get_body(), b"quick");
+    // Both overrunning handlers have returned by now, or soon will.
+    let started = std::time::Instant::now();
+    while connections.stats().abandoned_handlers > 0 {
+        assert!(started.elapsed() < Duration::from_secs(5));
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(connections.stats().handler_timeouts, 2);
+}
+
+#[test]
+fn test_handlers_ignoring_their_timeout_are_capped() {
+    use std::sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    };
+
+    // Ignores `cancelled()` until the test lets go.
+    let released = Arc::new(AtomicBool::new(false));
+    let hold = Arc::clone(&released);
+    let router = harbor::server::Router::new().get("/hang", move |_| {
+        while !hold.load(Ordering::SeqCst) {
+            thread::sleep(Duration::from_millis(5));
+        }
+        harbor::server::Response::new(200).body("done")
+    });
+    let server = harbor::server::Server::with_config(harbor::server::ServerConfig {
+        address: "127.0.0.1:0".to_string(),
+        workers: 16,
+        ..Default::default()
+    })
+    .router(router)
+    .handler_timeout(Duration::from_millis(20));
+    let connections = server.connections();
+    let server = harbor::test::TestServer::start(server);
+
+    let url = server.url("/hang");
+    let clients: Vec<_> = (0..64)
+        .map(|_| {
+            let url = url.clone();
+            thread::spawn(move || harbor::client::get(&url).expect("Request failed.").status())
+        })
+        .collect();
+    for client in clients {
+        assert_eq!(client.join().unwrap(), 504);
+    }
+    assert_eq!(connections.stats().abandoned_handlers, 64);
+
+    let refused = harbor::client::get(&url).expect("Request failed.");
+    assert_eq!(refused.status(), 503);
+    assert_eq!(refused.get_header("Retry-After"), Some("1"));
+
+    released.store(true, Ordering::SeqCst);
+    let started = std::time::Instant::now();
+    while connections.stats().abandoned_handlers > 0 {
+        assert!(started.elapsed() < Duration::from_secs(5));
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(connections.stats().handler_timeouts, 64);
+    assert_eq!(
+        harbor::client::get(&url).expect("Request failed.").status(),
+        200
+    );
+}
+
+#[test]
+fn test_server_refuses_methods_outside_the_allowed_set() {
+    let router = || {
+        harbor::server::Router::new()
+            .get("/", |_| harbor::server::Response::new(200).body("home"))
+            .route("BREW", "/pot", |_| {
+                harbor::server::Response::new(200).body("brewing")
+            })
+    };
+    let default = harbor::test::TestServer::start(
+        harbor::server::Server::new("127.0.0.1:0").router(router()),
+    );
+    let custom = harbor::test::TestServer::start(
+        harbor::server::Server::new("127.0.0.1:0")
+            .router(router())
+            .allowed_methods(&["GET", "BREW"]),
+    );
+
+    let status = |server: &harbor::test::TestServer, method: &str, path: &str| {
+        harbor::client::Request::new(method, &server.url(path))
+            .send()
+            .expect("Request failed.")
+    };
+    let trace = status(&default, "TRACE", "/");
+    assert_eq!(trace.status(), 405);
+    assert_eq!(
+        trace.get_header("Allow"),
+        Some("GET, HEAD, POST, PUT, DELETE, PATCH, OPTIONS")
+    );
+    assert_eq!(status(&default, "CONNECT", "/").status(), 405);
+    assert_eq!(status(&default, "BREW", "/pot").status(), 501);
+    assert_eq!(status(&default, "GET", "/").status(), 200);
+
+    assert_eq!(status(&custom, "BREW", "/pot").get_body(), b"brewing");
+    assert_eq!(
+        status(&custom, "POST", "/").get_header("Allow"),
+        Some("GET, BREW")
+    );
+}
+
+#[test]
+fn test_server_queues_startup_jobs_by_name() {
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let sender = std::sync::Mutex::new(sender);
+    let mut registry = harbor::registry::JobRegistry::new();
+    registry.register("greet", move |args| {
+        let name = args
+            .get("name")
+            .and_then(|name| name.as_str())
+            .unwrap_or("world");
+        sender
+            .lock()
+            .unwrap()
+            .send(format!("Hello, {}!", name))
+            .unwrap();
+        Ok(Box::new(|| Ok("greeted".to_string())))
+    });
+    let descriptor =
+        harbor::registry::JobDescriptor::parse(r#"{"job": "greet", "args": {"name": "harbor"}}"#)
+            .expect("Invalid descriptor.");
+
+    let _server = harbor::test::TestServer::start(
+        harbor::server::Server::new("127.0.0.1:0")
+            .registry(registry)
+            .job(descriptor),
+    );
+
+    assert_eq!(
+        receiver.recv_timeout(Duration::from_secs(5)),
+        Ok("Hello, harbor!".to_string())
+    );
+}
+
+#[cfg(feature = "brotli")]
+#[test]
+fn test_server_compresses_responses_for_clients_accepting_brotli() {
+    let page = "<p>Compressed by harbor.</p>\n".repeat(100);
+    let body = page.clone();
+    let server = harbor::test::TestServer::start(
+        harbor::server::Server::new("127.0.0.1:0")
+            .middleware(harbor::server::Compression::new())
+            .router(harbor::server::Router::new().get("/page", move |_| {
+                harbor::server::Response::html(200, body.clone())
+            })),
+    );
+
+    let response = harbor::client::Request::new("GET", &server.url("/page"))
+        .header("Accept-Encoding", "gzip, deflate, br")
+        .send()
+        .expect("Request failed.");
+    assert_eq!(response.get_header("Content-Encoding"), Some("br"));
+    assert_eq!(response.get_header("Vary"), Some("Accept-Encoding"));
+    assert!(response.get_body().len() < page.len() / 10);
+
+    let response = harbor::client::get(&server.url("/page")).expect("Request failed.");
+    assert_eq!(response.get_header("Content-Encoding"), None);
+    assert_eq!(response.get_body(), page.as_bytes());
+}
+
+fn upgrade_echo(server: harbor::server::Server) {
+    let router = harbor::server::Router::new().get("/echo", |_| {
+        harbor::server::Response::switching_protocols("echo", |mut connection| {
+            let mut buffer = [0; 1024];
+            while let Ok(read @ 1..) = connection.read(&mut buffer) {
+                if connection.write_all(&buffer[..read]).is_err() {
+                    break;
+                }
+            }
+        })
+    });
+    let server = harbor::test::TestServer::start(server.router(router));
+
+    let mut stream = TcpStream::connect(server.addr()).expect("Failed to connect to server.");
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    // The first bytes of the new protocol follow the request at once.
+    stream
+        .write_all(b"GET /echo HTTP/1.1\r\nConnection: Upgrade\r\nUpgrade: echo\r\n\r\nping")
+        .expect("Failed to write HTTP request.");
+
+    let mut received = Vec::new();
+    let mut buffer = [0; 1024];
+    while !received.ends_with(b"ping") {
+        let read = stream.read(&mut buffer).expect("Failed to read.");
+        assert!(read > 0, "Connection closed early: {:?}", received);
+        received.extend_from_slice(&buffer[..read]);
+    }
+    let received = String::from_utf8(received).unwrap();
+    assert!(received.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+    assert!(received.contains("Upgrade: echo\r\n"));
+    assert!(!received.contains("Content-Length"));
+
+    stream.write_all(b"pong").unwrap();
+    let mut pong = [0; 4];
+    stream.read_exact(&mut pong).expect("Failed to read.");
+    assert_eq!(&pong, b"pong");
+}
+
+#[test]
+fn test_switching_protocols_hands_the_connection_to_the_handler() {
+    upgrade_echo(harbor::server::Server::new("127.0.0.1:0"));
+}
+
+#[cfg(feature = "evented")]
+#[test]
+fn test_evented_backend_hands_upgraded_connections_to_a_worker() {
+    upgrade_echo(harbor::server::Server::new("127.0.0.1:0").evented(true));
+}
+
+#[test]
+fn test_traffic_counts_the_bytes_of_each_request_and_response() {
+    let server = harbor::server::Server::new("127.0.0.1:0").router(
+        harbor::server::Router::new().get("/hello", |_| {
+            harbor::server::Response::new(200).body("hello")
+        }),
+    );
+    let traffic = server.traffic();
+    let server = harbor::test::TestServer::start(server);
+
+    let request = b"GET /hello HTTP/1.1\r\nConnection: close\r\n\r\n";
+    let mut stream = TcpStream::connect(server.addr()).expect("Failed to connect to server.");
+    stream
+        .write_all(request)
+        .expect("Failed to write HTTP request.");
+    let mut buffer: Vec<u8> = Vec::new();
+    stream
+        .read_to_end(&mut buffer)
+        .expect("Failed to read server response.");
+
+    let stats = traffic.stats();
+    assert_eq!(stats.requests, 1);
+    assert_eq!(stats.bytes_read, request.len() as u64);
+    assert_eq!(stats.bytes_written, buffer.len() as u64);
+    assert_eq!(stats.response_sizes.max, buffer.len() as u64);
+}